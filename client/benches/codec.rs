@@ -0,0 +1,35 @@
+//! `cargo bench -p client --no-default-features --features bincode --bench codec`. Throughput is
+//! reported in elements per second, each a `MoveBody` encoded and decoded again, which is sent
+//! every frame while dragging. The size of one is printed for each codec.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use shared::codec::{self, Codec};
+use shared::viewer::Command;
+use shared::CustomMessage;
+
+fn drag() -> Command<CustomMessage> {
+    Command::Custom(
+        "ABCD".parse().unwrap(),
+        CustomMessage::MoveBody(0.123_456, -0.654_321),
+    )
+}
+
+fn move_body(c: &mut Criterion) {
+    let cmd = drag();
+    let mut group = c.benchmark_group("move_body");
+    group.throughput(Throughput::Elements(1));
+    for codec in [Codec::Json, Codec::Bincode] {
+        let size = codec.encode(&cmd).unwrap().len();
+        println!("{:?}: {} bytes", codec, size);
+        group.bench_function(BenchmarkId::from_parameter(format!("{:?}", codec)), |b| {
+            b.iter(|| {
+                let frame = codec.encode(black_box(&cmd)).unwrap();
+                codec::decode::<Command<CustomMessage>>(frame.as_frame_ref()).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, move_body);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+//! `cargo bench -p client --no-default-features --bench physics`. Throughput is reported in
+//! elements per second, which for `step` is physics steps per second.
+
+use client::sim::{Sim, ROOM_TYPES};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use std::time::Duration;
+
+/// Two seconds of game time, which covers a fresh stack settling.
+const TICKS: u64 = 120;
+
+fn step(c: &mut Criterion) {
+    // exactly one tick per call so that every `step` runs the pipeline
+    let dt = Duration::from_secs_f32(1. / 60.);
+    let mut group = c.benchmark_group("step");
+    group.throughput(Throughput::Elements(TICKS));
+    for room_type in ROOM_TYPES.iter() {
+        group.bench_function(BenchmarkId::from_parameter(room_type.name), |b| {
+            b.iter_batched(
+                room_type.gen,
+                |mut sim| {
+                    for _ in 0..TICKS {
+                        sim.step(dt);
+                    }
+                    sim
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn body_at_point(c: &mut Criterion) {
+    let sim = Sim::tower();
+    // covers the whole stack and some empty space around it so that misses are counted too
+    let points = (0..20)
+        .flat_map(|x| {
+            (0..20).map(move |y| (x as f32 / 19. * 0.8 - 0.4, y as f32 / 19. * 0.8 - 0.5))
+        })
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("body_at_point");
+    group.throughput(Throughput::Elements(points.len() as u64));
+    group.bench_function("tower", |b| {
+        b.iter(|| {
+            points
+                .iter()
+                .filter(|(x, y)| sim.body_at_point(black_box(*x), black_box(*y)).is_some())
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, step, body_at_point);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+use shared::viewer::User;
+
+/// Receives short descriptions of what's happening in the game, for screen readers and the like.
+pub trait Announcer {
+    fn announce(&self, text: &str);
+}
+
+/// Announces nothing. The default until something asks to be told.
+#[derive(Debug, Default)]
+pub struct Silent;
+
+impl Announcer for Silent {
+    fn announce(&self, _text: &str) {}
+}
+
+#[cfg(target_arch = "wasm32")]
+pub struct JsAnnouncer(pub js_sys::Function);
+
+#[cfg(target_arch = "wasm32")]
+impl Announcer for JsAnnouncer {
+    fn announce(&self, text: &str) {
+        let this = wasm_bindgen::JsValue::NULL;
+        if let Err(err) = self.0.call1(&this, &wasm_bindgen::JsValue::from_str(text)) {
+            log::warn!("announcer failed: {:?}", err);
+        }
+    }
+}
+
+pub fn joined_room(room_id: shared::RoomID) -> String {
+    format!("You joined room {}", room_id)
+}
+
+pub fn sim_events<I>(announcer: &dyn Announcer, events: I)
+where
+    I: IntoIterator<Item = crate::sim::SimEvent>,
+{
+    for event in events {
+        if event == crate::sim::SimEvent::Collapse {
+            announcer.announce("Tower collapsed");
+        }
+    }
+}
+
+/// Announces whose turn it is whenever that changes.
+#[derive(Debug, Default)]
+pub struct TurnWatcher {
+    current: Option<shared::PlayerID>,
+}
+
+impl TurnWatcher {
+    /// Returns whether the turn changed hands.
+    pub fn observe(
+        &mut self,
+        current: Option<&User>,
+        local_user: &User,
+        announcer: &dyn Announcer,
+    ) -> bool {
+        let id = current.map(|user| user.id);
+        if id == self.current {
+            return false;
+        }
+        self.current = id;
+        if let Some(user) = current {
+            if user.id == local_user.id {
+                announcer.announce("Your turn");
+            } else {
+                announcer.announce(&format!("{}'s turn", user.name));
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::user;
+
+    #[derive(Default)]
+    struct Transcript(std::cell::RefCell<Vec<String>>);
+
+    impl Announcer for Transcript {
+        fn announce(&self, text: &str) {
+            self.0.borrow_mut().push(text.to_owned());
+        }
+    }
+
+    #[test]
+    fn turn_changes_are_announced_once() {
+        let alice = user("1", "Alice");
+        let bob = user("2", "Bob");
+
+        let transcript = Transcript::default();
+        let mut turns = TurnWatcher::default();
+        assert!(!turns.observe(None, &bob, &transcript));
+        assert!(turns.observe(Some(&alice), &bob, &transcript));
+        assert!(!turns.observe(Some(&alice), &bob, &transcript));
+        assert!(turns.observe(Some(&bob), &bob, &transcript));
+        assert!(turns.observe(None, &bob, &transcript));
+
+        assert_eq!(
+            *transcript.0.borrow(),
+            vec!["Alice's turn".to_owned(), "Your turn".to_owned()]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! The native sandbox without the window around it, so that another application or a test can
+//! drive it by handing over its own events and graphics context.
+
+use crate::winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use crate::{audio, sim, MouseEvent};
+use solstice_2d::solstice::viewport::Viewport;
+
+/// What `GameApp::handle_event` needs from whoever owns the window.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AppRequest {
+    Continue,
+    Exit,
+    ToggleFullscreen,
+}
+
+pub struct GameApp {
+    sim: sim::Sim,
+    audio: Box<dyn audio::AudioBackend>,
+    viewport: Viewport<i32>,
+    /// Set by a resize until `render` passes the new viewport on to the graphics context.
+    resized: bool,
+    mouse: (f32, f32),
+    prev_t: Option<std::time::Duration>,
+}
+
+impl GameApp {
+    pub fn new(width: u32, height: u32, audio: Box<dyn audio::AudioBackend>) -> Self {
+        Self {
+            sim: sim::Sim::new(),
+            audio,
+            viewport: Viewport::new(0, 0, width as _, height as _),
+            resized: true,
+            mouse: (0., 0.),
+            prev_t: None,
+        }
+    }
+
+    pub fn viewport(&self) -> &Viewport<i32> {
+        &self.viewport
+    }
+
+    pub fn handle_event(&mut self, event: &WindowEvent<'_>) -> AppRequest {
+        match event {
+            WindowEvent::Resized(size) => {
+                let (width, height) = (size.width, size.height);
+                self.viewport = Viewport::new(0, 0, width as _, height as _);
+                self.resized = true;
+                // minimizing shrinks the window to nothing
+                self.audio.set_music_paused(width == 0 || height == 0);
+            }
+            WindowEvent::CloseRequested => return AppRequest::Exit,
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key_code),
+                        ..
+                    },
+                ..
+            } => match key_code {
+                VirtualKeyCode::Q => self.sim = sim::Sim::new(),
+                VirtualKeyCode::W => self.sim = sim::Sim::pyramid(),
+                VirtualKeyCode::E => self.sim = sim::Sim::tower(),
+                VirtualKeyCode::R => self.sim = sim::Sim::thin(),
+                // the resulting Resized event recomputes the viewport
+                VirtualKeyCode::F11 => return AppRequest::ToggleFullscreen,
+                _ => {}
+            },
+            event => {
+                if let Some(event) = MouseEvent::from_window_event(event) {
+                    self.handle_mouse_event(event);
+                }
+            }
+        }
+        AppRequest::Continue
+    }
+
+    /// Clicking a body removes it.
+    fn handle_mouse_event(&mut self, event: MouseEvent) {
+        match event {
+            MouseEvent::Moved(x, y) => self.mouse = (x, y),
+            event if event.is_left_press() => {
+                let (mx, my) = self.mouse;
+                let [x, y] = sim::Sim::screen_to_world(&self.viewport, mx, my);
+                if let Some(handle) = self.sim.body_at_point(x, y) {
+                    self.sim.try_remove_body(handle);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Steps the simulation up to `time`, measured from any fixed point, and draws it.
+    pub fn render(
+        &mut self,
+        time: std::time::Duration,
+        ctx: &mut solstice_2d::solstice::Context,
+        gfx: &mut solstice_2d::Graphics,
+    ) {
+        if std::mem::take(&mut self.resized) {
+            let vw = self.viewport;
+            ctx.set_viewport(0, 0, vw.width(), vw.height());
+            gfx.set_viewport(vw);
+        }
+
+        let dt = time - self.prev_t.unwrap_or(time);
+        self.prev_t = Some(time);
+        self.sim.step(dt);
+        // the sandbox has no settings menu to turn sound off from
+        let settings = crate::settings::Settings::default();
+        audio::play_sim_events(&*self.audio, self.sim.drain_events(), &settings);
+        self.sim.prepare_render();
+        self.sim.render(&mut gfx.lock(ctx));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resizing_updates_the_viewport() {
+        let mut app = GameApp::new(1280, 720, Box::new(audio::NoAudio));
+        assert_eq!(
+            (app.viewport().width(), app.viewport().height()),
+            (1280, 720)
+        );
+
+        let size = crate::winit::dpi::PhysicalSize::new(640, 360);
+        assert_eq!(
+            app.handle_event(&WindowEvent::Resized(size)),
+            AppRequest::Continue
+        );
+        assert_eq!(
+            (app.viewport().width(), app.viewport().height()),
+            (640, 360)
+        );
+        assert!(app.resized);
+    }
+}
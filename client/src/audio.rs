@@ -0,0 +1,286 @@
+/// Sound effects the game knows how to ask for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SoundId {
+    /// Two blocks knocking together.
+    Contact,
+    /// A block fell off of the tower.
+    Collapse,
+}
+
+impl SoundId {
+    /// There are no audio assets yet so every sound is a short tone: (frequency in Hz, seconds).
+    #[cfg_attr(
+        not(any(target_arch = "wasm32", feature = "native-audio")),
+        allow(dead_code)
+    )]
+    fn tone(&self) -> (f32, f32) {
+        match self {
+            SoundId::Contact => (180., 0.08),
+            SoundId::Collapse => (70., 0.6),
+        }
+    }
+}
+
+impl From<crate::sim::SimEvent> for SoundId {
+    fn from(event: crate::sim::SimEvent) -> Self {
+        match event {
+            crate::sim::SimEvent::Contact => SoundId::Contact,
+            crate::sim::SimEvent::Collapse => SoundId::Collapse,
+        }
+    }
+}
+
+pub trait AudioBackend {
+    fn play(&self, sound: SoundId);
+
+    /// Loops `track`, an encoded audio file, until the backend goes away.
+    fn start_music_loop(&self, _track: &[u8], _volume: f32) {}
+
+    fn set_music_volume(&self, _volume: f32) {}
+
+    fn set_music_paused(&self, _paused: bool) {}
+}
+
+/// Plays nothing. The default, so that nothing headless needs a sound device.
+#[derive(Debug, Default)]
+pub struct NoAudio;
+
+impl AudioBackend for NoAudio {
+    fn play(&self, _sound: SoundId) {}
+}
+
+/// Converts the percentage kept in settings to a gain.
+pub fn music_volume(percent: u8) -> f32 {
+    percent.min(100) as f32 / 100.
+}
+
+/// Starts `track` looping at the volume the player last chose.
+pub fn start_music(backend: &dyn AudioBackend, track: &[u8], settings: &crate::settings::Settings) {
+    backend.start_music_loop(track, music_volume(settings.music_volume));
+}
+
+/// Nothing is played while sound is turned off in `settings`.
+pub fn play_sim_events<I>(
+    backend: &dyn AudioBackend,
+    events: I,
+    settings: &crate::settings::Settings,
+) where
+    I: IntoIterator<Item = crate::sim::SimEvent>,
+{
+    if !settings.sound {
+        return;
+    }
+    for event in events {
+        backend.play(event.into());
+    }
+}
+
+/// Cheap to clone: every clone drives the same audio context.
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone)]
+pub struct WebAudio {
+    ctx: web_sys::AudioContext,
+    music: web_sys::GainNode,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl WebAudio {
+    pub fn new() -> Result<Self, wasm_bindgen::JsValue> {
+        let ctx = web_sys::AudioContext::new()?;
+        let music = ctx.create_gain()?;
+        music.connect_with_audio_node(&ctx.destination())?;
+        Ok(Self { ctx, music })
+    }
+
+    fn tone(&self, frequency: f32, duration: f32) -> Result<(), wasm_bindgen::JsValue> {
+        let now = self.ctx.current_time();
+        let end = now + duration as f64;
+
+        let oscillator = self.ctx.create_oscillator()?;
+        oscillator.frequency().set_value(frequency);
+        let gain = self.ctx.create_gain()?;
+        gain.gain().set_value_at_time(0.2, now)?;
+        gain.gain().exponential_ramp_to_value_at_time(0.001, end)?;
+
+        oscillator.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&self.ctx.destination())?;
+        oscillator.start()?;
+        oscillator.stop_with_when(end)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl AudioBackend for WebAudio {
+    fn play(&self, sound: SoundId) {
+        let (frequency, duration) = sound.tone();
+        if let Err(err) = self.tone(frequency, duration) {
+            log::warn!("failed to play {:?}: {:?}", sound, err);
+        }
+    }
+
+    fn start_music_loop(&self, track: &[u8], volume: f32) {
+        use wasm_bindgen::JsCast;
+        self.set_music_volume(volume);
+        let ctx = self.ctx.clone();
+        let music = self.music.clone();
+        let track = js_sys::Uint8Array::from(track).buffer();
+        wasm_bindgen_futures::spawn_local(async move {
+            // decoding is asynchronous so the music comes in a little after everything else
+            let started: Result<(), wasm_bindgen::JsValue> = async {
+                let buffer = ctx.decode_audio_data(&track)?;
+                let buffer = wasm_bindgen_futures::JsFuture::from(buffer)
+                    .await?
+                    .dyn_into::<web_sys::AudioBuffer>()?;
+                let source = ctx.create_buffer_source()?;
+                source.set_buffer(Some(&buffer));
+                source.set_loop(true);
+                source.connect_with_audio_node(&music)?;
+                source.start()
+            }
+            .await;
+            if let Err(err) = started {
+                log::warn!("failed to start music: {:?}", err);
+            }
+        });
+    }
+
+    fn set_music_volume(&self, volume: f32) {
+        self.music.gain().set_value(volume);
+    }
+
+    /// Suspends the whole context, which takes sound effects along with the music.
+    fn set_music_paused(&self, paused: bool) {
+        let result = if paused {
+            self.ctx.suspend()
+        } else {
+            self.ctx.resume()
+        };
+        if let Err(err) = result {
+            log::warn!("failed to pause music: {:?}", err);
+        }
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-audio"))]
+pub struct RodioAudio {
+    // playback stops when the stream is dropped
+    _stream: rodio::OutputStream,
+    handle: rodio::OutputStreamHandle,
+    music: rodio::Sink,
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-audio"))]
+impl RodioAudio {
+    pub fn new() -> eyre::Result<Self> {
+        let (_stream, handle) = rodio::OutputStream::try_default()?;
+        let music = rodio::Sink::try_new(&handle)?;
+        Ok(Self {
+            _stream,
+            handle,
+            music,
+        })
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-audio"))]
+impl AudioBackend for RodioAudio {
+    fn play(&self, sound: SoundId) {
+        use rodio::Source;
+        let (frequency, duration) = sound.tone();
+        let source = rodio::source::SineWave::new(frequency as u32)
+            .take_duration(std::time::Duration::from_secs_f32(duration))
+            .amplify(0.2);
+        if let Err(err) = self.handle.play_raw(source) {
+            log::warn!("failed to play {:?}: {}", sound, err);
+        }
+    }
+
+    fn start_music_loop(&self, track: &[u8], volume: f32) {
+        use rodio::Source;
+        let track = std::io::Cursor::new(track.to_vec());
+        match rodio::Decoder::new(track) {
+            Ok(track) => {
+                self.music.set_volume(volume);
+                self.music.append(track.repeat_infinite());
+            }
+            Err(err) => log::warn!("failed to start music: {}", err),
+        }
+    }
+
+    fn set_music_volume(&self, volume: f32) {
+        self.music.set_volume(volume);
+    }
+
+    fn set_music_paused(&self, paused: bool) {
+        if paused {
+            self.music.pause();
+        } else {
+            self.music.play();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Play(SoundId),
+        StartMusicLoop(usize, f32),
+    }
+
+    #[derive(Default)]
+    struct MockAudio {
+        calls: std::cell::RefCell<Vec<Call>>,
+    }
+
+    impl AudioBackend for MockAudio {
+        fn play(&self, sound: SoundId) {
+            self.calls.borrow_mut().push(Call::Play(sound));
+        }
+
+        fn start_music_loop(&self, track: &[u8], volume: f32) {
+            self.calls
+                .borrow_mut()
+                .push(Call::StartMusicLoop(track.len(), volume));
+        }
+    }
+
+    #[test]
+    fn collapse_plays_collapse() {
+        let mut settings = crate::settings::Settings::default();
+        let audio = MockAudio::default();
+        play_sim_events(&audio, vec![crate::sim::SimEvent::Collapse], &settings);
+        assert_eq!(*audio.calls.borrow(), vec![Call::Play(SoundId::Collapse)]);
+
+        settings.sound = false;
+        let muted = MockAudio::default();
+        play_sim_events(&muted, vec![crate::sim::SimEvent::Collapse], &settings);
+        assert!(muted.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn music_loops_at_the_saved_volume() {
+        let track = [0u8; 16];
+        let mut settings = crate::settings::Settings::default();
+
+        let audio = MockAudio::default();
+        start_music(&audio, &track, &settings);
+        assert_eq!(
+            *audio.calls.borrow(),
+            vec![Call::StartMusicLoop(
+                track.len(),
+                music_volume(settings.music_volume)
+            )]
+        );
+
+        settings.music_volume = 0;
+        let audio = MockAudio::default();
+        start_music(&audio, &track, &settings);
+        assert_eq!(
+            *audio.calls.borrow(),
+            vec![Call::StartMusicLoop(track.len(), 0.)]
+        );
+    }
+}
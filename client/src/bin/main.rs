@@ -1,94 +1,111 @@
 use client::*;
 
 fn main() -> eyre::Result<()> {
-    simple_logger::SimpleLogger::new()
-        .with_level(log::LevelFilter::Debug)
+    let log_filter = shared::log_filter::LogFilter::parse_env(
+        std::env::var(shared::LOG_LEVEL_VAR).ok().as_deref(),
+    );
+    let filter = log_filter.clone().unwrap_or_default();
+    filter
+        .targets
+        .iter()
+        .fold(
+            simple_logger::SimpleLogger::new().with_level(filter.default),
+            |logger, (target, level)| logger.with_module_level(target, *level),
+        )
         .init()?;
+    if let Err(err) = log_filter {
+        log::warn!("Invalid {}: {}", shared::LOG_LEVEL_VAR, err);
+    }
 
     let (width, height) = (1280, 720);
     let event_loop = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
         .with_title("TENSION")
         .with_inner_size(glutin::dpi::PhysicalSize::new(width, height));
-    let (glow_ctx, window) = window::init_ctx(wb, &event_loop);
+    let (glow_ctx, window) = window::init_ctx(wb, &event_loop, window::GfxConfig::from_env())?;
     let mut ctx = solstice_2d::solstice::Context::new(glow_ctx);
-    let mut gfx = solstice_2d::Graphics::new(&mut ctx, width as f32, height as f32)?;
+    let gfx = solstice_2d::Graphics::new(&mut ctx, width as f32, height as f32)?;
 
-    let now = {
-        let epoch = std::time::Instant::now();
-        move || epoch.elapsed()
+    let audio: Box<dyn audio::AudioBackend> = native_audio();
+    if let Some(path) = std::env::var_os("TENSION_MUSIC") {
+        match std::fs::read(&path) {
+            Ok(track) => audio::start_music(&*audio, &track, &settings::Store::default().load()),
+            Err(err) => log::warn!("failed to read music from {:?}: {}", path, err),
+        }
+    }
+    let mut sandbox = Sandbox {
+        app: app::GameApp::new(width, height, audio),
+        ctx,
+        gfx,
+    };
+    let mut native = NativePlatform {
+        clock: Default::default(),
+        window,
     };
-
-    let mut game = sim::Sim::new();
-
-    let mut prev_t = now();
-    let (mut mx, mut my) = (0., 0.);
 
     event_loop.run(move |event, _, cf| {
         use glutin::{event::*, event_loop::ControlFlow};
         match event {
-            Event::NewEvents(_) => {}
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::Resized(size) => {
-                    use solstice_2d::solstice::viewport::Viewport;
-                    let (win_width, win_height) = (size.width, size.height);
-                    let vw = Viewport::new(0, 0, win_width as _, win_height as _);
-                    ctx.set_viewport(0, 0, win_width as _, win_height as _);
-                    gfx.set_viewport(vw);
-                }
-                WindowEvent::CloseRequested => {
-                    *cf = ControlFlow::Exit;
+            Event::WindowEvent { event, .. } => match sandbox.app.handle_event(&event) {
+                app::AppRequest::Continue => {}
+                app::AppRequest::Exit => *cf = ControlFlow::Exit,
+                app::AppRequest::ToggleFullscreen => {
+                    let window = &native.window;
+                    let fullscreen = match window.fullscreen() {
+                        Some(_) => None,
+                        None => Some(glutin::window::Fullscreen::Borderless(
+                            window.current_monitor(),
+                        )),
+                    };
+                    window.set_fullscreen(fullscreen);
                 }
-                WindowEvent::KeyboardInput {
-                    input:
-                        KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(key_code),
-                            ..
-                        },
-                    ..
-                } => match key_code {
-                    VirtualKeyCode::Q => game = sim::Sim::new(),
-                    VirtualKeyCode::W => game = sim::Sim::pyramid(),
-                    VirtualKeyCode::E => game = sim::Sim::tower(),
-                    VirtualKeyCode::R => game = sim::Sim::thin(),
-                    _ => {}
-                },
-                WindowEvent::MouseInput { state, button, .. } => {
-                    if state == ElementState::Pressed && button == MouseButton::Left {
-                        let [x, y] = crate::sim::Sim::screen_to_world(gfx.viewport(), mx, my);
-                        if let Some(handle) = game.body_at_point(x, y) {
-                            game.try_remove_body(handle);
-                        }
-                    }
-                }
-                WindowEvent::CursorMoved { position, .. } => {
-                    mx = position.x as f32;
-                    my = position.y as f32;
-                }
-                _ => {}
             },
-            Event::DeviceEvent { .. } => {}
-            Event::UserEvent(_) => {}
-            Event::Suspended => {}
-            Event::Resumed => {}
             Event::MainEventsCleared => {
-                window.request_redraw();
+                native.window.request_redraw();
             }
             Event::RedrawRequested(_) => {
-                let t = now();
-                let dt = t - prev_t;
-                prev_t = t;
-                game.step(dt);
-                game.render(&mut gfx.lock(&mut ctx));
-                window.swap_buffers().expect("omfg");
+                platform::run_frame(&mut native, &mut sandbox).expect("omfg");
             }
-            Event::RedrawEventsCleared => {}
-            Event::LoopDestroyed => {}
+            _ => {}
         }
     });
 }
 
+/// Frames are timed by the wall clock and presented by swapping the window's buffers.
+struct NativePlatform {
+    clock: clock::RealClock,
+    window: window::Window,
+}
+
+impl platform::Platform for NativePlatform {
+    fn now(&self) -> std::time::Duration {
+        use clock::Clock;
+        self.clock.now()
+    }
+
+    fn present(&mut self) -> eyre::Result<()> {
+        self.window.swap_buffers()
+    }
+}
+
+/// The sandbox along with what it draws with.
+struct Sandbox {
+    app: app::GameApp,
+    ctx: solstice_2d::solstice::Context,
+    gfx: solstice_2d::Graphics,
+}
+
+impl platform::Frame for Sandbox {
+    fn frame(&mut self, time: std::time::Duration, render: bool) {
+        debug_assert!(render, "native contexts are never lost");
+        self.app.render(time, &mut self.ctx, &mut self.gfx);
+    }
+
+    fn restore_context(&mut self, _ctx: solstice_2d::solstice::Context) -> eyre::Result<()> {
+        Err(eyre::eyre!("native contexts are never lost"))
+    }
+}
+
 mod window {
     mod native {
         use glutin as winit;
@@ -118,26 +135,117 @@ mod window {
             type Target = Window;
 
             fn deref(&self) -> &Self::Target {
-                &self.inner.window()
+                self.inner.window()
+            }
+        }
+
+        #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+        pub struct GfxConfig {
+            pub msaa: u16,
+            pub vsync: bool,
+        }
+
+        impl Default for GfxConfig {
+            fn default() -> Self {
+                Self {
+                    msaa: 16,
+                    vsync: true,
+                }
             }
         }
 
-        pub fn init_ctx(wb: WindowBuilder, el: &EventLoop<()>) -> (Context, NativeWindow) {
-            let windowed_context = winit::ContextBuilder::new()
-                .with_multisampling(16)
-                .with_vsync(true)
-                .build_windowed(wb, &el)
-                .unwrap();
-            let windowed_context = unsafe { windowed_context.make_current().unwrap() };
-            let gfx = unsafe {
-                Context::from_loader_function(|s| windowed_context.get_proc_address(s) as *const _)
-            };
-            (gfx, NativeWindow::new(windowed_context))
+        impl GfxConfig {
+            /// Reads overrides from `TENSION_MSAA` and `TENSION_VSYNC`, keeping defaults for
+            /// anything missing or unparseable.
+            pub fn from_env() -> Self {
+                let default = Self::default();
+                let var = |key: &str| std::env::var(key).ok();
+                Self {
+                    msaa: var("TENSION_MSAA")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(default.msaa),
+                    vsync: var("TENSION_VSYNC")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(default.vsync),
+                }
+            }
+        }
+
+        /// The requested sample count followed by every lower level worth trying.
+        pub fn msaa_levels(requested: u16) -> impl Iterator<Item = u16> {
+            std::iter::once(requested)
+                .chain(IntoIterator::into_iter([8, 4, 0]).filter(move |level| *level < requested))
+        }
+
+        pub fn init_ctx(
+            wb: WindowBuilder,
+            el: &EventLoop<()>,
+            config: GfxConfig,
+        ) -> eyre::Result<(Context, NativeWindow)> {
+            let mut errors = vec![];
+            for msaa in msaa_levels(config.msaa) {
+                let windowed_context = match winit::ContextBuilder::new()
+                    .with_multisampling(msaa)
+                    .with_vsync(config.vsync)
+                    .build_windowed(wb.clone(), el)
+                {
+                    Ok(windowed_context) => windowed_context,
+                    Err(err) => {
+                        log::warn!("Could not create context with {}x MSAA: {}", msaa, err);
+                        errors.push(err.to_string());
+                        continue;
+                    }
+                };
+                let windowed_context = unsafe {
+                    windowed_context
+                        .make_current()
+                        .map_err(|(_ctx, err)| eyre::Report::new(err))?
+                };
+                let gfx = unsafe {
+                    Context::from_loader_function(|s| {
+                        windowed_context.get_proc_address(s) as *const _
+                    })
+                };
+                return Ok((gfx, NativeWindow::new(windowed_context)));
+            }
+            Err(eyre::Report::msg(format!(
+                "could not create a GL context: {}",
+                errors.join(", ")
+            )))
         }
     }
 
-    pub use {
-        glutin as winit,
-        native::{init_ctx, NativeWindow as Window},
-    };
+    pub use native::{init_ctx, GfxConfig, NativeWindow as Window};
+
+    #[cfg(test)]
+    pub use native::msaa_levels;
+}
+
+#[cfg(feature = "native-audio")]
+fn native_audio() -> Box<dyn audio::AudioBackend> {
+    match audio::RodioAudio::new() {
+        Ok(audio) => Box::new(audio),
+        Err(err) => {
+            log::warn!("continuing without sound: {}", err);
+            Box::new(audio::NoAudio)
+        }
+    }
+}
+
+#[cfg(not(feature = "native-audio"))]
+fn native_audio() -> Box<dyn audio::AudioBackend> {
+    Box::new(audio::NoAudio)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::window::msaa_levels;
+
+    #[test]
+    fn msaa_fallback() {
+        assert_eq!(msaa_levels(16).collect::<Vec<_>>(), vec![16, 8, 4, 0]);
+        assert_eq!(msaa_levels(8).collect::<Vec<_>>(), vec![8, 4, 0]);
+        assert_eq!(msaa_levels(2).collect::<Vec<_>>(), vec![2, 0]);
+        assert_eq!(msaa_levels(0).collect::<Vec<_>>(), vec![0]);
+    }
 }
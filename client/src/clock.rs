@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+/// Where the game gets the current time from. Only differences between readings matter.
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+/// Wall-clock time since the clock was made. Browsers should drive a `ManualClock` with
+/// their frame timestamps instead since `Instant` isn't available there.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct RealClock {
+    epoch: std::time::Instant,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for RealClock {
+    fn default() -> Self {
+        Self {
+            epoch: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+}
+
+/// Only moves when told to, like by a browser's frame timestamps. Clones share the same time, so
+/// one can be handed out while another is kept to drive it.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    time: std::rc::Rc<std::cell::Cell<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new(time: Duration) -> Self {
+        Self {
+            time: std::rc::Rc::new(std::cell::Cell::new(time)),
+        }
+    }
+
+    pub fn set(&self, time: Duration) {
+        self.time.set(time);
+    }
+
+    pub fn advance(&self, dt: Duration) {
+        self.time.set(self.time.get() + dt);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.time.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_drives_timers() {
+        let clock = ManualClock::default();
+        let driver = clock.clone();
+        let mut timer = crate::RepeatingTimer::new(Duration::from_millis(100));
+
+        let mut last = clock.now();
+        let mut fired = 0;
+        for _ in 0..25 {
+            driver.advance(Duration::from_millis(20));
+            let now = clock.now();
+            if timer.update(now - last) {
+                fired += 1;
+            }
+            last = now;
+        }
+
+        assert_eq!(clock.now(), Duration::from_millis(500));
+        assert_eq!(fired, 5);
+    }
+}
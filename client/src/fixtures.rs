@@ -0,0 +1,26 @@
+//! Players and rooms for tests to start from.
+
+use shared::viewer::{InitialRoomState, User};
+
+pub fn user(id: &str, name: &str) -> User {
+    User {
+        id: id.parse().unwrap(),
+        name: name.to_owned(),
+        connected: true,
+    }
+}
+
+/// A user named after their id, for tests where names don't matter.
+pub fn player(id: &str) -> User {
+    user(id, &format!("Player {}", id))
+}
+
+/// Room ABCD with the default settings and `users` in it, the first as DM.
+pub fn room_with(users: Vec<User>) -> InitialRoomState {
+    InitialRoomState {
+        id: "ABCD".parse().unwrap(),
+        name: None,
+        users,
+        settings: Default::default(),
+    }
+}
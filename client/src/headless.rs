@@ -0,0 +1,100 @@
+//! A GL context without a window, so that tests can build and draw a real `Game`. It comes from
+//! Mesa's surfaceless EGL platform and draws to an offscreen pbuffer.
+
+use std::ffi::c_void;
+use std::os::raw::c_char;
+
+type Display = *mut c_void;
+type Config = *mut c_void;
+type Context = *mut c_void;
+type Surface = *mut c_void;
+
+const PLATFORM_SURFACELESS_MESA: u32 = 0x31dd;
+const OPENGL_API: u32 = 0x30a2;
+const NONE: i32 = 0x3038;
+const SURFACE_TYPE: i32 = 0x3033;
+const PBUFFER_BIT: i32 = 0x1;
+const RENDERABLE_TYPE: i32 = 0x3040;
+const OPENGL_BIT: i32 = 0x8;
+const WIDTH: i32 = 0x3057;
+const HEIGHT: i32 = 0x3056;
+
+struct Egl {
+    get_proc_address: unsafe extern "C" fn(*const c_char) -> *const c_void,
+    get_platform_display: unsafe extern "C" fn(u32, *mut c_void, *const isize) -> Display,
+    initialize: unsafe extern "C" fn(Display, *mut i32, *mut i32) -> u32,
+    bind_api: unsafe extern "C" fn(u32) -> u32,
+    choose_config: unsafe extern "C" fn(Display, *const i32, *mut Config, i32, *mut i32) -> u32,
+    create_context: unsafe extern "C" fn(Display, Config, Context, *const i32) -> Context,
+    create_pbuffer_surface: unsafe extern "C" fn(Display, Config, *const i32) -> Surface,
+    make_current: unsafe extern "C" fn(Display, Surface, Surface, Context) -> u32,
+    /// Holds on to what the functions above point into.
+    _library: libloading::Library,
+}
+
+// only ever function pointers into a library that's never unloaded
+unsafe impl Send for Egl {}
+unsafe impl Sync for Egl {}
+
+impl Egl {
+    fn load() -> eyre::Result<Self> {
+        unsafe {
+            let library = libloading::Library::new("libEGL.so.1")?;
+            Ok(Self {
+                get_proc_address: *library.get(b"eglGetProcAddress\0")?,
+                get_platform_display: *library.get(b"eglGetPlatformDisplay\0")?,
+                initialize: *library.get(b"eglInitialize\0")?,
+                bind_api: *library.get(b"eglBindAPI\0")?,
+                choose_config: *library.get(b"eglChooseConfig\0")?,
+                create_context: *library.get(b"eglCreateContext\0")?,
+                create_pbuffer_surface: *library.get(b"eglCreatePbufferSurface\0")?,
+                make_current: *library.get(b"eglMakeCurrent\0")?,
+                _library: library,
+            })
+        }
+    }
+}
+
+static EGL: std::sync::OnceLock<Result<Egl, String>> = std::sync::OnceLock::new();
+
+/// Current on the calling thread, which is where it has to be used, for as long as the thread
+/// lives. Fails where there's no EGL to get one from, for tests to skip over.
+pub fn context(width: i32, height: i32) -> eyre::Result<solstice_2d::solstice::Context> {
+    let egl = EGL
+        .get_or_init(|| Egl::load().map_err(|err| err.to_string()))
+        .as_ref()
+        .map_err(|err| eyre::eyre!("couldn't load EGL: {}", err))?;
+    unsafe {
+        let display = (egl.get_platform_display)(
+            PLATFORM_SURFACELESS_MESA,
+            std::ptr::null_mut(),
+            [NONE as isize].as_ptr(),
+        );
+        let (mut major, mut minor) = (0, 0);
+        if display.is_null() || (egl.initialize)(display, &mut major, &mut minor) == 0 {
+            eyre::bail!("no surfaceless EGL display");
+        }
+        let attributes = [SURFACE_TYPE, PBUFFER_BIT, RENDERABLE_TYPE, OPENGL_BIT, NONE];
+        let (mut config, mut configs) = (std::ptr::null_mut(), 0);
+        if (egl.bind_api)(OPENGL_API) == 0
+            || (egl.choose_config)(display, attributes.as_ptr(), &mut config, 1, &mut configs) == 0
+            || configs == 0
+        {
+            eyre::bail!("no EGL config for offscreen OpenGL");
+        }
+        let context = (egl.create_context)(display, config, std::ptr::null_mut(), [NONE].as_ptr());
+        let size = [WIDTH, width, HEIGHT, height, NONE];
+        let surface = (egl.create_pbuffer_surface)(display, config, size.as_ptr());
+        if context.is_null()
+            || surface.is_null()
+            || (egl.make_current)(display, surface, surface, context) == 0
+        {
+            eyre::bail!("couldn't make an offscreen OpenGL context current");
+        }
+        let gl = solstice_2d::solstice::glow::Context::from_loader_function(|name| {
+            let name = std::ffi::CString::new(name).unwrap();
+            (egl.get_proc_address)(name.as_ptr())
+        });
+        Ok(solstice_2d::solstice::Context::new(gl))
+    }
+}
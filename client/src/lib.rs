@@ -1,7 +1,24 @@
+pub mod announce;
+#[cfg(all(not(target_arch = "wasm32"), feature = "render"))]
+pub mod app;
+pub mod audio;
+pub mod clock;
+#[cfg(test)]
+mod fixtures;
+#[cfg(all(test, feature = "render", not(target_arch = "wasm32")))]
+mod headless;
+pub mod net;
+#[cfg(feature = "render")]
+pub mod platform;
+#[cfg(feature = "render")]
 pub mod resources;
+pub mod settings;
 pub mod sim;
+#[cfg(feature = "render")]
 mod states;
-#[cfg(target_arch = "wasm32")]
+pub mod strings;
+pub mod turns;
+#[cfg(all(target_arch = "wasm32", feature = "render"))]
 pub mod web;
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -9,7 +26,7 @@ pub use glutin as winit;
 #[cfg(target_arch = "wasm32")]
 pub use winit;
 
-use winit::event::{ElementState, MouseButton};
+use winit::event::{ElementState, MouseButton, WindowEvent};
 
 pub enum MouseEvent {
     Button(ElementState, MouseButton),
@@ -17,57 +34,180 @@ pub enum MouseEvent {
 }
 
 impl MouseEvent {
-    pub fn is_left_press(&self) -> bool {
-        match self {
-            Self::Button(ElementState::Pressed, MouseButton::Left) => true,
-            _ => false,
+    /// The mouse part of a window event, for platforms that have a window.
+    pub fn from_window_event(event: &WindowEvent<'_>) -> Option<Self> {
+        match event {
+            WindowEvent::MouseInput { state, button, .. } => Some(Self::Button(*state, *button)),
+            WindowEvent::CursorMoved { position, .. } => {
+                Some(Self::Moved(position.x as f32, position.y as f32))
+            }
+            _ => None,
         }
     }
 
+    /// For platforms that only tell the left button from the rest, like the page around the
+    /// web build.
+    pub fn button(pressed: bool, is_left_button: bool) -> Self {
+        let state = match pressed {
+            true => ElementState::Pressed,
+            false => ElementState::Released,
+        };
+        let button = match is_left_button {
+            true => MouseButton::Left,
+            false => MouseButton::Right,
+        };
+        Self::Button(state, button)
+    }
+
+    pub fn is_left_press(&self) -> bool {
+        matches!(self, Self::Button(ElementState::Pressed, MouseButton::Left))
+    }
+
     pub fn is_left_release(&self) -> bool {
-        match self {
-            Self::Button(ElementState::Released, MouseButton::Left) => true,
-            _ => false,
+        matches!(
+            self,
+            Self::Button(ElementState::Released, MouseButton::Left)
+        )
+    }
+}
+
+/// A room member as the page around the game sees them.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct PlayerSummary {
+    /// A string because player IDs don't survive the trip through a JS number.
+    pub id: String,
+    pub name: String,
+    /// Blocks moved this game, 0 before one has started.
+    pub score: u32,
+    pub connected: bool,
+}
+
+impl PlayerSummary {
+    pub fn new(user: &shared::viewer::User, score: u32) -> Self {
+        Self {
+            id: user.id.to_string(),
+            name: user.name.clone(),
+            score,
+            connected: user.connected,
         }
     }
 }
 
-pub struct Game {
+/// What happened during one `Game::update`, for embedders that would rather check after each
+/// frame than be called back.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FrameReport {
+    /// Moved to a different screen, e.g. from the lobby into a game.
+    pub state_changed: bool,
+    pub collapsed: bool,
+    pub turn_changed: bool,
+    /// False when the frame budget skipped drawing.
+    pub rendered: bool,
+}
+
+impl FrameReport {
+    pub fn record_sim_events(&mut self, events: &[sim::SimEvent]) {
+        self.collapsed |= events.contains(&sim::SimEvent::Collapse);
+    }
+}
+
+/// Talks to the server through `T`, a websocket unless it's been built with something else
+/// like `net::loopback`.
+#[cfg(feature = "render")]
+pub struct Game<T = net::WebSocketTransport> {
     ctx: solstice_2d::solstice::Context,
     gfx: solstice_2d::Graphics,
     time: std::time::Duration,
+    frame_budget: FrameBudget,
+    clear_color: Option<[f32; 4]>,
     input_state: InputState,
-    ws: net::Client,
+    ws: net::Client<T>,
+    audio: Box<dyn audio::AudioBackend>,
+    announcer: Box<dyn announce::Announcer>,
+    settings: std::cell::Cell<settings::Settings>,
+    source_resources: resources::Resources,
     resources: resources::LoadedResources,
     state: Option<states::State>,
+    /// Reset at the start of every update.
+    report: std::cell::Cell<FrameReport>,
 }
 
-impl Game {
+#[cfg(feature = "render")]
+impl<T: net::Transport + 'static> Game<T> {
+    /// `time` is where the platform's clock starts out.
     pub fn new(
         mut ctx: solstice_2d::solstice::Context,
         time: std::time::Duration,
         width: f32,
         height: f32,
-        ws: net::Client,
+        ws: net::Client<T>,
         resources: resources::Resources,
     ) -> eyre::Result<Self> {
         let mut gfx = solstice_2d::Graphics::new(&mut ctx, width, height)?;
-        let resources = resources.try_into_loaded(&mut ctx, &mut gfx)?;
+        let source_resources = resources;
+        let resources = source_resources
+            .clone()
+            .try_into_loaded(&mut ctx, &mut gfx)?;
 
         Ok(Self {
             ctx,
             gfx,
             time,
+            frame_budget: Default::default(),
+            clear_color: None,
             input_state: Default::default(),
             ws,
+            audio: Box::new(audio::NoAudio),
+            announcer: Box::new(announce::Silent),
+            settings: std::cell::Cell::new(settings::Store::default().load()),
+            source_resources,
             resources,
             state: Default::default(),
+            report: Default::default(),
         })
     }
 
-    pub fn update(&mut self, time: std::time::Duration) {
-        let dt = time - self.time;
+    /// Rebuilds every GPU-side resource against a new context. Used when the old context has
+    /// been lost, which invalidates all of its textures and buffers.
+    pub fn restore_context(&mut self, mut ctx: solstice_2d::solstice::Context) -> eyre::Result<()> {
+        let vw = *self.gfx.viewport();
+        let (width, height) = (vw.width() as f32, vw.height() as f32);
+        let mut gfx = solstice_2d::Graphics::new(&mut ctx, width, height)?;
+        let resources = self
+            .source_resources
+            .clone()
+            .try_into_loaded(&mut ctx, &mut gfx)?;
+
+        self.ctx = ctx;
+        self.gfx = gfx;
+        self.resources = resources;
+        self.handle_resize(width, height);
+        Ok(())
+    }
+
+    /// Advances everything by however much time has passed since the last update.
+    pub fn update(&mut self, time: std::time::Duration) -> FrameReport {
+        self.step(time, true)
+    }
+
+    /// `update`, but only drawing if `render` is set and the frame budget allows it.
+    fn step(&mut self, time: std::time::Duration, render: bool) -> FrameReport {
+        let dt = time.checked_sub(self.time).unwrap_or_default();
         self.time = time;
+        self.report.set(FrameReport::default());
+        let previous = self.state.as_ref().map(std::mem::discriminant);
+
+        if self.ws.incompatible() {
+            if !self.state.as_ref().is_some_and(states::State::is_outdated) {
+                log::error!("the server is on another version of the game");
+                self.state = Some(states::State::outdated());
+            }
+        } else if let Some(code) = self.ws.closed() {
+            if !matches!(self.state, None | Some(states::State::NoRoom(_))) {
+                log::warn!("lost the connection to the server: {:?}", code);
+                self.state = Some(states::State::NoRoom(Default::default()));
+            }
+        }
 
         self.state = self.state.take().map(|state| {
             state.update(
@@ -78,18 +218,75 @@ impl Game {
                     ws: &self.ws,
                     input_state: &self.input_state,
                     time: &self.time,
+                    audio: &*self.audio,
+                    announcer: &*self.announcer,
+                    settings: &self.settings,
+                    report: &self.report,
                 },
             )
         });
-        self.state
-            .get_or_insert_with(Default::default)
-            .render(states::StateContext {
+
+        let state_changed = self.state.as_ref().map(std::mem::discriminant) != previous;
+        self.report.set(FrameReport {
+            state_changed,
+            ..self.report.get()
+        });
+
+        if !render || !self.frame_budget.should_render(dt) {
+            return self.report.get();
+        }
+        self.state.get_or_insert_with(Default::default).render(
+            self.clear_color,
+            states::StateContext {
                 g: self.gfx.lock(&mut self.ctx),
                 resources: &self.resources,
                 ws: &self.ws,
                 input_state: &self.input_state,
                 time: &self.time,
-            });
+                audio: &*self.audio,
+                announcer: &*self.announcer,
+                settings: &self.settings,
+                report: &self.report,
+            },
+        );
+        self.report.set(FrameReport {
+            rendered: true,
+            ..self.report.get()
+        });
+        self.report.get()
+    }
+
+    /// The report from the most recent update.
+    pub fn last_report(&self) -> FrameReport {
+        self.report.get()
+    }
+
+    /// Also starts the music, if there is any, on the new backend.
+    pub fn set_audio_backend(&mut self, audio: Box<dyn audio::AudioBackend>) {
+        self.audio = audio;
+        if let Some(track) = &self.source_resources.music_data {
+            audio::start_music(&*self.audio, track, &self.settings.get());
+        }
+    }
+
+    pub fn set_announcer(&mut self, announcer: Box<dyn announce::Announcer>) {
+        self.announcer = announcer;
+    }
+
+    pub fn set_music_paused(&self, paused: bool) {
+        self.audio.set_music_paused(paused);
+    }
+
+    /// Background every state clears to instead of its own. Use a zero alpha for a canvas that
+    /// shows the page behind it.
+    pub fn set_clear_color(&mut self, color: Option<[f32; 4]>) {
+        self.clear_color = color;
+    }
+
+    /// When set, frames that take longer than `target` cause every other render to be skipped
+    /// while the simulation keeps stepping.
+    pub fn set_target_frame_time(&mut self, target: Option<std::time::Duration>) {
+        self.frame_budget = FrameBudget::new(target);
     }
 
     pub fn handle_new_room_state(
@@ -97,22 +294,69 @@ impl Game {
         room: shared::viewer::InitialRoomState,
         local_user: shared::viewer::User,
     ) {
+        self.announcer.announce(&announce::joined_room(room.id));
         self.state = Some(states::State::lobby(local_user, room))
     }
 
+    /// Says `text` to the current room, if there is one.
+    pub fn send_chat(&self, text: String) {
+        if let Some(room) = self.room() {
+            if let Err(err) = self.ws.chat(room.id, text) {
+                log::warn!("{}", err);
+            }
+        }
+    }
+
+    /// Whether the current room hears from `player_id`, for the DM to decide.
+    pub fn mute(&self, player_id: shared::PlayerID, muted: bool) {
+        if let Some(room) = self.room() {
+            if let Err(err) = self.ws.mute(room.id, player_id, muted) {
+                log::warn!("{}", err);
+            }
+        }
+    }
+
+    /// Returns to room select without dropping the connection.
+    pub fn leave_room(&mut self) {
+        let ws = &self.ws;
+        self.state = self.state.take().map(|state| state.leave(ws));
+    }
+
+    pub fn room(&self) -> Option<&shared::viewer::InitialRoomState> {
+        self.state.as_ref().and_then(states::State::room)
+    }
+
+    /// Everyone in the current room, in seating order.
+    pub fn players(&self) -> Vec<PlayerSummary> {
+        let state = match &self.state {
+            Some(state) => state,
+            None => return vec![],
+        };
+        state.room().map_or_else(Vec::new, |room| {
+            room.users
+                .iter()
+                .map(|user| PlayerSummary::new(user, state.score(user.id)))
+                .collect()
+        })
+    }
+
+    pub fn is_dm(&self) -> bool {
+        self.state
+            .as_ref()
+            .map(states::State::is_dm)
+            .unwrap_or(false)
+    }
+
     pub fn handle_mouse_event(&mut self, event: MouseEvent) {
-        match event {
-            MouseEvent::Moved(x, y) => {
-                let mut is = &mut self.input_state;
-                if is.mouse_position == is.prev_mouse_position && is.mouse_position == (0., 0.) {
-                    is.prev_mouse_position = (x, y);
-                    is.mouse_position = (x, y);
-                } else {
-                    is.prev_mouse_position = is.mouse_position;
-                    is.mouse_position = (x, y);
-                }
+        if let MouseEvent::Moved(x, y) = event {
+            let is = &mut self.input_state;
+            if is.mouse_position == is.prev_mouse_position && is.mouse_position == (0., 0.) {
+                is.prev_mouse_position = (x, y);
+                is.mouse_position = (x, y);
+            } else {
+                is.prev_mouse_position = is.mouse_position;
+                is.mouse_position = (x, y);
             }
-            _ => {}
         }
         self.state = self.state.take().map(|state| {
             state.handle_mouse_event(
@@ -123,11 +367,20 @@ impl Game {
                     ws: &self.ws,
                     input_state: &self.input_state,
                     time: &self.time,
+                    audio: &*self.audio,
+                    announcer: &*self.announcer,
+                    settings: &self.settings,
+                    report: &self.report,
                 },
             )
         });
     }
 
+    /// Whether the snapping modifier is held.
+    pub fn set_snapping(&mut self, snapping: bool) {
+        self.input_state.snapping = snapping;
+    }
+
     pub fn handle_resize(&mut self, win_width: f32, win_height: f32) {
         use solstice_2d::solstice::viewport::Viewport;
         let vw = Viewport::new(0, 0, win_width as _, win_height as _);
@@ -149,10 +402,54 @@ impl Game {
     }
 }
 
+#[cfg(feature = "render")]
+impl<T: net::Transport + 'static> platform::Frame for Game<T> {
+    fn frame(&mut self, time: std::time::Duration, render: bool) {
+        let _report = self.step(time, render);
+    }
+
+    fn restore_context(&mut self, ctx: solstice_2d::solstice::Context) -> eyre::Result<()> {
+        Game::restore_context(self, ctx)
+    }
+}
+
+#[cfg(feature = "render")]
 #[derive(Default)]
 pub struct InputState {
     prev_mouse_position: (f32, f32),
     mouse_position: (f32, f32),
+    /// Held down to snap dragged blocks to the grid.
+    snapping: bool,
+}
+
+#[cfg(feature = "render")]
+#[derive(Debug, Default)]
+struct FrameBudget {
+    target: Option<std::time::Duration>,
+    skipped_last: bool,
+}
+
+#[cfg(feature = "render")]
+impl FrameBudget {
+    pub fn new(target: Option<std::time::Duration>) -> Self {
+        Self {
+            target,
+            skipped_last: false,
+        }
+    }
+
+    pub fn should_render(&mut self, dt: std::time::Duration) -> bool {
+        match self.target {
+            Some(target) if dt > target && !self.skipped_last => {
+                self.skipped_last = true;
+                false
+            }
+            _ => {
+                self.skipped_last = false;
+                true
+            }
+        }
+    }
 }
 
 struct RepeatingTimer {
@@ -179,118 +476,18 @@ impl RepeatingTimer {
     }
 }
 
-pub mod net {
-    use futures::{Future, FutureExt, TryFutureExt};
-
-    // could guard against polling the websocket buffer while a create/join request is in flight
-    pub struct Client {
-        base_url: reqwest::Url,
-        sx: websocket::WsSend,
-        rx: websocket::WsRecv,
-    }
-
-    impl Client {
-        pub async fn new(base_url: String) -> eyre::Result<Self> {
-            let base_url = reqwest::Url::parse(&base_url)?;
-            let mut ws_url = base_url.clone();
-            match base_url.scheme() {
-                "http" => {
-                    ws_url.set_scheme("ws").expect("set scheme failure");
-                }
-                "https" => {
-                    ws_url.set_scheme("wss").expect("set scheme failure");
-                }
-                _ => {
-                    return Err(eyre::Report::msg(format!(
-                        "Unrecognized scheme {}",
-                        base_url.scheme()
-                    )));
-                }
-            }
-            let ws_url = ws_url.join(shared::ENDPOINT_WS)?;
-            let ws = websocket::WebSocket::connect(ws_url.as_str()).await?;
-            let (sx, rx) = ws.into_channels();
-            Ok(Self { base_url, sx, rx })
-        }
-
-        pub fn send(&self, cmd: shared::viewer::Command<shared::CustomMessage>) {
-            match serde_json::to_string(&cmd) {
-                Ok(payload) => {
-                    if let Err(err) = self.sx.send(websocket::Message::Text(payload)) {
-                        log::error!("{}", err);
-                    }
-                }
-                Err(err) => {
-                    log::error!("{}", err);
-                }
-            }
-        }
-
-        pub fn try_recv_iter(
-            &self,
-        ) -> impl Iterator<Item = shared::viewer::StateChange<shared::CustomMessage>> + '_ {
-            std::iter::from_fn(move || {
-                while let Ok(msg) = self.rx.try_recv() {
-                    let parsed = match msg {
-                        websocket::Message::Text(text) => serde_json::from_str(&text),
-                        websocket::Message::Binary(bin) => serde_json::from_slice(&bin),
-                    };
-
-                    if let Ok(cmd) = parsed {
-                        return Some(cmd);
-                    } else {
-                        continue;
-                    }
-                }
-
-                None
-            })
-        }
-
-        pub fn create_room(
-            &self,
-            player: &shared::PlayerName,
-        ) -> eyre::Result<impl Future<Output = eyre::Result<shared::viewer::InitialRoomState>>>
-        {
-            let body = serde_json::to_string(&player)?;
-            let url = self.base_url.join(shared::ENDPOINT_CREATE_ROOM)?;
-
-            let client = reqwest::Client::new();
-            Ok(client
-                .post(url)
-                .header(reqwest::header::CONTENT_TYPE, "application/json")
-                .body(body)
-                .send()
-                .map_err(eyre::Report::from)
-                .and_then(|response| response.text().map_err(eyre::Report::from))
-                .map(|result: eyre::Result<String>| {
-                    result.and_then(|text| serde_json::from_str(&text).map_err(eyre::Report::from))
-                }))
-        }
-
-        pub fn join_room(
-            &self,
-            join_info: &shared::RoomJoinInfo,
-        ) -> eyre::Result<impl Future<Output = eyre::Result<shared::viewer::InitialRoomState>>>
-        {
-            let body = serde_json::to_string(&join_info)?;
-            let url = self.base_url.join(shared::ENDPOINT_JOIN_ROOM)?;
-
-            let client = reqwest::Client::new();
-            Ok(client
-                .post(url)
-                .header(reqwest::header::CONTENT_TYPE, "application/json")
-                .body(body)
-                .send()
-                .map_err(eyre::Report::from)
-                .and_then(|response| response.text().map_err(eyre::Report::from))
-                .map(|result: eyre::Result<String>| {
-                    result.and_then(|text| serde_json::from_str(&text).map_err(eyre::Report::from))
-                }))
-        }
+/// Scales a layout rectangle, position included, by the UI scale.
+#[cfg(feature = "render")]
+fn scale_rect(rect: solstice_2d::Rectangle, scale: f32) -> solstice_2d::Rectangle {
+    solstice_2d::Rectangle {
+        x: rect.x * scale,
+        y: rect.y * scale,
+        width: rect.width * scale,
+        height: rect.height * scale,
     }
 }
 
+#[cfg(feature = "render")]
 fn collides(p: [f32; 2], rect: &solstice_2d::Rectangle) -> bool {
     type Point = [f32; 2];
     fn vec(a: Point, b: Point) -> Point {
@@ -323,8 +520,193 @@ fn collides(p: [f32; 2], rect: &solstice_2d::Rectangle) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn player_summaries_follow_room_membership() {
+        let users = [
+            shared::viewer::User {
+                id: "18446744073709551615".parse().unwrap(),
+                name: "Alice".to_owned(),
+                connected: true,
+            },
+            shared::viewer::User {
+                id: "2".parse().unwrap(),
+                name: "Bob".to_owned(),
+                connected: false,
+            },
+        ];
+        let players = users
+            .iter()
+            .zip([3, 0].iter())
+            .map(|(user, score)| PlayerSummary::new(user, *score))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            players,
+            vec![
+                PlayerSummary {
+                    id: "18446744073709551615".to_owned(),
+                    name: "Alice".to_owned(),
+                    score: 3,
+                    connected: true,
+                },
+                PlayerSummary {
+                    id: "2".to_owned(),
+                    name: "Bob".to_owned(),
+                    score: 0,
+                    connected: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_with_a_collapse_report_it() {
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        let mut sim = sim::Sim::tower();
+        let body = rapier2d::dynamics::RigidBodyBuilder::new_dynamic()
+            .translation(1., 0.)
+            .build();
+        let collider = rapier2d::geometry::ColliderBuilder::cuboid(0.025, 0.025).build();
+        sim.add_body((body, vec![collider]));
+
+        let mut reports = (0..600).map(|_| {
+            sim.step(dt);
+            let mut report = FrameReport::default();
+            report.record_sim_events(&sim.drain_events().collect::<Vec<_>>());
+            report
+        });
+        assert!(!reports.next().unwrap().collapsed);
+        assert!(reports.any(|report| report.collapsed));
+    }
+
+    /// A game on a headless GL context, or `None` to skip the test where there isn't one.
+    #[cfg(all(feature = "render", not(target_arch = "wasm32")))]
+    fn headless_game<T: net::Transport + 'static>(ws: net::Client<T>) -> Option<Game<T>> {
+        let ctx = match headless::context(1280, 720) {
+            Ok(ctx) => ctx,
+            Err(err) => {
+                eprintln!("skipping, no headless GL context: {}", err);
+                return None;
+            }
+        };
+        let time = std::time::Duration::ZERO;
+        let resources = resources::Resources::default();
+        Some(Game::new(ctx, time, 1280., 720., ws, resources).unwrap())
+    }
+
+    #[cfg(all(feature = "render", not(target_arch = "wasm32")))]
+    #[test]
+    fn games_over_the_loopback_see_each_other_come_and_go() {
+        use crate::fixtures::user;
+
+        let server = net::loopback::Server::default();
+        let (dm, alice) = (user("1", "DM"), user("2", "Alice"));
+        let connect = |transport| {
+            net::Client::builder("http://loopback/")
+                .build_with(transport)
+                .unwrap()
+        };
+
+        let dm_transport = server.connect(dm.clone());
+        let room = server.create_room(&dm_transport);
+        let mut dm_game = match headless_game(connect(dm_transport)) {
+            Some(game) => game,
+            None => return,
+        };
+        dm_game.handle_new_room_state(room.clone(), dm.clone());
+
+        let alice_transport = server.connect(alice.clone());
+        let alice_room = server.join_room(room.id, &alice_transport).unwrap();
+        let mut alice_game = headless_game(connect(alice_transport)).unwrap();
+        alice_game.handle_new_room_state(alice_room, alice.clone());
+        assert!(dm_game.is_dm());
+        assert!(!alice_game.is_dm());
+
+        let frame = std::time::Duration::from_millis(16);
+        assert!(dm_game.update(frame).rendered);
+        assert!(alice_game.update(frame).rendered);
+        let members = |game: &Game<_>| {
+            game.room()
+                .map(|room| room.users.iter().map(|user| user.id).collect::<Vec<_>>())
+        };
+        assert_eq!(members(&dm_game), Some(vec![dm.id, alice.id]));
+        assert_eq!(members(&alice_game), Some(vec![dm.id, alice.id]));
+
+        alice_game.leave_room();
+        assert!(alice_game.room().is_none());
+        dm_game.update(frame * 2);
+        assert_eq!(members(&dm_game), Some(vec![dm.id]));
+    }
+
+    #[cfg(all(feature = "render", not(target_arch = "wasm32")))]
+    #[test]
+    fn games_run_a_frame_through_the_platform() {
+        #[derive(Default)]
+        struct Headless {
+            clock: clock::ManualClock,
+            presented: usize,
+        }
+
+        impl platform::Platform for Headless {
+            fn now(&self) -> std::time::Duration {
+                use clock::Clock;
+                self.clock.now()
+            }
+
+            fn present(&mut self) -> eyre::Result<()> {
+                self.presented += 1;
+                Ok(())
+            }
+        }
+
+        let server = net::loopback::Server::default();
+        let dm = crate::fixtures::user("1", "DM");
+        let transport = server.connect(dm.clone());
+        let room = server.create_room(&transport);
+        let ws = net::Client::builder("http://loopback/")
+            .build_with(transport)
+            .unwrap();
+        let mut game = match headless_game(ws) {
+            Some(game) => game,
+            None => return,
+        };
+        game.handle_new_room_state(room, dm);
+
+        let mut headless = Headless::default();
+        headless.clock.set(std::time::Duration::from_millis(16));
+        assert!(platform::run_frame(&mut headless, &mut game).unwrap());
+        assert_eq!(headless.presented, 1);
+        assert!(game.last_report().rendered);
+        assert!(game.is_dm());
+    }
+
+    #[cfg(feature = "render")]
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    fn frame_budget_skips_alternate_renders_when_slow() {
+        let target = std::time::Duration::from_millis(16);
+        let slow = std::time::Duration::from_millis(40);
+        let fast = std::time::Duration::from_millis(10);
+
+        let mut unlimited = FrameBudget::default();
+        assert!((0..4).all(|_| unlimited.should_render(slow)));
+
+        let mut budget = FrameBudget::new(Some(target));
+        let mut timer = RepeatingTimer::new(target);
+        let mut steps = 0;
+        let mut renders = 0;
+        for _ in 0..6 {
+            if timer.update(slow) {
+                steps += 1;
+            }
+            if budget.should_render(slow) {
+                renders += 1;
+            }
+        }
+        assert_eq!(steps, 6);
+        assert_eq!(renders, 3);
+
+        assert!(budget.should_render(fast));
+        assert!(budget.should_render(fast));
     }
 }
@@ -0,0 +1,948 @@
+use futures::{Future, FutureExt, TryFutureExt};
+use shared::codec::CodecError;
+use std::time::Duration;
+
+#[cfg(any(feature = "loopback", all(test, not(target_arch = "wasm32"))))]
+pub mod loopback;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NetError {
+    #[error("could not serialize command: {0}")]
+    Serialize(CodecError),
+    #[error("could not deserialize message: {0}")]
+    Deserialize(CodecError),
+    #[error("could not send command: {0}")]
+    Send(#[from] websocket::WebSocketError),
+    #[error("the server is running another version: {0}")]
+    Incompatible(#[from] shared::viewer::VersionMismatch),
+    #[error("too many commands are waiting on the server to be sent again")]
+    OutboxFull,
+}
+
+/// What became of a command that didn't fail outright.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Sent {
+    /// Handed to the socket.
+    Now,
+    /// Held until the socket is redialed, and then sent. Only guaranteed commands are.
+    Queued,
+}
+
+/// How hard to try to get a command to the server.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Delivery {
+    /// Queued on failure and replayed after a reconnect.
+    Guaranteed,
+    /// Dropped on failure. For things like drag updates that are stale by the time a retry
+    /// would happen.
+    BestEffort,
+}
+
+/// How commands sent with `Client::send` are retried.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RetryPolicy {
+    pub delivery: Delivery,
+    /// Bounds both the commands waiting on a reconnect and those waiting on an ack.
+    pub max_pending: usize,
+    /// How a dropped websocket is redialed. Once it gives up the close is passed on.
+    pub redial: websocket::RetryPolicy,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            delivery: Delivery::Guaranteed,
+            max_pending: Outbox::CAPACITY,
+            redial: Default::default(),
+        }
+    }
+}
+
+/// Wire format for websocket traffic.
+pub use shared::codec::Codec;
+
+/// Everything goes out in a `shared::viewer::Envelope` stamped with this build's protocol version.
+fn encode<T: serde::Serialize>(codec: Codec, value: &T) -> Result<websocket::Message, NetError> {
+    match codec.encode(value).map_err(NetError::Serialize)? {
+        shared::codec::Frame::Text(text) => Ok(websocket::Message::Text(text)),
+        shared::codec::Frame::Binary(bytes) => Ok(websocket::Message::Binary(bytes)),
+    }
+}
+
+/// `None` for control frames, which don't carry anything to decode.
+fn frame_ref(msg: &websocket::Message) -> Option<shared::codec::FrameRef<'_>> {
+    match msg {
+        websocket::Message::Text(text) => Some(shared::codec::FrameRef::Text(text)),
+        websocket::Message::Binary(bytes) => Some(shared::codec::FrameRef::Binary(bytes)),
+        websocket::Message::Ping(_) | websocket::Message::Pong(_) => None,
+    }
+}
+
+/// Whichever codec the server picked, going by the frame type.
+fn decode<T: serde::de::DeserializeOwned>(
+    frame: shared::codec::FrameRef<'_>,
+) -> Result<T, NetError> {
+    shared::codec::decode(frame).map_err(|err| match err {
+        CodecError::Version(mismatch) => NetError::Incompatible(mismatch),
+        err => NetError::Deserialize(err),
+    })
+}
+
+/// Guaranteed messages that either failed to send or haven't been acknowledged yet. Both are
+/// replayed in order once the socket is reconnected. Once `capacity` of them are waiting, new
+/// ones are refused rather than forgetting any already taken on.
+#[derive(Debug)]
+struct Outbox {
+    next_nonce: shared::viewer::Nonce,
+    capacity: usize,
+    queue: std::collections::VecDeque<(shared::viewer::Nonce, websocket::Message)>,
+    unacked: std::collections::VecDeque<(shared::viewer::Nonce, websocket::Message)>,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::with_capacity(Self::CAPACITY)
+    }
+}
+
+impl Outbox {
+    const CAPACITY: usize = 64;
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            next_nonce: 0,
+            capacity,
+            queue: Default::default(),
+            unacked: Default::default(),
+        }
+    }
+
+    fn next_nonce(&mut self) -> shared::viewer::Nonce {
+        let nonce = self.next_nonce;
+        self.next_nonce = nonce.wrapping_add(1);
+        nonce
+    }
+
+    fn is_full(&self) -> bool {
+        self.queue.len() + self.unacked.len() >= self.capacity
+    }
+
+    fn push(
+        &mut self,
+        nonce: shared::viewer::Nonce,
+        msg: websocket::Message,
+    ) -> Result<(), NetError> {
+        if self.is_full() {
+            return Err(NetError::OutboxFull);
+        }
+        self.queue.push_back((nonce, msg));
+        Ok(())
+    }
+
+    fn sent(&mut self, nonce: shared::viewer::Nonce, msg: websocket::Message) {
+        self.unacked.push_back((nonce, msg));
+    }
+
+    /// Hands `msg` to `send` unless earlier messages are still waiting on a reconnect, in which
+    /// case it has to wait behind them to preserve ordering. Only messages with a nonce are
+    /// retried.
+    fn send<F>(
+        &mut self,
+        msg: websocket::Message,
+        nonce: Option<shared::viewer::Nonce>,
+        send: F,
+    ) -> Result<Sent, NetError>
+    where
+        F: FnOnce(websocket::Message) -> Result<(), websocket::WebSocketError>,
+    {
+        match nonce {
+            None if self.queue.is_empty() => send(msg).map(|()| Sent::Now).map_err(NetError::from),
+            None => Err(websocket::WebSocketError::SendError.into()),
+            Some(_nonce) if self.is_full() => Err(NetError::OutboxFull),
+            Some(nonce) => {
+                let result = if self.queue.is_empty() {
+                    send(msg.clone())
+                } else {
+                    Err(websocket::WebSocketError::SendError)
+                };
+                match result {
+                    Ok(()) => {
+                        self.sent(nonce, msg);
+                        Ok(Sent::Now)
+                    }
+                    Err(_err) => self.push(nonce, msg).map(|()| Sent::Queued),
+                }
+            }
+        }
+    }
+
+    fn ack(&mut self, nonce: shared::viewer::Nonce) {
+        self.unacked.retain(|(other, _msg)| *other != nonce);
+    }
+
+    /// Resends everything unacknowledged, followed by everything never sent.
+    fn flush<F>(&mut self, mut send: F) -> Result<(), websocket::WebSocketError>
+    where
+        F: FnMut(websocket::Message) -> Result<(), websocket::WebSocketError>,
+    {
+        while let Some(entry) = self.unacked.pop_back() {
+            self.queue.push_front(entry);
+        }
+        while let Some((nonce, msg)) = self.queue.pop_front() {
+            if let Err(err) = send(msg.clone()) {
+                self.queue.push_front((nonce, msg));
+                return Err(err);
+            }
+            self.sent(nonce, msg);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    timeout: Option<Duration>,
+    headers: reqwest::header::HeaderMap,
+    retry_policy: RetryPolicy,
+    codec: Codec,
+    http_format: shared::codec::Format,
+    keepalive: Option<Duration>,
+}
+
+impl Settings {
+    fn request(
+        &self,
+        http: &reqwest::Client,
+        method: reqwest::Method,
+        url: reqwest::Url,
+    ) -> reqwest::RequestBuilder {
+        let request = http.request(method, url).headers(self.headers.clone());
+        // the browser owns request timeouts on the web
+        #[cfg(not(target_arch = "wasm32"))]
+        let request = match self.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        };
+        request
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    base_url: String,
+    settings: Settings,
+}
+
+impl ClientBuilder {
+    pub fn new<S: Into<String>>(base_url: S) -> Self {
+        Self {
+            base_url: base_url.into(),
+            settings: Default::default(),
+        }
+    }
+
+    pub fn base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Applies to HTTP requests. Ignored on wasm.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.settings.timeout = Some(timeout);
+        self
+    }
+
+    /// Added to every HTTP request and, off the web, to the websocket handshake.
+    pub fn headers(mut self, headers: reqwest::header::HeaderMap) -> Self {
+        self.settings.headers.extend(headers);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.settings.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.settings.codec = codec;
+        self
+    }
+
+    /// Encoding for HTTP request and response bodies. JSON by default.
+    pub fn http_format(mut self, format: shared::codec::Format) -> Self {
+        self.settings.http_format = format;
+        self
+    }
+
+    /// Interval for client-initiated pings. A connection that goes three intervals without a pong
+    /// is treated as lost. Only the native backend can ping; browsers ignore this.
+    pub fn keepalive(mut self, interval: Duration) -> Self {
+        self.settings.keepalive = Some(interval);
+        self
+    }
+
+    fn urls(&self) -> eyre::Result<(reqwest::Url, reqwest::Url)> {
+        let base_url = reqwest::Url::parse(&self.base_url)?;
+        let mut ws_url = base_url.clone();
+        match base_url.scheme() {
+            "http" => {
+                ws_url.set_scheme("ws").expect("set scheme failure");
+            }
+            "https" => {
+                ws_url.set_scheme("wss").expect("set scheme failure");
+            }
+            _ => {
+                return Err(eyre::Report::msg(format!(
+                    "Unrecognized scheme {}",
+                    base_url.scheme()
+                )));
+            }
+        }
+        let ws_url = ws_url.join(shared::ENDPOINT_WS)?;
+        Ok((base_url, ws_url))
+    }
+
+    pub async fn build(self) -> eyre::Result<Client> {
+        let (_base_url, ws_url) = self.urls()?;
+        let ws = connect_websocket(&ws_url, &self.settings).await?;
+        self.build_with(WebSocketTransport::from(ws))
+    }
+
+    /// Talks over `transport` instead of dialing a websocket. HTTP requests still go to the base
+    /// URL.
+    pub fn build_with<T: Transport>(self, transport: T) -> eyre::Result<Client<T>> {
+        let (base_url, _ws_url) = self.urls()?;
+        let outbox = Outbox::with_capacity(self.settings.retry_policy.max_pending);
+        Ok(Client {
+            base_url,
+            http: reqwest::Client::new(),
+            settings: self.settings,
+            transport,
+            outbox: std::cell::RefCell::new(outbox),
+            closed: Default::default(),
+            incompatible: Default::default(),
+        })
+    }
+}
+
+/// Carries encoded commands to the server and state changes back.
+pub trait Transport {
+    fn send(&self, msg: websocket::Message) -> Result<(), websocket::WebSocketError>;
+
+    /// `WebSocketError::Empty` when there's nothing waiting and `Closed` once the connection is
+    /// gone for good.
+    fn try_recv(&self) -> Result<websocket::Message, websocket::WebSocketError>;
+
+    /// Whether the connection has been redialed since this was last asked. Transports that
+    /// never drop can leave it false.
+    fn reopened(&self) -> bool {
+        false
+    }
+}
+
+pub struct WebSocketTransport {
+    sx: websocket::WsSend,
+    rx: websocket::WsRecv,
+    reopened: std::cell::Cell<bool>,
+}
+
+impl From<websocket::WebSocket> for WebSocketTransport {
+    fn from(ws: websocket::WebSocket) -> Self {
+        let (sx, rx) = ws.into_channels();
+        Self {
+            sx,
+            rx,
+            reopened: Default::default(),
+        }
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send(&self, msg: websocket::Message) -> Result<(), websocket::WebSocketError> {
+        self.sx.send(msg)
+    }
+
+    fn try_recv(&self) -> Result<websocket::Message, websocket::WebSocketError> {
+        loop {
+            match self.rx.try_recv_event()? {
+                websocket::WebSocketEvent::Message(msg) => return Ok(msg),
+                websocket::WebSocketEvent::Reopened => self.reopened.set(true),
+                _ => continue,
+            }
+        }
+    }
+
+    fn reopened(&self) -> bool {
+        self.reopened.replace(false)
+    }
+}
+
+/// Sends the HTTP headers with the handshake too so that a native client's `Cookie` identifies
+/// it to the socket the same way it does to everything else. Redials, with the same headers,
+/// going by the `RetryPolicy`.
+async fn connect_websocket(
+    url: &reqwest::Url,
+    settings: &Settings,
+) -> Result<websocket::WebSocket, websocket::WebSocketError> {
+    let headers = settings
+        .headers
+        .iter()
+        .map(|(name, value)| (name.as_str().to_owned(), value.as_bytes().to_vec()))
+        .collect();
+    let ws = websocket::WebSocket::connect_with_headers_and_retry(
+        url.as_str(),
+        headers,
+        settings.retry_policy.redial,
+    )
+    .await?;
+    Ok(match settings.keepalive {
+        Some(interval) => ws.with_keepalive(interval),
+        None => ws,
+    })
+}
+
+// could guard against polling the websocket buffer while a create/join request is in flight
+/// `&Client<T>` coerces to `&Client<dyn Transport>` for code that doesn't care what's underneath.
+pub struct Client<T: ?Sized = WebSocketTransport> {
+    base_url: reqwest::Url,
+    http: reqwest::Client,
+    settings: Settings,
+    outbox: std::cell::RefCell<Outbox>,
+    closed: std::cell::Cell<Option<websocket::CloseCode>>,
+    incompatible: std::cell::Cell<Option<shared::viewer::VersionMismatch>>,
+    /// Last so that it can be unsized.
+    transport: T,
+}
+
+impl Client {
+    /// Connects with the default `ClientBuilder` options.
+    pub async fn new(base_url: String) -> eyre::Result<Self> {
+        ClientBuilder::new(base_url).build().await
+    }
+
+    pub fn builder<S: Into<String>>(base_url: S) -> ClientBuilder {
+        ClientBuilder::new(base_url)
+    }
+}
+
+impl<T: Transport + ?Sized> Client<T> {
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.settings.keepalive
+    }
+
+    /// Sends with the `RetryPolicy`'s delivery, `Delivery::Guaranteed` by default.
+    pub fn send(
+        &self,
+        cmd: shared::viewer::Command<shared::CustomMessage>,
+    ) -> Result<Sent, NetError> {
+        self.send_with(cmd, self.settings.retry_policy.delivery)
+    }
+
+    /// A guaranteed command is sent as `Command::Reliable` and kept until the server
+    /// acknowledges it, being replayed once the socket is redialed if it hasn't been. That's
+    /// `Sent::Queued` if it couldn't go out yet, and `NetError::OutboxFull` if too many others are
+    /// already waiting. A best-effort one is dropped on failure.
+    pub fn send_with(
+        &self,
+        cmd: shared::viewer::Command<shared::CustomMessage>,
+        delivery: Delivery,
+    ) -> Result<Sent, NetError> {
+        use shared::viewer::Command;
+
+        let mut outbox = self.outbox.borrow_mut();
+        let (cmd, nonce) = match (cmd, delivery) {
+            (Command::Custom(room_id, payload), Delivery::Guaranteed) => {
+                let nonce = outbox.next_nonce();
+                (Command::Reliable(room_id, payload, nonce), Some(nonce))
+            }
+            (Command::Reliable(room_id, payload, nonce), _) => {
+                (Command::Reliable(room_id, payload, nonce), Some(nonce))
+            }
+            (cmd, _) => (cmd, None),
+        };
+        let msg = encode(self.settings.codec, &cmd)?;
+        let transport = &self.transport;
+        outbox.send(msg, nonce, |msg| transport.send(msg))
+    }
+
+    /// Gives up the seat in `room_id` but keeps the connection, so that another room can be
+    /// joined without reconnecting.
+    pub fn leave_room(&self, room_id: shared::RoomID) -> Result<Sent, NetError> {
+        self.send(shared::viewer::Command::Leave(room_id))
+    }
+
+    /// Says `text` to everyone in `room_id`. The server drops it if it's blank or longer than
+    /// `shared::viewer::MAX_CHAT_LENGTH`.
+    pub fn chat(&self, room_id: shared::RoomID, text: String) -> Result<Sent, NetError> {
+        self.send(shared::viewer::Command::Chat(room_id, text))
+    }
+
+    /// Stops relaying `player_id`'s chat to `room_id`, or starts again. Only the DM's count.
+    pub fn mute(
+        &self,
+        room_id: shared::RoomID,
+        player_id: shared::PlayerID,
+        muted: bool,
+    ) -> Result<Sent, NetError> {
+        self.send(shared::viewer::Command::Mute(room_id, player_id, muted))
+    }
+
+    pub fn try_recv_iter(
+        &self,
+    ) -> impl Iterator<Item = shared::viewer::StateChange<shared::CustomMessage>> + '_ {
+        std::iter::from_fn(move || loop {
+            let received = self.transport.try_recv();
+            if self.transport.reopened() {
+                let transport = &self.transport;
+                if let Err(err) = self.outbox.borrow_mut().flush(|msg| transport.send(msg)) {
+                    log::warn!("couldn't replay commands after reconnecting: {}", err);
+                }
+            }
+            let msg = match received {
+                Ok(msg) => msg,
+                Err(websocket::WebSocketError::Closed(code)) => {
+                    self.closed.set(Some(code));
+                    return None;
+                }
+                Err(_err) => return None,
+            };
+            let frame = match frame_ref(&msg) {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let parsed: Result<shared::viewer::StateChange<shared::CustomMessage>, _> =
+                decode(frame);
+
+            match parsed {
+                Ok(shared::viewer::StateChange {
+                    ty: shared::viewer::ChangeType::Ack(nonce),
+                    ..
+                }) => {
+                    self.outbox.borrow_mut().ack(nonce);
+                }
+                Ok(cmd) => return Some(cmd),
+                Err(NetError::Incompatible(mismatch)) => {
+                    log::error!("{}", mismatch);
+                    self.incompatible.set(Some(mismatch));
+                    return None;
+                }
+                Err(_) => continue,
+            }
+        })
+    }
+
+    /// Why the connection closed, once `try_recv_iter` has run into that.
+    pub fn closed(&self) -> Option<websocket::CloseCode> {
+        self.closed.get()
+    }
+
+    /// Whether this client is too old or too new for the server, going by a message from it or
+    /// by it closing the connection over one of ours. Nothing but a refresh fixes that.
+    pub fn incompatible(&self) -> bool {
+        self.incompatible.get().is_some()
+            || self.closed.get() == Some(websocket::CloseCode::Protocol)
+    }
+
+    pub fn create_room(
+        &self,
+        create_info: &shared::RoomCreateInfo,
+    ) -> eyre::Result<impl Future<Output = eyre::Result<shared::JoinedRoom>>> {
+        let request = self.post(shared::ENDPOINT_CREATE_ROOM, create_info)?;
+        Ok(self.response(request))
+    }
+
+    pub fn join_room(
+        &self,
+        join_info: &shared::RoomJoinInfo,
+    ) -> eyre::Result<impl Future<Output = eyre::Result<shared::JoinedRoom>>> {
+        let request = self.post(shared::ENDPOINT_JOIN_ROOM, join_info)?;
+        Ok(self.response(request))
+    }
+
+    /// Reclaims the seat a previous create or join handed out `rejoin_token` for.
+    pub fn rejoin_room(
+        &self,
+        rejoin_token: shared::RejoinToken,
+    ) -> eyre::Result<impl Future<Output = eyre::Result<shared::JoinedRoom>>> {
+        let request = self.post(
+            shared::ENDPOINT_REJOIN_ROOM,
+            &shared::RoomRejoinInfo { rejoin_token },
+        )?;
+        Ok(self.response(request))
+    }
+
+    /// A player id that stays ours wherever we play from. It's only accepted from clients that
+    /// send the returned token as the `game-account-token` cookie along with it, so both have to
+    /// go in the headers of the client that uses it.
+    pub fn register(&self) -> eyre::Result<impl Future<Output = eyre::Result<shared::Registered>>> {
+        let url = self.base_url.join(shared::ENDPOINT_REGISTER)?;
+        let request = self
+            .settings
+            .request(&self.http, reqwest::Method::POST, url);
+        Ok(self.response(request))
+    }
+
+    /// Settings such as the server's ping interval, for sizing client-side liveness checks.
+    pub fn server_config(
+        &self,
+    ) -> eyre::Result<impl Future<Output = eyre::Result<shared::ServerConfig>>> {
+        let url = self.base_url.join(shared::ENDPOINT_CONFIG)?;
+        let request = self.settings.request(&self.http, reqwest::Method::GET, url);
+        Ok(self.response(request))
+    }
+
+    fn post<B: serde::Serialize>(
+        &self,
+        endpoint: &str,
+        body: &B,
+    ) -> eyre::Result<reqwest::RequestBuilder> {
+        let format = self.settings.http_format;
+        let body = format.encode(body)?;
+        let url = self.base_url.join(endpoint)?;
+        Ok(self
+            .settings
+            .request(&self.http, reqwest::Method::POST, url)
+            .header(reqwest::header::CONTENT_TYPE, format.mime())
+            .body(body))
+    }
+
+    fn response<R: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> impl Future<Output = eyre::Result<R>> {
+        let format = self.settings.http_format;
+        request
+            .header(reqwest::header::ACCEPT, format.mime())
+            .send()
+            .map_err(eyre::Report::from)
+            .and_then(|response| response.bytes().map_err(eyre::Report::from))
+            .map(move |result| result.and_then(|body| parse_body(format, &body)))
+    }
+}
+
+/// Straight from the response's bytes, without copying them into a `String` first.
+/// `tests/allocations.rs` checks that this allocates less.
+fn parse_body<R: serde::de::DeserializeOwned>(
+    format: shared::codec::Format,
+    body: &[u8],
+) -> eyre::Result<R> {
+    format.decode(body).map_err(eyre::Report::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use websocket::{Message, WebSocketError};
+
+    fn text(s: &str) -> Message {
+        Message::Text(s.to_owned())
+    }
+
+    #[test]
+    fn outbox_retries_after_failure() {
+        let mut outbox = Outbox::default();
+        outbox.push(0, text("first")).unwrap();
+        outbox.push(1, text("second")).unwrap();
+
+        let result = outbox.flush(|_msg| Err(WebSocketError::SendError));
+        assert_eq!(result, Err(WebSocketError::SendError));
+        assert_eq!(outbox.queue.len(), 2);
+
+        let mut sent = vec![];
+        let result = outbox.flush(|msg| {
+            sent.push(msg);
+            Ok(())
+        });
+        assert_eq!(result, Ok(()));
+        assert!(outbox.queue.is_empty());
+        assert_eq!(sent, vec![text("first"), text("second")]);
+    }
+
+    #[test]
+    fn outbox_delivery_across_disconnect() {
+        let mut outbox = Outbox::default();
+        let disconnected = |_msg| Err(WebSocketError::SendError);
+
+        assert!(matches!(
+            outbox.send(text("remove"), Some(0), disconnected),
+            Ok(Sent::Queued)
+        ));
+        assert!(outbox.send(text("move"), None, disconnected).is_err());
+
+        let mut sent = vec![];
+        assert_eq!(
+            outbox.flush(|msg| {
+                sent.push(msg);
+                Ok(())
+            }),
+            Ok(())
+        );
+        assert_eq!(sent, vec![text("remove")]);
+    }
+
+    #[test]
+    fn outbox_preserves_order_behind_queued() {
+        let mut outbox = Outbox::default();
+        outbox.push(0, text("first")).unwrap();
+
+        let mut sent = vec![];
+        let result = outbox.send(text("second"), Some(1), |msg| {
+            sent.push(msg);
+            Ok(())
+        });
+        assert!(matches!(result, Ok(Sent::Queued)));
+        assert!(sent.is_empty());
+        assert_eq!(outbox.queue.len(), 2);
+    }
+
+    #[test]
+    fn outbox_replays_until_acked() {
+        let mut outbox = Outbox::default();
+        let connected = |_msg| Ok(());
+        let first = outbox.next_nonce();
+        let second = outbox.next_nonce();
+        assert!(matches!(
+            outbox.send(text("first"), Some(first), connected),
+            Ok(Sent::Now)
+        ));
+        assert!(matches!(
+            outbox.send(text("second"), Some(second), connected),
+            Ok(Sent::Now)
+        ));
+        outbox.ack(first);
+
+        let mut sent = vec![];
+        assert_eq!(
+            outbox.flush(|msg| {
+                sent.push(msg);
+                Ok(())
+            }),
+            Ok(())
+        );
+        assert_eq!(sent, vec![text("second")]);
+
+        outbox.ack(second);
+        assert!(outbox.unacked.is_empty());
+    }
+
+    #[test]
+    fn builder_options_are_applied() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            "x-tension",
+            reqwest::header::HeaderValue::from_static("yes"),
+        );
+        let builder = Client::builder("http://localhost:8000/api/")
+            .timeout(Duration::from_secs(3))
+            .headers(headers)
+            .retry_policy(RetryPolicy {
+                delivery: Delivery::BestEffort,
+                max_pending: 2,
+                ..Default::default()
+            })
+            .codec(Codec::Json)
+            .keepalive(Duration::from_secs(10));
+
+        let (base_url, ws_url) = builder.urls().unwrap();
+        assert_eq!(ws_url.as_str(), "ws://localhost:8000/api/socket");
+        assert_eq!(builder.settings.retry_policy.delivery, Delivery::BestEffort);
+        assert_eq!(builder.settings.keepalive, Some(Duration::from_secs(10)));
+
+        let request = builder
+            .settings
+            .request(&reqwest::Client::new(), reqwest::Method::GET, base_url)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["x-tension"], "yes");
+        assert_eq!(request.timeout(), Some(&Duration::from_secs(3)));
+
+        let mut outbox = Outbox::with_capacity(builder.settings.retry_policy.max_pending);
+        for i in 0..3 {
+            let _ = outbox.push(i, Message::Text(i.to_string()));
+        }
+        assert_eq!(outbox.queue.len(), 2);
+
+        assert!(ClientBuilder::new("ftp://localhost").urls().is_err());
+    }
+
+    /// Hands out what it was given and then reports the connection closed.
+    struct Closing(std::cell::RefCell<std::collections::VecDeque<Message>>);
+
+    impl Transport for Closing {
+        fn send(&self, _msg: Message) -> Result<(), WebSocketError> {
+            Ok(())
+        }
+
+        fn try_recv(&self) -> Result<Message, WebSocketError> {
+            self.0
+                .borrow_mut()
+                .pop_front()
+                .ok_or(WebSocketError::Closed(websocket::CloseCode::Away))
+        }
+    }
+
+    #[test]
+    fn closing_is_noticed() {
+        let change = shared::viewer::StateChange::<shared::CustomMessage> {
+            target: "ABCD".parse().unwrap(),
+            ty: shared::viewer::ChangeType::UserLeave("1".parse().unwrap()),
+        };
+        let msg = encode(Codec::default(), &change).unwrap();
+        let client = Client::builder("http://localhost/")
+            .build_with(Closing(std::cell::RefCell::new(vec![msg].into())))
+            .unwrap();
+
+        assert_eq!(client.closed(), None);
+        assert_eq!(client.try_recv_iter().count(), 1);
+        assert_eq!(client.closed(), Some(websocket::CloseCode::Away));
+    }
+
+    #[test]
+    fn control_frames_are_skipped() {
+        let change = shared::viewer::StateChange::<shared::CustomMessage> {
+            target: "ABCD".parse().unwrap(),
+            ty: shared::viewer::ChangeType::UserLeave("1".parse().unwrap()),
+        };
+        let msgs = vec![
+            Message::Ping(vec![]),
+            encode(Codec::default(), &change).unwrap(),
+            Message::Pong(vec![]),
+        ];
+        let client = Client::builder("http://localhost/")
+            .build_with(Closing(std::cell::RefCell::new(msgs.into())))
+            .unwrap();
+
+        assert!(frame_ref(&Message::Ping(vec![])).is_none());
+        assert_eq!(client.try_recv_iter().count(), 1);
+        assert!(!client.incompatible());
+    }
+
+    /// Keeps whatever it's sent and says it's been redialed once `redialed` is set.
+    #[derive(Default)]
+    struct Redialing {
+        sent: std::cell::RefCell<Vec<Message>>,
+        redialed: std::cell::Cell<bool>,
+    }
+
+    impl Transport for Redialing {
+        fn send(&self, msg: Message) -> Result<(), WebSocketError> {
+            self.sent.borrow_mut().push(msg);
+            Ok(())
+        }
+
+        fn try_recv(&self) -> Result<Message, WebSocketError> {
+            Err(WebSocketError::Empty)
+        }
+
+        fn reopened(&self) -> bool {
+            self.redialed.replace(false)
+        }
+    }
+
+    #[test]
+    fn unacked_commands_are_replayed_after_a_redial() {
+        let client = Client::builder("http://localhost/")
+            .build_with(Redialing::default())
+            .unwrap();
+        let room_id = "ABCD".parse().unwrap();
+        client
+            .send(shared::viewer::Command::Custom(
+                room_id,
+                shared::CustomMessage::StartGame(0),
+            ))
+            .unwrap();
+        assert_eq!(client.try_recv_iter().count(), 0);
+        assert_eq!(client.transport.sent.borrow().len(), 1);
+
+        client.transport.redialed.set(true);
+        assert_eq!(client.try_recv_iter().count(), 0);
+        let sent = client.transport.sent.borrow();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0], sent[1]);
+    }
+
+    #[test]
+    fn other_protocol_versions_are_noticed() {
+        let change = shared::viewer::StateChange::<shared::CustomMessage> {
+            target: "ABCD".parse().unwrap(),
+            ty: shared::viewer::ChangeType::UserLeave("1".parse().unwrap()),
+        };
+        let mut envelope = shared::viewer::Envelope::new(change);
+        envelope.version += 1;
+        let msg = Message::Text(serde_json::to_string(&envelope).unwrap());
+        let client = Client::builder("http://localhost/")
+            .build_with(Closing(std::cell::RefCell::new(vec![msg].into())))
+            .unwrap();
+
+        assert!(!client.incompatible());
+        assert_eq!(client.try_recv_iter().count(), 0);
+        assert!(client.incompatible());
+    }
+
+    #[test]
+    fn outbox_is_bounded() {
+        let mut outbox = Outbox::default();
+        let disconnected = |_msg| Err(WebSocketError::SendError);
+        for i in 0..Outbox::CAPACITY {
+            let sent = outbox.send(Message::Text(i.to_string()), Some(i as _), disconnected);
+            assert!(matches!(sent, Ok(Sent::Queued)));
+        }
+        // the new command is refused rather than the oldest being forgotten
+        let refused = outbox.send(text("one too many"), Some(Outbox::CAPACITY as _), |_msg| {
+            Ok(())
+        });
+        assert!(matches!(refused, Err(NetError::OutboxFull)));
+        assert_eq!(outbox.queue.len(), Outbox::CAPACITY);
+        assert_eq!(outbox.queue.front(), Some(&(0, text("0"))));
+
+        // commands waiting on an ack count too
+        let mut outbox = Outbox::with_capacity(1);
+        assert!(matches!(
+            outbox.send(text("sent"), Some(0), |_msg| Ok(())),
+            Ok(Sent::Now)
+        ));
+        assert!(matches!(
+            outbox.send(text("refused"), Some(1), |_msg| Ok(())),
+            Err(NetError::OutboxFull)
+        ));
+        outbox.ack(0);
+        assert!(matches!(
+            outbox.send(text("sent"), Some(1), |_msg| Ok(())),
+            Ok(Sent::Now)
+        ));
+    }
+
+    #[test]
+    fn responses_parse_from_bytes() {
+        let joined = shared::JoinedRoom {
+            room: shared::viewer::InitialRoomState {
+                name: Some("room".to_owned()),
+                settings: shared::viewer::RoomSettings {
+                    capacity: Some(8),
+                    min_players: 1,
+                    max_consecutive_turns: None,
+                },
+                ..crate::fixtures::room_with(vec![crate::fixtures::user("1", "DM")])
+            },
+            rejoin_token: "token".to_owned(),
+            player_id: "1".parse().unwrap(),
+        };
+        let body = serde_json::to_vec(&joined).unwrap();
+
+        // what `.text()` followed by `from_str` did
+        let before = {
+            let text = String::from_utf8_lossy(&body).into_owned();
+            serde_json::from_str::<shared::JoinedRoom>(&text).unwrap()
+        };
+        let after = parse_body::<shared::JoinedRoom>(shared::codec::Format::Json, &body).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&before).unwrap(),
+            serde_json::to_value(&after).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(&after).unwrap(),
+            serde_json::to_value(&joined).unwrap()
+        );
+    }
+}
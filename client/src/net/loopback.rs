@@ -0,0 +1,198 @@
+//! An in-process stand-in for the server so that clients can be driven without any sockets.
+//! Rooms are created and joined directly here rather than over HTTP.
+
+use super::{Codec, Transport};
+use shared::viewer::{state, Command, InitialRoomState, StateChange, User};
+use shared::{CustomMessage, PlayerID, RoomID};
+use std::cell::RefCell;
+use std::rc::Rc;
+use websocket::{Message, WebSocketError};
+
+type Change = StateChange<CustomMessage>;
+type Shared = Rc<RefCell<state::State<CustomMessage>>>;
+/// The next change broadcast to a room, if there is one yet.
+type NextChange = Box<dyn FnMut() -> Option<Change>>;
+
+pub struct Server {
+    state: Shared,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        let state = state::State::new()
+            .with_checkpoint(CustomMessage::starts_game)
+            .with_transient(CustomMessage::is_transient)
+            .with_guard(CustomMessage::allowed_in);
+        Self {
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+}
+
+impl Server {
+    /// Registers `user` and returns the transport their client should talk through.
+    pub fn connect(&self, user: User) -> Loopback {
+        let user_id = user.id;
+        self.state.borrow_mut().register_user(user);
+        Loopback {
+            user_id,
+            state: self.state.clone(),
+            inbox: Default::default(),
+            rooms: Default::default(),
+        }
+    }
+
+    pub fn create_room(&self, transport: &Loopback) -> InitialRoomState {
+        let room_id = self
+            .state
+            .borrow_mut()
+            .create_room()
+            .expect("loopback servers only have a handful of rooms");
+        self.join_room(room_id, transport)
+            .expect("the room was just created")
+    }
+
+    /// `None` if there's no such room.
+    pub fn join_room(&self, room_id: RoomID, transport: &Loopback) -> Option<InitialRoomState> {
+        let mut state = self.state.borrow_mut();
+        let _result = state.join(room_id, transport.user_id)?;
+        let (room, mut channel) = state.subscribe(room_id)?;
+        // caught up first, the same as the real server does
+        transport.inbox.borrow_mut().extend(state.replay(room_id));
+        transport
+            .rooms
+            .borrow_mut()
+            .push((room_id, Box::new(move || channel.try_recv().ok())));
+        Some(room)
+    }
+}
+
+pub struct Loopback {
+    user_id: PlayerID,
+    state: Shared,
+    /// Replies meant only for this user, like acks and replays.
+    inbox: RefCell<std::collections::VecDeque<Change>>,
+    rooms: RefCell<Vec<(RoomID, NextChange)>>,
+}
+
+impl Transport for Loopback {
+    fn send(&self, msg: Message) -> Result<(), WebSocketError> {
+        let frame = super::frame_ref(&msg).ok_or(WebSocketError::SendError)?;
+        let cmd: Command<CustomMessage> =
+            super::decode(frame).map_err(|_err| WebSocketError::SendError)?;
+        if let Command::Leave(room_id) = &cmd {
+            self.rooms
+                .borrow_mut()
+                .retain(|(id, _next_change)| id != room_id);
+        }
+        let reply = self.state.borrow_mut().handle_command(cmd, &self.user_id);
+        self.inbox.borrow_mut().extend(reply);
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Result<Message, WebSocketError> {
+        let change = self.inbox.borrow_mut().pop_front().or_else(|| {
+            self.rooms
+                .borrow_mut()
+                .iter_mut()
+                .find_map(|(_room_id, next_change)| next_change())
+        });
+        change.ok_or(WebSocketError::Empty).and_then(|change| {
+            super::encode(Codec::default(), &change).map_err(|_err| WebSocketError::ReceiveError)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::user;
+    use crate::turns::ClickQueue;
+    use shared::viewer::ChangeType;
+
+    #[test]
+    fn clients_agree_on_turns() {
+        let server = Server::default();
+        let (dm, alice, bob) = (user("1", "DM"), user("2", "Alice"), user("3", "Bob"));
+        let connect = |user: &User| {
+            crate::net::Client::builder("http://loopback/")
+                .build_with(server.connect(user.clone()))
+                .unwrap()
+        };
+
+        let dm_client = connect(&dm);
+        let room_id = server.create_room(&dm_client.transport).id;
+        let alice_client = connect(&alice);
+        let bob_client = connect(&bob);
+        assert!(server.join_room(room_id, &alice_client.transport).is_some());
+        assert!(server.join_room(room_id, &bob_client.transport).is_some());
+
+        let clients = [&dm_client, &alice_client, &bob_client];
+        let mut queues = vec![ClickQueue::default(); clients.len()];
+        let sync = |queues: &mut Vec<ClickQueue>| {
+            for (client, queue) in clients.iter().zip(queues.iter_mut()) {
+                for change in client.try_recv_iter() {
+                    assert_eq!(change.target, room_id);
+                    queue.apply(&change.ty);
+                }
+            }
+            assert!(queues.iter().all(|queue| queue == &queues[0]));
+            queues[0].current()
+        };
+
+        let send = |client: &crate::net::Client<Loopback>, msg| {
+            client
+                .send(Command::Custom(room_id, msg))
+                .expect("loopback sends can't fail")
+        };
+        send(&dm_client, CustomMessage::StartGame(0));
+        send(&dm_client, CustomMessage::AssignClick(alice.id, 1));
+        send(&dm_client, CustomMessage::AssignClick(bob.id, 2));
+        assert_eq!(sync(&mut queues), Some(alice.id));
+
+        send(&alice_client, CustomMessage::RemoveBody(0., 0.));
+        send(&alice_client, CustomMessage::DropBody(0.1, 0.));
+        assert_eq!(sync(&mut queues), Some(bob.id));
+
+        send(&bob_client, CustomMessage::RemoveBody(0., 0.));
+        send(&bob_client, CustomMessage::DropBody(0.1, 0.));
+        assert_eq!(sync(&mut queues), Some(bob.id));
+        assert_eq!(queues[0].remaining(bob.id), 1);
+
+        send(&bob_client, CustomMessage::RemoveBody(0., 0.));
+        send(&bob_client, CustomMessage::DropBody(0.1, 0.));
+        assert_eq!(sync(&mut queues), None);
+    }
+
+    #[test]
+    fn leaving_frees_the_seat() {
+        let server = Server::default();
+        let (dm, alice) = (user("1", "DM"), user("2", "Alice"));
+        let connect = |user: &User| {
+            crate::net::Client::builder("http://loopback/")
+                .build_with(server.connect(user.clone()))
+                .unwrap()
+        };
+
+        let dm_client = connect(&dm);
+        let room_id = server.create_room(&dm_client.transport).id;
+        let alice_client = connect(&alice);
+        assert!(server.join_room(room_id, &alice_client.transport).is_some());
+        assert_eq!(dm_client.try_recv_iter().count(), 1);
+
+        alice_client.leave_room(room_id).unwrap();
+        let changes = dm_client.try_recv_iter().collect::<Vec<_>>();
+        assert!(matches!(
+            changes.as_slice(),
+            [StateChange { ty: ChangeType::UserLeave(id), .. }] if *id == alice.id
+        ));
+        // no longer listening in
+        assert_eq!(alice_client.try_recv_iter().count(), 0);
+
+        // the same connection can take a seat again
+        assert!(server.join_room(room_id, &alice_client.transport).is_some());
+        alice_client.leave_room(room_id).unwrap();
+        dm_client.leave_room(room_id).unwrap();
+        assert!(server.join_room(room_id, &alice_client.transport).is_none());
+    }
+}
@@ -0,0 +1,120 @@
+//! What differs between running natively and in a browser, so that the frame loop around a game
+//! is only written once.
+
+use std::time::Duration;
+
+pub enum ContextStatus {
+    Ok,
+    /// Nothing can be drawn until it's restored.
+    Lost,
+    /// A new context to rebuild everything on, the old one having been lost. Boxed since it's
+    /// much bigger than the other variants.
+    Restored(Box<solstice_2d::solstice::Context>),
+}
+
+pub trait Platform {
+    /// Only differences between readings matter, like with `clock::Clock`.
+    fn now(&self) -> Duration;
+
+    /// Checked at the start of every frame. Only browsers lose their context.
+    fn context_status(&mut self) -> eyre::Result<ContextStatus> {
+        Ok(ContextStatus::Ok)
+    }
+
+    /// Shows the finished frame. Browsers do that themselves once the frame callback returns.
+    fn present(&mut self) -> eyre::Result<()> {
+        Ok(())
+    }
+}
+
+/// Something stepped and drawn a frame at a time, like `Game`.
+pub trait Frame {
+    /// Advances everything to `time` from `Platform::now`, and draws it unless `render` is
+    /// false because the context is lost.
+    fn frame(&mut self, time: Duration, render: bool);
+
+    /// Rebuilds every GPU-side resource against a new context.
+    fn restore_context(&mut self, ctx: solstice_2d::solstice::Context) -> eyre::Result<()>;
+}
+
+/// Returns whether anything was drawn, which it isn't while the context is lost. Everything
+/// keeps stepping regardless so that the game doesn't fall behind the server.
+pub fn run_frame<P: Platform, F: Frame>(platform: &mut P, frame: &mut F) -> eyre::Result<bool> {
+    let render = match platform.context_status()? {
+        ContextStatus::Ok => true,
+        ContextStatus::Lost => false,
+        ContextStatus::Restored(ctx) => {
+            frame.restore_context(*ctx)?;
+            true
+        }
+    };
+    frame.frame(platform.now(), render);
+    if render {
+        platform.present()?;
+    }
+    Ok(render)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MockPlatform {
+        clock: crate::clock::ManualClock,
+        lost: bool,
+        presented: usize,
+    }
+
+    impl Platform for MockPlatform {
+        fn now(&self) -> Duration {
+            use crate::clock::Clock;
+            self.clock.now()
+        }
+
+        fn context_status(&mut self) -> eyre::Result<ContextStatus> {
+            Ok(if self.lost {
+                ContextStatus::Lost
+            } else {
+                ContextStatus::Ok
+            })
+        }
+
+        fn present(&mut self) -> eyre::Result<()> {
+            self.presented += 1;
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct MockFrame {
+        frames: Vec<(Duration, bool)>,
+    }
+
+    impl Frame for MockFrame {
+        fn frame(&mut self, time: Duration, render: bool) {
+            self.frames.push((time, render));
+        }
+
+        fn restore_context(&mut self, _ctx: solstice_2d::solstice::Context) -> eyre::Result<()> {
+            unreachable!("the mock platform never restores its context")
+        }
+    }
+
+    #[test]
+    fn frames_are_stepped_and_presented() {
+        let mut platform = MockPlatform::default();
+        let mut frame = MockFrame::default();
+
+        platform.clock.set(Duration::from_millis(16));
+        assert!(run_frame(&mut platform, &mut frame).unwrap());
+        assert_eq!(frame.frames, vec![(Duration::from_millis(16), true)]);
+        assert_eq!(platform.presented, 1);
+
+        platform.lost = true;
+        platform.clock.set(Duration::from_millis(32));
+        assert!(!run_frame(&mut platform, &mut frame).unwrap());
+        assert_eq!(frame.frames[1], (Duration::from_millis(32), false));
+        assert_eq!(platform.presented, 1);
+    }
+}
@@ -2,8 +2,16 @@
 
 use solstice_2d::solstice::{self, Context};
 
+/// Used when no font is supplied, and in place of fonts that fail to parse, so there's always
+/// something to draw text with.
+const DEFAULT_FONT: &[u8] = include_bytes!("../../docs/fonts/Inconsolata-Regular.ttf");
+
+#[derive(Clone, Default)]
 pub struct Resources {
-    pub sans_font_data: Vec<u8>,
+    /// Overrides the bundled font.
+    pub sans_font_data: Option<Vec<u8>>,
+    /// An encoded track to loop in the background. Handed to the audio backend as-is.
+    pub music_data: Option<Vec<u8>>,
 }
 
 pub struct LoadedResources {
@@ -16,12 +24,35 @@ impl Resources {
         ctx: &mut Context,
         gfx: &mut solstice_2d::Graphics,
     ) -> eyre::Result<LoadedResources> {
-        use std::convert::TryInto;
-
         Ok(LoadedResources {
-            sans_font: gfx.add_font(self.sans_font_data.try_into()?),
+            sans_font: gfx.add_font(self.sans_font()?),
         })
     }
+
+    fn sans_font<F>(&self) -> Result<F, F::Error>
+    where
+        F: std::convert::TryFrom<Vec<u8>>,
+        F::Error: std::fmt::Display,
+    {
+        match &self.sans_font_data {
+            Some(data) => font_or_fallback(data.clone()),
+            None => F::try_from(DEFAULT_FONT.to_vec()),
+        }
+    }
+}
+
+fn font_or_fallback<F>(data: Vec<u8>) -> Result<F, F::Error>
+where
+    F: std::convert::TryFrom<Vec<u8>>,
+    F::Error: std::fmt::Display,
+{
+    F::try_from(data).or_else(|err| {
+        log::warn!(
+            "failed to load font, falling back to the bundled one: {}",
+            err
+        );
+        F::try_from(DEFAULT_FONT.to_vec())
+    })
 }
 
 pub enum ImageDataRepr {
@@ -56,11 +87,11 @@ impl ImageData {
         let settings = Settings {
             mipmaps: false,
             filter: if nearest {
-                solstice::texture::FilterMode::Nearest.into()
+                solstice::texture::FilterMode::Nearest
             } else {
-                solstice::texture::FilterMode::Linear.into()
+                solstice::texture::FilterMode::Linear
             },
-            wrap: solstice::texture::WrapMode::Repeat.into(),
+            wrap: solstice::texture::WrapMode::Repeat,
             ..Default::default()
         };
         let img = match data {
@@ -87,3 +118,43 @@ impl ImageData {
         Ok(img)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for the real font type, which needs a graphics context to be of any use.
+    #[derive(Debug)]
+    struct Font(Vec<u8>);
+
+    impl std::convert::TryFrom<Vec<u8>> for Font {
+        type Error = &'static str;
+
+        fn try_from(data: Vec<u8>) -> Result<Self, Self::Error> {
+            match data.get(..4) {
+                Some([0, 1, 0, 0]) | Some(b"OTTO") | Some(b"true") => Ok(Self(data)),
+                _ => Err("not a font"),
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_fonts_fall_back_to_the_bundled_one() {
+        let font: Font = font_or_fallback(b"definitely not a font".to_vec()).unwrap();
+        assert_eq!(font.0, DEFAULT_FONT);
+
+        let data = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../docs/fonts/04b03.ttf"
+        ))
+        .unwrap();
+        let font: Font = font_or_fallback(data.clone()).unwrap();
+        assert_eq!(font.0, data);
+    }
+
+    #[test]
+    fn the_bundled_font_is_used_by_default() {
+        let font: Font = Resources::default().sans_font().unwrap();
+        assert_eq!(font.0, DEFAULT_FONT);
+    }
+}
@@ -0,0 +1,278 @@
+use serde::{Deserialize, Serialize};
+
+/// Player preferences that outlive a session.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub dark_theme: bool,
+    pub colorblind_palette: bool,
+    pub sound: bool,
+    /// Percent, 0 through 100.
+    pub music_volume: u8,
+    /// Percent. Scales in-game text along with anything that's clicked by it.
+    pub ui_scale_percent: u16,
+    /// Percent of a block's width. How far apart the points that dragged blocks snap to are.
+    pub snap_grid_percent: u16,
+    pub locale: crate::strings::Locale,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            dark_theme: false,
+            colorblind_palette: false,
+            sound: true,
+            music_volume: 50,
+            ui_scale_percent: 100,
+            snap_grid_percent: 100,
+            locale: Default::default(),
+        }
+    }
+}
+
+/// What the menus are drawn in. Games are always drawn on a dark background.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Theme {
+    pub background: [f32; 4],
+    pub text: [f32; 4],
+}
+
+impl Theme {
+    pub const LIGHT: Self = Self {
+        background: [1., 1., 1., 1.],
+        text: [0., 0., 0., 1.],
+    };
+    pub const DARK: Self = Self {
+        background: [0.1, 0.1, 0.1, 1.],
+        text: [1., 1., 1., 1.],
+    };
+}
+
+/// Everything that can be flipped from the settings screen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Toggle {
+    DarkTheme,
+    ColorblindPalette,
+    Sound,
+}
+
+impl Toggle {
+    /// In display order.
+    pub const ALL: [Toggle; 3] = [Toggle::DarkTheme, Toggle::ColorblindPalette, Toggle::Sound];
+
+    pub fn text(&self) -> crate::strings::Text {
+        use crate::strings::Text;
+        match self {
+            Toggle::DarkTheme => Text::DarkTheme,
+            Toggle::ColorblindPalette => Text::ColorblindPalette,
+            Toggle::Sound => Text::Sound,
+        }
+    }
+}
+
+impl Settings {
+    pub fn get(&self, toggle: Toggle) -> bool {
+        match toggle {
+            Toggle::DarkTheme => self.dark_theme,
+            Toggle::ColorblindPalette => self.colorblind_palette,
+            Toggle::Sound => self.sound,
+        }
+    }
+
+    pub fn toggle(&mut self, toggle: Toggle) {
+        let value = match toggle {
+            Toggle::DarkTheme => &mut self.dark_theme,
+            Toggle::ColorblindPalette => &mut self.colorblind_palette,
+            Toggle::Sound => &mut self.sound,
+        };
+        *value = !*value;
+    }
+
+    pub fn theme(&self) -> Theme {
+        if self.dark_theme {
+            Theme::DARK
+        } else {
+            Theme::LIGHT
+        }
+    }
+
+    pub fn palette(&self) -> crate::sim::Palette {
+        if self.colorblind_palette {
+            crate::sim::Palette::COLORBLIND
+        } else {
+            crate::sim::Palette::DEFAULT
+        }
+    }
+
+    pub fn strings(&self) -> crate::strings::Strings {
+        crate::strings::Strings::new(self.locale)
+    }
+
+    pub fn cycle_locale(&mut self) {
+        use crate::strings::Locale;
+        let index = Locale::ALL.iter().position(|locale| *locale == self.locale);
+        let next = index.map(|index| index + 1).unwrap_or(0) % Locale::ALL.len();
+        self.locale = Locale::ALL[next];
+    }
+
+    pub fn ui_scale(&self) -> f32 {
+        self.ui_scale_percent.max(1) as f32 / 100.
+    }
+
+    pub fn snap_grid(&self) -> f32 {
+        crate::sim::BLOCK_SIZE * self.snap_grid_percent.max(1) as f32 / 100.
+    }
+
+    /// Steps through the supported text sizes, wrapping around to the smallest.
+    pub fn cycle_ui_scale(&mut self) {
+        const STEPS: [u16; 4] = [100, 125, 150, 200];
+        self.ui_scale_percent = STEPS
+            .iter()
+            .copied()
+            .find(|step| *step > self.ui_scale_percent)
+            .unwrap_or(STEPS[0]);
+    }
+
+    /// Steps the music volume up by a quarter, wrapping around to silence.
+    pub fn cycle_music_volume(&mut self) {
+        self.music_volume = if self.music_volume >= 100 {
+            0
+        } else {
+            (self.music_volume + 25).min(100)
+        };
+    }
+}
+
+/// Where settings are kept: local storage in the browser, a JSON file natively.
+pub struct Store {
+    #[cfg(not(target_arch = "wasm32"))]
+    path: std::path::PathBuf,
+}
+
+#[cfg(target_arch = "wasm32")]
+const STORAGE_KEY: &str = "tension-settings";
+
+#[cfg(target_arch = "wasm32")]
+impl Default for Store {
+    fn default() -> Self {
+        Self {}
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for Store {
+    fn default() -> Self {
+        Self::at("tension-settings.json")
+    }
+}
+
+impl Store {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn at<P: Into<std::path::PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Falls back to the defaults when nothing has been saved or what was saved is unreadable.
+    pub fn load(&self) -> Settings {
+        let saved = match self.read() {
+            Ok(saved) => saved,
+            Err(err) => {
+                log::warn!("failed to read settings: {}", err);
+                None
+            }
+        };
+        saved
+            .and_then(|saved| match serde_json::from_str(&saved) {
+                Ok(settings) => Some(settings),
+                Err(err) => {
+                    log::warn!("discarding unreadable settings: {}", err);
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, settings: &Settings) -> eyre::Result<()> {
+        self.write(&serde_json::to_string(settings)?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read(&self) -> eyre::Result<Option<String>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(saved) => Ok(Some(saved)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn write(&self, saved: &str) -> eyre::Result<()> {
+        Ok(std::fs::write(&self.path, saved)?)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn read(&self) -> eyre::Result<Option<String>> {
+        local_storage()?
+            .get_item(STORAGE_KEY)
+            .map_err(|err| eyre::eyre!("{:?}", err))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn write(&self, saved: &str) -> eyre::Result<()> {
+        local_storage()?
+            .set_item(STORAGE_KEY, saved)
+            .map_err(|err| eyre::eyre!("{:?}", err))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> eyre::Result<web_sys::Storage> {
+    web_sys::window()
+        .ok_or_else(|| eyre::eyre!("no window"))?
+        .local_storage()
+        .map_err(|err| eyre::eyre!("{:?}", err))?
+        .ok_or_else(|| eyre::eyre!("local storage is unavailable"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles_persist() {
+        let path =
+            std::env::temp_dir().join(format!("tension-settings-{}.json", std::process::id()));
+        let store = Store::at(&path);
+        let mut settings = store.load();
+        assert_eq!(settings, Settings::default());
+
+        settings.toggle(Toggle::Sound);
+        assert!(!settings.get(Toggle::Sound));
+        store.save(&settings).unwrap();
+
+        let reloaded = Store::at(&path).load();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded, settings);
+        assert!(!reloaded.sound);
+    }
+
+    #[test]
+    fn adjustable_settings_cycle() {
+        let mut settings = Settings::default();
+        let volumes = (0..5)
+            .map(|_| {
+                settings.cycle_music_volume();
+                settings.music_volume
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(volumes, vec![75, 100, 0, 25, 50]);
+
+        let scales = (0..4)
+            .map(|_| {
+                settings.cycle_ui_scale();
+                settings.ui_scale()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(scales, vec![1.25, 1.5, 2., 1.]);
+    }
+}
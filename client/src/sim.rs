@@ -1,3 +1,4 @@
+#[cfg(feature = "render")]
 use solstice_2d::solstice::viewport::Viewport;
 
 pub struct RoomType {
@@ -5,7 +6,7 @@ pub struct RoomType {
     pub gen: fn() -> Sim,
 }
 
-pub const ROOM_TYPES: [RoomType; 4] = [
+pub const ROOM_TYPES: [RoomType; shared::ROOM_TYPE_COUNT as usize] = [
     RoomType {
         name: "standard",
         gen: Sim::new,
@@ -24,47 +25,294 @@ pub const ROOM_TYPES: [RoomType; 4] = [
     },
 ];
 
+/// For an index that came over the network. `None`, with a warning, when it's out of range,
+/// like from a client with a different set of room types.
+pub fn room_type(index: u16) -> Option<&'static RoomType> {
+    let room_type = ROOM_TYPES.get(index as usize);
+    if room_type.is_none() {
+        log::warn!("ignoring unknown room type {}", index);
+    }
+    room_type
+}
+
 pub type PhysicsTuple = (
     rapier2d::dynamics::RigidBody,
     Vec<rapier2d::geometry::Collider>,
 );
 
+/// From the bodies of a sim to the same bodies in a fork of it.
+pub type BodyMap = std::collections::HashMap<
+    rapier2d::dynamics::RigidBodyHandle,
+    rapier2d::dynamics::RigidBodyHandle,
+>;
+
+/// Things that happened during a step that something outside of the simulation may want to
+/// react to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SimEvent {
+    /// At least one pair of bodies started touching.
+    Contact,
+    /// A body reached the kill sensor for the first time.
+    Collapse,
+}
+
+/// How wide the generated blocks are.
+pub const BLOCK_SIZE: f32 = 0.05;
+
+/// To the nearest point on a grid `increment` apart. With an increment of `BLOCK_SIZE`, blocks
+/// snapped to it sit flush on the default platform.
+pub fn snap_to_grid([x, y]: [f32; 2], increment: f32) -> [f32; 2] {
+    let snap = |value: f32| ((value / increment - 0.5).round() + 0.5) * increment;
+    [snap(x), snap(y)]
+}
+
+/// What moving bodies are drawn in, telling the ones still settling from the ones at rest.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Palette {
+    pub awake: [f32; 4],
+    pub asleep: [f32; 4],
+}
+
+impl Palette {
+    pub const DEFAULT: Self = Self {
+        awake: [0., 0.8, 0., 1.],
+        asleep: [0., 0., 0.8, 1.],
+    };
+    /// Orange and blue from the Okabe-Ito palette, which stay apart under the common kinds of
+    /// colorblindness and differ in brightness as well.
+    pub const COLORBLIND: Self = Self {
+        awake: [0.9, 0.6, 0., 1.],
+        asleep: [0., 0.45, 0.7, 1.],
+    };
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// `collider` as a block at `position`, which is either where it is in the world or where it is
+/// on its body.
+fn as_block(
+    collider: &rapier2d::geometry::Collider,
+    position: &rapier2d::math::Isometry<f32>,
+) -> Option<shared::layout::Block> {
+    let half = collider.shape().as_cuboid()?.half_extents;
+    // memberships in the high half, the filter in the low
+    let groups = collider.collision_groups().0;
+    Some(shared::layout::Block {
+        x: position.translation.x,
+        y: position.translation.y,
+        half_width: half.x,
+        half_height: half.y,
+        rotation: position.rotation.angle(),
+        groups: shared::layout::CollisionGroups {
+            memberships: (groups >> 16) as u16,
+            filter: groups as u16,
+        },
+        body: None,
+    })
+}
+
+/// The static ground that the blocks are stacked on. The kill sensor sits a fixed distance
+/// beneath it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PlatformConfig {
+    /// Fits the bottom of the stack when `None`.
+    pub width: Option<f32>,
+    pub thickness: f32,
+    /// Of the platform's center.
+    pub y: f32,
+}
+
+impl Default for PlatformConfig {
+    fn default() -> Self {
+        Self {
+            width: None,
+            thickness: 0.1,
+            y: -0.5,
+        }
+    }
+}
+
 pub struct Sim {
     physics: physics::PhysicsContext,
+    time_scale: f32,
+}
+
+impl Default for Sim {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Sim {
+    fn build(
+        init: physics::Gen<impl physics::GenResult>,
+        num: usize,
+        platform: PlatformConfig,
+    ) -> Self {
+        let physics = physics::PhysicsContext::new(0., -9.81 * 0.1, init, num, platform);
+        Self {
+            physics,
+            time_scale: 1.,
+        }
+    }
+
+    /// Built from `layout` on the default platform.
+    pub fn from_layout(layout: &shared::layout::TowerLayout) -> Self {
+        let physics =
+            physics::PhysicsContext::from_layout(0., -9.81 * 0.1, layout, Default::default());
+        Self {
+            physics,
+            time_scale: 1.,
+        }
+    }
+
+    /// For a sim built with `from_layout`, the index in the layout of the first block of each
+    /// body. Blocks without a size aren't built, so handles can't be counted off against the
+    /// layout.
+    pub fn layout_indices(
+        &self,
+    ) -> std::collections::HashMap<rapier2d::dynamics::RigidBodyHandle, usize> {
+        self.physics
+            .bodies
+            .iter()
+            .filter(|(_h, body)| body.is_dynamic())
+            .map(|(handle, body)| (handle, body.user_data as usize))
+            .collect()
+    }
+
+    /// Where every block is right now, for building the same tower again later. The blocks of
+    /// a compound body share a `body` so that they're built back into one.
+    pub fn current_layout(&self) -> shared::layout::TowerLayout {
+        let mut compounds = 0;
+        let blocks = self
+            .physics
+            .bodies
+            .iter()
+            .filter(|(_h, body)| body.is_dynamic())
+            .flat_map(|(_h, body)| {
+                let compound = if body.colliders().len() > 1 {
+                    compounds += 1;
+                    Some(compounds - 1)
+                } else {
+                    None
+                };
+                body.colliders().iter().filter_map(move |&handle| {
+                    let collider = &self.physics.colliders[handle];
+                    let block = as_block(collider, collider.position())?;
+                    Some(shared::layout::Block {
+                        body: compound,
+                        ..block
+                    })
+                })
+            })
+            .collect();
+        shared::layout::TowerLayout { blocks }
+    }
+
+    /// Everything another client needs to carry on from exactly here. See `resync`.
+    pub fn snapshot(&self) -> shared::layout::Snapshot {
+        let bodies = self
+            .physics
+            .bodies
+            .iter()
+            .filter(|(_h, body)| body.is_dynamic())
+            .map(|(_h, body)| {
+                let position = body.position();
+                let parts = body
+                    .colliders()
+                    .iter()
+                    .filter_map(|&handle| {
+                        let collider = &self.physics.colliders[handle];
+                        as_block(collider, collider.position_wrt_parent())
+                    })
+                    .collect();
+                shared::layout::Body {
+                    x: position.translation.x,
+                    y: position.translation.y,
+                    rotation: position.rotation.angle(),
+                    velocity_x: body.linvel().x,
+                    velocity_y: body.linvel().y,
+                    angular_velocity: body.angvel(),
+                    parts,
+                }
+            })
+            .collect();
+        let platform = self.physics.platform;
+        shared::layout::Snapshot {
+            bodies,
+            platform: shared::layout::Platform {
+                width: platform.width.unwrap_or_default(),
+                thickness: platform.thickness,
+                y: platform.y,
+            },
+        }
+    }
+
     pub fn new() -> Self {
-        let init = physics::PhysicsContext::special_tower;
-        let physics = physics::PhysicsContext::new(0., -9.81 * 0.1, init, 11);
-        Self { physics }
+        Self::build(
+            physics::PhysicsContext::special_tower,
+            11,
+            Default::default(),
+        )
     }
 
     pub fn tower() -> Self {
-        let init = physics::PhysicsContext::tower;
-        let physics = physics::PhysicsContext::new(0., -9.81 * 0.1, init, 12);
-        Self { physics }
+        Self::build(physics::PhysicsContext::tower, 12, Default::default())
+    }
+
+    /// The same stack as `tower` on a different platform.
+    pub fn tower_on(platform: PlatformConfig) -> Self {
+        Self::build(physics::PhysicsContext::tower, 12, platform)
     }
 
     pub fn pyramid() -> Self {
-        let init = physics::PhysicsContext::pyramid;
-        let physics = physics::PhysicsContext::new(0., -9.81 * 0.1, init, 9);
-        Self { physics }
+        Self::build(physics::PhysicsContext::pyramid, 9, Default::default())
     }
 
     pub fn thin() -> Self {
-        let init = physics::PhysicsContext::thin;
-        let physics = physics::PhysicsContext::new(0., -9.81 * 0.1, init, 13);
-        Self { physics }
+        Self::build(physics::PhysicsContext::thin, 13, Default::default())
+    }
+
+    /// A copy to try things out on without touching this one, like what removing a block would
+    /// do. See `PhysicsContext::fork`.
+    pub fn fork(&self) -> (Self, BodyMap) {
+        let (physics, bodies) = self.physics.fork();
+        let sim = Self {
+            physics,
+            time_scale: self.time_scale,
+        };
+        (sim, bodies)
     }
 
     pub fn step(&mut self, dt: std::time::Duration) {
-        self.physics.step(dt);
+        // in `dt` sized pieces so running faster takes as many steps as that many frames would,
+        // rather than the one a single long frame gets
+        let mut remaining = dt.mul_f32(self.time_scale);
+        while remaining > dt {
+            self.physics.step(dt);
+            remaining -= dt;
+        }
+        self.physics.step(remaining);
     }
 
-    pub fn render(&self, g: &mut solstice_2d::GraphicsLock) {
+    /// How fast the simulation runs relative to the time passed to `step`. Zero pauses it,
+    /// negative scales are ignored and anything over `shared::MAX_TIME_SCALE` is capped to it.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        if time_scale >= 0. && !time_scale.is_nan() {
+            self.time_scale = time_scale.min(shared::MAX_TIME_SCALE);
+        } else {
+            log::warn!("ignoring time scale of {}", time_scale);
+        }
+    }
+
+    #[cfg(feature = "render")]
+    pub fn render<'s>(&'s self, g: &mut solstice_2d::GraphicsLock<'_, 's>) {
         use solstice_2d::Draw;
-        let vw = g.gfx().viewport().clone();
+        let vw = *g.gfx().viewport();
         g.set_projection_mode(Some(Self::projection(&vw)));
 
         g.draw_with_color(
@@ -79,51 +327,130 @@ impl Sim {
         self.physics.debug_render(g);
     }
 
+    #[cfg(feature = "render")]
     pub fn projection(vw: &Viewport<i32>) -> solstice_2d::Projection {
-        let aspect = vw.width() as f32 / vw.height() as f32;
+        let view = Self::view_bounds(vw);
         solstice_2d::Projection::Orthographic(Some(solstice_2d::Orthographic {
-            left: -aspect / 2.,
-            right: aspect / 2.,
-            top: 0.5,
-            bottom: -0.5,
+            left: view.mins.x,
+            right: view.maxs.x,
+            top: view.maxs.y,
+            bottom: view.mins.y,
             near: 0.0,
             far: 100.0,
         }))
     }
 
+    /// The part of the world that `projection` shows.
+    #[cfg(feature = "render")]
+    pub fn view_bounds(vw: &Viewport<i32>) -> rapier2d::parry::bounding_volume::AABB {
+        let aspect = vw.width() as f32 / vw.height() as f32;
+        rapier2d::parry::bounding_volume::AABB::new(
+            rapier2d::na::Point2::new(-aspect / 2., -0.5),
+            rapier2d::na::Point2::new(aspect / 2., 0.5),
+        )
+    }
+
+    /// Bodies entirely outside of `view` are left out of the debug rendering.
+    #[cfg(feature = "render")]
+    pub fn set_view(&mut self, view: rapier2d::parry::bounding_volume::AABB) {
+        if self.physics.view != Some(view) {
+            self.physics.view = Some(view);
+            self.physics.debug_mesh_stale = true;
+        }
+    }
+
+    /// Brings what `render` draws up to date with the bodies. Called once before each frame that
+    /// is drawn, however many steps were taken since the last one.
+    #[cfg(feature = "render")]
+    pub fn prepare_render(&mut self) {
+        self.physics.refresh_debug_mesh();
+    }
+
+    /// Drawn along with everything else, for a body that's been picked up and not yet dropped.
+    #[cfg(feature = "render")]
+    pub fn set_held(&mut self, held: Option<&PhysicsTuple>) {
+        const HELD_BODY_COLOR: [f32; 4] = [1., 0.2, 0.2, 0.8];
+
+        let quads = held.into_iter().flat_map(|(body, colliders)| {
+            colliders.iter().filter_map(move |collider| {
+                collider.shape().as_cuboid().map(|shape| {
+                    let position = body.position() * collider.position_wrt_parent();
+                    physics::cuboid_quad(&position, shape.half_extents, HELD_BODY_COLOR)
+                })
+            })
+        });
+        self.physics.debug_mesh.set_held(quads);
+    }
+
+    #[cfg(feature = "render")]
     pub fn screen_to_world(screen: &Viewport<i32>, x: f32, y: f32) -> [f32; 2] {
         let (width, height) = (screen.width() as f32, screen.height() as f32);
+        let aspect = width / height;
         let norm_x = x / width;
         let norm_y = y / height;
-        [(norm_x - 0.5) * 16. / 9., 1.0 - norm_y - 0.5]
+        [(norm_x - 0.5) * aspect, 1.0 - norm_y - 0.5]
     }
 
     pub fn all_sleeping(&self) -> bool {
+        // not `iter_active_dynamic`, which misses bodies added since the last step
         self.physics
             .bodies
-            .iter_active_dynamic()
+            .iter()
+            .filter(|(_h, b)| b.is_dynamic())
             .all(|(_h, b)| b.is_sleeping())
     }
 
+    /// Takes fixed steps until every body is asleep, but no more than `max_steps` of them.
+    /// Returns whether it got there. The time scale doesn't apply, each step is one tick.
+    pub fn settle(&mut self, max_steps: u32) -> bool {
+        for _ in 0..max_steps {
+            if self.all_sleeping() {
+                return true;
+            }
+            self.physics.step_tick();
+        }
+        self.all_sleeping()
+    }
+
+    /// Every body that could be picked up right now, which leaves out the ground, the kill
+    /// sensor and whatever is already being held, since that isn't in the simulation.
+    pub fn removable_bodies(&self) -> Vec<rapier2d::dynamics::RigidBodyHandle> {
+        self.physics
+            .bodies
+            .iter()
+            .map(|(handle, _body)| handle)
+            .filter(|&handle| self.is_removable(handle))
+            .collect()
+    }
+
+    fn is_removable(&self, handle: rapier2d::dynamics::RigidBodyHandle) -> bool {
+        self.physics
+            .bodies
+            .get(handle)
+            .is_some_and(|body| body.is_dynamic())
+    }
+
+    /// The frontmost body containing the point where several overlap. Bodies are drawn in the
+    /// order they're stored in, so that's the last one of them.
     pub fn body_at_point(&self, x: f32, y: f32) -> Option<rapier2d::dynamics::RigidBodyHandle> {
         let point = rapier2d::na::Point2::new(x, y);
-        self.physics.colliders.iter().find_map(|(_h, c)| {
-            let c: &rapier2d::geometry::Collider = c;
-            if let Some(true) = self.physics.bodies.get(c.parent()).map(|b| b.is_dynamic()) {
-                let shape = c.shape();
-                let transform = c.position();
-                let clicked = rapier2d::parry::query::point::PointQuery::contains_point(
-                    shape, transform, &point,
-                );
-                if clicked {
-                    Some(c.parent())
-                } else {
-                    None
+        let mut found = None;
+        self.physics.query_pipeline.intersections_with_point(
+            &self.physics.colliders,
+            &point,
+            rapier2d::geometry::InteractionGroups::all(),
+            None,
+            |_handle, collider| {
+                let parent = collider.parent();
+                if self.is_removable(parent) {
+                    found = std::cmp::max_by_key(found, Some(parent), |handle| {
+                        handle.map(|handle| handle.into_raw_parts().0)
+                    });
                 }
-            } else {
-                None
-            }
-        })
+                true
+            },
+        );
+        found
     }
 
     pub fn try_remove_body(
@@ -140,17 +467,20 @@ impl Sim {
                 })
                 .collect::<Vec<_>>()
         });
-        self.physics
+        let removed = self
+            .physics
             .bodies
             .remove(
                 handle,
                 &mut self.physics.colliders,
                 &mut self.physics.joints,
             )
-            .zip(colliders)
+            .zip(colliders);
+        self.physics.bodies_changed();
+        removed
     }
 
-    pub fn add_body(&mut self, collection: PhysicsTuple) {
+    pub fn add_body(&mut self, collection: PhysicsTuple) -> rapier2d::dynamics::RigidBodyHandle {
         let (body, colliders) = collection;
 
         let handle = self.physics.bodies.insert(body);
@@ -159,25 +489,119 @@ impl Sim {
                 .colliders
                 .insert(collider, handle, &mut self.physics.bodies);
         }
+        self.physics.bodies_changed();
+        handle
+    }
+
+    /// An L made of two colliders: a block two high with another beside its bottom half, to the
+    /// right. Centered on the tall part.
+    pub fn l_piece(x: f32, y: f32) -> PhysicsTuple {
+        let rad = BLOCK_SIZE / 2.;
+        let body = rapier2d::dynamics::RigidBodyBuilder::new_dynamic()
+            .translation(x, y)
+            .build();
+        let colliders = vec![
+            rapier2d::geometry::ColliderBuilder::cuboid(rad, rad * 2.).build(),
+            rapier2d::geometry::ColliderBuilder::cuboid(rad, rad)
+                .translation(rad * 2., -rad)
+                .build(),
+        ];
+        (body, colliders)
     }
 
     pub fn kill_triggered(&self) -> bool {
         self.physics.kill_triggered()
     }
+
+    /// Rebuilds the simulation from another client's `snapshot`, taken at `tick`, once this one
+    /// has drifted from it. The client it came from rebuilds from it too since a rebuilt
+    /// simulation doesn't step quite like the original, having forgotten its contacts.
+    pub fn resync(&mut self, snapshot: &shared::layout::Snapshot, tick: u64) {
+        let palette = self.physics.palette;
+        self.physics = physics::PhysicsContext::from_snapshot(0., -9.81 * 0.1, snapshot);
+        self.physics.tick = tick;
+        self.physics.palette = palette;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        if self.physics.palette != palette {
+            self.physics.palette = palette;
+            #[cfg(feature = "render")]
+            {
+                self.physics.debug_mesh_stale = true;
+            }
+        }
+    }
+
+    /// How many fixed steps the simulation has taken. Unaffected by the time scale other than it
+    /// changing how often they happen.
+    pub fn tick(&self) -> u64 {
+        self.physics.tick
+    }
+
+    /// A hash of where every body is. Sims that were built and stepped the same way agree on it
+    /// at the same tick, so clients compare theirs to notice drifting apart.
+    pub fn checksum(&self) -> u64 {
+        // FNV-1a rather than a std hasher, whose output isn't promised to be the same everywhere
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+        let mut hash = OFFSET_BASIS;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes.iter() {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(PRIME);
+            }
+        };
+        for (handle, body) in self.physics.bodies.iter() {
+            let (index, generation) = handle.into_raw_parts();
+            // the index widened so wasm32 and native hash the same bytes
+            write(&(index as u64).to_le_bytes());
+            write(&generation.to_le_bytes());
+            let position = body.position();
+            let values = [
+                position.translation.x,
+                position.translation.y,
+                position.rotation.re,
+                position.rotation.im,
+            ];
+            for value in values.iter() {
+                write(&value.to_bits().to_le_bytes());
+            }
+        }
+        hash
+    }
+
+    /// The first body to fall into the kill sensor. It's been removed from the simulation since.
+    pub fn last_collapse_cause(&self) -> Option<rapier2d::dynamics::RigidBodyHandle> {
+        self.physics.collapse_cause
+    }
+
+    /// How many steps a body has to stay in the kill sensor before it counts as fallen. Bodies
+    /// falling all the way through it count regardless.
+    pub fn set_kill_dwell(&mut self, steps: u32) {
+        self.physics.kill_dwell = steps;
+    }
+
+    /// Takes the events of every step since the last drain.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = SimEvent> + '_ {
+        self.physics.events.drain(..)
+    }
 }
 
 mod physics {
     use crate::RepeatingTimer as Timer;
 
     use rapier2d::dynamics::{
-        CCDSolver, IntegrationParameters, JointSet, RigidBodyBuilder, RigidBodySet,
+        CCDSolver, IntegrationParameters, JointSet, RigidBodyBuilder, RigidBodyHandle, RigidBodySet,
     };
     use rapier2d::geometry::{
-        BroadPhase, ColliderBuilder, ColliderHandle, ColliderSet, ContactEvent, IntersectionEvent,
-        NarrowPhase, TypedShape,
+        BroadPhase, ColliderBuilder, ColliderHandle, ColliderSet, ContactEvent, InteractionGroups,
+        IntersectionEvent, NarrowPhase,
     };
-    use rapier2d::na::{Point2, Vector2};
+    use rapier2d::na::Vector2;
     use rapier2d::pipeline::{ChannelEventCollector, PhysicsPipeline, QueryPipeline};
+    #[cfg(feature = "render")]
+    use solstice_2d::solstice::quad_batch::Quad;
 
     pub struct PhysicsContext {
         pipeline: PhysicsPipeline,
@@ -195,69 +619,326 @@ mod physics {
         pub contact_events: crossbeam_channel::Receiver<ContactEvent>,
         pub intersection_events: crossbeam_channel::Receiver<IntersectionEvent>,
         kill_sensor: ColliderHandle,
+        /// Colliders touching the kill sensor and for how many steps they have been.
+        kill_candidates: std::collections::HashMap<ColliderHandle, u32>,
+        /// Steps a collider has to stay in the kill sensor before it's removed.
+        pub kill_dwell: u32,
+        /// What was built, with the width filled in when it was left to fit the stack.
+        pub platform: super::PlatformConfig,
 
         update_timer: Timer,
+        /// Fixed steps taken so far.
+        pub tick: u64,
         kill_triggered: bool,
+        pub collapse_cause: Option<RigidBodyHandle>,
+        pub events: Vec<super::SimEvent>,
+        pub palette: super::Palette,
+        #[cfg(feature = "render")]
+        pub debug_mesh: DebugMesh,
+        /// Whether the bodies have changed since `debug_mesh` was last rebuilt.
+        #[cfg(feature = "render")]
+        pub(super) debug_mesh_stale: bool,
+        /// Everything is drawn until this is set.
+        #[cfg(feature = "render")]
+        pub view: Option<rapier2d::parry::bounding_volume::AABB>,
+    }
+
+    /// What `debug_render` draws, rebuilt before a frame is drawn if the bodies have changed since
+    /// the last one, so that rendering only borrows it. The buffers are cleared and refilled in
+    /// place.
+    #[cfg(feature = "render")]
+    #[derive(Default)]
+    pub struct DebugMesh {
+        pub vertices: Vec<solstice_2d::Vertex2D>,
+        pub indices: Vec<u32>,
+        pub outlines: Vec<solstice_2d::LineVertex>,
+        /// How many of `vertices` are the simulated bodies'. The held body's quads follow them so
+        /// that everything goes out in one draw.
+        body_vertices: usize,
+        held: Vec<solstice_2d::Vertex2D>,
+    }
+
+    #[cfg(feature = "render")]
+    impl DebugMesh {
+        fn clear(&mut self) {
+            self.vertices.clear();
+            self.indices.clear();
+            self.outlines.clear();
+            self.body_vertices = 0;
+        }
+
+        fn push_quad(&mut self, quad: Quad<solstice_2d::Vertex2D>) {
+            let first = quad.vertices[0].position;
+            let last = quad.vertices[3].position;
+            self.outlines.push(solstice_2d::LineVertex {
+                position: [first[0], first[1], 0.],
+                width: 0.0,
+                color: [0., 0., 0., 0.],
+            });
+            self.outlines
+                .extend(quad.vertices.iter().map(|v| solstice_2d::LineVertex {
+                    position: [v.position[0], v.position[1], 0.],
+                    width: 2.,
+                    color: [0., 0., 0., 1.],
+                }));
+            self.outlines.push(solstice_2d::LineVertex {
+                position: [first[0], first[1], 0.],
+                width: 2.0,
+                color: [0., 0., 0., 1.],
+            });
+            self.outlines.push(solstice_2d::LineVertex {
+                position: [last[0], last[1], 0.],
+                width: 0.0,
+                color: [0., 0., 0., 0.],
+            });
+
+            self.push_fill(&quad.vertices);
+            self.body_vertices = self.vertices.len();
+        }
+
+        fn push_fill(&mut self, quad: &[solstice_2d::Vertex2D]) {
+            let offset = self.vertices.len() as u32;
+            self.indices.extend(
+                solstice_2d::solstice::quad_batch::INDICES
+                    .iter()
+                    .map(|i| *i as u32 + offset),
+            );
+            self.vertices.extend_from_slice(quad);
+        }
+
+        /// Replaces the held body's quads, leaving the rest as they are.
+        pub fn set_held(&mut self, quads: impl Iterator<Item = Quad<solstice_2d::Vertex2D>>) {
+            self.held.clear();
+            for quad in quads {
+                self.held.extend(quad.vertices);
+            }
+            self.vertices.truncate(self.body_vertices);
+            self.indices.truncate(self.body_vertices / 4 * 6);
+            self.push_held();
+        }
+
+        fn push_held(&mut self) {
+            let held = std::mem::take(&mut self.held);
+            for quad in held.chunks(4) {
+                self.push_fill(quad);
+            }
+            self.held = held;
+        }
+    }
+
+    /// `half` extents centered on `position`.
+    #[cfg(feature = "render")]
+    pub fn cuboid_quad(
+        position: &rapier2d::na::Isometry2<f32>,
+        half: Vector2<f32>,
+        color: [f32; 4],
+    ) -> Quad<solstice_2d::Vertex2D> {
+        Quad::<(f32, f32)>::from(solstice_2d::Rectangle::new(
+            -half.x,
+            -half.y,
+            half.x * 2.,
+            half.y * 2.,
+        ))
+        .map(|(x, y)| {
+            let p = rapier2d::na::Point2::new(x, y);
+            let p = position.transform_point(&p);
+            solstice_2d::Vertex2D {
+                position: [p.x, p.y],
+                uv: [x + 0.5, y + 0.5],
+                color,
+            }
+        })
+    }
+
+    /// How far beneath the platform's center the kill sensor is.
+    const KILL_SENSOR_DEPTH: f32 = 0.25;
+
+    /// A quarter of a second, long enough that blocks grazing the kill sensor survive it.
+    pub const KILL_DWELL: u32 = 15;
+
+    fn has_size(block: &shared::layout::Block) -> bool {
+        block.half_width > 0. && block.half_height > 0.
     }
 
-    pub trait GenResult: Iterator<Item = (ColliderBuilder, RigidBodyBuilder)> {}
-    impl<T> GenResult for T where T: Iterator<Item = (ColliderBuilder, RigidBodyBuilder)> {}
+    fn block_collider(block: &shared::layout::Block) -> ColliderBuilder {
+        let groups = InteractionGroups::new(block.groups.memberships, block.groups.filter);
+        ColliderBuilder::cuboid(block.half_width, block.half_height).collision_groups(groups)
+    }
+
+    /// Bodies along with every collider they're built from.
+    pub trait GenResult: Iterator<Item = (Vec<ColliderBuilder>, RigidBodyBuilder)> {}
+    impl<T> GenResult for T where T: Iterator<Item = (Vec<ColliderBuilder>, RigidBodyBuilder)> {}
     pub type Gen<I> = fn(usize, f32, f32) -> I;
 
     impl PhysicsContext {
-        pub fn new(gx: f32, gy: f32, init: Gen<impl GenResult>, num: usize) -> Self {
+        pub fn new(
+            gx: f32,
+            gy: f32,
+            init: Gen<impl GenResult>,
+            num: usize,
+            platform: super::PlatformConfig,
+        ) -> Self {
+            let rad = super::BLOCK_SIZE / 2.;
+            let offset_y = platform.y + platform.thickness / 2.;
+            Self::with_blocks(gx, gy, init(num, rad, offset_y), platform)
+        }
+
+        /// Blocks that share a body are put on the first of them.
+        pub fn from_layout(
+            gx: f32,
+            gy: f32,
+            layout: &shared::layout::TowerLayout,
+            platform: super::PlatformConfig,
+        ) -> Self {
+            let mut bodies: Vec<(Option<u16>, usize, Vec<&shared::layout::Block>)> = Vec::new();
+            for (index, block) in layout.blocks.iter().enumerate() {
+                if !has_size(block) {
+                    continue;
+                }
+                let compound = block
+                    .body
+                    .and_then(|id| bodies.iter_mut().find(|(other, ..)| *other == Some(id)));
+                match compound {
+                    Some((_id, _index, blocks)) => blocks.push(block),
+                    None => bodies.push((block.body, index, vec![block])),
+                }
+            }
+            let blocks = bodies.into_iter().map(|(_id, index, blocks)| {
+                let (first, rest) = blocks.split_first().expect("bodies start with a block");
+                let pose = |block: &shared::layout::Block| {
+                    rapier2d::math::Isometry::new(Vector2::new(block.x, block.y), block.rotation)
+                };
+                let origin = pose(first);
+                let parts = std::iter::once(block_collider(first))
+                    .chain(rest.iter().map(|block| {
+                        let offset = origin.inv_mul(&pose(block));
+                        block_collider(block)
+                            .translation(offset.translation.x, offset.translation.y)
+                            .rotation(offset.rotation.angle())
+                    }))
+                    .collect::<Vec<_>>();
+                // see `Sim::layout_indices`
+                let body = RigidBodyBuilder::new_dynamic()
+                    .translation(first.x, first.y)
+                    .rotation(first.rotation)
+                    .user_data(index as u128);
+                (parts, body)
+            });
+            Self::with_blocks(gx, gy, blocks, platform)
+        }
+
+        /// Bodies that had lost every part are left out.
+        pub fn from_snapshot(gx: f32, gy: f32, snapshot: &shared::layout::Snapshot) -> Self {
+            let blocks = snapshot
+                .bodies
+                .iter()
+                .map(|body| {
+                    let parts = body
+                        .parts
+                        .iter()
+                        .filter(|block| has_size(block))
+                        .map(|block| {
+                            block_collider(block)
+                                .translation(block.x, block.y)
+                                .rotation(block.rotation)
+                        })
+                        .collect::<Vec<_>>();
+                    let body = RigidBodyBuilder::new_dynamic()
+                        .translation(body.x, body.y)
+                        .rotation(body.rotation)
+                        .linvel(body.velocity_x, body.velocity_y)
+                        .angvel(body.angular_velocity);
+                    (parts, body)
+                })
+                .filter(|(parts, _body)| !parts.is_empty());
+            let platform = super::PlatformConfig {
+                width: Some(snapshot.platform.width),
+                thickness: snapshot.platform.thickness,
+                y: snapshot.platform.y,
+            };
+            Self::with_blocks(gx, gy, blocks, platform)
+        }
+
+        fn with_blocks(
+            gx: f32,
+            gy: f32,
+            blocks: impl GenResult,
+            mut platform: super::PlatformConfig,
+        ) -> Self {
             let mut bodies = RigidBodySet::new();
             let mut colliders = ColliderSet::new();
             let joints = JointSet::new();
 
             let kill_sensor = {
-                let ground_thickness = 0.05;
-                let camera_offset = -0.5;
-
-                let rad = 0.025;
-                let offset_y = ground_thickness + camera_offset;
+                let half_thickness = platform.thickness / 2.;
 
+                // measured from block edges rather than centers since wide blocks can stick out
+                // further than the outermost center
                 let mut ground_size = 0f32;
-                let pt = rapier2d::na::Point2::new(0., 0.);
-                for (collider, rigid_body) in init(num, rad, offset_y) {
-                    let rb = rigid_body.build();
-                    let pos = rb.position().transform_point(&pt);
-                    ground_size = ground_size.max(pos.x);
-                    let handle = bodies.insert(rb);
-                    colliders.insert(collider.friction(1.).build(), handle, &mut bodies);
+                for (parts, rigid_body) in blocks {
+                    let handle = bodies.insert(rigid_body.build());
+                    for collider in parts {
+                        let collider = collider.friction(1.).build();
+                        let collider = colliders.insert(collider, handle, &mut bodies);
+                        let aabb = colliders[collider].compute_aabb();
+                        ground_size = ground_size.max(aabb.maxs.x).max(-aabb.mins.x);
+                    }
                 }
+                if let Some(width) = platform.width {
+                    ground_size = width / 2.;
+                }
+                platform.width = Some(ground_size * 2.);
 
-                let collider = ColliderBuilder::cuboid(ground_size + rad, ground_thickness)
+                let collider = ColliderBuilder::cuboid(ground_size, half_thickness)
                     .friction(1.)
                     .build();
                 let body = RigidBodyBuilder::new_static()
-                    .translation(0., camera_offset)
+                    .translation(0., platform.y)
                     .build();
                 let parent_handle = bodies.insert(body);
                 colliders.insert(collider, parent_handle, &mut bodies);
 
                 let kill_sensor = bodies.insert(
                     RigidBodyBuilder::new_static()
-                        .translation(0.0, camera_offset * 1.5)
+                        .translation(0.0, platform.y - KILL_SENSOR_DEPTH)
                         .build(),
                 );
-                let kill_sensor = colliders.insert(
-                    ColliderBuilder::cuboid(4., ground_thickness)
+                colliders.insert(
+                    ColliderBuilder::cuboid(4., half_thickness)
                         .sensor(true)
                         .build(),
                     kill_sensor,
                     &mut bodies,
-                );
-                kill_sensor
+                )
             };
 
+            Self::with_sets(
+                Vector2::new(gx, gy),
+                bodies,
+                colliders,
+                joints,
+                kill_sensor,
+                platform,
+            )
+        }
+
+        /// Everything else that makes up a context starts over, the broad and narrow phases
+        /// included, so contacts are found again on the first step.
+        fn with_sets(
+            gravity: Vector2<f32>,
+            bodies: RigidBodySet,
+            colliders: ColliderSet,
+            joints: JointSet,
+            kill_sensor: ColliderHandle,
+            platform: super::PlatformConfig,
+        ) -> Self {
             let (contact_send, contact_recv) = crossbeam_channel::unbounded();
             let (intersection_send, intersection_recv) = crossbeam_channel::unbounded();
             let event_handler = ChannelEventCollector::new(intersection_send, contact_send);
 
-            Self {
+            let mut ctx = Self {
                 pipeline: PhysicsPipeline::new(),
-                gravity: Vector2::new(gx, gy),
+                gravity,
                 integration_parameters: Default::default(),
                 broad_phase: BroadPhase::new(),
                 narrow_phase: NarrowPhase::new(),
@@ -270,13 +951,96 @@ mod physics {
                 contact_events: contact_recv,
                 intersection_events: intersection_recv,
                 kill_sensor,
+                kill_candidates: Default::default(),
+                kill_dwell: KILL_DWELL,
+                platform,
                 update_timer: Timer::new(std::time::Duration::from_secs_f32(1. / 60.)),
+                tick: 0,
                 kill_triggered: false,
+                collapse_cause: None,
+                events: Vec::new(),
+                palette: Default::default(),
+                #[cfg(feature = "render")]
+                debug_mesh: Default::default(),
+                #[cfg(feature = "render")]
+                debug_mesh_stale: true,
+                #[cfg(feature = "render")]
+                view: None,
+            };
+            ctx.bodies_changed();
+            ctx
+        }
+
+        /// An independent copy to simulate ahead on and throw away. Bodies, colliders and joints
+        /// are cloned one by one into new sets, which is how rapier expects them to be copied, so
+        /// the fork's handles can differ from these and come back mapped. It has its own event
+        /// channels and kill sensor, and any dwell in the sensor starts over.
+        pub fn fork(&self) -> (Self, super::BodyMap) {
+            let mut bodies = RigidBodySet::new();
+            let mut colliders = ColliderSet::new();
+            let mut joints = JointSet::new();
+            let mut body_map = super::BodyMap::new();
+            let mut kill_sensor = None;
+            for (handle, body) in self.bodies.iter() {
+                let fork_handle = bodies.insert(body.clone());
+                body_map.insert(handle, fork_handle);
+                for &collider in body.colliders() {
+                    let fork_collider = colliders.insert(
+                        self.colliders[collider].clone(),
+                        fork_handle,
+                        &mut bodies,
+                    );
+                    if collider == self.kill_sensor {
+                        kill_sensor = Some(fork_collider);
+                    }
+                }
+            }
+            for (_handle, joint) in self.joints.iter() {
+                if let (Some(&body1), Some(&body2)) =
+                    (body_map.get(&joint.body1), body_map.get(&joint.body2))
+                {
+                    joints.insert(&mut bodies, body1, body2, joint.params);
+                }
+            }
+            let kill_sensor = kill_sensor.expect("the kill sensor is attached to a body");
+
+            let mut fork = Self::with_sets(
+                self.gravity,
+                bodies,
+                colliders,
+                joints,
+                kill_sensor,
+                self.platform,
+            );
+            fork.integration_parameters = self.integration_parameters;
+            fork.kill_dwell = self.kill_dwell;
+            fork.palette = self.palette;
+            fork.tick = self.tick;
+            fork.kill_triggered = self.kill_triggered;
+            fork.collapse_cause = self
+                .collapse_cause
+                .and_then(|handle| body_map.get(&handle).copied());
+            (fork, body_map)
+        }
+
+        /// Steps only refresh the colliders that moved, so bodies being added or removed between
+        /// steps need a fresh pipeline built from the whole set.
+        pub fn rebuild_query_pipeline(&mut self) {
+            self.query_pipeline = QueryPipeline::new();
+            self.query_pipeline.update(&self.bodies, &self.colliders);
+        }
+
+        /// For bodies added or removed outside of a step.
+        pub fn bodies_changed(&mut self) {
+            self.rebuild_query_pipeline();
+            #[cfg(feature = "render")]
+            {
+                self.debug_mesh_stale = true;
             }
         }
 
         pub fn special_tower(num: usize, rad: f32, offset_y: f32) -> impl GenResult {
-            type Gen = Box<dyn Fn(usize) -> (ColliderBuilder, RigidBodyBuilder)>;
+            type Gen = Box<dyn Fn(usize) -> (Vec<ColliderBuilder>, RigidBodyBuilder)>;
             (0usize..num).flat_map(move |y| {
                 let yf = y as f32;
                 if y % 2 == 0 {
@@ -291,7 +1055,7 @@ mod physics {
                         let x = (xf * shift) - center_x + x_offset * shift;
                         let y = yf * shift + center_y;
 
-                        let c = ColliderBuilder::cuboid(rad, rad);
+                        let c = vec![ColliderBuilder::cuboid(rad, rad)];
                         let b = RigidBodyBuilder::new_dynamic().translation(x, y);
                         (c, b)
                     }) as Gen)
@@ -308,7 +1072,7 @@ mod physics {
                         let x = (xf * shift * h_factor) - center_x + x_offset * rad * 2.;
                         let y = yf * shift + center_y;
 
-                        let c = ColliderBuilder::cuboid(rad * 2., rad);
+                        let c = vec![ColliderBuilder::cuboid(rad * 2., rad)];
                         let b = RigidBodyBuilder::new_dynamic().translation(x, y);
                         (c, b)
                     }) as Gen)
@@ -321,7 +1085,7 @@ mod physics {
             let center_x = size * ((num - 1) as f32) / 2.0;
             let center_y = size / 2.0 + offset_y;
 
-            let colliders = std::iter::repeat_with(move || ColliderBuilder::cuboid(rad, rad));
+            let colliders = std::iter::repeat_with(move || vec![ColliderBuilder::cuboid(rad, rad)]);
             let bodies = (0usize..num).flat_map(move |y| {
                 let x_count = if y % 2 == 0 { 3 } else { 4 };
                 let yf = y as f32;
@@ -343,7 +1107,7 @@ mod physics {
             let center_x = shift * ((num - 1) as f32) / 2.0;
             let center_y = shift / 2.0 + offset_y;
 
-            let colliders = std::iter::repeat_with(move || ColliderBuilder::cuboid(rad, rad));
+            let colliders = std::iter::repeat_with(move || vec![ColliderBuilder::cuboid(rad, rad)]);
             let bodies = (0usize..num).flat_map(move |y| {
                 let x_count = if y % 2 == 0 { num / 2 } else { num / 2 - 1 };
                 let yf = y as f32;
@@ -365,7 +1129,7 @@ mod physics {
             let center_x = shift * ((num - 1) as f32) / 2.0;
             let center_y = shift / 2.0 + offset_y;
 
-            let colliders = std::iter::repeat_with(move || ColliderBuilder::cuboid(rad, rad));
+            let colliders = std::iter::repeat_with(move || vec![ColliderBuilder::cuboid(rad, rad)]);
             let bodies = (0usize..num).flat_map(move |i| {
                 (i..num).map(move |j| {
                     let fj = j as f32;
@@ -394,160 +1158,976 @@ mod physics {
                     &self.event_handler,
                 );
                 self.query_pipeline.update(&self.bodies, &self.colliders);
+                self.tick += 1;
 
-                while let Ok(intersection_event) = self.intersection_events.try_recv() {
-                    if intersection_event.collider1 == self.kill_sensor {
-                        if let Some(other) = self.colliders.get(intersection_event.collider2) {
-                            self.kill_triggered = true;
-                            self.bodies.remove(
-                                other.parent(),
-                                &mut self.colliders,
-                                &mut self.joints,
-                            );
+                let was_killed = self.kill_triggered;
+                let sensor_y = self.colliders[self.kill_sensor].position().translation.y;
+                let mut killed = Vec::new();
+                while let Ok(event) = self.intersection_events.try_recv() {
+                    let other = if event.collider1 == self.kill_sensor {
+                        event.collider2
+                    } else if event.collider2 == self.kill_sensor {
+                        event.collider1
+                    } else {
+                        continue;
+                    };
+                    if event.intersecting {
+                        self.kill_candidates.insert(other, 0);
+                    } else if self.kill_candidates.remove(&other).is_some() {
+                        // falling all the way through counts, only grazing the top doesn't
+                        let below = self
+                            .colliders
+                            .get(other)
+                            .is_some_and(|c| c.position().translation.y < sensor_y);
+                        if below {
+                            killed.push(other);
                         }
                     }
+                }
+                let kill_dwell = self.kill_dwell;
+                self.kill_candidates.retain(|&handle, ticks| {
+                    *ticks += 1;
+                    if *ticks >= kill_dwell {
+                        killed.push(handle);
+                        false
+                    } else {
+                        true
+                    }
+                });
 
-                    if intersection_event.collider2 == self.kill_sensor {
-                        if let Some(other) = self.colliders.get(intersection_event.collider1) {
-                            self.kill_triggered = true;
-                            self.bodies.remove(
-                                other.parent(),
-                                &mut self.colliders,
-                                &mut self.joints,
-                            );
-                        }
+                // in handle order rather than the map's, which differs between clients, so
+                // everyone removes bodies and blames the collapse on them the same way
+                killed.sort_by_key(|handle| handle.into_raw_parts());
+                let mut removed = false;
+                for handle in killed {
+                    if let Some(other) = self.colliders.get(handle) {
+                        self.kill_triggered = true;
+                        self.collapse_cause.get_or_insert(other.parent());
+                        removed = true;
+                        self.bodies
+                            .remove(other.parent(), &mut self.colliders, &mut self.joints);
                     }
                 }
 
-                while let Ok(_contact_event) = self.contact_events.try_recv() {
-                    // println!("{:?}", contact_event);
+                if removed {
+                    self.rebuild_query_pipeline();
+                }
+                #[cfg(feature = "render")]
+                {
+                    self.debug_mesh_stale = true;
+                }
+                if self.kill_triggered && !was_killed {
+                    self.events.push(super::SimEvent::Collapse);
+                }
+
+                let mut contact = false;
+                while let Ok(contact_event) = self.contact_events.try_recv() {
+                    contact |= matches!(contact_event, ContactEvent::Started(..));
+                }
+                if contact {
+                    self.events.push(super::SimEvent::Contact);
                 }
             }
         }
 
+        /// Exactly one fixed step.
+        pub fn step_tick(&mut self) {
+            self.step(self.update_timer.time);
+        }
+
         pub fn kill_triggered(&self) -> bool {
             self.kill_triggered
         }
 
-        pub fn debug_render(&self, g: &mut solstice_2d::GraphicsLock) {
-            use solstice_2d::Draw;
+        #[cfg(feature = "render")]
+        pub fn refresh_debug_mesh(&mut self) {
+            if std::mem::take(&mut self.debug_mesh_stale) {
+                self.rebuild_debug_mesh();
+            }
+        }
 
-            const AWAKE_BODY_COLOR: [f32; 4] = [0., 0.8, 0., 1.];
-            const ASLEEP_BODY_COLOR: [f32; 4] = [0., 0., 0.8, 1.];
-            const STATIC_BODY_COLOR: [f32; 4] = [133. / 255., 87. / 255., 35. / 255., 1.];
+        #[cfg(feature = "render")]
+        fn rebuild_debug_mesh(&mut self) {
+            use rapier2d::parry::bounding_volume::BoundingVolume;
 
-            let mut rects = Vec::with_capacity(self.bodies.len());
+            const STATIC_BODY_COLOR: [f32; 4] = [133. / 255., 87. / 255., 35. / 255., 1.];
 
+            let mesh = &mut self.debug_mesh;
+            mesh.clear();
             for (_body_handle, body) in self.bodies.iter() {
                 let position = body.position();
+                let color = if body.is_static() {
+                    STATIC_BODY_COLOR
+                } else if body.is_sleeping() {
+                    self.palette.asleep
+                } else {
+                    self.palette.awake
+                };
                 for collider_handle in body.colliders() {
-                    if let Some(collider) = self.colliders.get(*collider_handle) {
-                        match collider.shape().as_typed_shape() {
-                            TypedShape::Ball(_) => {}
-                            TypedShape::Cuboid(shape) => {
-                                let half = shape.half_extents;
-                                let color = if body.is_static() {
-                                    STATIC_BODY_COLOR
-                                } else if body.is_sleeping() {
-                                    ASLEEP_BODY_COLOR
-                                } else {
-                                    AWAKE_BODY_COLOR
-                                };
-                                let quad =
-                                    solstice_2d::solstice::quad_batch::Quad::<(f32, f32)>::from(
-                                        solstice_2d::Rectangle::new(
-                                            -half.x,
-                                            -half.y,
-                                            half.x * 2.,
-                                            half.y * 2.,
-                                        ),
-                                    )
-                                    .map(|(x, y)| {
-                                        let p = Point2::new(x, y);
-                                        let p = position.transform_point(&p);
-                                        solstice_2d::Vertex2D {
-                                            position: [p.x, p.y],
-                                            uv: [x + 0.5, y + 0.5],
-                                            color,
-                                        }
-                                    });
-                                rects.push(quad);
-                            }
-                            TypedShape::Capsule(_) => {}
-                            TypedShape::Segment(_) => {}
-                            TypedShape::Triangle(_) => {}
-                            TypedShape::TriMesh(_) => {}
-                            TypedShape::Polyline(_) => {}
-                            TypedShape::HalfSpace(_) => {}
-                            TypedShape::HeightField(_) => {}
-                            TypedShape::Compound(_) => {}
-                            TypedShape::ConvexPolygon(_) => {}
-                            TypedShape::RoundCuboid(_) => {}
-                            TypedShape::RoundTriangle(_) => {}
-                            TypedShape::RoundConvexPolygon(_) => {}
-                            TypedShape::Custom(_) => {}
+                    let collider = match self.colliders.get(*collider_handle) {
+                        Some(collider) => collider,
+                        None => continue,
+                    };
+                    if let Some(view) = &self.view {
+                        if !view.intersects(&collider.compute_aabb()) {
+                            continue;
                         }
                     }
+                    if let Some(shape) = collider.shape().as_cuboid() {
+                        let position = position * collider.position_wrt_parent();
+                        mesh.push_quad(cuboid_quad(&position, shape.half_extents, color));
+                    }
+                }
+            }
+            mesh.push_held();
+        }
+
+        #[cfg(feature = "render")]
+        pub fn debug_render<'s>(&'s self, g: &mut solstice_2d::GraphicsLock<'_, 's>) {
+            use solstice_2d::Draw;
+
+            let mesh = &self.debug_mesh;
+            g.draw(solstice_2d::Geometry::new(
+                &mesh.vertices[..],
+                Some(&mesh.indices[..]),
+            ));
+            g.line_2d(&mesh.outlines[..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn screen_to_world_tracks_viewport() {
+        for &(width, height) in &[(1280, 720), (1920, 1200), (2560, 1080)] {
+            let vw = Viewport::new(0, 0, width, height);
+            let (left, right, top, bottom) = match Sim::projection(&vw) {
+                solstice_2d::Projection::Orthographic(Some(o)) => {
+                    (o.left, o.right, o.top, o.bottom)
                 }
+                _ => unreachable!(),
+            };
+
+            let [x, y] = Sim::screen_to_world(&vw, 0., 0.);
+            assert!((x - left).abs() < f32::EPSILON);
+            assert!((y - top).abs() < f32::EPSILON);
+
+            let [x, y] = Sim::screen_to_world(&vw, width as _, height as _);
+            assert!((x - right).abs() < f32::EPSILON);
+            assert!((y - bottom).abs() < f32::EPSILON);
+        }
+    }
+
+    #[test]
+    fn block_off_the_edge_collapses_headlessly() {
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        let mut sim = Sim::tower();
+        for _ in 0..60 {
+            sim.step(dt);
+        }
+        assert!(!sim.kill_triggered());
+
+        // well past the edge of the ground but still over the kill sensor
+        let body = rapier2d::dynamics::RigidBodyBuilder::new_dynamic()
+            .translation(1., 0.)
+            .build();
+        let collider = rapier2d::geometry::ColliderBuilder::cuboid(0.025, 0.025).build();
+        let handle = sim.add_body((body, vec![collider]));
+
+        for _ in 0..600 {
+            sim.step(dt);
+            if sim.kill_triggered() {
+                break;
             }
+        }
+        assert!(sim.kill_triggered());
+        assert!(sim.drain_events().any(|event| event == SimEvent::Collapse));
+        assert_eq!(sim.last_collapse_cause(), Some(handle));
+    }
 
-            let outlines = rects
+    #[test]
+    fn simultaneous_kills_are_blamed_the_same_way_every_time() {
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        for _ in 0..8 {
+            let mut sim = Sim::tower();
+            // hanging in the kill sensor side by side, so they've all dwelt there long enough on
+            // the same step
+            let handles = [0.9, 1., 1.1]
                 .iter()
-                .flat_map(|quad| {
-                    std::iter::once(solstice_2d::LineVertex {
-                        position: [
-                            quad.vertices[0].position[0],
-                            quad.vertices[0].position[1],
-                            0.,
-                        ],
-                        width: 0.0,
-                        color: [0., 0., 0., 0.],
-                    })
-                    .chain(std::array::IntoIter::new(quad.vertices).map(move |v| {
-                        solstice_2d::LineVertex {
-                            position: [v.position[0], v.position[1], 0.],
-                            width: 2.,
-                            color: [0., 0., 0., 1.],
-                        }
-                    }))
-                    .chain(std::array::IntoIter::new([
-                        solstice_2d::LineVertex {
-                            position: [
-                                quad.vertices[0].position[0],
-                                quad.vertices[0].position[1],
-                                0.,
-                            ],
-                            width: 2.0,
-                            color: [0., 0., 0., 1.],
-                        },
-                        solstice_2d::LineVertex {
-                            position: [
-                                quad.vertices[3].position[0],
-                                quad.vertices[3].position[1],
-                                0.,
-                            ],
-                            width: 0.0,
-                            color: [0., 0., 0., 0.],
-                        },
-                    ]))
+                .map(|&x| {
+                    let body = rapier2d::dynamics::RigidBodyBuilder::new_dynamic()
+                        .translation(x, -0.71)
+                        .gravity_scale(0.)
+                        .build();
+                    let collider =
+                        rapier2d::geometry::ColliderBuilder::cuboid(0.025, 0.025).build();
+                    sim.add_body((body, vec![collider]))
                 })
                 .collect::<Vec<_>>();
 
-            let indices = rects
+            for _ in 0..600 {
+                sim.step(dt);
+                if sim.kill_triggered() {
+                    break;
+                }
+            }
+            assert_eq!(sim.last_collapse_cause(), Some(handles[0]));
+        }
+    }
+
+    #[test]
+    fn time_scale_scales_simulation_progress() {
+        let dt = std::time::Duration::from_millis(10);
+        let falling = || {
+            let mut sim = Sim::tower();
+            let body = rapier2d::dynamics::RigidBodyBuilder::new_dynamic()
+                .translation(1., 0.)
+                .build();
+            let collider = rapier2d::geometry::ColliderBuilder::cuboid(0.025, 0.025).build();
+            sim.add_body((body, vec![collider]));
+            sim
+        };
+        let height = |sim: &Sim| {
+            sim.physics
+                .bodies
                 .iter()
-                .enumerate()
-                .flat_map(|(index, _)| {
-                    let offset = index as u32 * 4;
-                    std::array::IntoIter::new(solstice_2d::solstice::quad_batch::INDICES)
-                        .map(move |i| i as u32 + offset)
-                })
-                .collect::<Vec<_>>();
-            let vertices = rects
-                .into_iter()
-                .flat_map(|quad| std::array::IntoIter::new(quad.vertices))
+                .map(|(_h, body)| body.position().translation)
+                .find(|translation| translation.x > 0.9)
+                .unwrap()
+                .y
+        };
+
+        let (mut half, mut full, mut double, mut paused) =
+            (falling(), falling(), falling(), falling());
+        half.set_time_scale(0.5);
+        double.set_time_scale(2.);
+        paused.set_time_scale(0.);
+        paused.set_time_scale(-1.);
+        // would overflow `Duration`, or take a million steps a frame, if it weren't capped
+        let mut capped = falling();
+        capped.set_time_scale(1e30);
+        for _ in 0..50 {
+            half.step(dt);
+            double.step(dt);
+            paused.step(dt);
+            capped.step(dt);
+        }
+        let start = height(&full);
+        assert_eq!(height(&paused), start);
+
+        for _ in 0..25 {
+            full.step(dt);
+        }
+        assert_eq!(height(&half), height(&full));
+        for _ in 25..100 {
+            full.step(dt);
+        }
+        assert_eq!(height(&double), height(&full));
+        assert_eq!(height(&capped), height(&full));
+        assert!(height(&full) < height(&half));
+    }
+
+    #[test]
+    fn grazing_the_kill_sensor_is_forgiven() {
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        // the kill sensor's top edge is at -0.7
+        let block = |y: f32, vy: f32, gravity_scale: f32| {
+            let mut sim = Sim::tower();
+            let body = rapier2d::dynamics::RigidBodyBuilder::new_dynamic()
+                .translation(1., y)
+                .linvel(0., vy)
+                .gravity_scale(gravity_scale)
+                .build();
+            let collider = rapier2d::geometry::ColliderBuilder::cuboid(0.025, 0.025).build();
+            sim.add_body((body, vec![collider]));
+            sim
+        };
+        let run = |mut sim: Sim| {
+            for _ in 0..120 {
+                sim.step(dt);
+            }
+            sim.kill_triggered()
+        };
+
+        // dips in for a few steps then gets pushed back out the top
+        assert!(!run(block(-0.66, -1., -10.)));
+        // sits in it
+        assert!(run(block(-0.75, 0., 0.)));
+    }
+
+    #[test]
+    fn narrow_platforms_are_less_stable() {
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        let settle = |mut sim: Sim| {
+            for _ in 0..180 {
+                sim.step(dt);
+            }
+            sim
+        };
+        let count = |sim: &Sim| {
+            sim.physics
+                .bodies
+                .iter()
+                .filter(|(_h, b)| b.is_dynamic())
+                .count()
+        };
+
+        let fitted = settle(Sim::tower());
+        let narrow = settle(Sim::tower_on(PlatformConfig {
+            width: Some(0.05),
+            ..Default::default()
+        }));
+        assert!(!fitted.kill_triggered());
+        assert!(narrow.kill_triggered());
+        assert!(count(&narrow) < count(&fitted));
+    }
+
+    #[test]
+    fn towers_settle_within_the_step_cap() {
+        let mut tower = Sim::tower();
+        tower.set_time_scale(0.);
+        assert!(tower.settle(60 * 10));
+        assert!(tower.all_sleeping());
+        assert!(tower.tick() < 60 * 10);
+        assert!(!tower.kill_triggered());
+
+        // spinning in midair with nothing to slow it down
+        let mut unstable = Sim::tower();
+        let body = rapier2d::dynamics::RigidBodyBuilder::new_dynamic()
+            .translation(1., 0.5)
+            .angvel(5.)
+            .gravity_scale(0.)
+            .build();
+        let collider = rapier2d::geometry::ColliderBuilder::cuboid(0.025, 0.025).build();
+        unstable.add_body((body, vec![collider]));
+        assert!(!unstable.settle(60 * 10));
+        assert_eq!(unstable.tick(), 60 * 10);
+    }
+
+    #[test]
+    fn only_blocks_are_removable() {
+        let mut sim = Sim::tower();
+        let removable = sim.removable_bodies();
+        let (blocks, fixed): (Vec<_>, Vec<_>) = sim
+            .physics
+            .bodies
+            .iter()
+            .partition(|(_handle, body)| body.is_dynamic());
+        assert_eq!(fixed.len(), 2, "the ground and the kill sensor");
+        assert!(fixed
+            .iter()
+            .all(|(handle, _body)| !removable.contains(handle)));
+        assert_eq!(removable.len(), blocks.len());
+        assert!(blocks
+            .iter()
+            .all(|(handle, _body)| removable.contains(handle)));
+
+        let held = removable[0];
+        sim.try_remove_body(held);
+        assert!(!sim.removable_bodies().contains(&held));
+        assert_eq!(sim.removable_bodies().len(), removable.len() - 1);
+    }
+
+    #[test]
+    fn layouts_round_trip() {
+        let sim = Sim::tower();
+        let layout = sim.current_layout();
+        assert_eq!(layout.blocks.len(), 12 / 2 * 12 - 12 / 2);
+
+        let rebuilt = Sim::from_layout(&layout);
+        assert_eq!(rebuilt.current_layout(), layout);
+        assert_eq!(
+            rebuilt.body_at_point(0.025, -0.425),
+            sim.body_at_point(0.025, -0.425)
+        );
+    }
+
+    #[test]
+    fn bodies_know_their_place_in_the_layout() {
+        use shared::layout::{Block, TowerLayout};
+
+        let block = |x, half_size| Block {
+            x,
+            y: -0.425,
+            half_width: half_size,
+            half_height: half_size,
+            rotation: 0.,
+            groups: Default::default(),
+            body: None,
+        };
+        // the empty block isn't built, so the others' handles are off by one from the layout
+        let sim = Sim::from_layout(&TowerLayout {
+            blocks: vec![block(0.5, 0.), block(0.025, 0.025), block(-0.025, 0.025)],
+        });
+        let indices = sim.layout_indices();
+        assert_eq!(indices.len(), 2);
+        let at = |x| indices[&sim.body_at_point(x, -0.425).unwrap()];
+        assert_eq!(at(0.025), 1);
+        assert_eq!(at(-0.025), 2);
+    }
+
+    #[test]
+    fn unknown_room_types_are_ignored() {
+        assert_eq!(
+            room_type(0).map(|room_type| room_type.name),
+            Some("standard")
+        );
+        assert!(room_type(ROOM_TYPES.len() as u16).is_none());
+        assert!(room_type(u16::MAX).is_none());
+    }
+
+    #[test]
+    fn positions_snap_to_the_nearest_grid_point() {
+        let close = |[ax, ay]: [f32; 2], [bx, by]: [f32; 2]| {
+            (ax - bx).abs() < 1e-6 && (ay - by).abs() < 1e-6
+        };
+        assert!(close(
+            snap_to_grid([0.03, -0.41], BLOCK_SIZE),
+            [0.025, -0.425]
+        ));
+        assert!(close(
+            snap_to_grid([-0.02, -0.37], BLOCK_SIZE),
+            [-0.025, -0.375]
+        ));
+        assert!(close(snap_to_grid([0.03, -0.41], 0.1), [0.05, -0.45]));
+    }
+
+    #[test]
+    fn blocks_in_separate_groups_pass_through_each_other() {
+        use shared::layout::{Block, CollisionGroups, TowerLayout};
+
+        // how far apart two blocks that start out mostly overlapping end up
+        let separation = |a: CollisionGroups, b: CollisionGroups| {
+            let block = |x, groups| Block {
+                x,
+                y: -0.425,
+                half_width: 0.025,
+                half_height: 0.025,
+                rotation: 0.,
+                groups,
+                body: None,
+            };
+            let mut sim = Sim::from_layout(&TowerLayout {
+                blocks: vec![block(0., a), block(0.01, b)],
+            });
+            for _ in 0..30 {
+                sim.step(std::time::Duration::from_secs_f32(1. / 60.));
+            }
+            let layout = sim.current_layout();
+            assert_eq!(layout.blocks[1].groups, b);
+            let (a, b) = (&layout.blocks[0], &layout.blocks[1]);
+            ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt()
+        };
+
+        let ghost = CollisionGroups {
+            memberships: 0b10,
+            filter: 0b10,
+        };
+        let solid = CollisionGroups {
+            memberships: 0b01,
+            filter: 0b01,
+        };
+        assert!((separation(solid, ghost) - 0.01).abs() < 1e-4);
+        assert!(separation(Default::default(), Default::default()) > 0.04);
+    }
+
+    #[test]
+    fn identical_sims_agree_on_checksums() {
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        let (mut a, mut b, mut perturbed) = (Sim::tower(), Sim::tower(), Sim::tower());
+        let (_handle, body) = perturbed
+            .physics
+            .bodies
+            .iter_mut()
+            .find(|(_handle, body)| body.is_dynamic())
+            .unwrap();
+        body.set_linvel(rapier2d::na::Vector2::new(0.01, 0.), true);
+
+        for _ in 0..30 {
+            a.step(dt);
+            b.step(dt);
+            perturbed.step(dt);
+        }
+        assert_eq!(a.tick(), b.tick());
+        assert_eq!(a.tick(), perturbed.tick());
+        assert_eq!(a.checksum(), b.checksum());
+        assert_ne!(a.checksum(), perturbed.checksum());
+    }
+
+    #[test]
+    fn forks_leave_the_original_alone() {
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        let (mut sim, mut control) = (Sim::tower(), Sim::tower());
+        for _ in 0..30 {
+            sim.step(dt);
+            control.step(dt);
+        }
+
+        let (mut fork, bodies) = sim.fork();
+        assert_eq!(fork.tick(), sim.tick());
+        assert_eq!(fork.checksum(), sim.checksum());
+
+        let (bottom, _body) = sim
+            .physics
+            .bodies
+            .iter()
+            .filter(|(_handle, body)| body.is_dynamic())
+            .min_by(|(_a, a), (_b, b)| {
+                let (a, b) = (a.position().translation.y, b.position().translation.y);
+                a.partial_cmp(&b).unwrap()
+            })
+            .unwrap();
+        assert!(fork.try_remove_body(bodies[&bottom]).is_some());
+        for _ in 0..60 {
+            sim.step(dt);
+            control.step(dt);
+            fork.step(dt);
+        }
+
+        assert!(sim.physics.bodies.get(bottom).is_some());
+        assert_eq!(sim.checksum(), control.checksum());
+        assert_ne!(fork.checksum(), sim.checksum());
+    }
+
+    #[test]
+    fn resyncing_restores_agreement() {
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        let falling = || {
+            let mut sim = Sim::tower();
+            let (mut body, colliders) = Sim::l_piece(1., 0.3);
+            body.set_angvel(2., true);
+            sim.add_body((body, colliders));
+            sim
+        };
+        let compound = |sim: &Sim| {
+            let bodies = sim.physics.bodies.iter();
+            let mut compound = bodies.filter(|(_handle, body)| body.colliders().len() > 1);
+            let (_handle, body) = compound.next().expect("the L piece");
+            assert!(compound.next().is_none(), "the L piece in one piece");
+            (*body.linvel(), body.angvel())
+        };
+
+        let (mut authority, mut diverged) = (falling(), falling());
+        for _ in 0..20 {
+            authority.step(dt);
+            diverged.step(dt);
+        }
+        let (_handle, body) = diverged
+            .physics
+            .bodies
+            .iter_mut()
+            .find(|(_handle, body)| body.is_dynamic())
+            .unwrap();
+        body.set_linvel(rapier2d::na::Vector2::new(0.01, 0.), true);
+        diverged.step(dt);
+        authority.step(dt);
+        assert_ne!(authority.checksum(), diverged.checksum());
+
+        // the authority applies its own snapshot along with everyone else
+        let (snapshot, tick) = (authority.snapshot(), authority.tick());
+        let (velocity, angular_velocity) = compound(&authority);
+        assert!(velocity.y < 0.);
+        let bodies = authority.physics.bodies.len();
+        authority.resync(&snapshot, tick);
+        diverged.resync(&snapshot, tick);
+        assert_eq!(authority.checksum(), diverged.checksum());
+        // still falling, and still on the same platform
+        assert_eq!(diverged.physics.bodies.len(), bodies);
+        assert_eq!(compound(&diverged), (velocity, angular_velocity));
+        assert_eq!(diverged.snapshot().platform, snapshot.platform);
+
+        for _ in 0..30 {
+            authority.step(dt);
+            diverged.step(dt);
+        }
+        assert_eq!(authority.tick(), diverged.tick());
+        assert_eq!(authority.checksum(), diverged.checksum());
+    }
+
+    #[test]
+    fn compound_bodies_are_clickable_on_every_part() {
+        let mut sim = Sim::tower();
+        #[cfg(feature = "render")]
+        let before = {
+            sim.prepare_render();
+            sim.physics.debug_mesh.vertices.len()
+        };
+        let handle = sim.add_body(Sim::l_piece(1., 0.));
+
+        assert_eq!(sim.body_at_point(1., 0.04), Some(handle));
+        assert_eq!(sim.body_at_point(1.05, -0.025), Some(handle));
+        assert_eq!(sim.body_at_point(1.05, 0.025), None);
+
+        #[cfg(feature = "render")]
+        {
+            sim.prepare_render();
+            let mesh = &sim.physics.debug_mesh;
+            assert_eq!(mesh.vertices.len(), before + 8);
+            let rightmost = mesh.vertices[before..]
+                .iter()
+                .map(|v| v.position[0])
+                .fold(f32::MIN, f32::max);
+            assert!((rightmost - 1.075).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn compound_bodies_survive_a_layout_round_trip() {
+        let mut sim = Sim::tower();
+        let handle = sim.add_body(Sim::l_piece(1., 0.));
+        sim.physics.bodies[handle].set_position(
+            rapier2d::math::Isometry::new(rapier2d::na::Vector2::new(1., 0.), 0.5),
+            true,
+        );
+        sim.step(std::time::Duration::from_secs_f32(1. / 60.));
+
+        let layout = sim.current_layout();
+        let compound = layout
+            .blocks
+            .iter()
+            .filter(|block| block.body.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(compound.len(), 2);
+        assert_eq!(compound[0].body, compound[1].body);
+
+        let rebuilt = Sim::from_layout(&layout);
+        let parts = |sim: &Sim| {
+            let mut parts = sim
+                .physics
+                .bodies
+                .iter()
+                .filter(|(_h, body)| body.is_dynamic())
+                .map(|(_h, body)| body.colliders().len())
                 .collect::<Vec<_>>();
-            g.draw(solstice_2d::Geometry::new(vertices, Some(indices)));
-            g.line_2d(outlines);
+            parts.sort_unstable();
+            parts
+        };
+        assert_eq!(parts(&rebuilt), parts(&sim));
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        for (a, b) in rebuilt
+            .current_layout()
+            .blocks
+            .iter()
+            .zip(layout.blocks.iter())
+        {
+            assert!(close(a.x, b.x) && close(a.y, b.y) && close(a.rotation, b.rotation));
+            assert_eq!(a.body, b.body);
+        }
+    }
+
+    #[test]
+    fn the_frontmost_of_overlapping_bodies_is_clicked() {
+        let mut sim = Sim::tower();
+        let block = |x: f32| {
+            let body = rapier2d::dynamics::RigidBodyBuilder::new_dynamic()
+                .translation(x, 0.)
+                .build();
+            let collider = rapier2d::geometry::ColliderBuilder::cuboid(0.025, 0.025).build();
+            (body, vec![collider])
+        };
+        let back = sim.add_body(block(1.));
+        let front = sim.add_body(block(1.02));
+
+        assert_eq!(sim.body_at_point(1.01, 0.), Some(front));
+        assert_eq!(sim.body_at_point(0.98, 0.), Some(back));
+        assert_eq!(sim.body_at_point(1.04, 0.), Some(front));
+    }
+
+    /// What `body_at_point` does, without the query pipeline.
+    fn body_at_point_linear(
+        sim: &Sim,
+        x: f32,
+        y: f32,
+    ) -> Option<rapier2d::dynamics::RigidBodyHandle> {
+        let point = rapier2d::na::Point2::new(x, y);
+        sim.physics
+            .colliders
+            .iter()
+            .filter_map(|(_h, c)| {
+                let dynamic = sim.physics.bodies.get(c.parent()).map(|b| b.is_dynamic());
+                let clicked = rapier2d::parry::query::point::PointQuery::contains_point(
+                    c.shape(),
+                    c.position(),
+                    &point,
+                );
+                if dynamic == Some(true) && clicked {
+                    Some(c.parent())
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|handle| handle.into_raw_parts().0)
+    }
+
+    #[test]
+    fn body_at_point_matches_linear_scan() {
+        let points = (0..40)
+            .flat_map(|x| {
+                (0..40).map(move |y| (x as f32 / 39. * 0.8 - 0.4, y as f32 / 39. * 0.8 - 0.5))
+            })
+            .collect::<Vec<_>>();
+        let check = |sim: &Sim| {
+            let mut hits = 0;
+            for &(x, y) in points.iter() {
+                let expected = body_at_point_linear(sim, x, y);
+                assert_eq!(sim.body_at_point(x, y), expected, "at ({}, {})", x, y);
+                hits += expected.is_some() as usize;
+            }
+            hits
+        };
+
+        let mut sim = Sim::tower();
+        // also covers the ground, which is static and so never returned
+        assert!(check(&sim) > 0);
+
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        for _ in 0..30 {
+            sim.step(dt);
+        }
+        check(&sim);
+
+        let handle = sim
+            .body_at_point(0.025, -0.425)
+            .expect("the bottom of the tower");
+        let removed = sim.try_remove_body(handle).unwrap();
+        assert_eq!(sim.body_at_point(0.025, -0.425), None);
+        check(&sim);
+
+        sim.add_body(removed);
+        assert!(sim.body_at_point(0.025, -0.425).is_some());
+        check(&sim);
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn debug_mesh_reuses_its_buffers() {
+        let mut sim = Sim::tower();
+        sim.prepare_render();
+        let quads = sim.physics.colliders.len();
+        let mesh = &sim.physics.debug_mesh;
+        assert_eq!(mesh.vertices.len(), quads * 4);
+        assert_eq!(mesh.indices.len(), quads * 6);
+        let buffers = (
+            mesh.vertices.as_ptr(),
+            mesh.indices.as_ptr(),
+            mesh.outlines.as_ptr(),
+        );
+
+        let dt = std::time::Duration::from_secs_f32(1. / 60.);
+        for _ in 0..120 {
+            sim.step(dt);
+        }
+        let handle = sim.body_at_point(0.025, -0.425).unwrap();
+        let removed = sim.try_remove_body(handle).unwrap();
+        sim.add_body(removed);
+        sim.prepare_render();
+
+        let mesh = &sim.physics.debug_mesh;
+        assert_eq!(mesh.vertices.len(), sim.physics.colliders.len() * 4);
+        assert_eq!(
+            buffers,
+            (
+                mesh.vertices.as_ptr(),
+                mesh.indices.as_ptr(),
+                mesh.outlines.as_ptr()
+            )
+        );
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn debug_mesh_is_only_rebuilt_for_drawing() {
+        let mut sim = Sim::tower();
+        sim.prepare_render();
+        let drawn = sim.physics.debug_mesh.vertices.clone();
+
+        // however many steps are taken, the mesh waits for the next frame to be drawn
+        for _ in 0..120 {
+            sim.step(std::time::Duration::from_secs_f32(1. / 60.));
+        }
+        assert_eq!(sim.physics.debug_mesh.vertices, drawn);
+        sim.prepare_render();
+        assert_ne!(sim.physics.debug_mesh.vertices, drawn);
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn bodies_out_of_view_are_culled() {
+        let mut sim = Sim::tower();
+        let body = rapier2d::dynamics::RigidBodyBuilder::new_dynamic()
+            .translation(5., 0.)
+            .build();
+        let collider = rapier2d::geometry::ColliderBuilder::cuboid(0.025, 0.025).build();
+        sim.add_body((body, vec![collider]));
+        sim.prepare_render();
+        let quads = sim.physics.colliders.len();
+        assert_eq!(sim.physics.debug_mesh.vertices.len(), quads * 4);
+
+        sim.set_view(Sim::view_bounds(&Viewport::new(0, 0, 1280, 720)));
+        sim.prepare_render();
+        let mesh = &sim.physics.debug_mesh;
+        // the new body and the kill sensor, which sits below the bottom of the screen
+        assert_eq!(mesh.vertices.len(), (quads - 2) * 4);
+        assert_eq!(mesh.indices.len(), (quads - 2) * 6);
+        assert!(mesh.vertices.iter().all(|v| v.position[0] < 1.));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn bodies_are_drawn_in_the_palette() {
+        let mut sim = Sim::tower();
+        let colors = |sim: &Sim| {
+            sim.physics
+                .debug_mesh
+                .vertices
+                .iter()
+                .map(|v| v.color)
+                .collect::<Vec<_>>()
+        };
+        // nothing has settled before the first step
+        sim.prepare_render();
+        assert!(colors(&sim).contains(&Palette::DEFAULT.awake));
+
+        sim.set_palette(Palette::COLORBLIND);
+        sim.prepare_render();
+        assert!(colors(&sim).contains(&Palette::COLORBLIND.awake));
+        assert!(!colors(&sim).contains(&Palette::DEFAULT.awake));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn held_body_is_batched_with_the_rest() {
+        let mut sim = Sim::tower();
+        let handle = sim.body_at_point(0.025, -0.425).unwrap();
+        let held = sim.try_remove_body(handle).unwrap();
+        sim.prepare_render();
+        let bodies = sim.physics.debug_mesh.vertices.len();
+
+        sim.set_held(Some(&held));
+        let held_quads = |sim: &Sim| {
+            let mesh = &sim.physics.debug_mesh;
+            assert_eq!(mesh.indices.len(), mesh.vertices.len() / 4 * 6);
+            mesh.vertices[bodies..]
+                .iter()
+                .filter(|v| v.color == [1., 0.2, 0.2, 0.8])
+                .count()
+                / 4
+        };
+        assert_eq!(sim.physics.debug_mesh.vertices.len(), bodies + 4);
+        assert_eq!(held_quads(&sim), 1);
+
+        // still there once the rest is rebuilt
+        sim.step(std::time::Duration::from_secs_f32(1. / 60.));
+        sim.prepare_render();
+        assert_eq!(held_quads(&sim), 1);
+
+        sim.set_held(None);
+        assert_eq!(sim.physics.debug_mesh.vertices.len(), bodies);
+    }
+
+    type Aabb = rapier2d::parry::bounding_volume::AABB;
+
+    fn layout<I: physics::GenResult>(
+        gen: physics::Gen<I>,
+        num: usize,
+        rad: f32,
+        offset_y: f32,
+    ) -> Vec<Aabb> {
+        gen(num, rad, offset_y)
+            .flat_map(|(parts, body)| {
+                let body = body.build();
+                parts
+                    .into_iter()
+                    .map(move |collider| collider.shape.compute_aabb(body.position()))
+            })
+            .collect()
+    }
+
+    /// Blocks may touch but not overlap, sit on or above the platform and mirror about x = 0.
+    fn check_layout(blocks: &[Aabb], rad: f32, offset_y: f32) -> Result<(), TestCaseError> {
+        let eps = rad * 1e-3;
+        let close = |a: f32, b: f32| (a - b).abs() <= eps;
+        for (index, a) in blocks.iter().enumerate() {
+            prop_assert!(a.mins.y >= offset_y - eps, "{:?} is below the platform", a);
+            prop_assert!(
+                blocks.iter().any(|b| close(b.mins.x, -a.maxs.x)
+                    && close(b.maxs.x, -a.mins.x)
+                    && close(b.mins.y, a.mins.y)
+                    && close(b.maxs.y, a.maxs.y)),
+                "{:?} has no mirror image",
+                a
+            );
+            for b in &blocks[index + 1..] {
+                let overlap_x = a.maxs.x.min(b.maxs.x) - a.mins.x.max(b.mins.x);
+                let overlap_y = a.maxs.y.min(b.maxs.y) - a.mins.y.max(b.mins.y);
+                prop_assert!(
+                    overlap_x <= eps || overlap_y <= eps,
+                    "{:?} overlaps {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Every block has to start out over the ground that gets built underneath it.
+    fn check_platform<I: physics::GenResult>(
+        gen: physics::Gen<I>,
+        num: usize,
+    ) -> Result<(), TestCaseError> {
+        let ctx = physics::PhysicsContext::new(0., -9.81 * 0.1, gen, num, Default::default());
+        let parent_is_static = |c: &rapier2d::geometry::Collider| {
+            ctx.bodies.get(c.parent()).map(|b| b.is_static()) == Some(true)
+        };
+        let ground = ctx
+            .colliders
+            .iter()
+            .map(|(_h, c)| c)
+            .find(|c| parent_is_static(c) && !c.is_sensor())
+            .map(|c| c.compute_aabb())
+            .unwrap();
+        for (_h, collider) in ctx.colliders.iter() {
+            if parent_is_static(collider) {
+                continue;
+            }
+            let block = collider.compute_aabb();
+            prop_assert!(
+                block.mins.x >= ground.mins.x - 1e-5 && block.maxs.x <= ground.maxs.x + 1e-5,
+                "{:?} hangs off of {:?}",
+                block,
+                ground
+            );
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn generated_layouts_are_valid(
+            num in 4usize..24,
+            rad in 0.005f32..0.05,
+            offset_y in -1f32..1.,
+        ) {
+            use physics::PhysicsContext;
+            check_layout(&layout(PhysicsContext::special_tower, num, rad, offset_y), rad, offset_y)?;
+            check_layout(&layout(PhysicsContext::tower, num, rad, offset_y), rad, offset_y)?;
+            check_layout(&layout(PhysicsContext::pyramid, num, rad, offset_y), rad, offset_y)?;
+            check_layout(&layout(PhysicsContext::thin, num, rad, offset_y), rad, offset_y)?;
+        }
+
+        #[test]
+        fn generated_layouts_fit_the_platform(num in 4usize..24) {
+            use physics::PhysicsContext;
+            check_platform(PhysicsContext::special_tower, num)?;
+            check_platform(PhysicsContext::tower, num)?;
+            check_platform(PhysicsContext::pyramid, num)?;
+            check_platform(PhysicsContext::thin, num)?;
         }
     }
 }
@@ -1,11 +1,16 @@
+mod chat;
+mod editor;
 mod lobby;
 mod main;
 mod no_room;
+mod settings;
 
 pub enum State {
     NoRoom(no_room::NoRoom),
     Lobby(lobby::Lobby),
-    Main(main::Main),
+    Main(Box<main::Main>),
+    Settings(settings::SettingsMenu),
+    Editor(Box<editor::Editor>),
 }
 
 impl Default for State {
@@ -15,10 +20,61 @@ impl Default for State {
 }
 
 impl State {
+    /// Nothing works until the page is refreshed, the server being on another version.
+    pub fn outdated() -> Self {
+        Self::NoRoom(no_room::NoRoom::outdated())
+    }
+
+    pub fn is_outdated(&self) -> bool {
+        matches!(self, Self::NoRoom(inner) if inner.is_outdated())
+    }
+
+    /// Back to room select, giving up the seat in the current room if there is one. The
+    /// connection is kept for joining another.
+    pub fn leave(self, ws: &crate::net::Client<dyn crate::net::Transport>) -> Self {
+        if let Some(room) = self.room() {
+            if let Err(err) = ws.leave_room(room.id) {
+                log::error!("{}", err);
+            }
+        }
+        Self::default()
+    }
+
     pub fn lobby(local_user: shared::viewer::User, room: shared::viewer::InitialRoomState) -> Self {
         Self::Lobby(lobby::Lobby::new(local_user, room))
     }
 
+    /// Whether the local user runs the room. Never true outside of one.
+    pub fn is_dm(&self) -> bool {
+        match self {
+            Self::NoRoom(_) => false,
+            Self::Lobby(inner) => inner.is_local_dm(),
+            Self::Main(inner) => inner.is_local_dm(),
+            Self::Settings(inner) => inner.previous().is_dm(),
+            Self::Editor(inner) => inner.previous().is_dm(),
+        }
+    }
+
+    /// Blocks `player_id` has moved this game. Nothing before one has started.
+    pub fn score(&self, player_id: shared::PlayerID) -> u32 {
+        match self {
+            Self::NoRoom(_) | Self::Lobby(_) => 0,
+            Self::Main(inner) => inner.score(player_id),
+            Self::Settings(inner) => inner.previous().score(player_id),
+            Self::Editor(inner) => inner.previous().score(player_id),
+        }
+    }
+
+    pub fn room(&self) -> Option<&shared::viewer::InitialRoomState> {
+        match self {
+            Self::NoRoom(_) => None,
+            Self::Lobby(inner) => Some(inner.room()),
+            Self::Main(inner) => Some(inner.room()),
+            Self::Settings(inner) => inner.previous().room(),
+            Self::Editor(inner) => inner.previous().room(),
+        }
+    }
+
     pub fn update(mut self, dt: std::time::Duration, ctx: StateContext) -> Self {
         match self {
             Self::NoRoom(ref mut inner) => {
@@ -27,10 +83,25 @@ impl State {
             }
             Self::Main(inner) => inner.update(dt, ctx),
             Self::Lobby(inner) => inner.update(dt, ctx),
+            Self::Settings(inner) => inner.update(dt, ctx),
+            Self::Editor(inner) => inner.update(dt, ctx),
         }
     }
 
     pub fn handle_mouse_event(mut self, event: crate::MouseEvent, ctx: StateContext) -> State {
+        if self.offers_settings() && event.is_left_press() {
+            let (mx, my) = ctx.input_state.mouse_position;
+            if crate::collides([mx, my], &settings::OPEN_BOUNDS) {
+                return Self::Settings(settings::SettingsMenu::new(self));
+            }
+        }
+        if let Self::Lobby(inner) = &self {
+            let (mx, my) = ctx.input_state.mouse_position;
+            let bounds = lobby::Lobby::editor_bounds(ctx.settings.get().ui_scale());
+            if inner.is_local_dm() && event.is_left_press() && crate::collides([mx, my], &bounds) {
+                return Self::Editor(Box::new(editor::Editor::new(self)));
+            }
+        }
         match self {
             Self::Lobby(ref inner) => {
                 inner.handle_mouse_event(event, ctx);
@@ -40,11 +111,45 @@ impl State {
                 inner.handle_mouse_event(event, ctx);
                 self
             }
+            Self::Settings(inner) => inner.handle_mouse_event(event, ctx),
+            Self::Editor(inner) => inner.handle_mouse_event(event, ctx),
             _ => self,
         }
     }
 
-    pub fn render(&self, ctx: StateContext) {
+    /// `configured` wins over the state's own background when set. Menus follow the theme in
+    /// `settings`.
+    pub fn clear_color(
+        &self,
+        configured: Option<[f32; 4]>,
+        settings: &crate::settings::Settings,
+    ) -> [f32; 4] {
+        configured.unwrap_or(match self {
+            Self::NoRoom(_) => no_room::CLEAR_COLOR,
+            Self::Lobby(_) | Self::Settings(_) => settings.theme().background,
+            Self::Main(_) => main::CLEAR_COLOR,
+            Self::Editor(_) => editor::CLEAR_COLOR,
+        })
+    }
+
+    /// Mutable only to bring the simulation's debug mesh up to date first, once per frame drawn
+    /// rather than on every step.
+    pub fn render<'s>(&'s mut self, clear_color: Option<[f32; 4]>, ctx: StateContext<'_, '_, 's>) {
+        match self {
+            State::Main(inner) => inner.prepare_render(),
+            State::Editor(inner) => inner.prepare_render(),
+            State::NoRoom(_) | State::Lobby(_) | State::Settings(_) => {}
+        }
+        let this: &'s Self = self;
+        this.draw(clear_color, ctx);
+    }
+
+    fn draw<'s>(&'s self, clear_color: Option<[f32; 4]>, mut ctx: StateContext<'_, '_, 's>) {
+        ctx.g
+            .clear(self.clear_color(clear_color, &ctx.settings.get()));
+        if self.offers_settings() {
+            settings::SettingsMenu::render_open_button(&mut ctx);
+        }
         match self {
             State::NoRoom(inner) => {
                 inner.render(ctx);
@@ -55,6 +160,20 @@ impl State {
             State::Main(inner) => {
                 inner.render(ctx);
             }
+            State::Settings(inner) => {
+                inner.render(ctx);
+            }
+            State::Editor(inner) => {
+                inner.render(ctx);
+            }
+        }
+    }
+
+    /// Settings are reachable from outside of a game, not while one is being played.
+    fn offers_settings(&self) -> bool {
+        match self {
+            Self::NoRoom(_) | Self::Lobby(_) => true,
+            Self::Main(_) | Self::Settings(_) | Self::Editor(_) => false,
         }
     }
 }
@@ -62,7 +181,80 @@ impl State {
 pub struct StateContext<'a, 'b, 'c> {
     pub g: solstice_2d::GraphicsLock<'b, 'c>,
     pub resources: &'a super::resources::LoadedResources,
-    pub ws: &'a super::net::Client,
+    pub ws: &'a super::net::Client<dyn super::net::Transport>,
     pub input_state: &'a super::InputState,
     pub time: &'a std::time::Duration,
+    pub audio: &'a dyn super::audio::AudioBackend,
+    pub announcer: &'a dyn super::announce::Announcer,
+    pub settings: &'a std::cell::Cell<super::settings::Settings>,
+    /// What this frame has done so far, filled in as the state updates.
+    pub report: &'a std::cell::Cell<super::FrameReport>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{room_with, user};
+
+    #[test]
+    fn first_user_is_dm() {
+        let dm = user("1", "Alice");
+        let player = user("2", "Bob");
+        let room = room_with(vec![dm.clone(), player.clone()]);
+
+        assert!(!State::default().is_dm());
+        assert!(State::default().room().is_none());
+        assert!(State::lobby(dm.clone(), room.clone()).is_dm());
+        assert!(!State::lobby(player, room.clone()).is_dm());
+
+        // the page keeps its DM controls while a menu is open over the room
+        let settings = settings::SettingsMenu::new(State::lobby(dm.clone(), room.clone()));
+        assert!(State::Settings(settings).is_dm());
+        let editor = editor::Editor::new(State::lobby(dm, room));
+        assert!(State::Editor(Box::new(editor)).is_dm());
+    }
+
+    #[test]
+    fn leaving_gives_up_the_seat() {
+        use crate::net::loopback::Server;
+
+        let server = Server::default();
+        let dm = user("1", "Alice");
+        let transport = server.connect(dm.clone());
+        let room = server.create_room(&transport);
+        let room_id = room.id;
+        let ws = crate::net::Client::builder("http://loopback/")
+            .build_with(transport)
+            .unwrap();
+
+        let state = State::lobby(dm, room).leave(&ws);
+        assert!(matches!(state, State::NoRoom(_)));
+        // the room went with its only player
+        let bob = server.connect(user("2", "Bob"));
+        assert!(server.join_room(room_id, &bob).is_none());
+    }
+
+    #[test]
+    fn configured_clear_color_wins() {
+        let settings = crate::settings::Settings::default();
+        let state = State::default();
+        assert_eq!(state.clear_color(None, &settings), no_room::CLEAR_COLOR);
+        let transparent = [0., 0., 0., 0.];
+        assert_eq!(state.clear_color(Some(transparent), &settings), transparent);
+    }
+
+    #[test]
+    fn menus_follow_the_theme() {
+        use crate::settings::{Settings, Theme};
+        let menu = State::Settings(settings::SettingsMenu::new(State::default()));
+        let dark = Settings {
+            dark_theme: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            menu.clear_color(None, &Settings::default()),
+            Theme::LIGHT.background
+        );
+        assert_eq!(menu.clear_color(None, &dark), Theme::DARK.background);
+    }
 }
@@ -0,0 +1,72 @@
+use super::StateContext;
+use shared::viewer::User;
+
+/// Lines shown at once. Older ones scroll off the top.
+const VISIBLE_LINES: usize = 8;
+const LINE_HEIGHT: f32 = 1.1;
+
+/// What's been said in the room lately, shown in the lobby and during games.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChatLog {
+    lines: std::collections::VecDeque<String>,
+}
+
+impl ChatLog {
+    /// `users` are the room's, to put a name to `from`.
+    pub fn receive(&mut self, users: &[User], from: shared::PlayerID, text: &str) {
+        let line = match users.iter().find(|user| user.id == from) {
+            Some(user) => format!("{}: {}", user.name, text),
+            None => format!("{}: {}", from, text),
+        };
+        self.lines.push_back(line);
+        while self.lines.len() > VISIBLE_LINES {
+            self.lines.pop_front();
+        }
+    }
+
+    /// Oldest first, with the newest line's bottom edge at `bottom`.
+    pub fn render(&self, ctx: &mut StateContext, x: f32, bottom: f32, text_scale: f32) {
+        let font_id = ctx.resources.sans_font;
+        let vw = ctx.g.gfx().viewport();
+        let width = vw.width() as f32 - x;
+        let line_height = text_scale * LINE_HEIGHT;
+        let top = bottom - self.lines.len() as f32 * line_height;
+        for (index, line) in self.lines.iter().enumerate() {
+            ctx.g.print(
+                line.clone(),
+                font_id,
+                text_scale,
+                solstice_2d::Rectangle {
+                    x,
+                    y: (top + index as f32 * line_height).round(),
+                    width,
+                    height: line_height,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_lines_scroll_off() {
+        let alice = User {
+            id: "1".parse().unwrap(),
+            name: "Alice".to_owned(),
+            connected: true,
+        };
+        let mut chat = ChatLog::default();
+        for index in 0..VISIBLE_LINES + 2 {
+            chat.receive(std::slice::from_ref(&alice), alice.id, &index.to_string());
+        }
+        // someone who has since left goes by their id
+        chat.receive(&[], "2".parse().unwrap(), "bye");
+
+        assert_eq!(chat.lines.len(), VISIBLE_LINES);
+        assert_eq!(chat.lines.front().unwrap(), "Alice: 3");
+        assert_eq!(chat.lines.back().unwrap(), "2: bye");
+    }
+}
@@ -0,0 +1,209 @@
+//! Where the DM builds a tower of their own, block by block, to start a game with.
+
+use super::StateContext;
+use crate::strings::Text;
+use shared::layout::{Block, TowerLayout};
+use solstice_2d::Stroke;
+
+pub const CLEAR_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 1.];
+/// Blocks are placed on a grid of their own size.
+const BLOCK_HALF_SIZE: f32 = crate::sim::BLOCK_SIZE / 2.;
+const ROTATION_STEP: f32 = std::f32::consts::FRAC_PI_4;
+
+const PLAY_BOUNDS: solstice_2d::Rectangle = solstice_2d::Rectangle {
+    x: 32.,
+    y: 32.,
+    width: 160.,
+    height: 32.,
+};
+
+const BACK_BOUNDS: solstice_2d::Rectangle = solstice_2d::Rectangle {
+    y: 32. * 2.5,
+    ..PLAY_BOUNDS
+};
+
+/// Left clicking places a block or rotates the one under the cursor, right clicking removes it.
+pub struct Editor {
+    previous: Box<super::State>,
+    layout: TowerLayout,
+    /// Built from `layout` and never stepped so that nothing falls while it's being edited.
+    sim: crate::sim::Sim,
+    /// Which block of `layout` each body in `sim` was built from.
+    blocks: std::collections::HashMap<rapier2d::dynamics::RigidBodyHandle, usize>,
+}
+
+impl Editor {
+    pub fn new(previous: super::State) -> Self {
+        let layout = TowerLayout::default();
+        let sim = crate::sim::Sim::from_layout(&layout);
+        Self {
+            previous: Box::new(previous),
+            blocks: sim.layout_indices(),
+            sim,
+            layout,
+        }
+    }
+
+    pub fn previous(&self) -> &super::State {
+        &self.previous
+    }
+
+    /// The room keeps going underneath the same as it does behind the settings.
+    pub fn update(mut self, dt: std::time::Duration, ctx: StateContext) -> super::State {
+        self.sim.set_palette(ctx.settings.get().palette());
+        self.previous = Box::new(self.previous.update(dt, ctx));
+        super::State::Editor(Box::new(self))
+    }
+
+    pub fn handle_mouse_event(
+        mut self,
+        event: crate::MouseEvent,
+        ctx: StateContext,
+    ) -> super::State {
+        let (mx, my) = ctx.input_state.mouse_position;
+        let [x, y] = crate::sim::Sim::screen_to_world(ctx.g.gfx().viewport(), mx, my);
+        match event {
+            crate::MouseEvent::Button(
+                crate::winit::event::ElementState::Pressed,
+                crate::MouseButton::Left,
+            ) => {
+                if crate::collides([mx, my], &BACK_BOUNDS) {
+                    return *self.previous;
+                }
+                if crate::collides([mx, my], &PLAY_BOUNDS) {
+                    if let Some(room) = self.previous.room() {
+                        if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
+                            room.id,
+                            shared::CustomMessage::StartCustom(self.export()),
+                        )) {
+                            log::warn!("{}", err);
+                        }
+                    }
+                    return *self.previous;
+                }
+                match self.block_at(x, y) {
+                    Some(index) => self.rotate(index),
+                    None => self.place(x, y),
+                }
+            }
+            crate::MouseEvent::Button(
+                crate::winit::event::ElementState::Pressed,
+                crate::MouseButton::Right,
+            ) => {
+                if let Some(index) = self.block_at(x, y) {
+                    self.remove(index);
+                }
+            }
+            _ => {}
+        }
+        super::State::Editor(Box::new(self))
+    }
+
+    pub fn prepare_render(&mut self) {
+        self.sim.prepare_render();
+    }
+
+    pub fn render<'s>(&'s self, mut ctx: StateContext<'_, '_, 's>) {
+        self.sim.render(&mut ctx.g);
+        let (mx, my) = ctx.input_state.mouse_position;
+        let [x, y] = crate::sim::Sim::screen_to_world(ctx.g.gfx().viewport(), mx, my);
+        let [x, y] = crate::sim::snap_to_grid([x, y], crate::sim::BLOCK_SIZE);
+        ctx.g.stroke_with_color(
+            solstice_2d::Rectangle::new(
+                x - BLOCK_HALF_SIZE,
+                y - BLOCK_HALF_SIZE,
+                BLOCK_HALF_SIZE * 2.,
+                BLOCK_HALF_SIZE * 2.,
+            ),
+            [1., 1., 1., 0.5],
+        );
+
+        ctx.g.set_projection_mode(None);
+        let font_id = ctx.resources.sans_font;
+        let strings = ctx.settings.get().strings();
+        ctx.g.set_color([1., 1., 1., 1.]);
+        for (text, bounds) in [(Text::Play, PLAY_BOUNDS), (Text::Back, BACK_BOUNDS)].iter() {
+            ctx.g
+                .print(strings.get(*text), font_id, bounds.height, *bounds);
+            ctx.g.stroke(*bounds);
+        }
+    }
+
+    /// Adds a block to the grid cell around the point unless there's one there already.
+    fn place(&mut self, x: f32, y: f32) {
+        let [x, y] = crate::sim::snap_to_grid([x, y], crate::sim::BLOCK_SIZE);
+        let full = self.layout.blocks.len() >= shared::layout::MAX_LAYOUT_BLOCKS;
+        if !full && self.block_at(x, y).is_none() {
+            self.layout.blocks.push(Block {
+                x,
+                y,
+                half_width: BLOCK_HALF_SIZE,
+                half_height: BLOCK_HALF_SIZE,
+                rotation: 0.,
+                groups: Default::default(),
+                body: None,
+            });
+            self.rebuild();
+        }
+    }
+
+    fn rotate(&mut self, index: usize) {
+        if let Some(block) = self.layout.blocks.get_mut(index) {
+            block.rotation = (block.rotation + ROTATION_STEP) % std::f32::consts::TAU;
+            self.rebuild();
+        }
+    }
+
+    fn remove(&mut self, index: usize) {
+        if index < self.layout.blocks.len() {
+            self.layout.blocks.remove(index);
+            self.rebuild();
+        }
+    }
+
+    /// The block's index in the layout.
+    fn block_at(&self, x: f32, y: f32) -> Option<usize> {
+        let handle = self.sim.body_at_point(x, y)?;
+        self.blocks.get(&handle).copied()
+    }
+
+    fn rebuild(&mut self) {
+        self.sim = crate::sim::Sim::from_layout(&self.layout);
+        self.blocks = self.sim.layout_indices();
+    }
+
+    pub fn export(&self) -> TowerLayout {
+        self.sim.current_layout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placed_blocks_are_exported() {
+        let mut editor = Editor::new(super::super::State::NoRoom(Default::default()));
+        editor.place(0.03, -0.41);
+        editor.place(-0.02, -0.37);
+        // the same cell again
+        editor.place(0.02, -0.43);
+
+        let layout = editor.export();
+        let positions = layout
+            .blocks
+            .iter()
+            .map(|block| [block.x, block.y])
+            .collect::<Vec<_>>();
+        assert_eq!(positions.len(), 2);
+        for (actual, expected) in positions.iter().zip(&[[0.025, -0.425], [-0.025, -0.375]]) {
+            assert!((actual[0] - expected[0]).abs() < 1e-6, "{:?}", positions);
+            assert!((actual[1] - expected[1]).abs() < 1e-6, "{:?}", positions);
+        }
+
+        assert_eq!(editor.block_at(-0.025, -0.375), Some(1));
+        editor.remove(0);
+        assert_eq!(editor.export().blocks.len(), 1);
+        assert_eq!(editor.block_at(-0.025, -0.375), Some(0));
+    }
+}
@@ -1,17 +1,33 @@
 use super::StateContext;
+use crate::strings::{Strings, Text};
 use shared::viewer::*;
 use shared::CustomMessage;
 use solstice_2d::Stroke;
 
+const DISCONNECTED_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.];
+
+/// A line in the list of everyone in the room, as `render` draws it.
+#[derive(Debug, Clone, PartialEq)]
+struct UserLabel {
+    text: String,
+    bounds: solstice_2d::Rectangle,
+    color: [f32; 4],
+}
+
 #[derive(Debug)]
 pub struct Lobby {
     local_user: shared::viewer::User,
     room: InitialRoomState,
+    chat: super::chat::ChatLog,
 }
 
 impl Lobby {
     pub fn new(local_user: User, room: InitialRoomState) -> Self {
-        Self { local_user, room }
+        Self {
+            local_user,
+            room,
+            chat: Default::default(),
+        }
     }
 
     pub fn update(mut self, _dt: std::time::Duration, ctx: StateContext) -> super::State {
@@ -28,14 +44,33 @@ impl Lobby {
                     }
                     ChangeType::Custom(cmd) => match cmd {
                         CustomMessage::StartGame(index) => {
-                            let sim = (crate::sim::ROOM_TYPES[index as usize].gen)();
-                            let main = super::main::Main::new(self.local_user, self.room, sim);
-                            return super::State::Main(main);
+                            if let Some(room_type) = crate::sim::room_type(index) {
+                                let sim = (room_type.gen)();
+                                let main = super::main::Main::new(self.local_user, self.room, sim)
+                                    .with_chat(self.chat);
+                                return super::State::Main(Box::new(main));
+                            }
+                        }
+                        CustomMessage::StartCustom(layout) => {
+                            let sim = crate::sim::Sim::from_layout(&layout);
+                            let main = super::main::Main::new(self.local_user, self.room, sim)
+                                .with_chat(self.chat);
+                            return super::State::Main(Box::new(main));
                         }
                         _ => {
                             log::error!("Discarded a command!")
                         }
                     },
+                    ChangeType::UserStatus(user_id, connected) => {
+                        if let Some(user) = self.room.users.iter_mut().find(|u| u.id == user_id) {
+                            user.connected = connected;
+                        }
+                    }
+                    ChangeType::Chat { from, text } => {
+                        self.chat.receive(&self.room.users, from, &text);
+                    }
+                    // consumed by net::Client
+                    ChangeType::Ack(_) => {}
                 }
             } else {
                 log::error!(
@@ -49,14 +84,18 @@ impl Lobby {
     }
 
     pub fn handle_mouse_event(&self, event: crate::MouseEvent, ctx: StateContext) {
-        if self.is_dm(&self.local_user) && event.is_left_press() {
+        let scale = ctx.settings.get().ui_scale();
+        // the server would refuse to start anyway
+        if self.is_dm(&self.local_user) && self.players_missing() == 0 && event.is_left_press() {
             let (mx, my) = ctx.input_state.mouse_position;
             for (index, _) in crate::sim::ROOM_TYPES.iter().enumerate() {
-                if crate::collides([mx, my], &Self::room_type_bounds(index)) {
-                    ctx.ws.send(shared::viewer::Command::Custom(
+                if crate::collides([mx, my], &Self::room_type_bounds(index, scale)) {
+                    if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
                         self.room.id,
                         shared::CustomMessage::StartGame(index as _),
-                    ));
+                    )) {
+                        log::warn!("{}", err);
+                    }
                     break;
                 }
             }
@@ -64,9 +103,9 @@ impl Lobby {
     }
 
     pub fn render(&self, mut ctx: StateContext) {
-        ctx.g.clear([1., 1., 1., 1.]);
-
         let font_id = ctx.resources.sans_font;
+        let scale = ctx.settings.get().ui_scale();
+        let strings = ctx.settings.get().strings();
         let vw = ctx.g.gfx().viewport();
         let bounds = solstice_2d::Rectangle {
             x: vw.x() as f32,
@@ -74,37 +113,65 @@ impl Lobby {
             width: vw.width() as f32,
             height: vw.height() as f32,
         };
-        ctx.g.set_color([0., 0., 0., 1.]);
-        ctx.g
-            .print(format!("Room: {}", self.room.id), font_id, 32., bounds);
-        for (index, user) in self.room.users.iter().enumerate() {
-            let text = format!("{}. {}", index + 1, user.name);
-            let scale = 16.;
-            ctx.g.print(
-                text,
-                font_id,
-                scale,
-                solstice_2d::Rectangle {
-                    y: (scale * 1.1 * index as f32 + 32.).round(),
-                    ..bounds
-                },
-            );
+        let theme = ctx.settings.get().theme();
+        ctx.g.set_color(theme.text);
+        let room = strings.get(Text::Room);
+        let title = match &self.room.name {
+            Some(name) => format!("{}: {} ({})", room, name, self.room.id),
+            None => format!("{}: {}", room, self.room.id),
+        };
+        ctx.g.print(title, font_id, 32. * scale, bounds);
+        let text_scale = 16. * scale;
+        ctx.g.print(
+            self.player_count(&strings),
+            font_id,
+            text_scale,
+            solstice_2d::Rectangle {
+                y: 32. * scale,
+                ..bounds
+            },
+        );
+        for label in self.user_labels(bounds, scale, theme.text) {
+            ctx.g.set_color(label.color);
+            ctx.g.print(label.text, font_id, text_scale, label.bounds);
         }
 
+        ctx.g.set_color(theme.text);
+        // clear of the waiting message at the very bottom
+        self.chat
+            .render(&mut ctx, bounds.x, bounds.height - 40. * scale, text_scale);
+
         if self.is_dm(&self.local_user) {
+            let missing = self.players_missing();
+            if missing > 0 {
+                ctx.g.set_color(DISCONNECTED_COLOR);
+            }
             for (index, room_ty) in crate::sim::ROOM_TYPES.iter().enumerate() {
-                let bounds = Self::room_type_bounds(index);
-                ctx.g.print(room_ty.name, font_id, 32., bounds);
+                let bounds = Self::room_type_bounds(index, scale);
+                ctx.g.print(room_ty.name, font_id, bounds.height, bounds);
                 ctx.g.stroke(bounds);
             }
+            let bounds = Self::editor_bounds(scale);
+            ctx.g
+                .print(strings.get(Text::EditTower), font_id, bounds.height, bounds);
+            ctx.g.stroke(bounds);
+            if missing > 0 {
+                let bounds = Self::room_type_bounds(crate::sim::ROOM_TYPES.len(), scale);
+                ctx.g.print(
+                    format!("{}: {}", strings.get(Text::PlayersNeeded), missing),
+                    font_id,
+                    bounds.height / 2.,
+                    bounds,
+                );
+            }
         } else {
             ctx.g.print(
-                "Waiting For DM to start room.",
+                strings.get(Text::WaitingForDm),
                 font_id,
-                32.,
+                32. * scale,
                 solstice_2d::Rectangle {
-                    y: bounds.height - 32.,
-                    height: 32.,
+                    y: bounds.height - 32. * scale,
+                    height: 32. * scale,
                     ..bounds
                 },
             )
@@ -113,15 +180,74 @@ impl Lobby {
         ctx.g.set_color([1., 1., 1., 1.]);
     }
 
-    fn room_type_bounds(index: usize) -> solstice_2d::Rectangle {
-        solstice_2d::Rectangle {
-            x: 720.,
-            y: index as f32 * 32. * 1.5 + 32.,
-            width: 480.,
-            height: 32.,
+    /// "Players: 3/8", or without the cap if the room doesn't have one.
+    fn player_count(&self, strings: &Strings) -> String {
+        let count = self.room.users.len();
+        match self.room.settings.capacity {
+            Some(capacity) => format!("{}: {}/{}", strings.get(Text::Players), count, capacity),
+            None => format!("{}: {}", strings.get(Text::Players), count),
         }
     }
 
+    /// Everyone in the room, the DM included, listed down from the top of `bounds`. Connected
+    /// users are in `text_color`.
+    fn user_labels(
+        &self,
+        bounds: solstice_2d::Rectangle,
+        scale: f32,
+        text_color: [f32; 4],
+    ) -> Vec<UserLabel> {
+        let text_scale = 16. * scale;
+        self.room
+            .users
+            .iter()
+            .enumerate()
+            .map(|(index, user)| UserLabel {
+                text: format!("{}. {}", index + 1, user.name),
+                bounds: solstice_2d::Rectangle {
+                    y: (text_scale * 1.1 * (index + 1) as f32 + 32. * scale).round(),
+                    ..bounds
+                },
+                color: if user.connected {
+                    text_color
+                } else {
+                    DISCONNECTED_COLOR
+                },
+            })
+            .collect()
+    }
+
+    /// How many more players, not counting the DM, the room needs before it can start.
+    fn players_missing(&self) -> u32 {
+        let players = self.room.users.len().saturating_sub(1) as u32;
+        self.room.settings.min_players.saturating_sub(players)
+    }
+
+    fn room_type_bounds(index: usize, scale: f32) -> solstice_2d::Rectangle {
+        crate::scale_rect(
+            solstice_2d::Rectangle {
+                x: 720.,
+                y: index as f32 * 32. * 1.5 + 32.,
+                width: 480.,
+                height: 32.,
+            },
+            scale,
+        )
+    }
+
+    /// Under the room types, past where the missing players are listed.
+    pub fn editor_bounds(scale: f32) -> solstice_2d::Rectangle {
+        Self::room_type_bounds(crate::sim::ROOM_TYPES.len() + 1, scale)
+    }
+
+    pub fn room(&self) -> &InitialRoomState {
+        &self.room
+    }
+
+    pub fn is_local_dm(&self) -> bool {
+        self.is_dm(&self.local_user)
+    }
+
     fn is_dm(&self, user: &User) -> bool {
         if let Some(first) = self.room.users.first() {
             first.id == user.id
@@ -130,3 +256,67 @@ impl Lobby {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{player, room_with};
+
+    #[test]
+    fn player_count_tracks_membership_and_capacity() {
+        let strings = Strings::new(Default::default());
+        let room = InitialRoomState {
+            settings: RoomSettings {
+                capacity: Some(8),
+                min_players: 3,
+                max_consecutive_turns: None,
+            },
+            ..room_with(vec![player("1"), player("2"), player("3")])
+        };
+        let lobby = Lobby::new(player("1"), room.clone());
+        assert_eq!(lobby.player_count(&strings), "Players: 3/8");
+        assert_eq!(lobby.players_missing(), 1);
+
+        let mut lobby = Lobby::new(
+            player("1"),
+            InitialRoomState {
+                settings: Default::default(),
+                ..room
+            },
+        );
+        assert_eq!(lobby.player_count(&strings), "Players: 3");
+        assert_eq!(lobby.players_missing(), 0);
+
+        lobby.room.users.push(player("4"));
+        assert_eq!(lobby.player_count(&strings), "Players: 4");
+    }
+
+    #[test]
+    fn rooms_with_hardly_anyone_in_them_dont_panic() {
+        let strings = Strings::new(Default::default());
+        let room = InitialRoomState {
+            settings: RoomSettings {
+                capacity: None,
+                min_players: 2,
+                max_consecutive_turns: None,
+            },
+            ..room_with(vec![])
+        };
+        let screen = solstice_2d::Rectangle::new(0., 0., 1280., 720.);
+        let mut lobby = Lobby::new(player("1"), room);
+        assert!(!lobby.is_local_dm());
+        assert_eq!(lobby.player_count(&strings), "Players: 0");
+        assert_eq!(lobby.players_missing(), 2);
+        let text_color = crate::settings::Theme::LIGHT.text;
+        assert!(lobby.user_labels(screen, 1., text_color).is_empty());
+
+        lobby.room.users.push(player("1"));
+        assert!(lobby.is_local_dm());
+        assert_eq!(lobby.player_count(&strings), "Players: 1");
+        assert_eq!(lobby.players_missing(), 2);
+        let labels = lobby.user_labels(screen, 1., text_color);
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].text, "1. Player 1");
+        assert_eq!(labels[0].color, text_color);
+    }
+}
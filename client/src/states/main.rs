@@ -1,20 +1,75 @@
 use super::StateContext;
+use crate::strings::Text;
 use crate::winit::event::ElementState;
 use crate::MouseEvent;
 use shared::viewer::{ChangeType, InitialRoomState, User};
 use shared::CustomMessage;
 use solstice_2d::{Draw, Stroke};
 
+pub const CLEAR_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 1.];
 const TEXT_SCALE: f32 = 16.;
+const DISCONNECTED_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.];
+const POINTER_SIZE: f32 = 0.02;
+/// The collapse plays out in slow motion.
+const COLLAPSE_TIME_SCALE: f32 = 0.25;
+/// What the DM can set the simulation's speed to.
+const TIME_SCALES: [f32; 4] = [0., 0.5, 1., shared::MAX_TIME_SCALE];
+const REJECTED_CLICK_FLASH: std::time::Duration = std::time::Duration::from_millis(400);
+/// Logged for every body picked up, so kept below what's on by default.
+const CLICK_LOG_LEVEL: log::Level = log::Level::Trace;
+/// Ticks between checksums being sent to the rest of the room. About once a second.
+const CHECKSUM_INTERVAL: u64 = 60;
+/// How many of our own checksums are kept around to compare other clients' against.
+const CHECKSUM_HISTORY: usize = 8;
+
+/// The crosshair drawn where the local player is pointing, in world space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pointer {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+/// A player's line in the list down the side, outlined for the DM to click on.
+#[derive(Debug, Clone, PartialEq)]
+struct PlayerLabel {
+    text: String,
+    bounds: solstice_2d::Rectangle,
+    color: [f32; 4],
+}
+
+/// A press that couldn't be acted on, flashed at `position` for a moment so it doesn't just look
+/// ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RejectedClick {
+    position: [f32; 2],
+    at: std::time::Duration,
+}
+
+/// The game a restart replaced, as it was at the time.
+struct Restarted {
+    previous: Box<Main>,
+    at: std::time::Duration,
+}
 
 pub struct Main {
     sim: crate::sim::Sim,
     local_user: User,
     room: InitialRoomState,
     local_click_in_flight: bool,
-    click_queue: std::collections::VecDeque<(shared::PlayerID, u32)>,
+    click_queue: crate::turns::ClickQueue,
     previous_click: Option<shared::PlayerID>,
     moving: Option<crate::sim::PhysicsTuple>,
+    turns: crate::announce::TurnWatcher,
+    rejected_click: Option<RejectedClick>,
+    /// Logged events from the room applied since the game started or the last resync, which
+    /// everyone sees in the same order.
+    applied: u64,
+    /// Ours, as `(tick, applied, checksum)`, oldest first.
+    checksums: std::collections::VecDeque<(u64, u64, u64)>,
+    restarted: Option<Restarted>,
+    chat: super::chat::ChatLog,
+    /// Whether we've told the room the tower fell, as the DM.
+    collapse_reported: bool,
 }
 
 impl Main {
@@ -27,55 +82,100 @@ impl Main {
             click_queue: Default::default(),
             previous_click: None,
             moving: None,
+            turns: Default::default(),
+            rejected_click: None,
+            applied: 0,
+            checksums: Default::default(),
+            restarted: None,
+            chat: Default::default(),
+            collapse_reported: false,
         }
     }
 
+    /// Carries on the conversation from before the game started.
+    pub fn with_chat(self, chat: super::chat::ChatLog) -> Self {
+        Self { chat, ..self }
+    }
+
     pub fn update(mut self, dt: std::time::Duration, ctx: StateContext) -> super::State {
         for msg in ctx.ws.try_recv_iter() {
+            if let ChangeType::Custom(CustomMessage::AssignClick(player_id, _count)) = &msg.ty {
+                if !self.room.users.iter().any(|user| user.id == *player_id) {
+                    log::warn!("ignoring clicks assigned to unknown user {:?}", player_id);
+                    continue;
+                }
+            }
+            self.click_queue.apply(&msg.ty);
+            if matches!(&msg.ty, ChangeType::Custom(cmd) if !cmd.is_transient()) {
+                self.applied += 1;
+            }
             match msg.ty {
                 ChangeType::Custom(cmd) => match cmd {
                     CustomMessage::RemoveBody(x, y) => {
-                        log::debug!("CLICK ({}, {})", x, y);
+                        log::log!(CLICK_LOG_LEVEL, "CLICK ({}, {})", x, y);
                         self.local_click_in_flight = false;
                         if let Some(handle) = self.sim.body_at_point(x, y) {
                             self.moving = self.sim.try_remove_body(handle);
                         }
 
-                        self.previous_click = self.click_queue.front().map(|(user, _)| *user);
+                        self.previous_click = self.click_queue.current();
                     }
                     CustomMessage::MoveBody(x, y) => {
                         if let Some((body, _)) = &mut self.moving {
                             let translation =
                                 rapier2d::na::Translation2::from(rapier2d::na::Vector2::new(x, y));
-                            let mut position = body.position().clone();
+                            let mut position = *body.position();
                             position.translation = translation;
                             body.set_position(position, false);
                         }
                     }
                     CustomMessage::DropBody(x, y) => {
                         if let Some((mut body, colliders)) = self.moving.take() {
-                            let mut position = body.position().clone();
+                            let mut position = *body.position();
                             position.translation =
                                 rapier2d::na::Translation2::from(rapier2d::na::Vector2::new(x, y));
                             body.set_position(position, false);
                             self.sim.add_body((body, colliders));
                         }
-                        if let Some(count) = self.click_queue.front_mut().map(|(_user, count)| {
-                            *count -= 1;
-                            *count
-                        }) {
-                            if count == 0 {
-                                self.click_queue.pop_front();
+                    }
+                    CustomMessage::AssignClick(_, _) => {}
+                    CustomMessage::SetTimeScale(time_scale) => {
+                        self.sim.set_time_scale(time_scale);
+                    }
+                    CustomMessage::Checksum(tick, applied, checksum) => {
+                        if self.is_desynced(tick, applied, checksum) {
+                            log::warn!("simulation desynced from the room at tick {}", tick);
+                            if self.is_dm(&self.local_user) {
+                                self.send_resync(&ctx);
                             }
                         }
                     }
-                    CustomMessage::AssignClick(player_id, count) => {
-                        self.click_queue.push_back((player_id, count));
+                    CustomMessage::Resync(tick, snapshot) => {
+                        log::debug!("resyncing at tick {}", tick);
+                        self.sim.resync(&snapshot, tick);
+                        self.applied = 0;
+                        self.checksums.clear();
                     }
                     CustomMessage::StartGame(index) => {
-                        let sim = (crate::sim::ROOM_TYPES[index as usize].gen)();
-                        return super::State::Main(Self::new(self.local_user, self.room, sim));
+                        if let Some(room_type) = crate::sim::room_type(index) {
+                            let sim = (room_type.gen)();
+                            return super::State::Main(Box::new(self.restart(sim, *ctx.time)));
+                        }
+                    }
+                    CustomMessage::StartCustom(layout) => {
+                        let sim = crate::sim::Sim::from_layout(&layout);
+                        return super::State::Main(Box::new(self.restart(sim, *ctx.time)));
                     }
+                    CustomMessage::UndoRestart => {
+                        let mut main = self.undo_restart();
+                        // late joiners replay the undo with no earlier game to go back to
+                        if main.is_dm(&main.local_user) {
+                            main.send_resync(&ctx);
+                        }
+                        return super::State::Main(Box::new(main));
+                    }
+                    // for the server to record, the collapse itself plays out in every sim
+                    CustomMessage::Collapsed(_) => {}
                 },
                 ChangeType::UserJoin(user) => {
                     // users can join the room but they will be lobbied until the next game starts
@@ -88,20 +188,63 @@ impl Main {
                             log::debug!("DM lefted room!");
                             return super::State::NoRoom(Default::default());
                         } else {
-                            let user = self.room.users.remove(index);
-                            self.click_queue.retain(|(id, _count)| id != &user.id);
+                            self.room.users.remove(index);
                         }
                     }
                 }
+                ChangeType::UserStatus(user_id, connected) => {
+                    if let Some(user) = self.room.users.iter_mut().find(|u| u.id == user_id) {
+                        user.connected = connected;
+                    }
+                }
+                ChangeType::Chat { from, text } => {
+                    self.chat.receive(&self.room.users, from, &text);
+                }
+                // consumed by net::Client
+                ChangeType::Ack(_) => {}
             }
         }
 
+        let users = &self.room.users;
+        let next = self
+            .click_queue
+            .current()
+            .and_then(|id| users.iter().find(|user| user.id == id));
+        if self.turns.observe(next, &self.local_user, ctx.announcer) {
+            ctx.report.set(crate::FrameReport {
+                turn_changed: true,
+                ..ctx.report.get()
+            });
+        }
+
+        self.sim
+            .set_view(crate::sim::Sim::view_bounds(ctx.g.gfx().viewport()));
+        self.sim.set_held(self.moving.as_ref());
+        let tick = self.sim.tick();
+        self.sim.set_palette(ctx.settings.get().palette());
         self.sim.step(dt);
+        if self.sim.tick() != tick && self.sim.tick().is_multiple_of(CHECKSUM_INTERVAL) {
+            self.send_checksum(&ctx);
+        }
+        let events = self.sim.drain_events().collect::<Vec<_>>();
+        let mut report = ctx.report.get();
+        report.record_sim_events(&events);
+        ctx.report.set(report);
+        if report.collapsed {
+            self.sim.set_time_scale(COLLAPSE_TIME_SCALE);
+            if !self.collapse_reported && self.is_dm(&self.local_user) {
+                self.send_collapse(&ctx);
+            }
+        }
+        let settings = ctx.settings.get();
+        crate::audio::play_sim_events(ctx.audio, events.iter().copied(), &settings);
+        crate::announce::sim_events(ctx.announcer, events);
 
-        super::State::Main(self)
+        super::State::Main(Box::new(self))
     }
 
     pub fn handle_mouse_event(&mut self, event: crate::MouseEvent, ctx: StateContext) {
+        let scale = ctx.settings.get().ui_scale();
         if self.is_dm(&self.local_user) {
             if event.is_left_press() {
                 let (mx, my) = ctx.input_state.mouse_position;
@@ -109,28 +252,54 @@ impl Main {
                     .iter()
                     .enumerate()
                     .find_map(|(index, _)| {
-                        if crate::collides([mx, my], &Self::room_type_bounds(index)) {
+                        if crate::collides([mx, my], &Self::room_type_bounds(index, scale)) {
                             Some(index)
                         } else {
                             None
                         }
                     });
-                if let Some(index) = clicked {
-                    ctx.ws.send(shared::viewer::Command::Custom(
+                let time_scale = TIME_SCALES
+                    .iter()
+                    .enumerate()
+                    .find_map(|(index, time_scale)| {
+                        if crate::collides([mx, my], &Self::time_scale_bounds(index, scale)) {
+                            Some(*time_scale)
+                        } else {
+                            None
+                        }
+                    });
+                let undo = self.can_undo_restart(*ctx.time)
+                    && crate::collides([mx, my], &Self::undo_restart_bounds(scale));
+                if undo {
+                    if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
+                        self.room.id,
+                        shared::CustomMessage::UndoRestart,
+                    )) {
+                        log::warn!("{}", err);
+                    }
+                } else if let Some(index) = clicked {
+                    if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
                         self.room.id,
                         shared::CustomMessage::StartGame(index as _),
-                    ));
+                    )) {
+                        log::warn!("{}", err);
+                    }
+                } else if let Some(time_scale) = time_scale {
+                    if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
+                        self.room.id,
+                        shared::CustomMessage::SetTimeScale(time_scale),
+                    )) {
+                        log::warn!("{}", err);
+                    }
                 } else {
                     let (mx, my) = ctx.input_state.mouse_position;
-                    let clicked = self.room.users[1..].iter().find(|user| {
-                        let bbox = self.username_bbox(user).unwrap();
-                        crate::collides([mx, my], &bbox)
-                    });
-                    if let Some(user) = clicked {
-                        ctx.ws.send(shared::viewer::Command::Custom(
+                    if let Some(user) = self.player_at([mx, my], scale) {
+                        if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
                             self.room.id,
                             shared::CustomMessage::AssignClick(user.id, 1),
-                        ));
+                        )) {
+                            log::warn!("{}", err);
+                        }
                     }
                 }
             }
@@ -139,86 +308,224 @@ impl Main {
                 match event {
                     MouseEvent::Button(state, crate::MouseButton::Left) => match state {
                         ElementState::Pressed => {
-                            let can_click = !self.local_click_in_flight && self.sim.all_sleeping();
-
-                            if can_click {
-                                let (mx, my) = ctx.input_state.mouse_position;
-                                let [x, y] = crate::sim::Sim::screen_to_world(
-                                    ctx.g.gfx().viewport(),
-                                    mx,
-                                    my,
-                                );
+                            let (mx, my) = ctx.input_state.mouse_position;
+                            let [x, y] =
+                                crate::sim::Sim::screen_to_world(ctx.g.gfx().viewport(), mx, my);
+                            if self.press([x, y], *ctx.time) {
                                 let clicked = self.sim.body_at_point(x, y).is_some();
                                 if clicked {
                                     self.local_click_in_flight = true;
-                                    ctx.ws.send(shared::viewer::Command::Custom(
+                                    if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
                                         self.room.id,
                                         shared::CustomMessage::RemoveBody(x, y),
-                                    ));
+                                    )) {
+                                        log::warn!("{}", err);
+                                    }
                                 }
                             }
                         }
                         ElementState::Released => {
                             if self.local_click_in_flight || self.moving.is_some() {
                                 let (mx, my) = ctx.input_state.mouse_position;
-                                let [x, y] = crate::sim::Sim::screen_to_world(
-                                    ctx.g.gfx().viewport(),
-                                    mx,
-                                    my,
-                                );
-                                ctx.ws.send(shared::viewer::Command::Custom(
+                                let [x, y] = Self::drag_position(&ctx, mx, my);
+                                if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
                                     self.room.id,
                                     shared::CustomMessage::DropBody(x, y),
-                                ));
+                                )) {
+                                    log::warn!("{}", err);
+                                }
                             }
                         }
                     },
-                    MouseEvent::Moved(mx, my) => {
-                        if self.local_click_in_flight || self.moving.is_some() {
-                            let [x, y] =
-                                crate::sim::Sim::screen_to_world(ctx.g.gfx().viewport(), mx, my);
-                            ctx.ws.send(shared::viewer::Command::Custom(
+                    MouseEvent::Moved(mx, my)
+                        if self.local_click_in_flight || self.moving.is_some() =>
+                    {
+                        let [x, y] = Self::drag_position(&ctx, mx, my);
+                        if let Err(err) = ctx.ws.send_with(
+                            shared::viewer::Command::Custom(
                                 self.room.id,
                                 shared::CustomMessage::MoveBody(x, y),
-                            ));
+                            ),
+                            crate::net::Delivery::BestEffort,
+                        ) {
+                            log::warn!("{}", err);
                         }
                     }
                     _ => {}
                 }
+            } else if event.is_left_press() {
+                let (mx, my) = ctx.input_state.mouse_position;
+                let position = crate::sim::Sim::screen_to_world(ctx.g.gfx().viewport(), mx, my);
+                self.press(position, *ctx.time);
             }
         }
     }
 
-    pub fn render(&self, mut ctx: StateContext) {
-        ctx.g.clear([0.2, 0.2, 0.2, 1.]);
-        self.sim.render(&mut ctx.g);
+    /// Snapped before it's sent so that every client puts the block in the same place.
+    fn drag_position(ctx: &StateContext, mx: f32, my: f32) -> [f32; 2] {
+        let position = crate::sim::Sim::screen_to_world(ctx.g.gfx().viewport(), mx, my);
+        if ctx.input_state.snapping {
+            crate::sim::snap_to_grid(position, ctx.settings.get().snap_grid())
+        } else {
+            position
+        }
+    }
 
-        if let Some((body, colliders)) = &self.moving {
-            let position = body.position();
-            for collider in colliders {
-                if let Some(shape) = collider.shape().as_cuboid() {
-                    let half = shape.half_extents;
-                    let quad = solstice_2d::solstice::quad_batch::Quad::<(f32, f32)>::from(
-                        solstice_2d::Rectangle::new(-half.x, -half.y, half.x * 2., half.y * 2.),
-                    )
-                    .map(|(x, y)| {
-                        let p = rapier2d::na::Point2::new(x, y);
-                        let p = position.transform_point(&p);
-                        solstice_2d::Vertex2D {
-                            position: [p.x, p.y],
-                            uv: [x + 0.5, y + 0.5],
-                            color: [1., 0.2, 0.2, 0.8],
-                        }
-                    });
-                    ctx.g.draw(quad);
-                } else {
-                    log::debug!("unrecognized shape");
-                }
+    /// Whether the local player is allowed to pick up a block right now.
+    fn can_act(&self) -> bool {
+        self.is_next(&self.local_user) && !self.local_click_in_flight && self.sim.all_sleeping()
+    }
+
+    /// Flashes the clicked point if the press can't be acted on. Returns whether it can.
+    fn press(&mut self, position: [f32; 2], time: std::time::Duration) -> bool {
+        let can_act = self.can_act();
+        if !can_act {
+            self.rejected_click = Some(RejectedClick { position, at: time });
+        }
+        can_act
+    }
+
+    /// A new game on `sim` that keeps this one to go back to. Only the game just replaced is
+    /// kept, so undoing twice doesn't go any further back.
+    fn restart(mut self, sim: crate::sim::Sim, time: std::time::Duration) -> Self {
+        self.restarted = None;
+        let chat = std::mem::take(&mut self.chat);
+        let mut next = Self::new(self.local_user.clone(), self.room.clone(), sim).with_chat(chat);
+        next.restarted = Some(Restarted {
+            previous: Box::new(self),
+            at: time,
+        });
+        next
+    }
+
+    /// Back to the game the last restart replaced, with the room as it is now. Someone who
+    /// joined after the restart has nothing to go back to and carries on until the DM resyncs
+    /// them.
+    fn undo_restart(mut self) -> Self {
+        match self.restarted.take() {
+            Some(restarted) => {
+                let mut previous = *restarted.previous;
+                previous.local_user = self.local_user;
+                previous.room = self.room;
+                previous.chat = self.chat;
+                previous
+            }
+            None => self,
+        }
+    }
+
+    /// Whether the DM is still offered to undo the last restart.
+    fn can_undo_restart(&self, time: std::time::Duration) -> bool {
+        self.restarted.as_ref().is_some_and(|restarted| {
+            time.saturating_sub(restarted.at) < shared::UNDO_RESTART_WINDOW
+        })
+    }
+
+    /// Remembers the sim's checksum for the current tick and shares it with the room.
+    fn send_checksum(&mut self, ctx: &StateContext) {
+        let (tick, checksum) = (self.sim.tick(), self.sim.checksum());
+        self.record_checksum(tick, checksum);
+        if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
+            self.room.id,
+            CustomMessage::Checksum(tick, self.applied, checksum),
+        )) {
+            log::warn!("{}", err);
+        }
+    }
+
+    /// Blames whoever took the last turn, which ends the game for the server's records. Only
+    /// ever sent once a game.
+    fn send_collapse(&mut self, ctx: &StateContext) {
+        self.collapse_reported = true;
+        let loser = match self.previous_click {
+            Some(loser) => loser,
+            None => return,
+        };
+        if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
+            self.room.id,
+            CustomMessage::Collapsed(loser),
+        )) {
+            log::warn!("{}", err);
+        }
+    }
+
+    /// The DM's sim is the one everybody else goes back to. Our own checksums are forgotten so
+    /// the rest of the disagreements from before the resync lands don't each send another.
+    fn send_resync(&mut self, ctx: &StateContext) {
+        self.checksums.clear();
+        if let Err(err) = ctx.ws.send(shared::viewer::Command::Custom(
+            self.room.id,
+            CustomMessage::Resync(self.sim.tick(), self.sim.snapshot()),
+        )) {
+            log::warn!("{}", err);
+        }
+    }
+
+    fn record_checksum(&mut self, tick: u64, checksum: u64) {
+        self.checksums.push_back((tick, self.applied, checksum));
+        while self.checksums.len() > CHECKSUM_HISTORY {
+            self.checksums.pop_front();
+        }
+    }
+
+    /// Whether another client's `checksum` disagrees with ours at the same tick, after the same
+    /// events. Ticks we no longer, or never, had a checksum for can't tell either way, and
+    /// neither can one taken with an event more or less applied.
+    fn is_desynced(&self, tick: u64, applied: u64, checksum: u64) -> bool {
+        self.checksums
+            .iter()
+            .any(|(ours_tick, ours_applied, ours)| {
+                *ours_tick == tick && *ours_applied == applied && *ours != checksum
+            })
+    }
+
+    pub fn prepare_render(&mut self) {
+        self.sim.prepare_render();
+    }
+
+    pub fn render<'s>(&'s self, mut ctx: StateContext<'_, '_, 's>) {
+        self.sim.render(&mut ctx.g);
+        let vw = *ctx.g.gfx().viewport();
+        if let Some(pointer) = self.pointer(&vw, ctx.input_state.mouse_position) {
+            let [x, y] = pointer.position;
+            let thickness = POINTER_SIZE / 4.;
+            let horizontal = solstice_2d::Rectangle::new(
+                x - POINTER_SIZE,
+                y - thickness / 2.,
+                POINTER_SIZE * 2.,
+                thickness,
+            );
+            let vertical = solstice_2d::Rectangle::new(
+                x - thickness / 2.,
+                y - POINTER_SIZE,
+                thickness,
+                POINTER_SIZE * 2.,
+            );
+            ctx.g.draw_with_color(horizontal, pointer.color);
+            ctx.g.draw_with_color(vertical, pointer.color);
+        }
+        if let Some(rejected) = self.rejected_click {
+            let elapsed = ctx.time.checked_sub(rejected.at).unwrap_or_default();
+            if elapsed < REJECTED_CLICK_FLASH {
+                let fade = 1. - elapsed.as_secs_f32() / REJECTED_CLICK_FLASH.as_secs_f32();
+                let [x, y] = rejected.position;
+                ctx.g.draw_with_color(
+                    solstice_2d::Circle {
+                        x,
+                        y,
+                        radius: POINTER_SIZE * (2. - fade),
+                        segments: 16,
+                    },
+                    [1., 0., 0., 0.6 * fade],
+                );
             }
         }
 
         ctx.g.set_projection_mode(None);
         let font_id = ctx.resources.sans_font;
+        let scale = ctx.settings.get().ui_scale();
+        let text_scale = TEXT_SCALE * scale;
+        let strings = ctx.settings.get().strings();
         if self.sim.kill_triggered() {
             let vw = ctx.g.gfx().viewport();
             let screen = solstice_2d::Rectangle {
@@ -232,14 +539,14 @@ impl Main {
                 .previous_click
                 .and_then(|id| self.room.users.iter().find(|user| user.id == id));
             if let Some(user) = clicker {
-                let text = format!("{} knocked over the tower!", user.name);
+                let text = strings.with_name(Text::KnockedOverTower, &user.name);
                 ctx.g.print(
                     text,
                     font_id,
-                    TEXT_SCALE * 3.,
+                    text_scale * 3.,
                     solstice_2d::Rectangle {
-                        x: 38.0,
-                        y: screen.height / 2. - TEXT_SCALE * 3. / 2.,
+                        x: 38.0 * scale,
+                        y: screen.height / 2. - text_scale * 3. / 2.,
                         ..screen
                     },
                 );
@@ -249,100 +556,191 @@ impl Main {
         {
             let vw = ctx.g.gfx().viewport();
             let bounds = solstice_2d::Rectangle {
-                x: vw.x() as f32 + 8.,
+                x: vw.x() as f32 + 8. * scale,
                 y: vw.y() as f32,
                 width: vw.width() as f32,
                 height: vw.height() as f32,
             };
-            let room_code_text = format!("ROOM CODE: {}", self.room.id);
+            let room_code = strings.get(Text::RoomCode);
+            let room_code_text = match &self.room.name {
+                Some(name) => format!("{} - {}: {}", name, room_code, self.room.id),
+                None => format!("{}: {}", room_code, self.room.id),
+            };
             ctx.g.print(
                 room_code_text,
                 font_id,
-                TEXT_SCALE,
-                solstice_2d::Rectangle { y: 8., ..bounds },
+                text_scale,
+                solstice_2d::Rectangle {
+                    y: 8. * scale,
+                    ..bounds
+                },
             );
             if let Some(dm) = self.room.users.first() {
-                let text = format!("DM: {}", dm.name);
+                let text = format!("{}: {}", strings.get(Text::Dm), dm.name);
                 ctx.g.print(
                     text,
                     font_id,
-                    TEXT_SCALE,
+                    text_scale,
                     solstice_2d::Rectangle {
-                        y: 8. + TEXT_SCALE,
+                        y: (8. + TEXT_SCALE) * scale,
                         ..bounds
                     },
                 );
             }
-            for (index, user) in self.room.users[1..].iter().enumerate() {
-                let color = if self.is_next(user) {
-                    [1., 1., 0., 1.]
-                } else {
-                    [1., 1., 1., 1.]
-                };
-                ctx.g.set_color(color);
-
-                let click_count = self
-                    .click_queue
-                    .iter()
-                    .filter_map(
-                        |(id, count)| {
-                            if id == &user.id {
-                                Some(*count)
-                            } else {
-                                None
-                            }
-                        },
-                    )
-                    .sum::<u32>();
-                let text = if user.id == self.local_user.id {
-                    format!("{}. *{}*: {}", index + 1, user.name, click_count)
-                } else {
-                    format!("{}. {}: {}", index + 1, user.name, click_count)
-                };
-                let bounds = self.username_bbox(user).unwrap();
-                ctx.g.print(text, font_id, TEXT_SCALE, bounds);
+            for label in self.player_labels(scale) {
+                ctx.g.set_color(label.color);
+                ctx.g
+                    .print(label.text, font_id, label.bounds.height, label.bounds);
                 if self.is_dm(&self.local_user) {
-                    ctx.g.stroke(bounds);
+                    ctx.g.stroke(label.bounds);
                 }
             }
         }
 
+        ctx.g.set_color([1., 1., 1., 1.]);
+        let bottom = ctx.g.gfx().viewport().height() as f32 - 8. * scale;
+        self.chat.render(&mut ctx, 8. * scale, bottom, text_scale);
+
         if self.is_dm(&self.local_user) {
             ctx.g.set_color([1., 1., 1., 1.]);
             for (index, room_ty) in crate::sim::ROOM_TYPES.iter().enumerate() {
-                let bounds = Self::room_type_bounds(index);
-                ctx.g.print(room_ty.name, font_id, 32., bounds);
+                let bounds = Self::room_type_bounds(index, scale);
+                ctx.g.print(room_ty.name, font_id, bounds.height, bounds);
+                ctx.g.stroke(bounds);
+            }
+            for (index, time_scale) in TIME_SCALES.iter().enumerate() {
+                let bounds = Self::time_scale_bounds(index, scale);
+                let text = format!("{}x", time_scale);
+                ctx.g.print(text, font_id, bounds.height, bounds);
+                ctx.g.stroke(bounds);
+            }
+            if self.can_undo_restart(*ctx.time) {
+                let bounds = Self::undo_restart_bounds(scale);
+                let text = strings.get(Text::UndoRestart);
+                ctx.g.print(text, font_id, bounds.height, bounds);
                 ctx.g.stroke(bounds);
             }
         }
     }
 
-    fn username_bbox(&self, user: &User) -> Option<solstice_2d::Rectangle> {
-        self.room.users[1..]
+    /// `None` unless it's the local player's turn.
+    fn pointer(
+        &self,
+        vw: &solstice_2d::solstice::viewport::Viewport<i32>,
+        (mx, my): (f32, f32),
+    ) -> Option<Pointer> {
+        if !self.is_next(&self.local_user) {
+            return None;
+        }
+        let [x, y] = crate::sim::Sim::screen_to_world(vw, mx, my);
+        let color = if self.local_click_in_flight || self.moving.is_some() {
+            [1., 0.2, 0.2, 1.]
+        } else if self.sim.body_at_point(x, y).is_some() {
+            [1., 1., 0., 1.]
+        } else {
+            [1., 1., 1., 0.8]
+        };
+        Some(Pointer {
+            position: [x, y],
+            color,
+        })
+    }
+
+    /// Everyone but the DM. Empty rather than panicking when the room briefly has no one in it.
+    fn players(&self) -> &[User] {
+        self.room.users.get(1..).unwrap_or_default()
+    }
+
+    fn username_bbox(&self, user: &User, scale: f32) -> Option<solstice_2d::Rectangle> {
+        self.players()
             .iter()
             .position(|other| user.id == other.id)
-            .map(|index| solstice_2d::Rectangle {
-                x: 8.,
-                y: (TEXT_SCALE * 1.1 * (index + 2) as f32 + 8.).round(),
-                width: 200.,
-                height: TEXT_SCALE,
+            .map(|index| username_bounds(index, scale))
+    }
+
+    /// The player whose name is under `position`, in screen space.
+    fn player_at(&self, position: [f32; 2], scale: f32) -> Option<&User> {
+        self.players().iter().find(|user| {
+            self.username_bbox(user, scale)
+                .is_some_and(|bbox| crate::collides(position, &bbox))
+        })
+    }
+
+    /// Each player's line in the list, with how many clicks they have left.
+    fn player_labels(&self, scale: f32) -> Vec<PlayerLabel> {
+        self.players()
+            .iter()
+            .enumerate()
+            .map(|(index, user)| {
+                let color = if !user.connected {
+                    DISCONNECTED_COLOR
+                } else if self.is_next(user) {
+                    [1., 1., 0., 1.]
+                } else {
+                    [1., 1., 1., 1.]
+                };
+                let click_count = self.click_queue.remaining(user.id);
+                let text = if user.id == self.local_user.id {
+                    format!("{}. *{}*: {}", index + 1, user.name, click_count)
+                } else {
+                    format!("{}. {}: {}", index + 1, user.name, click_count)
+                };
+                PlayerLabel {
+                    text,
+                    bounds: username_bounds(index, scale),
+                    color,
+                }
             })
+            .collect()
     }
 
-    fn room_type_bounds(index: usize) -> solstice_2d::Rectangle {
-        solstice_2d::Rectangle {
-            x: 720.,
-            y: index as f32 * 32. * 1.5 + 32.,
-            width: 480.,
-            height: 32.,
-        }
+    fn room_type_bounds(index: usize, scale: f32) -> solstice_2d::Rectangle {
+        crate::scale_rect(
+            solstice_2d::Rectangle {
+                x: 720.,
+                y: index as f32 * 32. * 1.5 + 32.,
+                width: 480.,
+                height: 32.,
+            },
+            scale,
+        )
+    }
+
+    /// In a row beneath the room types.
+    fn time_scale_bounds(index: usize, scale: f32) -> solstice_2d::Rectangle {
+        let below = Self::room_type_bounds(crate::sim::ROOM_TYPES.len(), 1.);
+        crate::scale_rect(
+            solstice_2d::Rectangle {
+                x: below.x + index as f32 * 120.,
+                width: 96.,
+                ..below
+            },
+            scale,
+        )
+    }
+
+    /// Beneath the time scales.
+    fn undo_restart_bounds(scale: f32) -> solstice_2d::Rectangle {
+        crate::scale_rect(
+            Self::room_type_bounds(crate::sim::ROOM_TYPES.len() + 1, 1.),
+            scale,
+        )
     }
 
     fn is_next(&self, user: &User) -> bool {
-        self.click_queue
-            .front()
-            .map(|(id, _)| id == &user.id)
-            .unwrap_or(false)
+        self.click_queue.current() == Some(user.id)
+    }
+
+    pub fn room(&self) -> &InitialRoomState {
+        &self.room
+    }
+
+    pub fn score(&self, player_id: shared::PlayerID) -> u32 {
+        self.click_queue.used(player_id)
+    }
+
+    pub fn is_local_dm(&self) -> bool {
+        self.is_dm(&self.local_user)
     }
 
     fn is_dm(&self, user: &User) -> bool {
@@ -353,3 +751,193 @@ impl Main {
         }
     }
 }
+
+/// Where the `index`th player, not counting the DM, is listed. Their name is printed at the
+/// height of these bounds so the two always line up.
+fn username_bounds(index: usize, scale: f32) -> solstice_2d::Rectangle {
+    crate::scale_rect(
+        solstice_2d::Rectangle {
+            x: 8.,
+            y: (TEXT_SCALE * 1.1 * (index + 2) as f32 + 8.).round(),
+            width: 200.,
+            height: TEXT_SCALE,
+        },
+        scale,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{player, room_with};
+
+    #[test]
+    fn clicks_are_not_logged_by_default() {
+        let click = log::Metadata::builder()
+            .level(CLICK_LOG_LEVEL)
+            .target(module_path!())
+            .build();
+        assert!(!shared::log_filter::LogFilter::default().enabled(&click));
+    }
+
+    #[test]
+    fn pointer_follows_the_mouse_on_your_turn() {
+        let room = room_with(vec![player("1"), player("2")]);
+        let mut main = Main::new(player("2"), room, crate::sim::Sim::new());
+        let vw = solstice_2d::solstice::viewport::Viewport::new(0, 0, 1280, 720);
+        assert_eq!(main.pointer(&vw, (320., 180.)), None);
+
+        main.click_queue
+            .apply(&ChangeType::Custom(CustomMessage::AssignClick(
+                main.local_user.id,
+                1,
+            )));
+        let pointer = main.pointer(&vw, (320., 180.)).unwrap();
+        assert_eq!(
+            pointer.position,
+            crate::sim::Sim::screen_to_world(&vw, 320., 180.)
+        );
+        assert!((pointer.position[0] + 16. / 9. / 4.).abs() < 1e-6);
+        assert!((pointer.position[1] - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn only_rejected_presses_flash() {
+        let room = room_with(vec![player("1"), player("2")]);
+        let mut main = Main::new(player("2"), room, crate::sim::Sim::tower());
+        assert!(main.sim.settle(60 * 10));
+
+        let time = std::time::Duration::from_secs(3);
+        assert!(!main.press([0., 0.], time));
+        assert_eq!(
+            main.rejected_click,
+            Some(RejectedClick {
+                position: [0., 0.],
+                at: time
+            })
+        );
+
+        main.rejected_click = None;
+        main.click_queue
+            .apply(&ChangeType::Custom(CustomMessage::AssignClick(
+                main.local_user.id,
+                1,
+            )));
+        assert!(main.press([0., 0.], time));
+        assert_eq!(main.rejected_click, None);
+    }
+
+    #[test]
+    fn scores_are_the_clicks_used_this_game() {
+        let room = room_with(vec![player("1"), player("2"), player("3")]);
+        let mut main = Main::new(player("2"), room, crate::sim::Sim::new());
+        let player = "2".parse().unwrap();
+        for change in [
+            CustomMessage::AssignClick(player, 2),
+            CustomMessage::DropBody(0., 0.),
+            CustomMessage::DropBody(0., 0.),
+        ]
+        .iter()
+        {
+            main.click_queue.apply(&ChangeType::Custom(change.clone()));
+        }
+        assert_eq!(main.score(player), 2);
+        assert_eq!(main.score("3".parse().unwrap()), 0);
+
+        main.click_queue
+            .apply(&ChangeType::Custom(CustomMessage::StartGame(0)));
+        assert_eq!(main.score(player), 0);
+    }
+
+    #[test]
+    fn checksums_only_compare_after_the_same_events() {
+        let room = room_with(vec![player("1"), player("2")]);
+        let mut main = Main::new(player("2"), room, crate::sim::Sim::tower());
+        main.record_checksum(60, 1);
+        assert!(main.is_desynced(60, 0, 2));
+        assert!(!main.is_desynced(60, 0, 1));
+        // a client that had already applied the next click can't be compared with
+        assert!(!main.is_desynced(60, 1, 2));
+        assert!(!main.is_desynced(120, 0, 2));
+    }
+
+    #[test]
+    fn undoing_a_restart_brings_back_the_old_tower() {
+        let room = room_with(vec![player("1"), player("2")]);
+        let mut main = Main::new(player("1"), room, crate::sim::Sim::tower());
+        assert!(main.sim.settle(60 * 10));
+        let handle = main.sim.removable_bodies()[0];
+        main.moving = main.sim.try_remove_body(handle);
+        let before = main.sim.current_layout();
+
+        let time = std::time::Duration::from_secs(10);
+        let main = main.restart(crate::sim::Sim::pyramid(), time);
+        assert_ne!(main.sim.current_layout(), before);
+        assert!(main.can_undo_restart(time + std::time::Duration::from_secs(1)));
+        assert!(!main.can_undo_restart(time + shared::UNDO_RESTART_WINDOW));
+
+        let main = main.undo_restart();
+        assert_eq!(main.sim.current_layout(), before);
+        assert!(main.moving.is_some());
+        // nothing further back to go to
+        assert!(!main.can_undo_restart(time));
+        let main = main.undo_restart();
+        assert_eq!(main.sim.current_layout(), before);
+    }
+
+    #[test]
+    fn rooms_with_hardly_anyone_in_them_dont_panic() {
+        // where the first player's name would be
+        let bounds = username_bounds(0, 1.);
+        let first_name = [bounds.x + 1., bounds.y + 1.];
+
+        let empty = Main::new(player("1"), room_with(vec![]), crate::sim::Sim::tower());
+        assert!(empty.players().is_empty());
+        assert!(!empty.is_local_dm());
+        assert!(empty.username_bbox(&player("1"), 1.).is_none());
+        assert!(empty.player_labels(1.).is_empty());
+        assert!(empty.player_at(first_name, 1.).is_none());
+
+        let alone = Main::new(
+            player("1"),
+            room_with(vec![player("1")]),
+            crate::sim::Sim::tower(),
+        );
+        assert!(alone.players().is_empty());
+        assert!(alone.is_local_dm());
+        assert!(alone.username_bbox(&player("1"), 1.).is_none());
+        assert!(alone.player_labels(1.).is_empty());
+        assert!(alone.player_at(first_name, 1.).is_none());
+
+        let pair = Main::new(
+            player("1"),
+            room_with(vec![player("1"), player("2")]),
+            crate::sim::Sim::tower(),
+        );
+        let labels = pair.player_labels(1.);
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].bounds, bounds);
+        assert_eq!(
+            pair.player_at(first_name, 1.).map(|user| user.id),
+            Some(player("2").id)
+        );
+    }
+
+    #[test]
+    fn ui_scale_scales_username_bounds() {
+        for index in 0..4 {
+            let normal = username_bounds(index, 1.);
+            let doubled = username_bounds(index, 2.);
+            assert_eq!(normal.height, TEXT_SCALE);
+            assert_eq!(
+                [doubled.x, doubled.y, doubled.width, doubled.height],
+                [
+                    normal.x * 2.,
+                    normal.y * 2.,
+                    normal.width * 2.,
+                    normal.height * 2.
+                ]
+            );
+        }
+    }
+}
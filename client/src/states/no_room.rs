@@ -1,12 +1,29 @@
 use super::StateContext;
+use crate::strings::Text;
 use solstice_2d::Draw;
 
+pub const CLEAR_COLOR: [f32; 4] = [1., 0., 0., 1.];
+
 #[derive(Debug, Default)]
 pub struct NoRoom {
     elapsed: std::time::Duration,
+    /// Shown over the spinner when there's no point in waiting.
+    message: Option<Text>,
 }
 
 impl NoRoom {
+    /// For when the server is on another version of the game, asking the player to refresh.
+    pub fn outdated() -> Self {
+        Self {
+            message: Some(Text::Outdated),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_outdated(&self) -> bool {
+        self.message == Some(Text::Outdated)
+    }
+
     pub fn update(&mut self, dt: std::time::Duration) {
         self.elapsed += dt;
     }
@@ -15,8 +32,6 @@ impl NoRoom {
         let width = ctx.g.gfx().viewport().width() as f32;
         let height = ctx.g.gfx().viewport().height() as f32;
 
-        ctx.g.clear([1., 0., 0., 1.]);
-
         let count = 10;
         let geometry = solstice_2d::Circle {
             x: 0.0,
@@ -35,5 +50,22 @@ impl NoRoom {
                 ..geometry
             });
         }
+
+        if let Some(message) = self.message {
+            let scale = ctx.settings.get().ui_scale();
+            let text = ctx.settings.get().strings().get(message);
+            ctx.g.set_color([1., 1., 1., 1.]);
+            ctx.g.print(
+                text,
+                ctx.resources.sans_font,
+                32. * scale,
+                solstice_2d::Rectangle {
+                    x: 16. * scale,
+                    y: 16. * scale,
+                    width,
+                    height: 32. * scale,
+                },
+            );
+        }
     }
 }
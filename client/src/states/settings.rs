@@ -0,0 +1,170 @@
+use super::StateContext;
+use crate::settings::{Store, Toggle};
+use crate::strings::Text;
+use solstice_2d::Stroke;
+
+/// Where the way into this screen is drawn on the screens that offer it.
+pub const OPEN_BOUNDS: solstice_2d::Rectangle = solstice_2d::Rectangle {
+    x: 1040.,
+    y: 0.,
+    width: 160.,
+    height: 24.,
+};
+
+/// The music volume, text size and language rows sit right under the toggles.
+const VOLUME_ROW: usize = Toggle::ALL.len();
+const UI_SCALE_ROW: usize = VOLUME_ROW + 1;
+const LOCALE_ROW: usize = UI_SCALE_ROW + 1;
+
+pub struct SettingsMenu {
+    previous: Box<super::State>,
+    store: Store,
+}
+
+impl SettingsMenu {
+    pub fn new(previous: super::State) -> Self {
+        Self {
+            previous: Box::new(previous),
+            store: Store::default(),
+        }
+    }
+
+    pub fn previous(&self) -> &super::State {
+        &self.previous
+    }
+
+    /// The screen underneath keeps running so that, say, a lobby doesn't miss its game starting.
+    pub fn update(mut self, dt: std::time::Duration, ctx: StateContext) -> super::State {
+        self.previous = Box::new(self.previous.update(dt, ctx));
+        super::State::Settings(self)
+    }
+
+    pub fn handle_mouse_event(self, event: crate::MouseEvent, ctx: StateContext) -> super::State {
+        if event.is_left_press() {
+            let (mx, my) = ctx.input_state.mouse_position;
+            let mut settings = ctx.settings.get();
+            let scale = settings.ui_scale();
+            if crate::collides([mx, my], &Self::back_bounds(scale)) {
+                return *self.previous;
+            }
+            let hit = Toggle::ALL
+                .iter()
+                .enumerate()
+                .find(|(index, _)| crate::collides([mx, my], &Self::toggle_bounds(*index, scale)));
+            if let Some((_, toggle)) = hit {
+                settings.toggle(*toggle);
+            } else if crate::collides([mx, my], &Self::toggle_bounds(VOLUME_ROW, scale)) {
+                settings.cycle_music_volume();
+                ctx.audio
+                    .set_music_volume(crate::audio::music_volume(settings.music_volume));
+            } else if crate::collides([mx, my], &Self::toggle_bounds(UI_SCALE_ROW, scale)) {
+                settings.cycle_ui_scale();
+            } else if crate::collides([mx, my], &Self::toggle_bounds(LOCALE_ROW, scale)) {
+                settings.cycle_locale();
+            }
+            if settings != ctx.settings.get() {
+                ctx.settings.set(settings);
+                if let Err(err) = self.store.save(&settings) {
+                    log::warn!("failed to save settings: {}", err);
+                }
+            }
+        }
+        super::State::Settings(self)
+    }
+
+    pub fn render(&self, mut ctx: StateContext) {
+        let font_id = ctx.resources.sans_font;
+        let vw = ctx.g.gfx().viewport();
+        let bounds = solstice_2d::Rectangle {
+            x: vw.x() as f32,
+            y: vw.y() as f32,
+            width: vw.width() as f32,
+            height: vw.height() as f32,
+        };
+
+        let settings = ctx.settings.get();
+        let scale = settings.ui_scale();
+        let text_scale = 32. * scale;
+        let strings = settings.strings();
+        ctx.g.set_color(settings.theme().text);
+        ctx.g
+            .print(strings.get(Text::Settings), font_id, text_scale, bounds);
+        for (index, toggle) in Toggle::ALL.iter().enumerate() {
+            let bounds = Self::toggle_bounds(index, scale);
+            let mark = if settings.get(*toggle) { "x" } else { " " };
+            ctx.g.print(
+                format!("[{}] {}", mark, strings.get(toggle.text())),
+                font_id,
+                text_scale,
+                bounds,
+            );
+        }
+        ctx.g.print(
+            format!(
+                "{}: {}%",
+                strings.get(Text::MusicVolume),
+                settings.music_volume
+            ),
+            font_id,
+            text_scale,
+            Self::toggle_bounds(VOLUME_ROW, scale),
+        );
+        ctx.g.print(
+            format!(
+                "{}: {}%",
+                strings.get(Text::TextSize),
+                settings.ui_scale_percent
+            ),
+            font_id,
+            text_scale,
+            Self::toggle_bounds(UI_SCALE_ROW, scale),
+        );
+        ctx.g.print(
+            format!(
+                "{}: {}",
+                strings.get(Text::Language),
+                settings.locale.name()
+            ),
+            font_id,
+            text_scale,
+            Self::toggle_bounds(LOCALE_ROW, scale),
+        );
+        let back = Self::back_bounds(scale);
+        ctx.g
+            .print(strings.get(Text::Back), font_id, text_scale, back);
+        ctx.g.stroke(back);
+        ctx.g.set_color([1., 1., 1., 1.]);
+    }
+
+    /// Draws the way in, for the screens that offer it.
+    pub fn render_open_button(ctx: &mut StateContext) {
+        let font_id = ctx.resources.sans_font;
+        let settings = ctx.settings.get();
+        ctx.g.set_color(settings.theme().text);
+        let strings = settings.strings();
+        ctx.g
+            .print(strings.get(Text::Settings), font_id, 16., OPEN_BOUNDS);
+        ctx.g.stroke(OPEN_BOUNDS);
+        ctx.g.set_color([1., 1., 1., 1.]);
+    }
+
+    fn toggle_bounds(index: usize, scale: f32) -> solstice_2d::Rectangle {
+        crate::scale_rect(
+            solstice_2d::Rectangle {
+                x: 32.,
+                y: index as f32 * 32. * 1.5 + 48.,
+                width: 480.,
+                height: 32.,
+            },
+            scale,
+        )
+    }
+
+    /// A row below the language.
+    fn back_bounds(scale: f32) -> solstice_2d::Rectangle {
+        solstice_2d::Rectangle {
+            width: 160. * scale,
+            ..Self::toggle_bounds(LOCALE_ROW + 1, scale)
+        }
+    }
+}
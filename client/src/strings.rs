@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Locale {
+    #[default]
+    English,
+    Spanish,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::English, Locale::Spanish];
+
+    /// In the language itself, so that it can be found by someone who can't read the current one.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Locale::English => "English",
+            Locale::Spanish => "Español",
+        }
+    }
+}
+
+/// Every piece of UI text. Entries containing `{name}` are meant for `Strings::with_name`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Text {
+    Room,
+    RoomCode,
+    Dm,
+    WaitingForDm,
+    Players,
+    PlayersNeeded,
+    KnockedOverTower,
+    EditTower,
+    Play,
+    Settings,
+    Back,
+    DarkTheme,
+    ColorblindPalette,
+    Sound,
+    MusicVolume,
+    TextSize,
+    Language,
+    Outdated,
+    UndoRestart,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Strings {
+    locale: Locale,
+}
+
+impl Strings {
+    pub fn new(locale: Locale) -> Self {
+        Self { locale }
+    }
+
+    pub fn get(&self, text: Text) -> &'static str {
+        match self.locale {
+            Locale::English => english(text),
+            Locale::Spanish => spanish(text),
+        }
+    }
+
+    pub fn with_name(&self, text: Text, name: &str) -> String {
+        self.get(text).replace("{name}", name)
+    }
+}
+
+fn english(text: Text) -> &'static str {
+    match text {
+        Text::Room => "Room",
+        Text::RoomCode => "ROOM CODE",
+        Text::Dm => "DM",
+        Text::WaitingForDm => "Waiting For DM to start room.",
+        Text::Players => "Players",
+        Text::PlayersNeeded => "More players needed to start",
+        Text::KnockedOverTower => "{name} knocked over the tower!",
+        Text::EditTower => "Build a tower",
+        Text::Play => "Play",
+        Text::Settings => "Settings",
+        Text::Back => "Back",
+        Text::DarkTheme => "Dark theme",
+        Text::ColorblindPalette => "Colorblind palette",
+        Text::Sound => "Sound",
+        Text::MusicVolume => "Music volume",
+        Text::TextSize => "Text size",
+        Text::Language => "Language",
+        Text::Outdated => "This version of the game is out of date. Please refresh.",
+        Text::UndoRestart => "Undo restart",
+    }
+}
+
+fn spanish(text: Text) -> &'static str {
+    match text {
+        Text::Room => "Sala",
+        Text::RoomCode => "CÓDIGO DE SALA",
+        Text::Dm => "DM",
+        Text::WaitingForDm => "Esperando a que el DM empiece la sala.",
+        Text::Players => "Jugadores",
+        Text::PlayersNeeded => "Faltan jugadores para empezar",
+        Text::KnockedOverTower => "¡{name} derribó la torre!",
+        Text::EditTower => "Construir una torre",
+        Text::Play => "Jugar",
+        Text::Settings => "Ajustes",
+        Text::Back => "Volver",
+        Text::DarkTheme => "Tema oscuro",
+        Text::ColorblindPalette => "Paleta para daltónicos",
+        Text::Sound => "Sonido",
+        Text::MusicVolume => "Volumen de la música",
+        Text::TextSize => "Tamaño del texto",
+        Text::Language => "Idioma",
+        Text::Outdated => "Esta versión del juego está desactualizada. Recarga la página.",
+        Text::UndoRestart => "Deshacer reinicio",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switching_locale_switches_strings() {
+        let english = Strings::new(Locale::default());
+        let spanish = Strings::new(Locale::Spanish);
+
+        assert_eq!(english.get(Text::Back), "Back");
+        assert_eq!(spanish.get(Text::Back), "Volver");
+        assert_eq!(
+            english.with_name(Text::KnockedOverTower, "Alice"),
+            "Alice knocked over the tower!"
+        );
+        assert_eq!(
+            spanish.with_name(Text::KnockedOverTower, "Alice"),
+            "¡Alice derribó la torre!"
+        );
+    }
+}
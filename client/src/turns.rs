@@ -0,0 +1,57 @@
+use shared::viewer::ChangeType;
+use shared::{CustomMessage, PlayerID};
+
+/// Whose turn it is. Players go in the order the DM assigned them clicks, each keeping the turn
+/// until those clicks are used up. Every client builds its own from the room's messages so they
+/// all agree as long as they've seen the same ones.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClickQueue {
+    queue: std::collections::VecDeque<(PlayerID, u32)>,
+    /// Clicks each player has used up since the game started.
+    used: std::collections::HashMap<PlayerID, u32>,
+}
+
+impl ClickQueue {
+    pub fn apply(&mut self, change: &ChangeType<CustomMessage>) {
+        match change {
+            ChangeType::Custom(CustomMessage::AssignClick(player_id, count)) => {
+                self.queue.push_back((*player_id, *count));
+            }
+            ChangeType::Custom(CustomMessage::DropBody(_, _)) => {
+                if let Some((player_id, count)) = self.queue.front_mut() {
+                    *self.used.entry(*player_id).or_default() += 1;
+                    *count = count.saturating_sub(1);
+                    if *count == 0 {
+                        self.queue.pop_front();
+                    }
+                }
+            }
+            ChangeType::Custom(msg) if msg.starts_game() => {
+                self.queue.clear();
+                self.used.clear();
+            }
+            ChangeType::UserLeave(player_id) => {
+                self.queue.retain(|(id, _count)| id != player_id);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn current(&self) -> Option<PlayerID> {
+        self.queue.front().map(|(player_id, _count)| *player_id)
+    }
+
+    /// Clicks still coming to `player_id` across all of their turns.
+    pub fn remaining(&self, player_id: PlayerID) -> u32 {
+        self.queue
+            .iter()
+            .filter(|(id, _count)| *id == player_id)
+            .map(|(_id, count)| count)
+            .sum()
+    }
+
+    /// Clicks `player_id` has used up this game, which is what they score.
+    pub fn used(&self, player_id: PlayerID) -> u32 {
+        self.used.get(&player_id).copied().unwrap_or(0)
+    }
+}
@@ -5,19 +5,69 @@ fn to_js<E: std::fmt::Display>(v: E) -> JsValue {
     JsValue::from_str(&format!("{}", v))
 }
 
+/// Replaced by `setLogLevel`.
+static LOG_FILTER: std::sync::RwLock<shared::log_filter::LogFilter> = std::sync::RwLock::new(
+    shared::log_filter::LogFilter::new(shared::DEFAULT_LOG_LEVEL),
+);
+
+/// Writes to the browser console, filtered by `LOG_FILTER`.
+struct ConsoleLogger;
+
+impl log::Log for ConsoleLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        LOG_FILTER
+            .read()
+            .map_or(true, |filter| filter.enabled(metadata))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = JsValue::from_str(&format!(
+            "{} {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+        match record.level() {
+            log::Level::Error => web_sys::console::error_1(&line),
+            log::Level::Warn => web_sys::console::warn_1(&line),
+            log::Level::Info => web_sys::console::info_1(&line),
+            log::Level::Debug | log::Level::Trace => web_sys::console::debug_1(&line),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
 #[wasm_bindgen(start)]
 pub fn js_main() {
-    // #[cfg(debug_assertions)]
-    let level = log::Level::Debug;
-    // #[cfg(not(debug_assertions))]
-    // let level = log::Level::Error;
-    wasm_logger::init(wasm_logger::Config::new(level));
+    static LOGGER: ConsoleLogger = ConsoleLogger;
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(shared::DEFAULT_LOG_LEVEL);
+    }
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
 }
 
+/// Takes the same filters as `RUST_LOG` does natively, e.g. `"warn"` for production or
+/// `"debug,client::states::main=info"` to quiet the game screen down.
+#[wasm_bindgen(js_name = setLogLevel)]
+pub fn set_log_level(filter: &str) -> Result<(), JsValue> {
+    let filter = filter
+        .parse::<shared::log_filter::LogFilter>()
+        .map_err(to_js)?;
+    log::set_max_level(filter.max_level());
+    if let Ok(mut current) = LOG_FILTER.write() {
+        *current = filter;
+    }
+    Ok(())
+}
+
 #[wasm_bindgen(js_name = Resources)]
 pub struct ResourcesWrapper {
     sans_font_data: Option<Vec<u8>>,
+    music_data: Option<Vec<u8>>,
 }
 
 #[wasm_bindgen(js_class = Resources)]
@@ -26,17 +76,156 @@ impl ResourcesWrapper {
     pub fn new() -> Self {
         Self {
             sans_font_data: None,
+            music_data: None,
         }
     }
 
+    /// Optional, the game bundles a font of its own.
     pub fn set_sans_font_data(&mut self, data: Vec<u8>) {
         self.sans_font_data = Some(data);
     }
+
+    /// Any format the browser can decode.
+    pub fn set_music_data(&mut self, data: Vec<u8>) {
+        self.music_data = Some(data);
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ContextStatus {
+    Ok,
+    Lost,
+    Restored,
+}
+
+fn graphics_context(
+    canvas: &web_sys::HtmlCanvasElement,
+) -> Result<solstice_2d::solstice::Context, JsValue> {
+    use wasm_bindgen::JsCast;
+    // needed for a transparent clear color to show the page underneath
+    let options = JsValue::from_serde(&serde_json::json!({ "alpha": true })).map_err(to_js)?;
+    let webgl_context = canvas
+        .get_context_with_context_options("webgl", &options)?
+        .ok_or_else(|| JsValue::from_str("could not get a webgl context"))?
+        .dyn_into::<web_sys::WebGlRenderingContext>()?;
+    let ctx = solstice_2d::solstice::glow::Context::from_webgl1_context(webgl_context);
+    Ok(solstice_2d::solstice::Context::new(ctx))
+}
+
+/// Frames are timed by the browser's timestamps and presented by the browser once they're done.
+struct WebPlatform {
+    clock: crate::clock::ManualClock,
+    canvas: web_sys::HtmlCanvasElement,
+    context_status: std::rc::Rc<std::cell::Cell<ContextStatus>>,
+    on_context_lost_callback: Closure<dyn FnMut(web_sys::Event)>,
+    on_context_restored_callback: Closure<dyn FnMut(web_sys::Event)>,
+}
+
+impl WebPlatform {
+    /// Listens for `canvas` losing and regaining its context until dropped.
+    fn new(
+        canvas: web_sys::HtmlCanvasElement,
+        clock: crate::clock::ManualClock,
+    ) -> Result<Self, JsValue> {
+        use wasm_bindgen::JsCast;
+        let context_status = std::rc::Rc::new(std::cell::Cell::new(ContextStatus::Ok));
+        let on_context_lost_callback = {
+            let context_status = context_status.clone();
+            Closure::wrap(Box::new(move |event: web_sys::Event| {
+                // without this the browser will never attempt to restore the context
+                event.prevent_default();
+                log::warn!("WebGL context lost");
+                context_status.set(ContextStatus::Lost);
+            }) as Box<dyn FnMut(web_sys::Event)>)
+        };
+        let on_context_restored_callback = {
+            let context_status = context_status.clone();
+            Closure::wrap(Box::new(move |_event: web_sys::Event| {
+                log::info!("WebGL context restored");
+                context_status.set(ContextStatus::Restored);
+            }) as Box<dyn FnMut(web_sys::Event)>)
+        };
+        canvas.add_event_listener_with_callback(
+            "webglcontextlost",
+            on_context_lost_callback.as_ref().unchecked_ref(),
+        )?;
+        canvas.add_event_listener_with_callback(
+            "webglcontextrestored",
+            on_context_restored_callback.as_ref().unchecked_ref(),
+        )?;
+        Ok(Self {
+            clock,
+            canvas,
+            context_status,
+            on_context_lost_callback,
+            on_context_restored_callback,
+        })
+    }
+
+    /// Stays true after the context is restored until the next frame rebuilds everything on it.
+    fn is_context_lost(&self) -> bool {
+        self.context_status.get() != ContextStatus::Ok
+    }
+}
+
+impl Drop for WebPlatform {
+    fn drop(&mut self) {
+        use wasm_bindgen::JsCast;
+        self.canvas
+            .remove_event_listener_with_callback(
+                "webglcontextlost",
+                self.on_context_lost_callback.as_ref().unchecked_ref(),
+            )
+            .expect("failed to remove context lost event listener");
+        self.canvas
+            .remove_event_listener_with_callback(
+                "webglcontextrestored",
+                self.on_context_restored_callback.as_ref().unchecked_ref(),
+            )
+            .expect("failed to remove context restored event listener");
+    }
+}
+
+impl crate::platform::Platform for WebPlatform {
+    fn now(&self) -> std::time::Duration {
+        use crate::clock::Clock;
+        self.clock.now()
+    }
+
+    fn context_status(&mut self) -> eyre::Result<crate::platform::ContextStatus> {
+        use crate::platform::ContextStatus as Status;
+        Ok(match self.context_status.get() {
+            ContextStatus::Ok => Status::Ok,
+            ContextStatus::Lost => Status::Lost,
+            ContextStatus::Restored => {
+                let ctx = graphics_context(&self.canvas)
+                    .map_err(|err| eyre::eyre!("could not restore the context: {:?}", err))?;
+                self.context_status.set(ContextStatus::Ok);
+                Status::Restored(Box::new(ctx))
+            }
+        })
+    }
 }
 
 #[wasm_bindgen(js_name = Tension)]
 pub struct GameWrapper {
     inner: super::Game,
+    platform: WebPlatform,
+    on_visibility_change_callback: Closure<dyn FnMut(web_sys::Event)>,
+}
+
+impl Drop for GameWrapper {
+    fn drop(&mut self) {
+        use wasm_bindgen::JsCast;
+        if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+            document
+                .remove_event_listener_with_callback(
+                    "visibilitychange",
+                    self.on_visibility_change_callback.as_ref().unchecked_ref(),
+                )
+                .expect("failed to remove visibility change event listener");
+        }
+    }
 }
 
 #[wasm_bindgen(js_class = Tension)]
@@ -48,54 +237,125 @@ impl GameWrapper {
         network: NetworkWrapper,
         resources: ResourcesWrapper,
     ) -> Result<GameWrapper, JsValue> {
-        let webgl_context = {
-            use wasm_bindgen::JsCast;
-            canvas
-                .get_context("webgl")
-                .unwrap()
-                .unwrap()
-                .dyn_into::<web_sys::WebGlRenderingContext>()
-                .unwrap()
-        };
-        let ctx = solstice_2d::solstice::glow::Context::from_webgl1_context(webgl_context);
-        let ctx = solstice_2d::solstice::Context::new(ctx);
+        let ctx = graphics_context(&canvas)?;
 
         let resources = crate::resources::Resources {
-            sans_font_data: resources
-                .sans_font_data
-                .ok_or(JsValue::from_str("missing debug font data"))?,
+            sans_font_data: resources.sans_font_data,
+            music_data: resources.music_data,
         };
 
         let width = canvas.width();
         let height = canvas.height();
 
-        let time = duration_from_f64(time_ms);
-        let inner = super::Game::new(ctx, time, width as _, height as _, network.inner, resources)
-            .map_err(to_js)?;
-        Ok(Self { inner })
+        // driven by the timestamps handed to `step`
+        let clock = crate::clock::ManualClock::new(duration_from_f64(time_ms));
+        let mut inner = super::Game::new(
+            ctx,
+            duration_from_f64(time_ms),
+            width as _,
+            height as _,
+            network.inner,
+            resources,
+        )
+        .map_err(to_js)?;
+        let audio = match crate::audio::WebAudio::new() {
+            Ok(audio) => {
+                inner.set_audio_backend(Box::new(audio.clone()));
+                Some(audio)
+            }
+            Err(err) => {
+                log::warn!("continuing without sound: {:?}", err);
+                None
+            }
+        };
+
+        let platform = WebPlatform::new(canvas, clock)?;
+        // steps stop while the tab is hidden, so this can't wait to be picked up by one
+        let document = web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or_else(|| JsValue::from_str("could not get the document"))?;
+        let on_visibility_change_callback = {
+            let document = document.clone();
+            Closure::wrap(Box::new(move |_event: web_sys::Event| {
+                use crate::audio::AudioBackend;
+                if let Some(audio) = &audio {
+                    audio.set_music_paused(document.hidden());
+                }
+            }) as Box<dyn FnMut(web_sys::Event)>)
+        };
+        {
+            use wasm_bindgen::JsCast;
+            document.add_event_listener_with_callback(
+                "visibilitychange",
+                on_visibility_change_callback.as_ref().unchecked_ref(),
+            )?;
+        }
+
+        Ok(Self {
+            inner,
+            platform,
+            on_visibility_change_callback,
+        })
     }
 
-    pub fn step(&mut self, time_ms: f64) {
-        self.inner.update(duration_from_f64(time_ms));
+    /// Returns a `{state_changed, collapsed, turn_changed, rendered}` summary of the frame.
+    /// The game keeps stepping while the context is lost, only `rendered` stays false.
+    pub fn step(&mut self, time_ms: f64) -> Result<JsValue, JsValue> {
+        self.platform.clock.set(duration_from_f64(time_ms));
+        crate::platform::run_frame(&mut self.platform, &mut self.inner).map_err(to_js)?;
+        JsValue::from_serde(&self.inner.last_report()).map_err(to_js)
     }
 
-    pub fn handle_mouse_down(&mut self, is_left_button: bool) {
-        let state = winit::event::ElementState::Pressed;
-        let button = match is_left_button {
-            true => winit::event::MouseButton::Left,
-            false => winit::event::MouseButton::Right,
+    pub fn set_target_frame_time(&mut self, target_ms: Option<f64>) {
+        self.inner
+            .set_target_frame_time(target_ms.map(duration_from_f64));
+    }
+
+    /// Takes `[r, g, b, a]` in 0..1, or nothing to go back to each screen's own background.
+    pub fn set_clear_color(&mut self, color: Option<Vec<f32>>) -> Result<(), JsValue> {
+        let color = match color.as_deref() {
+            None => None,
+            Some(&[r, g, b, a]) => Some([r, g, b, a]),
+            Some(_) => return Err(JsValue::from_str("clear color needs exactly 4 components")),
         };
-        let event = crate::MouseEvent::Button(state, button);
+        self.inner.set_clear_color(color);
+        Ok(())
+    }
+
+    pub fn is_context_lost(&self) -> bool {
+        self.platform.is_context_lost()
+    }
+
+    /// `announcer` is called with a line of text for each notable event, e.g. to fill an ARIA
+    /// live region.
+    pub fn set_announcer(&mut self, announcer: js_sys::Function) {
+        self.inner
+            .set_announcer(Box::new(crate::announce::JsAnnouncer(announcer)));
+    }
+
+    /// Whether the local user is the DM of the current room, for showing DM-only controls.
+    pub fn is_dm(&self) -> bool {
+        self.inner.is_dm()
+    }
+
+    /// The code others use to join the current room.
+    pub fn room_code(&self) -> Option<String> {
+        self.inner.room().map(|room| room.id.to_string())
+    }
+
+    /// An array of `{id, name, score, connected}` for everyone in the current room, in seating
+    /// order.
+    pub fn players(&self) -> Result<JsValue, JsValue> {
+        players_to_js(&self.inner.players())
+    }
+
+    pub fn handle_mouse_down(&mut self, is_left_button: bool) {
+        let event = crate::MouseEvent::button(true, is_left_button);
         self.inner.handle_mouse_event(event);
     }
 
     pub fn handle_mouse_up(&mut self, is_left_button: bool) {
-        let state = winit::event::ElementState::Released;
-        let button = match is_left_button {
-            true => winit::event::MouseButton::Left,
-            false => winit::event::MouseButton::Right,
-        };
-        let event = crate::MouseEvent::Button(state, button);
+        let event = crate::MouseEvent::button(false, is_left_button);
         self.inner.handle_mouse_event(event);
     }
 
@@ -104,6 +364,27 @@ impl GameWrapper {
         self.inner.handle_mouse_event(event);
     }
 
+    /// Call with whether shift is held before passing on the mouse event.
+    pub fn set_snapping(&mut self, snapping: bool) {
+        self.inner.set_snapping(snapping)
+    }
+
+    pub fn send_chat(&self, text: String) {
+        self.inner.send_chat(text)
+    }
+
+    /// Only does anything for the DM. `player_id` as it appears in `players`.
+    pub fn mute(&self, player_id: String, muted: bool) -> Result<(), JsValue> {
+        let player_id = std::str::FromStr::from_str(&player_id).map_err(to_js)?;
+        self.inner.mute(player_id, muted);
+        Ok(())
+    }
+
+    /// Gives up the seat in the current room. The network stays connected for joining another.
+    pub fn leave_room(&mut self) {
+        self.inner.leave_room()
+    }
+
     pub fn handle_room_state(&mut self, state: RoomStateWrapper) {
         self.inner
             .handle_new_room_state(state.room, state.local_user)
@@ -126,62 +407,94 @@ impl NetworkWrapper {
 
     pub fn create_room(
         &self,
-        player_id: String,
         player_name: shared::PlayerName,
+        room_name: Option<String>,
+        password: Option<String>,
     ) -> Result<FutureWrapper, JsValue> {
-        let player_id = std::str::FromStr::from_str(&player_id).map_err(to_js)?;
+        let room_name = match room_name.filter(|name| !name.trim().is_empty()) {
+            Some(name) => Some(shared::validate_room_name(&name).map_err(to_js)?),
+            None => None,
+        };
+        let create_info = shared::RoomCreateInfo {
+            player_name,
+            room_name,
+            password: password.filter(|password| !password.is_empty()),
+        };
         self.inner
-            .create_room(&player_name)
+            .create_room(&create_info)
             .map_err(to_js)
             .map(|fut| FutureWrapper {
                 fut: fut.boxed_local(),
-                local_user: shared::viewer::User {
-                    id: player_id,
-                    name: player_name,
-                },
             })
     }
 
     pub fn join_room(
         &self,
-        player_id: String,
         player_name: shared::PlayerName,
         room_id: String,
+        password: Option<String>,
     ) -> Result<FutureWrapper, JsValue> {
-        let player_id = std::str::FromStr::from_str(&player_id).map_err(to_js)?;
         let room_id = std::str::FromStr::from_str(&room_id).map_err(to_js)?;
         let join_info = shared::RoomJoinInfo {
             room_id,
-            player_name: player_name.clone(),
+            player_name,
+            password: password.filter(|password| !password.is_empty()),
         };
         self.inner
             .join_room(&join_info)
             .map_err(to_js)
             .map(|fut| FutureWrapper {
                 fut: fut.boxed_local(),
-                local_user: shared::viewer::User {
-                    id: player_id,
-                    name: player_name,
-                },
             })
     }
+
+    pub fn rejoin_room(&self, rejoin_token: String) -> Result<FutureWrapper, JsValue> {
+        self.inner
+            .rejoin_room(rejoin_token)
+            .map_err(to_js)
+            .map(|fut| FutureWrapper {
+                fut: fut.boxed_local(),
+            })
+    }
+
+    /// Resolves to `{ player_id, account_token }`, with the id as a string since it doesn't fit
+    /// in a JS number. The server sets them as the `game-player-id` and `game-account-token`
+    /// cookies, which the websocket only picks up on the next `Network.connect`.
+    pub fn register(&self) -> Result<js_sys::Promise, JsValue> {
+        let fut = self.inner.register().map_err(to_js)?;
+        Ok(wasm_bindgen_futures::future_to_promise(async move {
+            let registered = fut.await.map_err(to_js)?;
+            JsValue::from_serde(&serde_json::json!({
+                "player_id": registered.player_id.to_string(),
+                "account_token": registered.account_token,
+            }))
+            .map_err(to_js)
+        }))
+    }
 }
 
 #[wasm_bindgen]
 pub struct FutureWrapper {
-    fut: futures::future::LocalBoxFuture<'static, eyre::Result<shared::viewer::InitialRoomState>>,
-    local_user: shared::viewer::User,
+    fut: futures::future::LocalBoxFuture<'static, eyre::Result<shared::JoinedRoom>>,
 }
 
 #[wasm_bindgen]
 impl FutureWrapper {
     #[wasm_bindgen(js_name = "await")]
     pub async fn process(self) -> Result<RoomStateWrapper, JsValue> {
-        let local_user = self.local_user;
-        self.fut
-            .map_ok(move |room| RoomStateWrapper { room, local_user })
-            .map_err(to_js)
-            .await
+        let joined = self.fut.await.map_err(to_js)?;
+        let local_user = joined
+            .room
+            .users
+            .iter()
+            .find(|user| user.id == joined.player_id)
+            .cloned()
+            .ok_or_else(|| JsValue::from_str("server did not seat us in the room"))?;
+        Ok(RoomStateWrapper {
+            room: joined.room,
+            local_user,
+            rejoin_token: joined.rejoin_token,
+        })
     }
 }
 
@@ -189,20 +502,158 @@ impl FutureWrapper {
 pub struct RoomStateWrapper {
     room: shared::viewer::InitialRoomState,
     local_user: shared::viewer::User,
+    rejoin_token: shared::RejoinToken,
 }
 
-#[wasm_bindgen]
-pub fn gen_user_id() -> Option<String> {
-    let window = web_sys::window()?;
-    let crypto = window.crypto().ok()?;
-
-    let mut b = 0u64.to_ne_bytes();
-    crypto.get_random_values_with_u8_array(&mut b).unwrap();
+#[wasm_bindgen(js_class = RoomState)]
+impl RoomStateWrapper {
+    /// Keep this somewhere that survives a page refresh and hand it to `Network.rejoin_room`.
+    #[wasm_bindgen(getter)]
+    pub fn rejoin_token(&self) -> String {
+        self.rejoin_token.clone()
+    }
+}
 
-    Some(u64::from_ne_bytes(b).to_string())
+/// The array handed out by `Tension.players`.
+fn players_to_js(players: &[crate::PlayerSummary]) -> Result<JsValue, JsValue> {
+    JsValue::from_serde(players).map_err(to_js)
 }
 
 fn duration_from_f64(millis: f64) -> std::time::Duration {
     std::time::Duration::from_millis(millis.trunc() as u64)
         + std::time::Duration::from_nanos((millis.fract() * 1.0e6) as u64)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn canvas() -> web_sys::HtmlCanvasElement {
+        web_sys::window()
+            .and_then(|window| window.document())
+            .expect("could not get the document")
+            .create_element("canvas")
+            .unwrap()
+            .dyn_into()
+            .unwrap()
+    }
+
+    fn dispatch(canvas: &web_sys::HtmlCanvasElement, event: &str) {
+        canvas
+            .dispatch_event(&web_sys::Event::new(event).unwrap())
+            .unwrap();
+    }
+
+    /// Reloads resources the way `Game::restore_context` does, without needing a server.
+    #[derive(Default)]
+    struct Reloader {
+        frames: usize,
+        rendered: usize,
+        resources: Option<crate::resources::LoadedResources>,
+    }
+
+    impl crate::platform::Frame for Reloader {
+        fn frame(&mut self, _time: std::time::Duration, render: bool) {
+            self.frames += 1;
+            self.rendered += render as usize;
+        }
+
+        fn restore_context(&mut self, mut ctx: solstice_2d::solstice::Context) -> eyre::Result<()> {
+            let mut gfx = solstice_2d::Graphics::new(&mut ctx, 1., 1.)?;
+            let resources =
+                crate::resources::Resources::default().try_into_loaded(&mut ctx, &mut gfx)?;
+            self.resources = Some(resources);
+            Ok(())
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn lost_contexts_are_restored_with_resources_reloaded() {
+        use crate::platform::run_frame;
+        let canvas = canvas();
+        graphics_context(&canvas).unwrap();
+        let mut platform = WebPlatform::new(canvas.clone(), Default::default()).unwrap();
+        let mut frame = Reloader::default();
+        assert!(!platform.is_context_lost());
+
+        dispatch(&canvas, "webglcontextlost");
+        assert!(platform.is_context_lost());
+        assert!(!run_frame(&mut platform, &mut frame).unwrap());
+        assert_eq!((frame.frames, frame.rendered), (1, 0));
+
+        dispatch(&canvas, "webglcontextrestored");
+        assert!(platform.is_context_lost());
+        assert!(run_frame(&mut platform, &mut frame).unwrap());
+        assert!(!platform.is_context_lost());
+        assert_eq!((frame.frames, frame.rendered), (2, 1));
+        assert!(frame.resources.is_some());
+    }
+
+    #[wasm_bindgen_test]
+    fn players_are_an_array_of_summaries() {
+        let player = |id: &str, name: &str, score, connected| {
+            let user = shared::viewer::User {
+                connected,
+                ..crate::fixtures::user(id, name)
+            };
+            crate::PlayerSummary::new(&user, score)
+        };
+        let players = players_to_js(&[
+            player("18446744073709551615", "Alice", 2, true),
+            player("2", "Bob", 0, false),
+        ])
+        .unwrap()
+        .dyn_into::<js_sys::Array>()
+        .unwrap();
+        let field = |index: u32, key: &str| {
+            js_sys::Reflect::get(&players.get(index), &JsValue::from_str(key)).unwrap()
+        };
+
+        assert_eq!(players.length(), 2);
+        // a string since a JS number would round it
+        assert_eq!(
+            field(0, "id").as_string().as_deref(),
+            Some("18446744073709551615")
+        );
+        assert_eq!(field(0, "name").as_string().as_deref(), Some("Alice"));
+        assert_eq!(field(0, "score").as_f64(), Some(2.));
+        assert_eq!(field(0, "connected").as_bool(), Some(true));
+        assert_eq!(field(1, "id").as_string().as_deref(), Some("2"));
+        assert_eq!(field(1, "score").as_f64(), Some(0.));
+        assert_eq!(field(1, "connected").as_bool(), Some(false));
+
+        let empty = players_to_js(&[]).unwrap();
+        assert_eq!(empty.dyn_into::<js_sys::Array>().unwrap().length(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn turn_changes_call_the_js_announcer() {
+        use crate::announce::{JsAnnouncer, TurnWatcher};
+        use crate::fixtures::user;
+        let alice = user("1", "Alice");
+        let bob = user("2", "Bob");
+
+        let announced = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let callback = {
+            let announced = announced.clone();
+            Closure::wrap(Box::new(move |text: String| {
+                announced.borrow_mut().push(text);
+            }) as Box<dyn FnMut(String)>)
+        };
+        let announcer = JsAnnouncer(
+            callback
+                .as_ref()
+                .unchecked_ref::<js_sys::Function>()
+                .clone(),
+        );
+
+        let mut turns = TurnWatcher::default();
+        assert!(turns.observe(Some(&alice), &bob, &announcer));
+        assert!(turns.observe(Some(&bob), &bob, &announcer));
+        assert_eq!(*announced.borrow(), vec!["Alice's turn", "Your turn"]);
+    }
+}
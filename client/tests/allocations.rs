@@ -0,0 +1,74 @@
+//! Counts allocations while parsing HTTP response bodies. It's a binary of its own since the
+//! counting allocator replaces the global one for everything built into it. `net::Client` hands
+//! bodies straight to `Format::decode`, which is what's measured here against the `.text()` and
+//! `from_str` it replaced.
+#![cfg(not(target_arch = "wasm32"))]
+
+/// Counts the allocations made on the current thread, so that the test harness's own threads
+/// don't skew the numbers.
+struct CountingAllocator;
+
+std::thread_local! {
+    static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(|count| count.get());
+    let result = f();
+    (result, ALLOCATIONS.with(|count| count.get()) - before)
+}
+
+#[test]
+fn responses_parse_from_bytes_with_fewer_allocations() {
+    let user = |id: &str, name: &str| shared::viewer::User {
+        id: id.parse().unwrap(),
+        name: name.to_owned(),
+        connected: true,
+    };
+    let joined = shared::JoinedRoom {
+        room: shared::viewer::InitialRoomState {
+            id: "ABCD".parse().unwrap(),
+            name: Some("room".to_owned()),
+            users: vec![user("1", "DM"), user("2", "Alice"), user("3", "Bob")],
+            settings: Default::default(),
+        },
+        rejoin_token: "token".to_owned(),
+        player_id: "2".parse().unwrap(),
+    };
+    let body = serde_json::to_vec(&joined).unwrap();
+
+    let (before, before_allocations) = allocations(|| {
+        let text = String::from_utf8_lossy(&body).into_owned();
+        serde_json::from_str::<shared::JoinedRoom>(&text).unwrap()
+    });
+    let (after, after_allocations) = allocations(|| {
+        shared::codec::Format::Json
+            .decode::<shared::JoinedRoom>(&body)
+            .unwrap()
+    });
+
+    assert_eq!(
+        serde_json::to_value(&before).unwrap(),
+        serde_json::to_value(&after).unwrap()
+    );
+    assert!(
+        after_allocations < before_allocations,
+        "{} allocations from bytes, {} through a String",
+        after_allocations,
+        before_allocations
+    );
+}
@@ -0,0 +1,22 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use shared::codec::{self, FrameRef};
+use shared::{viewer::StateChange, CustomMessage};
+
+// Mirrors the client's `net::decode`, which decodes text and binary frames alike.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        check(FrameRef::Text(text));
+    }
+    check(FrameRef::Binary(data));
+});
+
+fn check(frame: FrameRef) {
+    if let Ok(change) = codec::decode::<StateChange<CustomMessage>>(frame) {
+        let _ = change.target.to_string();
+        let reencoded = codec::encode(&change).expect("a decoded state change should re-encode");
+        let redecoded: StateChange<CustomMessage> = codec::decode(reencoded.as_frame_ref())
+            .expect("a re-encoded state change should decode");
+        assert_eq!(codec::encode(&redecoded).unwrap(), reencoded);
+    }
+}
@@ -0,0 +1,29 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use shared::codec::{self, FrameRef};
+use shared::{viewer::Command, CustomMessage};
+
+// Mirrors `on_ws_connect`, which decodes text and binary frames alike.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        check(FrameRef::Text(text));
+    }
+    check(FrameRef::Binary(data));
+});
+
+fn check(frame: FrameRef) {
+    if let Ok(cmd) = codec::decode::<Command<CustomMessage>>(frame) {
+        let room = match &cmd {
+            Command::Custom(room, _)
+            | Command::Reliable(room, _, _)
+            | Command::Leave(room)
+            | Command::Chat(room, _)
+            | Command::Mute(room, _, _) => room,
+        };
+        let _ = room.to_string();
+        let reencoded = codec::encode(&cmd).expect("a decoded command should re-encode");
+        let redecoded: Command<CustomMessage> = codec::decode(reencoded.as_frame_ref())
+            .expect("a re-encoded command should decode");
+        assert_eq!(codec::encode(&redecoded).unwrap(), reencoded);
+    }
+}
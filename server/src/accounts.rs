@@ -0,0 +1,72 @@
+//! Optional accounts, for keeping the same `PlayerID` across browsers and cleared cookies. An
+//! account is just a player id the server handed out along with a token. Requests using that id
+//! have to bring the token, while ids nobody registered are played anonymously as ever.
+
+use serde::{Deserialize, Serialize};
+use shared::PlayerID;
+
+/// Tokens are kept the same way as room passwords, as argon2 hashes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub player_id: PlayerID,
+    pub token_hash: String,
+}
+
+/// Called from blocking tasks, like `ResultStore`.
+pub trait AccountStore: Send + Sync {
+    /// Fails if the id is already registered.
+    fn insert(&self, account: Account) -> eyre::Result<()>;
+
+    /// `None` for anonymous players.
+    fn token_hash(&self, player_id: PlayerID) -> eyre::Result<Option<String>>;
+}
+
+/// Forgets everything when the server stops.
+#[derive(Debug, Default)]
+pub struct MemoryAccounts(std::sync::Mutex<std::collections::HashMap<PlayerID, String>>);
+
+impl AccountStore for MemoryAccounts {
+    fn insert(&self, account: Account) -> eyre::Result<()> {
+        use std::collections::hash_map::Entry;
+        match crate::lock(&self.0).entry(account.player_id) {
+            Entry::Occupied(_) => Err(eyre::eyre!("{} is already registered", account.player_id)),
+            Entry::Vacant(entry) => {
+                entry.insert(account.token_hash);
+                Ok(())
+            }
+        }
+    }
+
+    fn token_hash(&self, player_id: PlayerID) -> eyre::Result<Option<String>> {
+        Ok(crate::lock(&self.0).get(&player_id).cloned())
+    }
+}
+
+/// Appended to as players register and read once at startup.
+#[derive(Debug)]
+pub struct JsonFileAccounts {
+    file: crate::jsonl::JsonLines,
+    accounts: MemoryAccounts,
+}
+
+impl JsonFileAccounts {
+    pub fn open<P: Into<std::path::PathBuf>>(path: P) -> eyre::Result<Self> {
+        let file = crate::jsonl::JsonLines::new(path);
+        let accounts = MemoryAccounts::default();
+        for account in file.read::<Account>()? {
+            accounts.insert(account)?;
+        }
+        Ok(Self { file, accounts })
+    }
+}
+
+impl AccountStore for JsonFileAccounts {
+    fn insert(&self, account: Account) -> eyre::Result<()> {
+        self.accounts.insert(account.clone())?;
+        self.file.append(&account)
+    }
+
+    fn token_hash(&self, player_id: PlayerID) -> eyre::Result<Option<String>> {
+        self.accounts.token_hash(player_id)
+    }
+}
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+pub const PING_INTERVAL_VAR: &str = "TENSION_PING_INTERVAL_MS";
+pub const PONG_TIMEOUT_VAR: &str = "TENSION_PONG_TIMEOUT_MS";
+pub const RECONNECT_GRACE_VAR: &str = "TENSION_RECONNECT_GRACE_MS";
+pub const MAX_CONNECTIONS_PER_IP_VAR: &str = "TENSION_MAX_CONNECTIONS_PER_IP";
+pub const ROOM_CAPACITY_VAR: &str = "TENSION_ROOM_CAPACITY";
+pub const MIN_PLAYERS_VAR: &str = "TENSION_MIN_PLAYERS";
+pub const MAX_CONSECUTIVE_TURNS_VAR: &str = "TENSION_MAX_CONSECUTIVE_TURNS";
+pub const RESULTS_PATH_VAR: &str = "TENSION_RESULTS_PATH";
+pub const ACCOUNTS_PATH_VAR: &str = "TENSION_ACCOUNTS_PATH";
+pub const CHAT_FILTER_VAR: &str = "TENSION_CHAT_FILTER";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Config {
+    pub ping_interval: Duration,
+    /// Connections that haven't answered a ping for this long are considered dead.
+    pub pong_timeout: Duration,
+    /// Websocket upgrades from an address that already has this many open are refused.
+    pub max_connections_per_ip: usize,
+    /// How long a disconnected player keeps their place in a room.
+    pub reconnect_grace: Duration,
+    /// Joins past this many users, the DM included, are refused. `None` for no limit.
+    pub room_capacity: Option<u32>,
+    /// Players needed besides the DM before a game can start.
+    pub min_players: u32,
+    /// Turns in a row one player can be given while others wait. `None` for no limit.
+    pub max_consecutive_turns: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let ping_interval = Duration::from_millis(shared::DEFAULT_PING_INTERVAL_MS);
+        Self {
+            ping_interval,
+            pong_timeout: ping_interval * 3,
+            // generous enough for a household or classroom behind one NAT
+            max_connections_per_ip: 16,
+            reconnect_grace: Duration::from_secs(30),
+            room_capacity: Some(8),
+            min_players: 1,
+            max_consecutive_turns: None,
+        }
+    }
+}
+
+impl Config {
+    /// Pinging more often than this mostly serves to flood slow clients.
+    pub const MIN_PING_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn from_env() -> Self {
+        let var = |key: &str| std::env::var(key).ok();
+        let ping_interval = parse_ping_interval(var(PING_INTERVAL_VAR).as_deref());
+        Self {
+            ping_interval,
+            pong_timeout: parse_pong_timeout(var(PONG_TIMEOUT_VAR).as_deref(), ping_interval),
+            max_connections_per_ip: parse_max_connections_per_ip(
+                var(MAX_CONNECTIONS_PER_IP_VAR).as_deref(),
+            ),
+            reconnect_grace: parse_millis(RECONNECT_GRACE_VAR, var(RECONNECT_GRACE_VAR).as_deref())
+                .unwrap_or(Config::default().reconnect_grace),
+            room_capacity: parse_room_capacity(var(ROOM_CAPACITY_VAR).as_deref()),
+            min_players: parse(MIN_PLAYERS_VAR, var(MIN_PLAYERS_VAR).as_deref())
+                .unwrap_or(Config::default().min_players),
+            max_consecutive_turns: parse_max_consecutive_turns(
+                var(MAX_CONSECUTIVE_TURNS_VAR).as_deref(),
+            ),
+        }
+    }
+
+    /// What new rooms start out with.
+    pub fn room_settings(&self) -> shared::viewer::RoomSettings {
+        shared::viewer::RoomSettings {
+            capacity: self.room_capacity,
+            min_players: self.min_players,
+            max_consecutive_turns: self.max_consecutive_turns,
+        }
+    }
+
+    pub fn shared(&self) -> shared::ServerConfig {
+        shared::ServerConfig {
+            ping_interval_ms: self.ping_interval.as_millis() as u64,
+        }
+    }
+}
+
+/// Where finished matches are written, or `None` if set empty to keep them in memory only. Kept
+/// out of `Config` so that it can stay `Copy`.
+pub fn results_path() -> Option<std::path::PathBuf> {
+    store_path(RESULTS_PATH_VAR, "results.jsonl")
+}
+
+/// Like `results_path`, for registered accounts.
+pub fn accounts_path() -> Option<std::path::PathBuf> {
+    store_path(ACCOUNTS_PATH_VAR, "accounts.jsonl")
+}
+
+/// Comma separated words to mask out of chat. Nothing is filtered if it's missing.
+pub fn chat_filter() -> shared::viewer::state::ChatFilter {
+    parse_chat_filter(std::env::var(CHAT_FILTER_VAR).ok().as_deref())
+}
+
+pub fn parse_chat_filter(value: Option<&str>) -> shared::viewer::state::ChatFilter {
+    shared::viewer::state::ChatFilter::new(value.unwrap_or_default().split(','))
+}
+
+fn store_path(key: &str, default: &str) -> Option<std::path::PathBuf> {
+    match std::env::var_os(key) {
+        None => Some(default.into()),
+        Some(path) if path.is_empty() => None,
+        Some(path) => Some(path.into()),
+    }
+}
+
+/// Milliseconds, clamped to `Config::MIN_PING_INTERVAL`. Falls back to the default if missing
+/// or unparseable.
+pub fn parse_ping_interval(value: Option<&str>) -> Duration {
+    parse_millis(PING_INTERVAL_VAR, value)
+        .map(|interval| interval.max(Config::MIN_PING_INTERVAL))
+        .unwrap_or(Config::default().ping_interval)
+}
+
+/// Milliseconds, defaulting to three ping intervals. Anything shorter than two intervals would
+/// reap connections over a single late pong so it is clamped to that.
+pub fn parse_pong_timeout(value: Option<&str>, ping_interval: Duration) -> Duration {
+    parse_millis(PONG_TIMEOUT_VAR, value)
+        .unwrap_or(ping_interval * 3)
+        .max(ping_interval * 2)
+}
+
+/// At least one, otherwise nobody could connect.
+pub fn parse_max_connections_per_ip(value: Option<&str>) -> usize {
+    parse(MAX_CONNECTIONS_PER_IP_VAR, value)
+        .unwrap_or(Config::default().max_connections_per_ip)
+        .max(1)
+}
+
+/// Zero lifts the limit. A room needs at least the DM and one player.
+pub fn parse_room_capacity(value: Option<&str>) -> Option<u32> {
+    match parse::<u32>(ROOM_CAPACITY_VAR, value) {
+        None => Config::default().room_capacity,
+        Some(0) => None,
+        Some(capacity) => Some(capacity.max(2)),
+    }
+}
+
+/// Zero or missing for no limit.
+pub fn parse_max_consecutive_turns(value: Option<&str>) -> Option<u32> {
+    parse::<u32>(MAX_CONSECUTIVE_TURNS_VAR, value).filter(|turns| *turns > 0)
+}
+
+fn parse_millis(key: &str, value: Option<&str>) -> Option<Duration> {
+    parse(key, value).map(Duration::from_millis)
+}
+
+fn parse<T>(key: &str, value: Option<&str>) -> Option<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match value?.trim().parse::<T>() {
+        Ok(value) => Some(value),
+        Err(err) => {
+            log::warn!("Invalid {}: {}", key, err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_interval_parsing() {
+        let default = Duration::from_millis(shared::DEFAULT_PING_INTERVAL_MS);
+        assert_eq!(parse_ping_interval(None), default);
+        assert_eq!(parse_ping_interval(Some("")), default);
+        assert_eq!(parse_ping_interval(Some("soon")), default);
+        assert_eq!(parse_ping_interval(Some("-5")), default);
+        assert_eq!(
+            parse_ping_interval(Some("2500")),
+            Duration::from_millis(2500)
+        );
+        assert_eq!(
+            parse_ping_interval(Some(" 300 ")),
+            Duration::from_millis(300)
+        );
+        assert_eq!(parse_ping_interval(Some("1")), Config::MIN_PING_INTERVAL);
+    }
+
+    #[test]
+    fn pong_timeout_parsing() {
+        let interval = Duration::from_millis(1000);
+        assert_eq!(parse_pong_timeout(None, interval), interval * 3);
+        assert_eq!(parse_pong_timeout(Some("nope"), interval), interval * 3);
+        assert_eq!(
+            parse_pong_timeout(Some("5000"), interval),
+            Duration::from_millis(5000)
+        );
+        assert_eq!(parse_pong_timeout(Some("500"), interval), interval * 2);
+    }
+
+    #[test]
+    fn max_connections_per_ip_parsing() {
+        let default = Config::default().max_connections_per_ip;
+        assert_eq!(parse_max_connections_per_ip(None), default);
+        assert_eq!(parse_max_connections_per_ip(Some("lots")), default);
+        assert_eq!(parse_max_connections_per_ip(Some("4")), 4);
+        assert_eq!(parse_max_connections_per_ip(Some("0")), 1);
+    }
+
+    #[test]
+    fn room_capacity_parsing() {
+        let default = Config::default().room_capacity;
+        assert_eq!(parse_room_capacity(None), default);
+        assert_eq!(parse_room_capacity(Some("many")), default);
+        assert_eq!(parse_room_capacity(Some("12")), Some(12));
+        assert_eq!(parse_room_capacity(Some("1")), Some(2));
+        assert_eq!(parse_room_capacity(Some("0")), None);
+    }
+
+    #[test]
+    fn max_consecutive_turns_parsing() {
+        assert_eq!(parse_max_consecutive_turns(None), None);
+        assert_eq!(parse_max_consecutive_turns(Some("lots")), None);
+        assert_eq!(parse_max_consecutive_turns(Some("0")), None);
+        assert_eq!(parse_max_consecutive_turns(Some("2")), Some(2));
+    }
+
+    #[test]
+    fn chat_filter_parsing() {
+        assert_eq!(parse_chat_filter(None), Default::default());
+        assert_eq!(parse_chat_filter(Some(" , ")), Default::default());
+        let filter = parse_chat_filter(Some("heck, Darn,"));
+        assert_eq!(filter.mask("Heck darn"), "**** ****");
+    }
+}
@@ -0,0 +1,46 @@
+//! Files of one JSON value per line, only ever appended to. All the persistence the server
+//! needs, without a database to run alongside it.
+
+#[derive(Debug)]
+pub struct JsonLines {
+    path: std::path::PathBuf,
+    /// Keeps concurrent appends from interleaving.
+    lock: std::sync::Mutex<()>,
+}
+
+impl JsonLines {
+    pub fn new<P: Into<std::path::PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            lock: Default::default(),
+        }
+    }
+
+    pub fn append<T: serde::Serialize>(&self, value: &T) -> eyre::Result<()> {
+        use std::io::Write;
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        let _guard = crate::lock(&self.lock);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Oldest first. A missing file is empty.
+    pub fn read<T: serde::de::DeserializeOwned>(&self) -> eyre::Result<Vec<T>> {
+        let _guard = crate::lock(&self.lock);
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(eyre::Report::from))
+            .collect()
+    }
+}
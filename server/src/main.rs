@@ -1,7 +1,16 @@
+mod accounts;
+mod config;
+mod jsonl;
+mod password;
+mod results;
+
 use futures::{SinkExt, StreamExt};
+use shared::codec::{self, CodecError, Frame, FrameRef};
+use std::net::IpAddr;
 use warp::{Filter, Reply};
 
 type ArcRw<T> = std::sync::Arc<tokio::sync::RwLock<T>>;
+type ArcMutex<T> = std::sync::Arc<std::sync::Mutex<T>>;
 type CustomMessageType = shared::CustomMessage;
 
 type WsSink = tokio::sync::mpsc::UnboundedSender<warp::ws::Message>;
@@ -9,83 +18,508 @@ type PlayerConnections = ArcRw<std::collections::HashMap<shared::PlayerID, WsSin
 
 type State = std::sync::Arc<tokio::sync::RwLock<shared::viewer::state::State<CustomMessageType>>>;
 
+type Results = std::sync::Arc<dyn results::ResultStore>;
+type Accounts = std::sync::Arc<dyn accounts::AccountStore>;
+
+type IpConnections = ArcMutex<std::collections::HashMap<IpAddr, usize>>;
+
+type Seat = (shared::PlayerID, shared::RoomID);
+
+/// Every update under these locks is a single insert or removal, so a panic elsewhere while one
+/// was held can't have left it half done. Poisoned ones are used as they are.
+fn lock<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// One of an address's open websocket connections. Gives the slot back when dropped.
+struct IpSlot {
+    ip: IpAddr,
+    counts: IpConnections,
+}
+
+impl IpSlot {
+    fn acquire(ip: IpAddr, counts: &IpConnections, max: usize) -> Option<Self> {
+        let mut guard = lock(counts);
+        let count = guard.entry(ip).or_insert(0);
+        if *count >= max {
+            None
+        } else {
+            *count += 1;
+            Some(Self {
+                ip,
+                counts: counts.clone(),
+            })
+        }
+    }
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let mut counts = lock(&self.counts);
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Who is sitting where. Each seat gets a token that reclaims it and a single task forwarding
+/// its room's broadcasts, so taking a seat again doesn't double up on messages.
+#[derive(Clone, Default)]
+struct Seats {
+    tokens: ArcMutex<std::collections::HashMap<shared::RejoinToken, Seat>>,
+    forwarders: ArcMutex<std::collections::HashMap<Seat, tokio::task::JoinHandle<()>>>,
+}
+
+impl Seats {
+    /// Replaces any token the player already had.
+    fn issue_token(
+        &self,
+        player_id: shared::PlayerID,
+        room_id: shared::RoomID,
+    ) -> shared::RejoinToken {
+        let token = format!("{:032x}", rand::random::<u128>());
+        let mut tokens = lock(&self.tokens);
+        tokens.retain(|_token, (owner, _room_id)| *owner != player_id);
+        tokens.insert(token.clone(), (player_id, room_id));
+        token
+    }
+
+    fn lookup(&self, token: &str) -> Option<Seat> {
+        lock(&self.tokens).get(token).copied()
+    }
+
+    fn revoke(&self, token: &str) {
+        lock(&self.tokens).remove(token);
+    }
+
+    /// Stops forwarding the room to a player who left it and forgets their seat there.
+    fn vacate(&self, player_id: shared::PlayerID, room_id: shared::RoomID) {
+        lock(&self.tokens).retain(|_token, seat| *seat != (player_id, room_id));
+        if let Some(task) = lock(&self.forwarders).remove(&(player_id, room_id)) {
+            task.abort();
+        }
+    }
+
+    fn forward(
+        &self,
+        player_id: shared::PlayerID,
+        room_id: shared::RoomID,
+        backlog: Vec<shared::viewer::StateChange<shared::CustomMessage>>,
+        channel: tokio::sync::broadcast::Receiver<
+            shared::viewer::StateChange<shared::CustomMessage>,
+        >,
+        connections: PlayerConnections,
+    ) {
+        let task = tokio::spawn(ws_forward(player_id, backlog, channel, connections));
+        let previous = lock(&self.forwarders).insert((player_id, room_id), task);
+        if let Some(previous) = previous {
+            previous.abort();
+        }
+    }
+
+    /// Drops the forwarders of players who are gone for good. Their tokens stay so that trying
+    /// to rejoin says the seat is gone rather than that it never existed.
+    fn forget(&self, player_ids: &[shared::PlayerID]) {
+        lock(&self.forwarders).retain(|(player_id, _room_id), task| {
+            let gone = player_ids.contains(player_id);
+            if gone {
+                task.abort();
+            }
+            !gone
+        });
+    }
+}
+
+#[derive(Debug)]
+struct InvalidBody;
+
+impl warp::reject::Reject for InvalidBody {}
+
+#[derive(Debug)]
+struct NotYourAccount;
+
+impl warp::reject::Reject for NotYourAccount {}
+
+/// Seconds a cookie the server sets lasts. Long enough that players keep their stats.
+const COOKIE_MAX_AGE: u64 = 60 * 60 * 24 * 365;
+
+fn set_cookie(reply: warp::reply::Response, name: &str, value: &str) -> warp::reply::Response {
+    let cookie = format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        name, value, COOKIE_MAX_AGE
+    );
+    let mut reply = reply;
+    match warp::http::HeaderValue::from_str(&cookie) {
+        Ok(cookie) => {
+            reply
+                .headers_mut()
+                .append(warp::http::header::SET_COOKIE, cookie);
+        }
+        Err(err) => log::error!("{}", err),
+    }
+    reply
+}
+
+/// The `game-player-id` cookie as it was sent, or a new id for a request without one.
+#[derive(Debug, Clone)]
+struct PlayerCookie {
+    id: String,
+    issued: bool,
+}
+
+impl PlayerCookie {
+    /// Hands a newly issued id to the client.
+    fn set_on(&self, reply: warp::reply::Response) -> warp::reply::Response {
+        if self.issued {
+            set_cookie(reply, "game-player-id", &self.id)
+        } else {
+            reply
+        }
+    }
+}
+
+/// `player_cookie` for requests that have to come with one.
+fn player_id_cookie(
+    accounts: Accounts,
+) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    warp::cookie::cookie("game-player-id")
+        .and(player_cookie(accounts))
+        .map(|_id: String, player: PlayerCookie| player.id)
+}
+
+/// The `game-player-id` cookie, as long as it's anonymous or comes with its account's
+/// `game-account-token`. Left as it was sent for handlers to parse. Requests without one are
+/// issued a new id.
+fn player_cookie(
+    accounts: Accounts,
+) -> impl Filter<Extract = (PlayerCookie,), Error = warp::Rejection> + Clone {
+    warp::cookie::optional("game-player-id")
+        .and(warp::cookie::optional("game-account-token"))
+        .and_then(
+            move |id: Option<String>, token: Option<shared::AccountToken>| {
+                let accounts = accounts.clone();
+                async move {
+                    let id = match id {
+                        Some(id) => id,
+                        None => {
+                            return Ok(PlayerCookie {
+                                id: shared::PlayerID::gen(&mut rand::thread_rng()).to_string(),
+                                issued: true,
+                            })
+                        }
+                    };
+                    let player_id = match std::str::FromStr::from_str(&id) {
+                        Ok(player_id) => player_id,
+                        Err(_err) => return Ok(PlayerCookie { id, issued: false }),
+                    };
+                    let verified = tokio::task::spawn_blocking(move || -> eyre::Result<bool> {
+                        Ok(match accounts.token_hash(player_id)? {
+                            None => true,
+                            Some(hash) => {
+                                token.is_some_and(|token| password::verify(&hash, &token))
+                            }
+                        })
+                    })
+                    .await;
+                    match verified {
+                        Ok(Ok(true)) => Ok(PlayerCookie { id, issued: false }),
+                        Ok(Ok(false)) => Err(warp::reject::custom(NotYourAccount)),
+                        Ok(Err(err)) => {
+                            log::error!("{}", err);
+                            Err(warp::reject())
+                        }
+                        Err(err) => {
+                            log::error!("{}", err);
+                            Err(warp::reject())
+                        }
+                    }
+                }
+            },
+        )
+}
+
+/// Statuses for our own rejections. Anything else is left to warp.
+async fn explain_rejection(
+    rejection: warp::Rejection,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if rejection.find::<NotYourAccount>().is_some() {
+        Ok(warp::reply::with_status(
+            "player id belongs to an account",
+            warp::hyper::StatusCode::FORBIDDEN,
+        )
+        .into_response())
+    } else if rejection.find::<InvalidBody>().is_some() {
+        Ok(warp::reply::with_status(
+            "malformed request body",
+            warp::hyper::StatusCode::BAD_REQUEST,
+        )
+        .into_response())
+    } else {
+        Err(rejection)
+    }
+}
+
+/// A request body in the `Format` its Content-Type names. Anything other than MessagePack goes
+/// through `warp::body::json`, as everything did before there was a choice.
+fn body<T: serde::de::DeserializeOwned + Send + 'static>(
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    use shared::codec::Format;
+    let limit = warp::body::content_length_limit(1024 * 16);
+    let msgpack = warp::header::exact_ignore_case("content-type", Format::MessagePack.mime())
+        .and(limit)
+        .and(warp::body::bytes())
+        .and_then(|body: warp::hyper::body::Bytes| async move {
+            Format::MessagePack
+                .decode(&body)
+                .map_err(|_err| warp::reject::custom(InvalidBody))
+        });
+    msgpack.or(limit.and(warp::body::json())).unify()
+}
+
+/// What replies are encoded as, going by the `Accept` header.
+fn accept() -> impl Filter<Extract = (shared::codec::Format,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("accept").map(|accept: Option<String>| {
+        accept
+            .as_deref()
+            .and_then(shared::codec::Format::from_accept)
+            .unwrap_or_default()
+    })
+}
+
+fn encoded<T: serde::Serialize>(format: shared::codec::Format, value: &T) -> warp::reply::Response {
+    match format.encode(value) {
+        Ok(body) => warp::reply::with_header(body, "content-type", format.mime()).into_response(),
+        Err(err) => {
+            log::error!("failed to encode reply: {}", err);
+            warp::reply::with_status(
+                "could not encode reply",
+                warp::hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response()
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-    simple_logger::SimpleLogger::new()
-        .with_level(log::LevelFilter::Debug)
+    let log_filter = shared::log_filter::LogFilter::parse_env(
+        std::env::var(shared::LOG_LEVEL_VAR).ok().as_deref(),
+    );
+    let filter = log_filter.clone().unwrap_or_default();
+    filter
+        .targets
+        .iter()
+        .fold(
+            simple_logger::SimpleLogger::new().with_level(filter.default),
+            |logger, (target, level)| logger.with_module_level(target, *level),
+        )
         .init()?;
+    if let Err(err) = log_filter {
+        log::warn!("Invalid {}: {}", shared::LOG_LEVEL_VAR, err);
+    }
 
     log::debug!("Server version: {}", env!("CARGO_PKG_VERSION"));
 
-    let state = std::sync::Arc::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+    let config = config::Config::from_env();
+    log::debug!("{:?}", config);
+
+    let state = shared::viewer::state::State::new()
+        .with_checkpoint(shared::CustomMessage::starts_game)
+        .with_transient(shared::CustomMessage::is_transient)
+        .with_guard(shared::CustomMessage::allowed_in)
+        .with_chat_filter(config::chat_filter());
+    let state = std::sync::Arc::new(tokio::sync::RwLock::new(state));
     let connections = PlayerConnections::default();
+    let results: Results = match config::results_path() {
+        Some(path) => std::sync::Arc::new(results::JsonFileStore::new(path)),
+        None => std::sync::Arc::new(results::MemoryStore::default()),
+    };
+    let accounts: Accounts = match config::accounts_path() {
+        Some(path) => std::sync::Arc::new(accounts::JsonFileAccounts::open(path)?),
+        None => std::sync::Arc::new(accounts::MemoryAccounts::default()),
+    };
+
+    let api = api(state, connections, results, accounts, config);
+    #[cfg(debug_assertions)]
+    let api = warp::path("api").and(api);
+
+    let root = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .map(std::path::PathBuf::from)
+        .ok_or(eyre::Error::msg("there's no father to his style"))?;
 
+    let routes = api.or(warp::fs::dir(root.join("docs")));
+
+    warp::serve(routes).run(([0, 0, 0, 0], 8000)).await;
+    Ok(())
+}
+
+fn api(
+    state: State,
+    connections: PlayerConnections,
+    results: Results,
+    accounts: Accounts,
+    config: config::Config,
+) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
     let client_state = warp::any().map(move || state.clone());
+    let results = warp::any().map(move || results.clone());
     let connections = warp::any().map(move || connections.clone());
-    let player_id_cookie = warp::cookie::cookie("game-player-id");
+    let player_cookie = player_cookie(accounts.clone());
+    let player_id_cookie = player_id_cookie(accounts.clone());
+    let accounts = warp::any().map(move || accounts.clone());
+    let server_config = warp::any().map(move || config);
+    let ip_connections = IpConnections::default();
+    let ip_connections = warp::any().map(move || ip_connections.clone());
+    let seats = Seats::default();
+    let seats = warp::any().map(move || seats.clone());
 
     let ws = warp::path(shared::ENDPOINT_WS)
         .and(warp::ws())
-        .and(player_id_cookie)
+        .and(player_cookie.clone())
         .and(connections.clone())
         .and(client_state.clone())
+        .and(server_config)
+        .and(warp::addr::remote())
+        .and(ip_connections)
+        .and(seats.clone())
+        .and(results.clone())
         .map(
-            |ws: warp::ws::Ws, id: String, connections: PlayerConnections, state: State| {
+            |ws: warp::ws::Ws,
+             player: PlayerCookie,
+             connections: PlayerConnections,
+             state: State,
+             config: config::Config,
+             remote: Option<std::net::SocketAddr>,
+             ip_connections: IpConnections,
+             seats: Seats,
+             results: Results| {
                 use warp::Reply;
-                match std::str::FromStr::from_str(&id) {
-                    Ok(id) => ws
-                        .on_upgrade(move |websocket| {
-                            on_ws_connect(websocket, id, connections, state)
-                        })
-                        .into_response(),
+                let reply = match std::str::FromStr::from_str(&player.id) {
+                    Ok(id) => {
+                        let slot = remote.map(|remote| {
+                            IpSlot::acquire(
+                                remote.ip(),
+                                &ip_connections,
+                                config.max_connections_per_ip,
+                            )
+                        });
+                        match slot {
+                            Some(None) => {
+                                log::warn!("Too many connections from {:?}", remote);
+                                ws.on_upgrade(|mut websocket| async move {
+                                    let close = warp::ws::Message::close_with(
+                                        1008u16,
+                                        "too many connections",
+                                    );
+                                    let _result = websocket.send(close).await;
+                                    let _result = websocket.close().await;
+                                })
+                                .into_response()
+                            }
+                            slot => ws
+                                .on_upgrade(move |websocket| async move {
+                                    // held until the connection is cleaned up
+                                    let _slot = slot;
+                                    on_ws_connect(
+                                        websocket,
+                                        id,
+                                        connections,
+                                        state,
+                                        seats,
+                                        results,
+                                        config,
+                                    )
+                                    .await
+                                })
+                                .into_response(),
+                        }
+                    }
                     Err(_err) => {
                         warp::reply::with_status("Invalid ID", warp::hyper::StatusCode::BAD_REQUEST)
                             .into_response()
                     }
-                }
+                };
+                // browsers keep cookies set on the handshake for the requests that follow it
+                player.set_on(reply)
             },
         );
 
     let create_room = warp::path(shared::ENDPOINT_CREATE_ROOM)
         .and(warp::post())
-        .and(player_id_cookie)
+        .and(player_cookie.clone())
         .and(client_state.clone())
         .and(connections.clone())
-        .and(warp::body::content_length_limit(1024 * 16))
-        .and(warp::body::json())
+        .and(seats.clone())
+        .and(server_config)
+        .and(accept())
+        .and(body())
         .and_then(create_room);
 
     let join_room = warp::path(shared::ENDPOINT_JOIN_ROOM)
         .and(warp::post())
-        .and(player_id_cookie)
+        .and(player_cookie)
         .and(client_state.clone())
         .and(connections.clone())
-        .and(warp::body::content_length_limit(1024 * 16))
-        .and(warp::body::json())
+        .and(seats.clone())
+        .and(accept())
+        .and(body())
         .and_then(join_room);
 
+    let rejoin_room = warp::path(shared::ENDPOINT_REJOIN_ROOM)
+        .and(warp::post())
+        .and(player_id_cookie)
+        .and(client_state.clone())
+        .and(connections.clone())
+        .and(seats)
+        .and(accept())
+        .and(body())
+        .and_then(rejoin_room);
+
     let debug_state = warp::path("debug")
         .and(client_state.clone())
         .and_then(debug_state);
 
     let health_check = warp::path("health").map(|| "OK");
 
-    let root = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .parent()
-        .map(std::path::PathBuf::from)
-        .ok_or(eyre::Error::msg("there's no father to his style"))?;
+    let server_config = warp::path(shared::ENDPOINT_CONFIG)
+        .and(server_config)
+        .and(accept())
+        .map(|config: config::Config, format| encoded(format, &config.shared()));
 
-    let api = ws
-        .or(create_room)
-        .or(join_room)
-        .or(debug_state)
-        .or(health_check);
-    #[cfg(debug_assertions)]
-    let api = warp::path("api").and(api);
+    let leaderboard = warp::path(shared::ENDPOINT_LEADERBOARD)
+        .and(warp::get())
+        .and(results.clone())
+        .and(accept())
+        .and_then(leaderboard);
 
-    let routes = api.or(warp::fs::dir(root.join("docs")));
+    let register = warp::path(shared::ENDPOINT_REGISTER)
+        .and(warp::post())
+        .and(accounts)
+        .and(accept())
+        .and_then(register);
+
+    let player_stats = warp::path(shared::ENDPOINT_STATS)
+        .and(warp::path::param::<shared::PlayerID>())
+        .and(warp::get())
+        .and(results)
+        .and(accept())
+        .and_then(player_stats);
 
-    Ok(warp::serve(routes).run(([0, 0, 0, 0], 8000)).await)
+    ws.or(create_room)
+        .or(join_room)
+        .or(rejoin_room)
+        .or(debug_state)
+        .or(health_check)
+        .or(server_config)
+        .or(leaderboard)
+        .or(player_stats)
+        .or(register)
+        .recover(explain_rejection)
 }
 
 async fn on_ws_connect(
@@ -93,6 +527,9 @@ async fn on_ws_connect(
     id: shared::PlayerID,
     connections: PlayerConnections,
     state: State,
+    seats: Seats,
+    results: Results,
+    config: config::Config,
 ) {
     log::debug!("New WS connection for User {:?}", id);
     let (mut user_ws_tx, mut user_ws_rx) = ws.split();
@@ -100,37 +537,116 @@ async fn on_ws_connect(
     let (sx, rx) = tokio::sync::mpsc::unbounded_channel();
     let rx = tokio_stream::wrappers::UnboundedReceiverStream::new(rx);
     tokio::task::spawn(async move {
-        let interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        let interval = tokio::time::interval(config.ping_interval);
         let interval = tokio_stream::wrappers::IntervalStream::new(interval);
         let interval = interval.map(|_t| warp::ws::Message::ping(vec![]));
 
         let mut stream = futures::stream::select(rx, interval);
 
         while let Some(msg) = stream.next().await {
+            let is_close = msg.is_close();
             if let Err(err) = user_ws_tx.send(msg).await {
                 log::error!("websocket send error: {}", err);
                 break;
             }
+            if is_close {
+                break;
+            }
         }
     });
 
-    connections.write().await.insert(id, sx);
+    {
+        // held until the player is marked connected, so that an older socket of theirs winding
+        // down can't get in between and mark them disconnected again
+        let mut connections = connections.write().await;
+        connections.insert(id, sx.clone());
+        state
+            .write()
+            .await
+            .set_connected(id, true, std::time::Instant::now());
+    }
 
-    while let Some(result) = user_ws_rx.next().await {
+    let mut last_pong = tokio::time::Instant::now();
+    let mut timed_out = false;
+    loop {
+        let deadline = last_pong + config.pong_timeout;
+        let result = match tokio::time::timeout_at(deadline, user_ws_rx.next()).await {
+            Ok(Some(result)) => result,
+            Ok(None) => break,
+            Err(_elapsed) => {
+                log::info!(
+                    "No pong from User {:?} in {:?}. Dropping connection.",
+                    id,
+                    config.pong_timeout
+                );
+                timed_out = true;
+                break;
+            }
+        };
         match result {
             Ok(msg) => {
-                let parse_attempt: Result<shared::viewer::Command<CustomMessageType>, _> =
-                    if let Ok(text) = msg.to_str() {
-                        serde_json::from_str(text)
-                    } else if msg.is_binary() {
-                        serde_json::from_slice(msg.as_bytes())
-                    } else {
-                        continue;
-                    };
+                if msg.is_pong() {
+                    last_pong = tokio::time::Instant::now();
+                    continue;
+                }
 
-                match parse_attempt {
+                let frame = match frame_ref(&msg) {
+                    Some(frame) => frame,
+                    None => continue,
+                };
+                match codec::decode(frame) {
                     Ok(cmd) => {
-                        state.write().await.handle_command(cmd, &id);
+                        use shared::viewer::Command;
+                        use shared::CustomMessage::Collapsed;
+                        let left = match &cmd {
+                            Command::Leave(room_id) => Some(*room_id),
+                            _ => None,
+                        };
+                        let collapsed = match &cmd {
+                            Command::Custom(room_id, Collapsed(_))
+                            | Command::Reliable(room_id, Collapsed(_), _) => Some(*room_id),
+                            _ => None,
+                        };
+                        let (reply, finished) = {
+                            let mut state = state.write().await;
+                            let finished = |state: &shared::viewer::state::State<_>| {
+                                collapsed.and_then(|room_id| {
+                                    results::MatchResult::of_room(state, room_id)
+                                })
+                            };
+                            let already_finished = finished(&state).is_some();
+                            let reply = handle_command(&mut state, cmd, &id);
+                            (reply, finished(&state).filter(|_| !already_finished))
+                        };
+                        if let Some(room_id) = left {
+                            seats.vacate(id, room_id);
+                        }
+                        if let Some(finished) = finished {
+                            let results = results.clone();
+                            tokio::task::spawn_blocking(move || {
+                                if let Err(err) = results.record(&finished) {
+                                    log::error!("failed to record {:?}: {}", finished, err);
+                                }
+                            });
+                        }
+                        if let Some(reply) = reply {
+                            match codec::encode(&reply) {
+                                Ok(reply) => {
+                                    if let Err(err) = sx.send(ws_message(reply)) {
+                                        log::error!("{}", err);
+                                    }
+                                }
+                                Err(err) => {
+                                    log::error!("{}", err);
+                                }
+                            }
+                        }
+                    }
+                    Err(CodecError::Version(mismatch)) => {
+                        log::warn!("Closing WS connection for User {:?}: {}", id, mismatch);
+                        let reason = format!("{}, please refresh", mismatch);
+                        let _ = sx.send(warp::ws::Message::close_with(1002u16, reason));
+                        break;
                     }
                     Err(err) => {
                         log::error!("{:?}", err);
@@ -143,27 +659,104 @@ async fn on_ws_connect(
         }
     }
 
-    state.write().await.unregister_user(id);
-    if let None = connections.write().await.remove(&id) {
-        log::warn!("Attempted to remove player connection that was not present.");
-    } else {
+    // a player who reconnected before this socket ended is already using another one, which
+    // this mustn't unplug
+    let current = {
+        let mut connections = connections.write().await;
+        let current = connections
+            .get(&id)
+            .is_some_and(|other| other.same_channel(&sx));
+        if current {
+            connections.remove(&id);
+            state
+                .write()
+                .await
+                .set_connected(id, false, std::time::Instant::now());
+        }
+        current
+    };
+    if current {
+        tokio::spawn({
+            let state = state.clone();
+            let seats = seats.clone();
+            let grace = config.reconnect_grace;
+            async move {
+                tokio::time::sleep(grace).await;
+                let reaped = state
+                    .write()
+                    .await
+                    .reap_disconnected(grace, std::time::Instant::now());
+                if !reaped.is_empty() {
+                    log::debug!("Removed disconnected users {:?}", reaped);
+                    seats.forget(&reaped);
+                }
+            }
+        });
         log::debug!("Ended WS connection for User {:?}", id);
+    } else {
+        log::debug!("Ended superseded WS connection for User {:?}", id);
+    }
+
+    if timed_out {
+        // also stops the send task, which would otherwise keep pinging into the void
+        let _ = sx.send(warp::ws::Message::close_with(1001u16, "pong timeout"));
     }
 }
 
+/// `None` for control frames.
+fn frame_ref(msg: &warp::ws::Message) -> Option<FrameRef<'_>> {
+    if let Ok(text) = msg.to_str() {
+        Some(FrameRef::Text(text))
+    } else if msg.is_binary() {
+        Some(FrameRef::Binary(msg.as_bytes()))
+    } else {
+        None
+    }
+}
+
+fn ws_message(frame: Frame) -> warp::ws::Message {
+    match frame {
+        Frame::Text(text) => warp::ws::Message::text(text),
+        Frame::Binary(bytes) => warp::ws::Message::binary(bytes),
+    }
+}
+
+/// A panic in a handler only loses that one command rather than the connection, and with it the
+/// player's seat. Whatever the handler changed before panicking stays changed.
+fn handle_command(
+    state: &mut shared::viewer::state::State<CustomMessageType>,
+    cmd: shared::viewer::Command<CustomMessageType>,
+    from: &shared::PlayerID,
+) -> Option<shared::viewer::StateChange<CustomMessageType>> {
+    let handled = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        state.handle_command(cmd, from)
+    }));
+    handled.unwrap_or_else(|panic| {
+        let reason = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown");
+        log::error!("Command from User {:?} panicked: {}", from, reason);
+        None
+    })
+}
+
 async fn ws_forward(
     player_id: shared::PlayerID,
+    backlog: Vec<shared::viewer::StateChange<shared::CustomMessage>>,
     channel: tokio::sync::broadcast::Receiver<shared::viewer::StateChange<shared::CustomMessage>>,
     connections: PlayerConnections,
 ) {
-    let mut channel = tokio_stream::wrappers::BroadcastStream::new(channel);
+    let backlog = futures::stream::iter(backlog.into_iter().map(Ok));
+    let mut channel = backlog.chain(tokio_stream::wrappers::BroadcastStream::new(channel));
     while let Some(msg) = channel.next().await {
         match msg {
-            Ok(msg) => match serde_json::to_string(&msg) {
+            Ok(msg) => match codec::encode(&msg) {
                 Ok(msg) => {
                     let mut connections = connections.write().await;
                     if let Some(socket) = connections.get_mut(&player_id) {
-                        if let Err(err) = socket.send(warp::ws::Message::text(msg)) {
+                        if let Err(err) = socket.send(ws_message(msg)) {
                             log::error!("{}", err);
                         }
                     } else {
@@ -183,52 +776,156 @@ async fn ws_forward(
 }
 
 async fn create_room(
-    player_id: String,
+    player: PlayerCookie,
     state: State,
     connections: PlayerConnections,
-    player_name: shared::PlayerName,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    if let Ok(player_id) = std::str::FromStr::from_str(&player_id) {
-        let mut state = state.write().await;
-        let user = shared::viewer::User {
-            id: player_id,
-            name: player_name,
-        };
-        state.register_user(user.clone());
-        let room_id = state.create_room();
-        state.join(room_id, player_id);
-        let (room_state, channel) = state.subscribe(room_id).unwrap();
-        drop(state);
+    seats: Seats,
+    config: config::Config,
+    format: shared::codec::Format,
+    create_info: shared::RoomCreateInfo,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let player_id = match std::str::FromStr::from_str(&player.id) {
+        Ok(player_id) => player_id,
+        Err(_err) => return Err(warp::reject()),
+    };
+    let room_name = match create_info
+        .room_name
+        .as_deref()
+        .map(shared::validate_room_name)
+    {
+        None => None,
+        Some(Ok(room_name)) => Some(room_name),
+        Some(Err(err)) => {
+            return Ok(warp::reply::with_status(
+                err.to_string(),
+                warp::hyper::StatusCode::BAD_REQUEST,
+            )
+            .into_response())
+        }
+    };
 
-        tokio::spawn(ws_forward(player_id, channel, connections));
-        Ok(warp::reply::json(&room_state))
-    } else {
-        Err(warp::reject())
-    }
+    let password_hash = match create_info.password.filter(|password| !password.is_empty()) {
+        None => None,
+        Some(password) if password.len() > password::MAX_LENGTH => {
+            return Ok(warp::reply::with_status(
+                "password is too long",
+                warp::hyper::StatusCode::BAD_REQUEST,
+            )
+            .into_response())
+        }
+        Some(password) => {
+            match tokio::task::spawn_blocking(move || password::hash(&password)).await {
+                Ok(Ok(hash)) => Some(hash),
+                Ok(Err(err)) => {
+                    log::error!("{}", err);
+                    return Err(warp::reject());
+                }
+                Err(err) => {
+                    log::error!("{}", err);
+                    return Err(warp::reject());
+                }
+            }
+        }
+    };
+
+    let connected = connections.read().await.contains_key(&player_id);
+    let mut state = state.write().await;
+    let room_id = match state.create_named_room(room_name) {
+        Ok(room_id) => room_id,
+        Err(err) => {
+            log::error!("{}", err);
+            return Ok(warp::reply::with_status(
+                err.to_string(),
+                warp::hyper::StatusCode::SERVICE_UNAVAILABLE,
+            )
+            .into_response());
+        }
+    };
+    let user = shared::viewer::User {
+        id: player_id,
+        name: create_info.player_name,
+        connected,
+    };
+    state.register_user(user.clone());
+    state.set_room_password_hash(room_id, password_hash);
+    state.set_room_settings(room_id, config.room_settings());
+    state.join(room_id, player_id);
+    let (room_state, channel) = state.subscribe(room_id).unwrap();
+    let backlog = state.replay(room_id);
+    drop(state);
+
+    seats.forward(player_id, room_id, backlog, channel, connections);
+    Ok(player.set_on(encoded(
+        format,
+        &shared::JoinedRoom {
+            room: room_state,
+            rejoin_token: seats.issue_token(player_id, room_id),
+            player_id,
+        },
+    )))
 }
 
 async fn join_room(
-    player_id: String,
+    player: PlayerCookie,
     state: State,
     connections: PlayerConnections,
+    seats: Seats,
+    format: shared::codec::Format,
     join_info: shared::RoomJoinInfo,
 ) -> Result<impl warp::Reply, std::convert::Infallible> {
     let room_id = std::convert::TryInto::<shared::RoomID>::try_into(join_info.room_id).ok();
-    let player_id = std::str::FromStr::from_str(&player_id).ok();
+    let player_id = std::str::FromStr::from_str(&player.id).ok();
     let result = match room_id.zip(player_id) {
         Some((room_id, player_id)) => {
+            let password_hash = state
+                .read()
+                .await
+                .room_password_hash(room_id)
+                .map(str::to_owned);
+            if let Some(hash) = password_hash {
+                let password = join_info.password.unwrap_or_default();
+                let verified = password.len() <= password::MAX_LENGTH
+                    && tokio::task::spawn_blocking(move || password::verify(&hash, &password))
+                        .await
+                        .unwrap_or(false);
+                if !verified {
+                    return Ok(warp::reply::with_status(
+                        "incorrect room password",
+                        warp::hyper::StatusCode::FORBIDDEN,
+                    )
+                    .into_response());
+                }
+            }
+
+            let connected = connections.read().await.contains_key(&player_id);
             let mut state = state.write().await;
+            if state.has_space(room_id) == Some(false) {
+                return Ok(warp::reply::with_status(
+                    "room is full",
+                    warp::hyper::StatusCode::CONFLICT,
+                )
+                .into_response());
+            }
             let user = shared::viewer::User {
                 id: player_id,
                 name: join_info.player_name,
+                connected,
             };
             state.register_user(user.clone());
             state.join(room_id, player_id);
             let (room_state, channel) = state.subscribe(room_id).unwrap();
+            let backlog = state.replay(room_id);
             drop(state);
 
-            tokio::spawn(ws_forward(player_id, channel, connections));
-            warp::reply::json(&room_state).into_response()
+            seats.forward(player_id, room_id, backlog, channel, connections);
+            player.set_on(encoded(
+                format,
+                &shared::JoinedRoom {
+                    room: room_state,
+                    rejoin_token: seats.issue_token(player_id, room_id),
+                    player_id,
+                },
+            ))
         }
         None => warp::reply::with_status(
             "could not parse room id",
@@ -239,6 +936,133 @@ async fn join_room(
     Ok(result)
 }
 
+/// Puts a player back in the seat their token was issued for, keeping their place in the user
+/// list and so their role. Only works while the server is still holding that seat.
+async fn rejoin_room(
+    player_id: String,
+    state: State,
+    connections: PlayerConnections,
+    seats: Seats,
+    format: shared::codec::Format,
+    rejoin_info: shared::RoomRejoinInfo,
+) -> Result<warp::reply::Response, std::convert::Infallible> {
+    use warp::hyper::StatusCode;
+    let reply = |msg: &'static str, status: StatusCode| {
+        Ok(warp::reply::with_status(msg, status).into_response())
+    };
+
+    let player_id: shared::PlayerID = match std::str::FromStr::from_str(&player_id) {
+        Ok(player_id) => player_id,
+        Err(_err) => return reply("could not parse player id", StatusCode::BAD_REQUEST),
+    };
+    let token = rejoin_info.rejoin_token;
+    let (owner, room_id) = match seats.lookup(&token) {
+        Some(seat) => seat,
+        None => return reply("unknown rejoin token", StatusCode::NOT_FOUND),
+    };
+    if owner != player_id {
+        return reply(
+            "rejoin token belongs to another player",
+            StatusCode::FORBIDDEN,
+        );
+    }
+
+    let (room_state, channel, backlog) = match state.write().await.reconnect(room_id, player_id) {
+        Some(reconnected) => reconnected,
+        None => {
+            seats.revoke(&token);
+            return reply("seat is no longer held", StatusCode::GONE);
+        }
+    };
+
+    seats.forward(player_id, room_id, backlog, channel, connections);
+    Ok(encoded(
+        format,
+        &shared::JoinedRoom {
+            room: room_state,
+            rejoin_token: token,
+            player_id,
+        },
+    ))
+}
+
+async fn leaderboard(
+    results: Results,
+    format: shared::codec::Format,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match tokio::task::spawn_blocking(move || results.results()).await {
+        Ok(Ok(results)) => Ok(encoded(format, &results::leaderboard(&results))),
+        Ok(Err(err)) => {
+            log::error!("{}", err);
+            Err(warp::reject())
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Err(warp::reject())
+        }
+    }
+}
+
+/// A new player id that only requests with the returned token can use. Both are set as cookies
+/// too, for browsers.
+async fn register(
+    accounts: Accounts,
+    format: shared::codec::Format,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let registered = tokio::task::spawn_blocking(move || -> eyre::Result<shared::Registered> {
+        let account_token = format!("{:032x}", rand::random::<u128>());
+        let account = accounts::Account {
+            player_id: shared::PlayerID::gen(&mut rand::thread_rng()),
+            token_hash: password::hash(&account_token)?,
+        };
+        let player_id = account.player_id;
+        accounts.insert(account)?;
+        Ok(shared::Registered {
+            player_id,
+            account_token,
+        })
+    })
+    .await;
+    match registered {
+        Ok(Ok(registered)) => {
+            let reply = encoded(format, &registered);
+            let reply = set_cookie(reply, "game-player-id", &registered.player_id.to_string());
+            Ok(set_cookie(
+                reply,
+                "game-account-token",
+                &registered.account_token,
+            ))
+        }
+        Ok(Err(err)) => {
+            log::error!("{}", err);
+            Err(warp::reject())
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Err(warp::reject())
+        }
+    }
+}
+
+/// Zeroes for anyone without any recorded matches.
+async fn player_stats(
+    player_id: shared::PlayerID,
+    results: Results,
+    format: shared::codec::Format,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match tokio::task::spawn_blocking(move || results.stats(player_id)).await {
+        Ok(Ok(stats)) => Ok(encoded(format, &stats)),
+        Ok(Err(err)) => {
+            log::error!("{}", err);
+            Err(warp::reject())
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Err(warp::reject())
+        }
+    }
+}
+
 async fn debug_state(state: State) -> Result<impl warp::Reply, std::convert::Infallible> {
     let state = state.read().await;
     let state = state
@@ -253,7 +1077,9 @@ async fn debug_state(state: State) -> Result<impl warp::Reply, std::convert::Inf
                 .collect::<Vec<_>>();
             shared::viewer::InitialRoomState {
                 id: room.state.id,
+                name: room.state.name.clone(),
                 users,
+                settings: room.state.settings,
             }
         })
         .collect::<Vec<_>>();
@@ -263,8 +1089,916 @@ async fn debug_state(state: State) -> Result<impl warp::Reply, std::convert::Inf
 
 #[cfg(test)]
 mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    use super::*;
+    use std::time::Duration;
+
+    /// Forgets results and accounts, for the tests that don't care about them.
+    fn api(
+        state: State,
+        connections: PlayerConnections,
+        config: config::Config,
+    ) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+        super::api(
+            state,
+            connections,
+            std::sync::Arc::new(results::MemoryStore::default()),
+            std::sync::Arc::new(accounts::MemoryAccounts::default()),
+            config,
+        )
+    }
+
+    #[tokio::test]
+    async fn silent_connection_is_reaped() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let connections = PlayerConnections::default();
+        // pings are rare enough that the client never gets a chance to answer in time
+        let config = config::Config {
+            ping_interval: Duration::from_secs(60),
+            pong_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let player_id = "1".parse::<shared::PlayerID>().unwrap();
+
+        let mut client = warp::test::ws()
+            .path(&format!("/{}", shared::ENDPOINT_WS))
+            .header("cookie", "game-player-id=1")
+            .handshake(api(state, connections.clone(), config))
+            .await
+            .expect("handshake");
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(connections.read().await.contains_key(&player_id));
+
+        let closed = tokio::time::timeout(Duration::from_secs(2), async {
+            while client.recv().await.is_ok() {}
+        })
+        .await;
+        assert!(closed.is_ok(), "connection was never closed");
+        assert!(!connections.read().await.contains_key(&player_id));
+    }
+
+    #[tokio::test]
+    async fn overlapping_sockets_keep_the_newer_one() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let connections = PlayerConnections::default();
+        let config = config::Config {
+            reconnect_grace: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let api = api(state.clone(), connections.clone(), config);
+        let player_id = "1".parse::<shared::PlayerID>().unwrap();
+        let connect = || {
+            warp::test::ws()
+                .path(&format!("/{}", shared::ENDPOINT_WS))
+                .header("cookie", "game-player-id=1")
+                .handshake(api.clone())
+        };
+
+        let old = connect().await.expect("handshake");
+        let room_id = seat_players(&api, &["Alice"]).await;
+        // a refresh connects again before the old socket has noticed it's gone
+        let mut new = connect().await.expect("handshake");
+        drop(old);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let current = state.read().await;
+        assert!(current.users[&player_id].connected);
+        assert!(current.rooms[&room_id].state.users.contains(&player_id));
+        drop(current);
+        assert!(connections.read().await.contains_key(&player_id));
+
+        // and the room is still forwarded to the new socket
+        let cmd = shared::viewer::Command::Chat(room_id, "still here".to_owned());
+        let cmd = codec::encode(&cmd as &shared::viewer::Command<shared::CustomMessage>).unwrap();
+        new.send(ws_message(cmd)).await;
+        let said = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let msg = new.recv().await.expect("socket stays open");
+                if let Some(frame) = frame_ref(&msg) {
+                    let change: shared::viewer::StateChange<shared::CustomMessage> =
+                        codec::decode(frame).unwrap();
+                    if let shared::viewer::ChangeType::Chat { text, .. } = change.ty {
+                        break text;
+                    }
+                }
+            }
+        })
+        .await;
+        assert_eq!(said.as_deref(), Ok("still here"));
+    }
+
+    #[tokio::test]
+    async fn handler_panics_are_survived() {
+        use shared::viewer::{ChangeType, Command};
+        use shared::CustomMessage;
+
+        fn touchy(
+            msg: &CustomMessage,
+            _: &shared::viewer::RoomState,
+            _: &shared::PlayerID,
+            _: &[CustomMessage],
+            _: std::time::Duration,
+        ) -> bool {
+            match msg {
+                CustomMessage::StartGame(3) => panic!("room type 3"),
+                _ => true,
+            }
+        }
+        let state = shared::viewer::state::State::new().with_guard(touchy);
+        let state = State::new(tokio::sync::RwLock::new(state));
+        let api = api(state, PlayerConnections::default(), Default::default());
+
+        let response = create_room(&api, "Alice", None, None).await;
+        let room_id = serde_json::from_slice::<shared::JoinedRoom>(response.body())
+            .unwrap()
+            .room
+            .id;
+
+        let mut client = warp::test::ws()
+            .path(&format!("/{}", shared::ENDPOINT_WS))
+            .header("cookie", "game-player-id=1")
+            .handshake(api)
+            .await
+            .expect("handshake");
+        let send = |msg: CustomMessage, nonce| {
+            let cmd = Command::Reliable(room_id, msg, nonce);
+            ws_message(codec::encode(&cmd).unwrap())
+        };
+        // parses, so it reaches the guard, which panics before it can be acknowledged
+        client.send(send(CustomMessage::StartGame(3), 1)).await;
+        client.send(send(CustomMessage::StartGame(0), 2)).await;
+
+        let acked = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let msg = client.recv().await.expect("connection dropped");
+                let change = frame_ref(&msg).and_then(|frame| {
+                    codec::decode::<shared::viewer::StateChange<CustomMessage>>(frame).ok()
+                });
+                if let Some(ChangeType::Ack(nonce)) = change.map(|change| change.ty) {
+                    return nonce;
+                }
+            }
+        })
+        .await;
+        assert_eq!(acked.ok(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn other_protocol_versions_are_turned_away() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let connections = PlayerConnections::default();
+        let mut client = warp::test::ws()
+            .path(&format!("/{}", shared::ENDPOINT_WS))
+            .header("cookie", "game-player-id=1")
+            .handshake(api(state, connections.clone(), Default::default()))
+            .await
+            .expect("handshake");
+
+        let cmd = shared::viewer::Command::Custom(
+            "ABCD".parse::<shared::RoomID>().unwrap(),
+            shared::CustomMessage::StartGame(0),
+        );
+        let mut envelope = shared::viewer::Envelope::new(cmd);
+        envelope.version = shared::viewer::PROTOCOL_VERSION + 1;
+        client
+            .send_text(serde_json::to_string(&envelope).unwrap())
+            .await;
+
+        let closed = tokio::time::timeout(Duration::from_secs(2), async {
+            while client.recv().await.is_ok() {}
+        })
+        .await;
+        assert!(closed.is_ok(), "connection was never closed");
+        assert!(!connections.read().await.contains_key(&"1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn leaving_a_room_keeps_the_connection() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let connections = PlayerConnections::default();
+        let api = api(state.clone(), connections.clone(), Default::default());
+        let player_id = "1".parse::<shared::PlayerID>().unwrap();
+        let mut client = warp::test::ws()
+            .path(&format!("/{}", shared::ENDPOINT_WS))
+            .header("cookie", "game-player-id=1")
+            .handshake(api.clone())
+            .await
+            .expect("handshake");
+
+        let response = create_room(&api, "Alice", None, None).await;
+        let created: shared::JoinedRoom = serde_json::from_slice(response.body()).unwrap();
+
+        let cmd = shared::viewer::Command::<shared::CustomMessage>::Leave(created.room.id);
+        client.send(ws_message(codec::encode(&cmd).unwrap())).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // the room went with its last player, who is still connected and registered
+        let state = state.read().await;
+        assert!(!state.rooms.contains_key(&created.room.id));
+        assert!(state.users.contains_key(&player_id));
+        drop(state);
+        assert!(connections.read().await.contains_key(&player_id));
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/{}", shared::ENDPOINT_REJOIN_ROOM))
+            .header("cookie", "game-player-id=1")
+            .json(&shared::RoomRejoinInfo {
+                rejoin_token: created.rejoin_token,
+            })
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn finished_matches_make_the_leaderboard() {
+        use results::ResultStore;
+        use shared::viewer::Command;
+        use shared::CustomMessage;
+
+        let path = std::env::temp_dir().join(format!(
+            "tension-results-{:016x}.jsonl",
+            rand::random::<u64>()
+        ));
+        let store = std::sync::Arc::new(results::JsonFileStore::new(&path));
+        let api = game_api(
+            store.clone(),
+            std::sync::Arc::new(accounts::MemoryAccounts::default()),
+        );
+        let mut dm = warp::test::ws()
+            .path(&format!("/{}", shared::ENDPOINT_WS))
+            .header("cookie", "game-player-id=1")
+            .handshake(api.clone())
+            .await
+            .expect("handshake");
+        let room_id = seat_players(&api, &["Dee", "Alice", "Bob"]).await;
+
+        let bob = "3".parse().unwrap();
+        for msg in [
+            CustomMessage::StartGame(0),
+            CustomMessage::Collapsed(bob),
+            // only the first collapse of a game counts
+            CustomMessage::Collapsed(bob),
+        ]
+        .iter()
+        {
+            let cmd = Command::Custom(room_id, msg.clone());
+            dm.send(ws_message(codec::encode(&cmd).unwrap())).await;
+        }
+
+        let recorded = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let results = store.results().unwrap();
+                if !results.is_empty() {
+                    break results;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the match was never recorded");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(store.results().unwrap().len(), 1);
+        assert_eq!(recorded[0].room_id, room_id);
+        let names = recorded[0]
+            .players
+            .iter()
+            .map(|player| player.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Alice", "Bob"]);
+        assert_eq!(recorded[0].winners, vec!["2".parse().unwrap()]);
+
+        let response = warp::test::request()
+            .path(&format!("/{}", shared::ENDPOINT_LEADERBOARD))
+            .reply(&api)
+            .await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(response.status(), 200);
+        let leaderboard: Vec<shared::LeaderboardEntry> =
+            serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            leaderboard,
+            vec![shared::LeaderboardEntry {
+                id: "2".parse().unwrap(),
+                name: "Alice".to_owned(),
+                wins: 1,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn stats_add_up_over_matches() {
+        use results::ResultStore;
+        use shared::viewer::Command;
+        use shared::CustomMessage;
+
+        let store = std::sync::Arc::new(results::MemoryStore::default());
+        let api = game_api(
+            store.clone(),
+            std::sync::Arc::new(accounts::MemoryAccounts::default()),
+        );
+        let mut dm = warp::test::ws()
+            .path(&format!("/{}", shared::ENDPOINT_WS))
+            .header("cookie", "game-player-id=1")
+            .handshake(api.clone())
+            .await
+            .expect("handshake");
+        let room_id = seat_players(&api, &["Dee", "Alice", "Bob"]).await;
+
+        let (alice, bob) = ("2".parse().unwrap(), "3".parse().unwrap());
+        for (played, loser) in [bob, bob, alice].iter().enumerate() {
+            for msg in [
+                CustomMessage::StartGame(0),
+                CustomMessage::Collapsed(*loser),
+            ]
+            .iter()
+            {
+                let cmd = Command::Custom(room_id, msg.clone());
+                dm.send(ws_message(codec::encode(&cmd).unwrap())).await;
+            }
+            // streaks depend on the order the matches are recorded in
+            let recorded = tokio::time::timeout(Duration::from_secs(2), async {
+                while store.results().unwrap().len() <= played {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await;
+            assert!(recorded.is_ok(), "match {} was never recorded", played);
+        }
+
+        let stats = |player_id: u32| {
+            let api = api.clone();
+            async move {
+                let response = warp::test::request()
+                    .path(&format!("/{}/{}", shared::ENDPOINT_STATS, player_id))
+                    .reply(&api)
+                    .await;
+                assert_eq!(response.status(), 200);
+                serde_json::from_slice::<shared::PlayerStats>(response.body()).unwrap()
+            }
+        };
+        assert_eq!(
+            stats(2).await,
+            shared::PlayerStats {
+                games_played: 3,
+                towers_toppled: 1,
+                longest_survival_streak: 2,
+            }
+        );
+        assert_eq!(
+            stats(3).await,
+            shared::PlayerStats {
+                games_played: 3,
+                towers_toppled: 2,
+                longest_survival_streak: 1,
+            }
+        );
+        // the DM doesn't play
+        assert_eq!(stats(1).await, shared::PlayerStats::default());
+    }
+
+    #[tokio::test]
+    async fn accounts_keep_their_id_across_sessions() {
+        use results::ResultStore;
+        use shared::viewer::Command;
+        use shared::CustomMessage;
+
+        let store = std::sync::Arc::new(results::MemoryStore::default());
+        let accounts = std::sync::Arc::new(accounts::MemoryAccounts::default());
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/{}", shared::ENDPOINT_REGISTER))
+            .reply(&game_api(store.clone(), accounts.clone()))
+            .await;
+        assert_eq!(response.status(), 200);
+        let registered: shared::Registered = serde_json::from_slice(response.body()).unwrap();
+        let account_id = registered.player_id;
+
+        let bob = "2".parse().unwrap();
+        for (session, loser) in [account_id, bob].iter().enumerate() {
+            // a restarted server, and the player on another browser with only their account
+            let api = game_api(store.clone(), accounts.clone());
+            let mut dm = warp::test::ws()
+                .path(&format!("/{}", shared::ENDPOINT_WS))
+                .header("cookie", "game-player-id=1")
+                .handshake(api.clone())
+                .await
+                .expect("handshake");
+            let room_id = seat_players(&api, &["Dee", "Bob"]).await;
+            let join = |cookie: String| {
+                warp::test::request()
+                    .method("POST")
+                    .path(&format!("/{}", shared::ENDPOINT_JOIN_ROOM))
+                    .header("cookie", cookie)
+                    .json(&shared::RoomJoinInfo {
+                        room_id,
+                        player_name: "Alice".to_owned(),
+                        password: None,
+                    })
+                    .reply(&api)
+            };
+            let impostor = join(format!("game-player-id={}", account_id)).await;
+            assert_eq!(impostor.status(), 403);
+            let joined = join(format!(
+                "game-player-id={}; game-account-token={}",
+                account_id, registered.account_token
+            ))
+            .await;
+            assert_eq!(joined.status(), 200);
+
+            for msg in [
+                CustomMessage::StartGame(0),
+                CustomMessage::Collapsed(*loser),
+            ]
+            .iter()
+            {
+                let cmd = Command::Custom(room_id, msg.clone());
+                dm.send(ws_message(codec::encode(&cmd).unwrap())).await;
+            }
+            let recorded = tokio::time::timeout(Duration::from_secs(2), async {
+                while store.results().unwrap().len() <= session {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await;
+            assert!(recorded.is_ok(), "session {} was never recorded", session);
+        }
+
+        let response = warp::test::request()
+            .path(&format!("/{}/{}", shared::ENDPOINT_STATS, account_id))
+            .reply(&game_api(store, accounts))
+            .await;
+        let stats: shared::PlayerStats = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            stats,
+            shared::PlayerStats {
+                games_played: 2,
+                towers_toppled: 1,
+                longest_survival_streak: 1,
+            }
+        );
+    }
+
+    /// With the game's own rules, which the match results depend on.
+    fn game_api(
+        results: Results,
+        accounts: Accounts,
+    ) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+        let state = shared::viewer::state::State::new()
+            .with_checkpoint(shared::CustomMessage::starts_game)
+            .with_transient(shared::CustomMessage::is_transient)
+            .with_guard(shared::CustomMessage::allowed_in);
+        super::api(
+            State::new(tokio::sync::RwLock::new(state)),
+            PlayerConnections::default(),
+            results,
+            accounts,
+            Default::default(),
+        )
+    }
+
+    /// Player 1 creates a room, as `player_name`.
+    async fn create_room<F>(
+        api: &F,
+        player_name: &str,
+        room_name: Option<&str>,
+        password: Option<&str>,
+    ) -> warp::http::Response<warp::hyper::body::Bytes>
+    where
+        F: Filter + 'static,
+        F::Extract: Reply + Send,
+    {
+        warp::test::request()
+            .method("POST")
+            .path(&format!("/{}", shared::ENDPOINT_CREATE_ROOM))
+            .header("cookie", "game-player-id=1")
+            .json(&shared::RoomCreateInfo {
+                player_name: player_name.to_owned(),
+                room_name: room_name.map(str::to_owned),
+                password: password.map(str::to_owned),
+            })
+            .reply(api)
+            .await
+    }
+
+    /// The first of `names` creates the room and the rest join it, with ids counting up from 1.
+    async fn seat_players<F>(api: &F, names: &[&str]) -> shared::RoomID
+    where
+        F: Filter + 'static,
+        F::Extract: Reply + Send,
+    {
+        let response = create_room(api, names[0], None, None).await;
+        let room_id = serde_json::from_slice::<shared::JoinedRoom>(response.body())
+            .unwrap()
+            .room
+            .id;
+        for (index, name) in names.iter().enumerate().skip(1) {
+            let response = warp::test::request()
+                .method("POST")
+                .path(&format!("/{}", shared::ENDPOINT_JOIN_ROOM))
+                .header("cookie", format!("game-player-id={}", index + 1))
+                .json(&shared::RoomJoinInfo {
+                    room_id,
+                    player_name: (*name).to_owned(),
+                    password: None,
+                })
+                .reply(api)
+                .await;
+            assert_eq!(response.status(), 200);
+        }
+        room_id
+    }
+
+    #[tokio::test]
+    async fn connections_per_ip_are_capped() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let connections = PlayerConnections::default();
+        let config = config::Config {
+            max_connections_per_ip: 1,
+            ..Default::default()
+        };
+        let api = api(state, connections.clone(), config);
+        let connect = |id: &str| {
+            warp::test::ws()
+                .path(&format!("/{}", shared::ENDPOINT_WS))
+                .header("cookie", format!("game-player-id={}", id))
+                .handshake(api.clone())
+        };
+
+        let _first = connect("1").await.expect("handshake");
+        let mut second = connect("2").await.expect("handshake");
+
+        let closed = tokio::time::timeout(Duration::from_secs(2), second.recv_closed()).await;
+        assert!(matches!(closed, Ok(Ok(()))), "{:?}", closed);
+        let connections = connections.read().await;
+        assert!(connections.contains_key(&"1".parse().unwrap()));
+        assert!(!connections.contains_key(&"2".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn room_name_round_trips_through_create() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let api = api(state, PlayerConnections::default(), Default::default());
+        let create = |room_name| create_room(&api, "Alice", room_name, None);
+
+        let response = create(Some(" Game Night ")).await;
+        assert_eq!(response.status(), 200);
+        let joined: shared::JoinedRoom = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(joined.room.name.as_deref(), Some("Game Night"));
+
+        let response = create(None).await;
+        let joined: shared::JoinedRoom = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(joined.room.name, None);
+
+        let too_long = "a".repeat(shared::MAX_ROOM_NAME_LENGTH + 1);
+        let response = create(Some(&too_long)).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn private_room_requires_password() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let api = api(state, PlayerConnections::default(), Default::default());
+
+        let response = create_room(&api, "Alice", None, Some("hunter2")).await;
+        assert_eq!(response.status(), 200);
+        let body = String::from_utf8_lossy(response.body());
+        assert!(!body.contains("hunter2"));
+        let room = serde_json::from_slice::<shared::JoinedRoom>(response.body())
+            .unwrap()
+            .room;
+
+        let join = |player_id: u32, password: Option<&str>| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/{}", shared::ENDPOINT_JOIN_ROOM))
+                .header("cookie", format!("game-player-id={}", player_id))
+                .json(&shared::RoomJoinInfo {
+                    room_id: room.id,
+                    player_name: "Bob".to_owned(),
+                    password: password.map(str::to_owned),
+                })
+                .reply(&api)
+        };
+
+        assert_eq!(join(2, None).await.status(), 403);
+        assert_eq!(join(2, Some("hunter3")).await.status(), 403);
+
+        let response = join(2, Some("hunter2")).await;
+        assert_eq!(response.status(), 200);
+        let joined: shared::JoinedRoom = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(joined.room.users.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn full_rooms_refuse_joins() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let config = config::Config {
+            room_capacity: Some(2),
+            ..Default::default()
+        };
+        let api = api(state, PlayerConnections::default(), config);
+
+        let response = create_room(&api, "Alice", None, None).await;
+        let room = serde_json::from_slice::<shared::JoinedRoom>(response.body())
+            .unwrap()
+            .room;
+        assert_eq!(room.settings.capacity, Some(2));
+
+        let join = |player_id: u32| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/{}", shared::ENDPOINT_JOIN_ROOM))
+                .header("cookie", format!("game-player-id={}", player_id))
+                .json(&shared::RoomJoinInfo {
+                    room_id: room.id,
+                    player_name: "Bob".to_owned(),
+                    password: None,
+                })
+                .reply(&api)
+        };
+        let response = join(2).await;
+        assert_eq!(response.status(), 200);
+        let joined: shared::JoinedRoom = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(joined.room.users.len(), 2);
+        assert_eq!(join(3).await.status(), 409);
+    }
+
+    #[tokio::test]
+    async fn join_speaks_msgpack_when_asked() {
+        use shared::codec::Format;
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let api = api(state, PlayerConnections::default(), Default::default());
+
+        let response = create_room(&api, "Alice", None, None).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], Format::Json.mime());
+        let room = serde_json::from_slice::<shared::JoinedRoom>(response.body())
+            .unwrap()
+            .room;
+
+        let body = Format::MessagePack
+            .encode(&shared::RoomJoinInfo {
+                room_id: room.id,
+                player_name: "Bob".to_owned(),
+                password: None,
+            })
+            .unwrap();
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/{}", shared::ENDPOINT_JOIN_ROOM))
+            .header("cookie", "game-player-id=2")
+            .header("content-type", Format::MessagePack.mime())
+            .header("accept", Format::MessagePack.mime())
+            .body(body)
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            Format::MessagePack.mime()
+        );
+        let joined: shared::JoinedRoom = Format::MessagePack.decode(response.body()).unwrap();
+        assert_eq!(joined.room.id, room.id);
+        assert_eq!(joined.room.users.len(), 2);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/{}", shared::ENDPOINT_JOIN_ROOM))
+            .header("cookie", "game-player-id=3")
+            .header("content-type", Format::MessagePack.mime())
+            .body("not msgpack")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), warp::hyper::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rejoin_token_restores_seat() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let api = api(
+            state.clone(),
+            PlayerConnections::default(),
+            Default::default(),
+        );
+        let dm = "1".parse::<shared::PlayerID>().unwrap();
+
+        let response = create_room(&api, "Alice", None, None).await;
+        let created: shared::JoinedRoom = serde_json::from_slice(response.body()).unwrap();
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/{}", shared::ENDPOINT_JOIN_ROOM))
+            .header("cookie", "game-player-id=2")
+            .json(&shared::RoomJoinInfo {
+                room_id: created.room.id,
+                player_name: "Bob".to_owned(),
+                password: None,
+            })
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        // the DM refreshes their page, dropping the connection
+        state
+            .write()
+            .await
+            .set_connected(dm, false, std::time::Instant::now());
+
+        let rejoin = |player_id: u32, token: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/{}", shared::ENDPOINT_REJOIN_ROOM))
+                .header("cookie", format!("game-player-id={}", player_id))
+                .json(&shared::RoomRejoinInfo {
+                    rejoin_token: token.to_owned(),
+                })
+                .reply(&api)
+        };
+
+        assert_eq!(rejoin(1, "nonsense").await.status(), 404);
+        assert_eq!(rejoin(2, &created.rejoin_token).await.status(), 403);
+
+        let response = rejoin(1, &created.rejoin_token).await;
+        assert_eq!(response.status(), 200);
+        let rejoined: shared::JoinedRoom = serde_json::from_slice(response.body()).unwrap();
+        let users = rejoined
+            .room
+            .users
+            .iter()
+            .map(|user| user.id)
+            .collect::<Vec<_>>();
+        // still first in line, which is what makes them the DM
+        assert_eq!(users, vec![dm, "2".parse().unwrap()]);
+
+        state
+            .write()
+            .await
+            .reap_disconnected(Duration::from_secs(0), std::time::Instant::now());
+        assert_eq!(rejoin(1, &created.rejoin_token).await.status(), 410);
+    }
+
+    #[tokio::test]
+    async fn reaped_players_are_no_longer_forwarded() {
+        let seats = Seats::default();
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+        let room_id: shared::RoomID = "ABCD".parse().unwrap();
+        let (alice, bob) = ("1".parse().unwrap(), "2".parse().unwrap());
+        for player_id in [alice, bob].iter() {
+            let channel = sender.subscribe();
+            seats.forward(*player_id, room_id, Vec::new(), channel, Default::default());
+        }
+
+        seats.forget(&[alice]);
+        let forwarded = lock(&seats.forwarders).keys().copied().collect::<Vec<_>>();
+        assert_eq!(forwarded, vec![(bob, room_id)]);
+    }
+
+    #[tokio::test]
+    async fn browsers_are_issued_player_ids() {
+        let state = State::new(tokio::sync::RwLock::new(shared::viewer::state::State::new()));
+        let api = api(state, PlayerConnections::default(), Default::default());
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/{}", shared::ENDPOINT_CREATE_ROOM))
+            .json(&shared::RoomCreateInfo {
+                player_name: "Alice".to_owned(),
+                room_name: None,
+                password: None,
+            })
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+        let created: shared::JoinedRoom = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(created.room.users[0].id, created.player_id);
+        let cookie = response
+            .headers()
+            .get(warp::http::header::SET_COOKIE)
+            .expect("a player id cookie")
+            .to_str()
+            .unwrap();
+        assert!(cookie.starts_with(&format!("game-player-id={};", created.player_id)));
+        assert!(cookie.contains("HttpOnly"));
+        assert!(cookie.contains("SameSite=Lax"));
+
+        // players who already have one keep it
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/{}", shared::ENDPOINT_JOIN_ROOM))
+            .header("cookie", "game-player-id=2")
+            .json(&shared::RoomJoinInfo {
+                room_id: created.room.id,
+                player_name: "Bob".to_owned(),
+                password: None,
+            })
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(response
+            .headers()
+            .get(warp::http::header::SET_COOKIE)
+            .is_none());
+        let joined: shared::JoinedRoom = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(joined.player_id, "2".parse().unwrap());
+    }
+
+    /// Runs the real server and talks to it the way the native client does.
+    #[cfg(feature = "e2e")]
+    #[tokio::test]
+    async fn clients_observe_the_same_game() {
+        use shared::viewer::{ChangeType, Command};
+        use shared::CustomMessage;
+
+        let state = shared::viewer::state::State::new()
+            .with_checkpoint(CustomMessage::starts_game)
+            .with_transient(CustomMessage::is_transient)
+            .with_guard(CustomMessage::allowed_in);
+        let state = State::new(tokio::sync::RwLock::new(state));
+        let connections = PlayerConnections::default();
+        let api = api(state, connections.clone(), Default::default());
+        let (addr, server) = warp::serve(api).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let connect = |id: &str| {
+            let mut headers = warp::http::HeaderMap::new();
+            headers.insert(
+                warp::http::header::COOKIE,
+                format!("game-player-id={}", id).parse().unwrap(),
+            );
+            client::net::Client::builder(format!("http://{}/", addr))
+                .headers(headers)
+                .build()
+        };
+        let alice = connect("1").await.expect("alice connects");
+        let bob = connect("2").await.expect("bob connects");
+
+        let registered = tokio::time::timeout(Duration::from_secs(5), async {
+            while connections.read().await.len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(registered.is_ok(), "sockets were never registered");
+
+        let created = alice
+            .create_room(&shared::RoomCreateInfo {
+                player_name: "Alice".to_owned(),
+                room_name: None,
+                password: None,
+            })
+            .unwrap()
+            .await
+            .expect("create room");
+        let room_id = created.room.id;
+        let joined = bob
+            .join_room(&shared::RoomJoinInfo {
+                room_id,
+                player_name: "Bob".to_owned(),
+                password: None,
+            })
+            .unwrap()
+            .await
+            .expect("join room");
+        assert_eq!(joined.room.users.len(), 2);
+
+        alice
+            .send(Command::Custom(room_id, CustomMessage::StartGame(0)))
+            .unwrap();
+        bob.send(Command::Custom(
+            room_id,
+            CustomMessage::RemoveBody(0.1, -0.2),
+        ))
+        .unwrap();
+
+        // `CustomMessage` can't be compared directly so the debug output stands in for it
+        let mut seen = (Vec::new(), Vec::new());
+        let observed = tokio::time::timeout(Duration::from_secs(5), async {
+            while seen.0.len() < 2 || seen.1.len() < 2 {
+                for (client, seen) in
+                    IntoIterator::into_iter([(&alice, &mut seen.0), (&bob, &mut seen.1)])
+                {
+                    seen.extend(client.try_recv_iter().filter_map(|change| match change.ty {
+                        ChangeType::Custom(msg) if change.target == room_id => {
+                            Some(format!("{:?}", msg))
+                        }
+                        _ => None,
+                    }));
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(observed.is_ok(), "only saw {:?}", seen);
+
+        assert_eq!(seen.0, seen.1);
+        let mut expected = vec![
+            format!("{:?}", CustomMessage::StartGame(0)),
+            format!("{:?}", CustomMessage::RemoveBody(0.1, -0.2)),
+        ];
+        let mut observed = seen.0;
+        expected.sort();
+        observed.sort();
+        assert_eq!(observed, expected);
     }
 }
@@ -0,0 +1,34 @@
+//! Room passwords are only ever kept as salted argon2 hashes.
+
+/// Bounds the work a single join request can cause.
+pub const MAX_LENGTH: usize = 128;
+
+pub fn hash(password: &str) -> Result<String, argon2::Error> {
+    let salt: [u8; 16] = rand::random();
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        ..Default::default()
+    };
+    argon2::hash_encoded(password.as_bytes(), &salt, &config)
+}
+
+pub fn verify(hash: &str, password: &str) -> bool {
+    argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_are_salted_and_verifiable() {
+        let first = hash("hunter2").unwrap();
+        let second = hash("hunter2").unwrap();
+        assert_ne!(first, second);
+        assert!(!first.contains("hunter2"));
+        assert!(verify(&first, "hunter2"));
+        assert!(verify(&second, "hunter2"));
+        assert!(!verify(&first, "hunter3"));
+        assert!(!verify("not a hash", "hunter2"));
+    }
+}
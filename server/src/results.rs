@@ -0,0 +1,239 @@
+//! Finished matches, kept across restarts so that there's a leaderboard to show. A match is
+//! over once the DM reports the tower collapsing, which every player but the one who knocked it
+//! over counts as a win.
+
+use serde::{Deserialize, Serialize};
+use shared::viewer::{state, ChangeType};
+use shared::{CustomMessage, LeaderboardEntry, Player, PlayerID, PlayerStats, RoomID};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchResult {
+    pub room_id: RoomID,
+    /// Everyone who played, not counting the DM.
+    pub players: Vec<Player>,
+    pub winners: Vec<PlayerID>,
+    /// Seconds since the Unix epoch.
+    pub finished_at: u64,
+}
+
+impl MatchResult {
+    /// The game being played in `room_id`, if it has ended.
+    pub fn of_room(state: &state::State<CustomMessage>, room_id: RoomID) -> Option<Self> {
+        let loser = state
+            .replay(room_id)
+            .into_iter()
+            .find_map(|change| match change.ty {
+                ChangeType::Custom(CustomMessage::Collapsed(player_id)) => Some(player_id),
+                _ => None,
+            })?;
+        let room = state.rooms.get(&room_id)?;
+        let players = room
+            .state
+            .users
+            .iter()
+            .skip(1)
+            .filter_map(|user_id| state.users.get(user_id))
+            .map(|user| Player {
+                id: user.id,
+                name: user.name.clone(),
+            })
+            .collect::<Vec<_>>();
+        let winners = players
+            .iter()
+            .map(|player| player.id)
+            .filter(|player_id| *player_id != loser)
+            .collect();
+        let finished_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Some(Self {
+            room_id,
+            players,
+            winners,
+            finished_at,
+        })
+    }
+}
+
+/// Where match results are kept. Called from blocking tasks, so implementations are free to do
+/// file or database IO directly.
+pub trait ResultStore: Send + Sync {
+    fn record(&self, result: &MatchResult) -> eyre::Result<()>;
+
+    /// Oldest first.
+    fn results(&self) -> eyre::Result<Vec<MatchResult>>;
+
+    /// Goes through every result by default. Stores that can look a player up directly should.
+    fn stats(&self, player_id: PlayerID) -> eyre::Result<PlayerStats> {
+        Ok(stats(&self.results()?, player_id))
+    }
+}
+
+/// One JSON object per line, appended to as matches finish. A missing file has no results.
+#[derive(Debug)]
+pub struct JsonFileStore(crate::jsonl::JsonLines);
+
+impl JsonFileStore {
+    pub fn new<P: Into<std::path::PathBuf>>(path: P) -> Self {
+        Self(crate::jsonl::JsonLines::new(path))
+    }
+}
+
+impl ResultStore for JsonFileStore {
+    fn record(&self, result: &MatchResult) -> eyre::Result<()> {
+        self.0.append(result)
+    }
+
+    fn results(&self) -> eyre::Result<Vec<MatchResult>> {
+        self.0.read()
+    }
+}
+
+/// Forgets everything when the server stops.
+#[derive(Debug, Default)]
+pub struct MemoryStore(std::sync::Mutex<Vec<MatchResult>>);
+
+impl ResultStore for MemoryStore {
+    fn record(&self, result: &MatchResult) -> eyre::Result<()> {
+        crate::lock(&self.0).push(result.clone());
+        Ok(())
+    }
+
+    fn results(&self) -> eyre::Result<Vec<MatchResult>> {
+        Ok(crate::lock(&self.0).clone())
+    }
+}
+
+/// Wins by player id, most first and then alphabetically, each under the name the player last
+/// played with. `results` oldest first.
+pub fn leaderboard(results: &[MatchResult]) -> Vec<LeaderboardEntry> {
+    let mut entries = std::collections::HashMap::<PlayerID, LeaderboardEntry>::new();
+    for result in results {
+        for player in result.players.iter() {
+            let entry = entries
+                .entry(player.id)
+                .or_insert_with(|| LeaderboardEntry {
+                    id: player.id,
+                    name: Default::default(),
+                    wins: 0,
+                });
+            entry.name = player.name.clone();
+            if result.winners.contains(&player.id) {
+                entry.wins += 1;
+            }
+        }
+    }
+    let mut entries = entries
+        .into_values()
+        .filter(|entry| entry.wins > 0)
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| {
+        b.wins
+            .cmp(&a.wins)
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    entries
+}
+
+/// `results` oldest first, so that streaks run in the order the games were played.
+pub fn stats(results: &[MatchResult], player_id: PlayerID) -> PlayerStats {
+    let played = results
+        .iter()
+        .filter(|result| result.players.iter().any(|player| player.id == player_id));
+    let mut stats = PlayerStats::default();
+    let mut streak = 0;
+    for result in played {
+        stats.games_played += 1;
+        if result.winners.contains(&player_id) {
+            streak += 1;
+            stats.longest_survival_streak = stats.longest_survival_streak.max(streak);
+        } else {
+            stats.towers_toppled += 1;
+            streak = 0;
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(players: &[(&str, &str)], loser: &str) -> MatchResult {
+        let players = players
+            .iter()
+            .map(|(id, name)| Player {
+                id: id.parse().unwrap(),
+                name: (*name).to_owned(),
+            })
+            .collect::<Vec<_>>();
+        let loser = loser.parse().unwrap();
+        MatchResult {
+            room_id: "ABCD".parse().unwrap(),
+            winners: players
+                .iter()
+                .map(|player| player.id)
+                .filter(|id| *id != loser)
+                .collect(),
+            players,
+            finished_at: 1_600_000_000,
+        }
+    }
+
+    #[test]
+    fn results_survive_a_round_trip_through_the_file() {
+        let path = std::env::temp_dir().join(format!(
+            "tension-results-{:016x}.jsonl",
+            rand::random::<u64>()
+        ));
+        let store = JsonFileStore::new(&path);
+        assert_eq!(store.results().unwrap(), vec![]);
+
+        let results = vec![
+            result(&[("2", "Alice"), ("3", "Bob")], "3"),
+            result(&[("2", "Alice"), ("3", "Bob"), ("4", "Carol")], "2"),
+            // a different Alice, on another browser
+            result(&[("5", "Alice"), ("4", "Carol")], "4"),
+        ];
+        for result in results.iter() {
+            store.record(result).unwrap();
+        }
+        assert_eq!(store.results().unwrap(), results);
+        std::fs::remove_file(&path).unwrap();
+
+        let entry = |id: &str, name: &str, wins| LeaderboardEntry {
+            id: id.parse().unwrap(),
+            name: name.to_owned(),
+            wins,
+        };
+        assert_eq!(
+            leaderboard(&results),
+            vec![
+                entry("2", "Alice", 1),
+                entry("5", "Alice", 1),
+                entry("3", "Bob", 1),
+                entry("4", "Carol", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn leaderboard_goes_by_player_not_name() {
+        let results = vec![
+            result(&[("2", "Alice"), ("3", "Alice")], "3"),
+            result(&[("2", "Alice"), ("3", "Alice")], "3"),
+            // the second Alice had enough of being mixed up
+            result(&[("2", "Alice"), ("3", "Bob")], "2"),
+        ];
+        let entry = |id: &str, name: &str, wins| LeaderboardEntry {
+            id: id.parse().unwrap(),
+            name: name.to_owned(),
+            wins,
+        };
+        assert_eq!(
+            leaderboard(&results),
+            vec![entry("2", "Alice", 2), entry("3", "Bob", 1)]
+        );
+    }
+}
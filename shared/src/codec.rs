@@ -0,0 +1,305 @@
+//! How `viewer::Command`s and `viewer::StateChange`s go over the websocket, each wrapped in a
+//! `viewer::Envelope`. JSON in text frames, or with the `bincode` feature, bincode in binary
+//! frames, which is a fraction of the size for things sent every frame like `MoveBody`.
+//!
+//! Decoding goes by the frame type rather than the feature, so a peer built with `bincode` still
+//! understands one built without it. Both ends pick what they send independently.
+//!
+//! HTTP bodies aren't enveloped and go by MIME type instead, as a `Format`.
+
+#[cfg(feature = "bincode")]
+use crate::viewer::PROTOCOL_VERSION;
+use crate::viewer::{Envelope, VersionMismatch};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Codec {
+    Json,
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+/// Bincode when it's available.
+impl Default for Codec {
+    #[cfg(feature = "bincode")]
+    fn default() -> Self {
+        Codec::Bincode
+    }
+
+    #[cfg(not(feature = "bincode"))]
+    fn default() -> Self {
+        Codec::Json
+    }
+}
+
+/// An encoded websocket message, for whichever websocket library is sending it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Frame {
+    pub fn as_frame_ref(&self) -> FrameRef<'_> {
+        match self {
+            Frame::Text(text) => FrameRef::Text(text),
+            Frame::Binary(bytes) => FrameRef::Binary(bytes),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Frame::Text(text) => text.len(),
+            Frame::Binary(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A received websocket message.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FrameRef<'a> {
+    Text(&'a str),
+    Binary(&'a [u8]),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "bincode")]
+    #[error(transparent)]
+    Bincode(#[from] bincode::Error),
+    #[error(transparent)]
+    EncodeMessagePack(#[from] rmp_serde::encode::Error),
+    #[error(transparent)]
+    DecodeMessagePack(#[from] rmp_serde::decode::Error),
+    #[error(transparent)]
+    Version(#[from] VersionMismatch),
+}
+
+impl Codec {
+    pub fn encode<T: serde::Serialize>(&self, payload: &T) -> Result<Frame, CodecError> {
+        let envelope = Envelope::new(payload);
+        match self {
+            Codec::Json => Ok(Frame::Text(serde_json::to_string(&envelope)?)),
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => Ok(Frame::Binary(bincode::serialize(&envelope)?)),
+        }
+    }
+}
+
+/// With the default `Codec`.
+pub fn encode<T: serde::Serialize>(payload: &T) -> Result<Frame, CodecError> {
+    Codec::default().encode(payload)
+}
+
+/// Binary frames are JSON too without the `bincode` feature. Anything from another protocol
+/// version is a `CodecError::Version` rather than whatever error its payload happened to cause.
+pub fn decode<T: serde::de::DeserializeOwned>(frame: FrameRef) -> Result<T, CodecError> {
+    match frame {
+        FrameRef::Text(text) => decode_json(text.as_bytes()),
+        #[cfg(feature = "bincode")]
+        FrameRef::Binary(bytes) => bincode::deserialize::<Envelope<T>>(bytes)
+            .map(|envelope| envelope.payload)
+            .map_err(|err| match bincode_version(bytes) {
+                Some(version) if version != PROTOCOL_VERSION => VersionMismatch(version).into(),
+                _ => err.into(),
+            }),
+        #[cfg(not(feature = "bincode"))]
+        FrameRef::Binary(bytes) => decode_json(bytes),
+    }
+}
+
+fn decode_json<T: serde::de::DeserializeOwned>(json: &[u8]) -> Result<T, CodecError> {
+    serde_json::from_slice::<Envelope<T>>(json)
+        .map(|envelope| envelope.payload)
+        .map_err(|err| match VersionMismatch::in_json(json) {
+            Some(mismatch) => mismatch.into(),
+            None => err.into(),
+        })
+}
+
+/// Body encodings for the HTTP endpoints, picked by MIME type. JSON unless asked otherwise.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl Format {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// Parameters like `charset` are ignored.
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        let mime = mime.split(';').next().unwrap_or_default().trim();
+        if mime.eq_ignore_ascii_case("application/json") {
+            Some(Format::Json)
+        } else if mime.eq_ignore_ascii_case("application/msgpack")
+            || mime.eq_ignore_ascii_case("application/x-msgpack")
+        {
+            Some(Format::MessagePack)
+        } else {
+            None
+        }
+    }
+
+    /// The first supported type in an `Accept` header. Quality values aren't weighed, clients
+    /// are expected to list what they want first.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        accept.split(',').find_map(Self::from_mime)
+    }
+
+    pub fn encode<T: serde::Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Format::Json => Ok(serde_json::to_vec(value)?),
+            // as maps rather than arrays so that `#[serde(default)]` fields can be left out
+            Format::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+        }
+    }
+
+    pub fn decode<T: serde::de::DeserializeOwned>(&self, body: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Format::Json => Ok(serde_json::from_slice(body)?),
+            Format::MessagePack => Ok(rmp_serde::from_slice(body)?),
+        }
+    }
+}
+
+/// The version leads every envelope, as a fixed-width little-endian integer with bincode's
+/// default options.
+#[cfg(feature = "bincode")]
+fn bincode_version(bytes: &[u8]) -> Option<u32> {
+    let mut version = [0; 4];
+    version.copy_from_slice(bytes.get(..4)?);
+    Some(u32::from_le_bytes(version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::viewer::{Command, StateChange, PROTOCOL_VERSION};
+    use crate::CustomMessage;
+
+    fn drag() -> Command<CustomMessage> {
+        Command::Custom("ABCD".parse().unwrap(), CustomMessage::MoveBody(0.25, -0.5))
+    }
+
+    fn codecs() -> Vec<Codec> {
+        vec![
+            Codec::Json,
+            #[cfg(feature = "bincode")]
+            Codec::Bincode,
+        ]
+    }
+
+    #[test]
+    fn every_codec_round_trips() {
+        for codec in codecs() {
+            let frame = codec.encode(&drag()).unwrap();
+            match (codec, &frame) {
+                (Codec::Json, Frame::Text(_)) => {}
+                #[cfg(feature = "bincode")]
+                (Codec::Bincode, Frame::Binary(_)) => {}
+                _ => panic!("{:?} sent {:?}", codec, frame),
+            }
+            let decoded = decode::<Command<CustomMessage>>(frame.as_frame_ref()).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", drag()));
+        }
+    }
+
+    #[test]
+    fn other_versions_are_told_apart_from_garbage() {
+        for codec in codecs() {
+            let mut envelope = Envelope::new(drag());
+            envelope.version = PROTOCOL_VERSION + 1;
+            let frame = match codec {
+                Codec::Json => Frame::Text(serde_json::to_string(&envelope).unwrap()),
+                #[cfg(feature = "bincode")]
+                Codec::Bincode => Frame::Binary(bincode::serialize(&envelope).unwrap()),
+            };
+            let decoded = decode::<Command<CustomMessage>>(frame.as_frame_ref());
+            assert!(
+                matches!(decoded, Err(CodecError::Version(VersionMismatch(v))) if v == envelope.version),
+                "{:?}: {:?}",
+                codec,
+                decoded
+            );
+        }
+
+        let garbage = decode::<Command<CustomMessage>>(FrameRef::Binary(&[1, 2]));
+        assert!(garbage.is_err());
+        assert!(!matches!(garbage, Err(CodecError::Version(_))));
+    }
+
+    #[test]
+    fn formats_are_negotiated_from_headers() {
+        assert_eq!(Format::from_mime("application/json"), Some(Format::Json));
+        assert_eq!(
+            Format::from_mime("Application/JSON; charset=utf-8"),
+            Some(Format::Json)
+        );
+        assert_eq!(
+            Format::from_mime("application/x-msgpack"),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(Format::from_mime("text/html"), None);
+        assert_eq!(
+            Format::from_accept("text/html, application/msgpack;q=0.9, application/json"),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(Format::from_accept("*/*"), None);
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let info = crate::RoomJoinInfo {
+            room_id: "ABCD".parse().unwrap(),
+            player_name: "Alice".to_owned(),
+            password: None,
+        };
+        for format in [Format::Json, Format::MessagePack].iter() {
+            let body = format.encode(&info).unwrap();
+            let decoded: crate::RoomJoinInfo = format.decode(&body).unwrap();
+            assert_eq!(decoded.room_id, info.room_id);
+            assert_eq!(decoded.player_name, info.player_name);
+            assert_eq!(decoded.password, info.password);
+        }
+    }
+
+    /// The fuzzers only get anywhere from seeds that decode, so they're regenerated whenever
+    /// `PROTOCOL_VERSION` or the wire format changes.
+    #[test]
+    fn fuzz_corpus_is_current() {
+        fn seeds(target: &str) -> Vec<String> {
+            let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../fuzz/corpus")
+                .join(target);
+            std::fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+                .collect()
+        }
+
+        let commands = seeds("server_command");
+        assert!(!commands.is_empty());
+        for seed in commands {
+            let decoded = decode::<Command<CustomMessage>>(FrameRef::Text(&seed));
+            assert!(decoded.is_ok(), "{}: {:?}", seed, decoded);
+        }
+        let changes = seeds("client_state_change");
+        assert!(!changes.is_empty());
+        for seed in changes {
+            let decoded = decode::<StateChange<CustomMessage>>(FrameRef::Text(&seed));
+            assert!(decoded.is_ok(), "{}: {:?}", seed, decoded);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Block arrangements that can be saved, shared and built into a game in place of the generated
+//! ones.
+
+use serde::{Deserialize, Serialize};
+
+/// The most blocks a `TowerLayout` can have, since every client in the room simulates them all.
+pub const MAX_LAYOUT_BLOCKS: usize = 256;
+/// The furthest a block can be from the origin along either axis. The platform is a couple of
+/// units across.
+pub const MAX_COORDINATE: f32 = 10.;
+/// The largest a block can be along either axis, many times the size of a generated one.
+pub const MAX_HALF_EXTENT: f32 = 1.;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Block {
+    #[serde(deserialize_with = "coordinate")]
+    pub x: f32,
+    #[serde(deserialize_with = "coordinate")]
+    pub y: f32,
+    #[serde(deserialize_with = "half_extent")]
+    pub half_width: f32,
+    #[serde(deserialize_with = "half_extent")]
+    pub half_height: f32,
+    /// In radians, counter-clockwise.
+    #[serde(default, deserialize_with = "crate::finite")]
+    pub rotation: f32,
+    #[serde(default)]
+    pub groups: CollisionGroups,
+    /// Blocks in a `TowerLayout` that share a body are built into one rigid body, like an
+    /// L-piece. Those without one are each a body of their own.
+    #[serde(default)]
+    pub body: Option<u16>,
+}
+
+/// Which blocks collide with which. Two blocks only touch if each is a member of a group the
+/// other's filter includes. Everything collides with everything by default.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CollisionGroups {
+    pub memberships: u16,
+    pub filter: u16,
+}
+
+impl Default for CollisionGroups {
+    fn default() -> Self {
+        Self {
+            memberships: u16::MAX,
+            filter: u16::MAX,
+        }
+    }
+}
+
+/// Positions are in world space, the same as the simulation's.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TowerLayout {
+    #[serde(deserialize_with = "blocks")]
+    pub blocks: Vec<Block>,
+}
+
+/// A whole simulation partway through, for another client to carry on from exactly where this
+/// one is. Unlike a `TowerLayout` bodies keep their velocities and stay in one piece.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub bodies: Vec<Body>,
+    pub platform: Platform,
+}
+
+/// A body of one or more blocks, in world space.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Body {
+    #[serde(deserialize_with = "crate::finite")]
+    pub x: f32,
+    #[serde(deserialize_with = "crate::finite")]
+    pub y: f32,
+    #[serde(deserialize_with = "crate::finite")]
+    pub rotation: f32,
+    #[serde(deserialize_with = "crate::finite")]
+    pub velocity_x: f32,
+    #[serde(deserialize_with = "crate::finite")]
+    pub velocity_y: f32,
+    /// In radians per second, counter-clockwise.
+    #[serde(deserialize_with = "crate::finite")]
+    pub angular_velocity: f32,
+    /// Positioned relative to the body rather than the world.
+    pub parts: Vec<Block>,
+}
+
+/// The ground under the tower, as it was built rather than as it was asked for, since a
+/// platform that fits the stack wouldn't fit the same blocks once they've moved.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Platform {
+    #[serde(deserialize_with = "crate::finite")]
+    pub width: f32,
+    #[serde(deserialize_with = "crate::finite")]
+    pub thickness: f32,
+    /// Of the platform's center.
+    #[serde(deserialize_with = "crate::finite")]
+    pub y: f32,
+}
+
+fn coordinate<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = crate::finite(deserializer)?;
+    if value.abs() <= MAX_COORDINATE {
+        Ok(value)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "{} is further than {} from the origin",
+            value, MAX_COORDINATE
+        )))
+    }
+}
+
+fn half_extent<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = crate::finite(deserializer)?;
+    if (0. ..=MAX_HALF_EXTENT).contains(&value) {
+        Ok(value)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "half extent {} is outside of 0 to {}",
+            value, MAX_HALF_EXTENT
+        )))
+    }
+}
+
+fn blocks<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<Block>, D::Error> {
+    let blocks = Vec::<Block>::deserialize(deserializer)?;
+    if blocks.len() <= MAX_LAYOUT_BLOCKS {
+        Ok(blocks)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "{} blocks is more than the {} a layout can have",
+            blocks.len(),
+            MAX_LAYOUT_BLOCKS
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_default_to_unrotated() {
+        let layout: TowerLayout = serde_json::from_str(
+            r#"{"blocks":[{"x":0,"y":-0.425,"half_width":0.025,"half_height":0.025}]}"#,
+        )
+        .unwrap();
+        assert_eq!(layout.blocks[0].rotation, 0.);
+        assert_eq!(layout.blocks[0].groups, CollisionGroups::default());
+        assert_eq!(layout.blocks[0].body, None);
+        assert!(serde_json::from_str::<TowerLayout>(
+            r#"{"blocks":[{"x":1e39,"y":0,"half_width":0.025,"half_height":0.025}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn oversized_layouts_are_refused() {
+        let parse = |json: &str| serde_json::from_str::<TowerLayout>(json);
+        let block = |x: f32, half_width: f32| Block {
+            x,
+            y: 0.,
+            half_width,
+            half_height: 0.025,
+            rotation: 0.,
+            groups: Default::default(),
+            body: None,
+        };
+        let layout = |blocks: Vec<Block>| serde_json::to_string(&TowerLayout { blocks }).unwrap();
+
+        assert!(parse(&layout(vec![block(0., 0.025); MAX_LAYOUT_BLOCKS])).is_ok());
+        assert!(parse(&layout(vec![block(0., 0.025); MAX_LAYOUT_BLOCKS + 1])).is_err());
+        assert!(parse(&layout(vec![block(MAX_COORDINATE, MAX_HALF_EXTENT)])).is_ok());
+        assert!(parse(&layout(vec![block(-11., 0.025)])).is_err());
+        assert!(parse(&layout(vec![block(0., 1e30)])).is_err());
+        assert!(parse(&layout(vec![block(0., -0.025)])).is_err());
+    }
+}
@@ -1,63 +1,113 @@
+pub mod codec;
+pub mod layout;
+pub mod log_filter;
 pub mod viewer;
 
 use serde::{Deserialize, Serialize};
 
-pub const ENDPOINT_WS: &'static str = "socket";
-pub const ENDPOINT_CREATE_ROOM: &'static str = "create";
-pub const ENDPOINT_JOIN_ROOM: &'static str = "join";
+pub const ENDPOINT_WS: &str = "socket";
+pub const ENDPOINT_CREATE_ROOM: &str = "create";
+pub const ENDPOINT_JOIN_ROOM: &str = "join";
+pub const ENDPOINT_REJOIN_ROOM: &str = "rejoin";
+pub const ENDPOINT_CONFIG: &str = "config";
+pub const ENDPOINT_LEADERBOARD: &str = "leaderboard";
+/// Followed by a player's id.
+pub const ENDPOINT_STATS: &str = "stats";
+pub const ENDPOINT_REGISTER: &str = "register";
 
-#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
-pub struct RoomID([u8; 4]);
+pub const DEFAULT_PING_INTERVAL_MS: u64 = 1000;
 
-impl RoomID {
-    const LENGTH: usize = 4;
+/// Where both native binaries read their `log_filter::LogFilter` from.
+pub const LOG_LEVEL_VAR: &str = "RUST_LOG";
+pub const DEFAULT_LOG_LEVEL: log::LevelFilter = log::LevelFilter::Debug;
+
+/// How many kinds of generated tower there are for `CustomMessage::StartGame` to pick from.
+pub const ROOM_TYPE_COUNT: u16 = 4;
+
+/// The fastest `CustomMessage::SetTimeScale` can run a room's simulation. Every extra step per
+/// frame is paid for by every client in the room.
+pub const MAX_TIME_SCALE: f32 = 2.;
+
+/// How long the DM has to take back starting a new game. The server gives the undo a second
+/// longer to get there.
+pub const UNDO_RESTART_WINDOW: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Server settings clients may need to be compatible with.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ServerConfig {
+    pub ping_interval_ms: u64,
+}
+
+/// Every room's code is this many letters long. Each extra letter makes codes 26 times harder
+/// to collide or guess.
+pub const ROOM_ID_LENGTH: usize = 4;
+
+pub type RoomID = RoomCode<ROOM_ID_LENGTH>;
+
+/// Goes over the wire as the same letters it's displayed as.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct RoomCode<const LENGTH: usize>([u8; LENGTH]);
+
+/// What new room codes are made of. Leaves out letters that are easily misheard or misread
+/// when a code is passed along, though any letter is still accepted when parsing one.
+pub const ROOM_ID_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPRSTUVWXYZ";
+
+impl<const LENGTH: usize> RoomCode<LENGTH> {
+    pub const LENGTH: usize = LENGTH;
 
     pub fn new<R: rand::Rng>(rng: &mut R) -> Self {
-        let mut gen = || rng.gen_range(b'A'..=b'Z');
-        Self([gen(), gen(), gen(), gen()])
+        let mut code = [0; LENGTH];
+        for c in code.iter_mut() {
+            *c = ROOM_ID_ALPHABET[rng.gen_range(0..ROOM_ID_ALPHABET.len() as u32) as usize];
+        }
+        Self(code)
     }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, thiserror::Error)]
 pub enum RoomIDParseError {
-    #[error("Room ID must be exactly {} characters long.", RoomID::LENGTH)]
-    TooShort,
+    #[error("Room ID must be exactly {0} characters long.")]
+    WrongLength(usize),
     #[error("Encountered an invalid character: {0}")]
     UnrecognizedCharacter(char),
 }
 
-impl std::str::FromStr for RoomID {
+impl<const LENGTH: usize> std::str::FromStr for RoomCode<LENGTH> {
     type Err = RoomIDParseError;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        if value.len() != Self::LENGTH {
-            Err(RoomIDParseError::TooShort)
-        } else {
-            let mut iter = value.chars().map(|c| {
-                if c.is_ascii_alphabetic() {
-                    Ok(c.to_ascii_uppercase() as u8)
-                } else {
-                    Err(RoomIDParseError::UnrecognizedCharacter(c))
-                }
-            });
-            Ok(Self([
-                iter.next().unwrap()?,
-                iter.next().unwrap()?,
-                iter.next().unwrap()?,
-                iter.next().unwrap()?,
-            ]))
+        if value.chars().count() != LENGTH {
+            return Err(RoomIDParseError::WrongLength(LENGTH));
+        }
+        let mut code = [0; LENGTH];
+        for (byte, c) in code.iter_mut().zip(value.chars()) {
+            if !c.is_ascii_alphabetic() {
+                return Err(RoomIDParseError::UnrecognizedCharacter(c));
+            }
+            *byte = c.to_ascii_uppercase() as u8;
         }
+        Ok(Self(code))
     }
 }
 
-impl std::fmt::Display for RoomID {
+impl<const LENGTH: usize> std::fmt::Display for RoomCode<LENGTH> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let i = &self.0;
-        write!(
-            f,
-            "{}{}{}{}",
-            i[0] as char, i[1] as char, i[2] as char, i[3] as char
-        )
+        use std::fmt::Write;
+        self.0.iter().try_for_each(|&c| f.write_char(c as char))
+    }
+}
+
+impl<const LENGTH: usize> Serialize for RoomCode<LENGTH> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de, const LENGTH: usize> Deserialize<'de> for RoomCode<LENGTH> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
     }
 }
 
@@ -67,14 +117,79 @@ pub struct RoomState {
     pub players: Vec<Player>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomCreateInfo {
+    pub player_name: PlayerName,
+    /// Purely cosmetic. Rooms are still joined by code.
+    #[serde(default)]
+    pub room_name: Option<String>,
+    /// Makes the room private. Joining then requires the same password.
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+pub const MAX_ROOM_NAME_LENGTH: usize = 32;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, thiserror::Error)]
+pub enum RoomNameError {
+    #[error("Room name can't be blank.")]
+    Empty,
+    #[error("Room name can be at most {} characters long.", MAX_ROOM_NAME_LENGTH)]
+    TooLong,
+}
+
+/// Trims surrounding whitespace and checks the length in characters.
+pub fn validate_room_name(name: &str) -> Result<String, RoomNameError> {
+    let name = name.trim();
+    if name.is_empty() {
+        Err(RoomNameError::Empty)
+    } else if name.chars().count() > MAX_ROOM_NAME_LENGTH {
+        Err(RoomNameError::TooLong)
+    } else {
+        Ok(name.to_owned())
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct RoomJoinInfo {
     pub room_id: RoomID,
     pub player_name: PlayerName,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// Issued when taking a seat in a room. Presenting it again reclaims that same seat, and with
+/// it the player's role, as long as the server hasn't given up on them yet.
+pub type RejoinToken = String;
+
+/// Response to creating, joining or rejoining a room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinedRoom {
+    pub room: viewer::InitialRoomState,
+    pub rejoin_token: RejoinToken,
+    /// Which of the room's users is the one who asked. Browsers only get their id as an
+    /// HttpOnly cookie so this is how they find out.
+    pub player_id: PlayerID,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomRejoinInfo {
+    pub rejoin_token: RejoinToken,
 }
 
 pub type PlayerName = String;
 
+/// Sent as the `game-account-token` cookie alongside a registered `game-player-id`.
+pub type AccountToken = String;
+
+/// Response to registering. The player id is this player's from now on, in place of a
+/// randomly generated one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registered {
+    pub player_id: PlayerID,
+    pub account_token: AccountToken,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct PlayerID(u64);
 
@@ -92,19 +207,186 @@ impl std::str::FromStr for PlayerID {
     }
 }
 
+impl std::fmt::Display for PlayerID {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Player {
     pub id: PlayerID,
     pub name: PlayerName,
 }
 
+/// One line of the server's leaderboard, best first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub id: PlayerID,
+    /// The name the player last played under.
+    pub name: PlayerName,
+    pub wins: u32,
+}
+
+/// How a player has done over every match the server has recorded for their id.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub games_played: u32,
+    /// Games lost by knocking the tower over.
+    pub towers_toppled: u32,
+    /// The most games in a row without toppling the tower.
+    pub longest_survival_streak: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CustomMessage {
     StartGame(u16),
-    RemoveBody(f32, f32),
-    MoveBody(f32, f32),
-    DropBody(f32, f32),
+    RemoveBody(
+        #[serde(deserialize_with = "finite")] f32,
+        #[serde(deserialize_with = "finite")] f32,
+    ),
+    MoveBody(
+        #[serde(deserialize_with = "finite")] f32,
+        #[serde(deserialize_with = "finite")] f32,
+    ),
+    DropBody(
+        #[serde(deserialize_with = "finite")] f32,
+        #[serde(deserialize_with = "finite")] f32,
+    ),
     AssignClick(PlayerID, u32),
+    /// Like `StartGame` but with a layout the DM brought rather than a generated one.
+    StartCustom(layout::TowerLayout),
+    /// Sent by the DM. Zero pauses the simulation.
+    SetTimeScale(#[serde(deserialize_with = "time_scale")] f32),
+    /// A client's simulation checksum at a tick, for the others to compare theirs against, and
+    /// how many of the room's logged events it had applied by then. Events land on whichever
+    /// tick they arrive, so checksums only compare when both the tick and the count match.
+    Checksum(u64, u64, u64),
+    /// Sent by the DM when a checksum disagreed with theirs. Everyone rebuilds from this snapshot
+    /// and carries on from the tick it was taken at.
+    Resync(u64, layout::Snapshot),
+    /// Sent by the DM shortly after starting a new game to go back to the one it replaced.
+    UndoRestart,
+    /// Sent by the DM when the tower falls, naming the player who knocked it over. Ends the game
+    /// as far as the server's match results go.
+    Collapsed(PlayerID),
+}
+
+impl CustomMessage {
+    /// For `viewer::state::State::with_checkpoint`.
+    pub fn starts_game(&self) -> bool {
+        matches!(
+            self,
+            CustomMessage::StartGame(_) | CustomMessage::StartCustom(_)
+        )
+    }
+
+    /// For `viewer::state::State::with_transient`. Bodies being dragged have a `DropBody` to
+    /// come and checksums are only compared as they arrive, so neither is kept for late joiners.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            CustomMessage::MoveBody(..) | CustomMessage::Checksum(..)
+        )
+    }
+
+    /// For `viewer::state::State::with_guard`. Games only start once the room has the players
+    /// its settings ask for, not counting the DM, and only the DM starts one on a custom layout.
+    /// Clicks are handed out by the DM, the room's first user, and only to the other users in
+    /// the room, with no more turns in a row to one of them than the settings allow. Only the DM
+    /// undoes a restart, within `UNDO_RESTART_WINDOW` of it, or reports a collapse, each once
+    /// per game, and only the DM sets the time scale, up to `MAX_TIME_SCALE`, or resyncs
+    /// everyone's simulation.
+    pub fn allowed_in(
+        &self,
+        room: &viewer::RoomState,
+        from: &PlayerID,
+        log: &[Self],
+        since_checkpoint: std::time::Duration,
+    ) -> bool {
+        match self {
+            CustomMessage::StartGame(index) if *index >= ROOM_TYPE_COUNT => false,
+            CustomMessage::StartCustom(_) if room.users.first() != Some(from) => false,
+            CustomMessage::AssignClick(player_id, _count) => match room.users.split_first() {
+                Some((dm, players)) => {
+                    let fair = || match room.settings.max_consecutive_turns {
+                        // nobody else to take a turn
+                        Some(_) if players.len() < 2 => true,
+                        Some(max) => consecutive_turns(log, *player_id) < max,
+                        None => true,
+                    };
+                    dm == from && players.contains(player_id) && fair()
+                }
+                None => false,
+            },
+            CustomMessage::StartGame(_) | CustomMessage::StartCustom(_) => {
+                let players = room.users.len().saturating_sub(1);
+                players >= room.settings.min_players as usize
+            }
+            CustomMessage::UndoRestart => {
+                room.users.first() == Some(from)
+                    && since_checkpoint < UNDO_RESTART_WINDOW + std::time::Duration::from_secs(1)
+                    && log.first().is_some_and(Self::starts_game)
+                    && !log
+                        .iter()
+                        .any(|msg| matches!(msg, CustomMessage::UndoRestart))
+            }
+            CustomMessage::SetTimeScale(scale) => {
+                room.users.first() == Some(from) && (0. ..=MAX_TIME_SCALE).contains(scale)
+            }
+            CustomMessage::Resync(_, _) => room.users.first() == Some(from),
+            CustomMessage::Collapsed(player_id) => match room.users.split_first() {
+                Some((dm, players)) => {
+                    dm == from
+                        && players.contains(player_id)
+                        && log.first().is_some_and(Self::starts_game)
+                        && !log
+                            .iter()
+                            .any(|msg| matches!(msg, CustomMessage::Collapsed(_)))
+                }
+                None => false,
+            },
+            _ => true,
+        }
+    }
+}
+
+/// How many of the latest turns in `log` went to `player_id`.
+fn consecutive_turns(log: &[CustomMessage], player_id: PlayerID) -> u32 {
+    log.iter()
+        .rev()
+        .filter_map(|msg| match msg {
+            CustomMessage::AssignClick(id, _count) => Some(*id),
+            _ => None,
+        })
+        .take_while(|id| *id == player_id)
+        .count() as u32
+}
+
+/// Numbers too big for an `f32` otherwise come through as infinities, which are sent on to the
+/// rest of the room as `null` and fail to parse there.
+fn finite<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "{} is not a finite number",
+            value
+        )))
+    }
+}
+
+fn time_scale<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = finite(deserializer)?;
+    if (0. ..=MAX_TIME_SCALE).contains(&value) {
+        Ok(value)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "time scale {} is outside of 0 to {}",
+            value, MAX_TIME_SCALE
+        )))
+    }
 }
 
 #[cfg(test)]
@@ -115,6 +397,467 @@ mod tests {
     fn room_id_test() {
         let mut rng = rand::rngs::mock::StepRng::new(u64::MAX / 1000, u64::MAX / 100);
         let room = RoomID::new(&mut rng);
-        assert_eq!(room.to_string(), String::from("HGFE"));
+        assert_eq!(room.to_string(), String::from("EDCB"));
+    }
+
+    #[test]
+    fn generated_room_ids_avoid_ambiguous_letters() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1264);
+        for _ in 0..1000 {
+            let room = RoomID::new(&mut rng).to_string();
+            assert!(!room.contains(&['I', 'O', 'Q'][..]), "{}", room);
+        }
+        assert_eq!(
+            "iOqL".parse::<RoomID>().map(|r| r.to_string()),
+            Ok("IOQL".into())
+        );
+    }
+
+    #[test]
+    fn room_codes_are_as_long_as_configured() {
+        let mut rng = rand::rngs::mock::StepRng::new(u64::MAX / 1000, u64::MAX / 100);
+        let room = RoomCode::<6>::new(&mut rng);
+        assert_eq!(room.to_string(), String::from("EDCBAZ"));
+        assert_eq!("edcbaz".parse(), Ok(room));
+
+        assert_eq!(
+            "HGFE".parse::<RoomCode<4>>().map(|r| r.to_string()),
+            Ok("HGFE".into())
+        );
+        assert_eq!(
+            "HGFE".parse::<RoomCode<6>>(),
+            Err(RoomIDParseError::WrongLength(6))
+        );
+        assert_eq!(
+            "HGFEDCB".parse::<RoomCode<6>>(),
+            Err(RoomIDParseError::WrongLength(6))
+        );
+        assert_eq!(
+            "HGFÉ".parse::<RoomCode<4>>(),
+            Err(RoomIDParseError::UnrecognizedCharacter('É'))
+        );
+    }
+
+    #[test]
+    fn room_ids_serialize_as_strings() {
+        let room: RoomID = "HGFE".parse().unwrap();
+        assert_eq!(serde_json::to_string(&room).unwrap(), r#""HGFE""#);
+        assert_eq!(serde_json::from_str::<RoomID>(r#""hgfe""#).unwrap(), room);
+
+        let bytes = rmp_serde::to_vec(&room).unwrap();
+        assert_eq!(rmp_serde::from_slice::<RoomID>(&bytes).unwrap(), room);
+
+        assert!(serde_json::from_str::<RoomID>(r#""HGF""#).is_err());
+        assert!(serde_json::from_str::<RoomID>(r#""HG1E""#).is_err());
+        assert!(serde_json::from_str::<RoomID>("[72,71,70,69]").is_err());
+    }
+
+    #[test]
+    fn custom_messages_keep_their_wire_format() {
+        let player_id = "7".parse::<PlayerID>().unwrap();
+        let messages = [
+            (CustomMessage::StartGame(2), r#"{"StartGame":2}"#),
+            (
+                CustomMessage::RemoveBody(0.5, -0.25),
+                r#"{"RemoveBody":[0.5,-0.25]}"#,
+            ),
+            (
+                CustomMessage::MoveBody(0.5, -0.25),
+                r#"{"MoveBody":[0.5,-0.25]}"#,
+            ),
+            (
+                CustomMessage::DropBody(0.5, -0.25),
+                r#"{"DropBody":[0.5,-0.25]}"#,
+            ),
+            (
+                CustomMessage::AssignClick(player_id, 3),
+                &format!(
+                    r#"{{"AssignClick":[{},3]}}"#,
+                    serde_json::to_string(&player_id).unwrap()
+                ),
+            ),
+            (CustomMessage::UndoRestart, r#""UndoRestart""#),
+            (
+                CustomMessage::Collapsed(player_id),
+                &format!(
+                    r#"{{"Collapsed":{}}}"#,
+                    serde_json::to_string(&player_id).unwrap()
+                ),
+            ),
+        ];
+        for (msg, json) in messages.iter() {
+            assert_eq!(&serde_json::to_string(msg).unwrap(), json);
+            let parsed = serde_json::from_str::<CustomMessage>(json).unwrap();
+            // `CustomMessage` can't be compared directly so the debug output stands in for it
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", msg));
+        }
+    }
+
+    #[test]
+    fn custom_messages_reject_infinite_coordinates() {
+        let parsed = serde_json::from_str::<CustomMessage>(r#"{"MoveBody":[0.5,-0.25]}"#);
+        assert!(matches!(parsed, Ok(CustomMessage::MoveBody(x, y)) if x == 0.5 && y == -0.25));
+        assert!(serde_json::from_str::<CustomMessage>(r#"{"MoveBody":[1e300,0.5]}"#).is_err());
+        assert!(serde_json::from_str::<CustomMessage>(r#"{"DropBody":[0.5,-1e39]}"#).is_err());
+        assert!(serde_json::from_str::<CustomMessage>(r#"{"SetTimeScale":0}"#).is_ok());
+        assert!(serde_json::from_str::<CustomMessage>(r#"{"SetTimeScale":-0.5}"#).is_err());
+        assert!(serde_json::from_str::<CustomMessage>(r#"{"SetTimeScale":2}"#).is_ok());
+        assert!(serde_json::from_str::<CustomMessage>(r#"{"SetTimeScale":2.5}"#).is_err());
+        assert!(serde_json::from_str::<CustomMessage>(r#"{"SetTimeScale":1e30}"#).is_err());
+    }
+
+    #[test]
+    fn room_name_validation() {
+        assert_eq!(
+            validate_room_name("  Game Night "),
+            Ok("Game Night".to_owned())
+        );
+        assert_eq!(validate_room_name("   "), Err(RoomNameError::Empty));
+        let longest = "é".repeat(MAX_ROOM_NAME_LENGTH);
+        assert_eq!(validate_room_name(&longest), Ok(longest.clone()));
+        assert_eq!(
+            validate_room_name(&format!("{}e", longest)),
+            Err(RoomNameError::TooLong)
+        );
+    }
+
+    #[cfg(feature = "server")]
+    fn user(id: &str) -> viewer::User {
+        viewer::User {
+            id: id.parse().unwrap(),
+            name: id.to_owned(),
+            connected: true,
+        }
+    }
+
+    /// Registers `users` and opens a room with all of them in it, the first as DM.
+    #[cfg(feature = "server")]
+    fn room_with(
+        state: &mut viewer::state::State<CustomMessage>,
+        users: &[&viewer::User],
+    ) -> RoomID {
+        for user in users {
+            state.register_user((*user).clone());
+        }
+        let room_id = state.create_room().unwrap();
+        for user in users {
+            state.join(room_id, user.id);
+        }
+        room_id
+    }
+
+    #[cfg(feature = "server")]
+    fn send(
+        state: &mut viewer::state::State<CustomMessage>,
+        room_id: RoomID,
+        from: &viewer::User,
+        msg: CustomMessage,
+    ) {
+        state.handle_command(viewer::Command::Custom(room_id, msg), &from.id);
+    }
+
+    /// The custom messages the room has let through so far, as far as `pick` is interested.
+    #[cfg(feature = "server")]
+    fn replayed<T>(
+        state: &viewer::state::State<CustomMessage>,
+        room_id: RoomID,
+        pick: impl Fn(CustomMessage) -> Option<T>,
+    ) -> Vec<T> {
+        state
+            .replay(room_id)
+            .into_iter()
+            .filter_map(|change| match change.ty {
+                viewer::ChangeType::Custom(msg) => pick(msg),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn start_game_waits_for_min_players() {
+        use viewer::{state, Command, RoomSettings};
+
+        let mut state = state::State::new().with_guard(CustomMessage::allowed_in);
+        let (dm, alice, bob) = (user("1"), user("2"), user("3"));
+        let room_id = room_with(&mut state, &[&dm, &alice]);
+        state.register_user(bob.clone());
+        state.set_room_settings(
+            room_id,
+            RoomSettings {
+                capacity: None,
+                min_players: 2,
+                max_consecutive_turns: None,
+            },
+        );
+        let started = |state: &state::State<CustomMessage>| {
+            replayed(state, room_id, |msg| match msg {
+                CustomMessage::StartGame(index) => Some(index),
+                _ => None,
+            })
+            .len()
+        };
+
+        send(&mut state, room_id, &dm, CustomMessage::StartGame(0));
+        assert_eq!(started(&state), 0);
+        // refused, but still acknowledged
+        let ack = state.handle_command(
+            Command::Reliable(room_id, CustomMessage::StartGame(0), 7),
+            &dm.id,
+        );
+        assert!(matches!(
+            ack.map(|change| change.ty),
+            Some(viewer::ChangeType::Ack(7))
+        ));
+        assert_eq!(started(&state), 0);
+
+        state.join(room_id, bob.id);
+        send(&mut state, room_id, &dm, CustomMessage::StartGame(0));
+        assert_eq!(started(&state), 1);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn only_the_dm_starts_custom_layouts() {
+        let mut state = viewer::state::State::new().with_guard(CustomMessage::allowed_in);
+        let (dm, alice) = (user("1"), user("2"));
+        let room_id = room_with(&mut state, &[&dm, &alice]);
+
+        for from in [&alice, &dm].iter() {
+            let msg = CustomMessage::StartCustom(Default::default());
+            send(&mut state, room_id, from, msg);
+        }
+        let started = replayed(&state, room_id, |msg| match msg {
+            CustomMessage::StartCustom(layout) => Some(layout),
+            _ => None,
+        });
+        assert_eq!(started.len(), 1);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn unknown_room_types_are_refused() {
+        let mut state = viewer::state::State::new().with_guard(CustomMessage::allowed_in);
+        let dm = user("1");
+        let room_id = room_with(&mut state, &[&dm]);
+
+        for index in [ROOM_TYPE_COUNT, u16::MAX, ROOM_TYPE_COUNT - 1].iter() {
+            send(&mut state, room_id, &dm, CustomMessage::StartGame(*index));
+        }
+        let started = replayed(&state, room_id, |msg| match msg {
+            CustomMessage::StartGame(index) => Some(index),
+            _ => None,
+        });
+        assert_eq!(started, vec![ROOM_TYPE_COUNT - 1]);
+    }
+
+    #[cfg(feature = "server")]
+    fn assigned_clicks(msg: CustomMessage) -> Option<PlayerID> {
+        match msg {
+            CustomMessage::AssignClick(player_id, _count) => Some(player_id),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn clicks_for_non_members_are_dropped() {
+        let mut state = viewer::state::State::new().with_guard(CustomMessage::allowed_in);
+        let (dm, alice, stranger) = (user("1"), user("2"), user("3"));
+        let room_id = room_with(&mut state, &[&dm, &alice]);
+        state.register_user(stranger.clone());
+
+        for player_id in [stranger.id, alice.id].iter() {
+            send(
+                &mut state,
+                room_id,
+                &dm,
+                CustomMessage::AssignClick(*player_id, 1),
+            );
+        }
+        assert_eq!(replayed(&state, room_id, assigned_clicks), vec![alice.id]);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn only_the_dm_assigns_clicks_to_players() {
+        let mut state = viewer::state::State::new().with_guard(CustomMessage::allowed_in);
+        let (dm, alice, bob) = (user("1"), user("2"), user("3"));
+        let room_id = room_with(&mut state, &[&dm, &alice, &bob]);
+
+        let assignments = [(&alice, bob.id), (&dm, dm.id), (&dm, alice.id)];
+        for (from, player_id) in assignments.iter() {
+            send(
+                &mut state,
+                room_id,
+                from,
+                CustomMessage::AssignClick(*player_id, 1),
+            );
+        }
+        assert_eq!(replayed(&state, room_id, assigned_clicks), vec![alice.id]);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn turns_in_a_row_are_capped() {
+        use viewer::{state, RoomSettings};
+
+        let mut state = state::State::new()
+            .with_checkpoint(CustomMessage::starts_game)
+            .with_guard(CustomMessage::allowed_in);
+        let (dm, alice, bob) = (user("1"), user("2"), user("3"));
+        let room_id = room_with(&mut state, &[&dm, &alice, &bob]);
+        state.set_room_settings(
+            room_id,
+            RoomSettings {
+                capacity: None,
+                min_players: 1,
+                max_consecutive_turns: Some(1),
+            },
+        );
+
+        send(&mut state, room_id, &dm, CustomMessage::StartGame(0));
+        for player_id in [alice.id, alice.id, bob.id, alice.id].iter() {
+            send(
+                &mut state,
+                room_id,
+                &dm,
+                CustomMessage::AssignClick(*player_id, 1),
+            );
+        }
+        assert_eq!(
+            replayed(&state, room_id, assigned_clicks),
+            vec![alice.id, bob.id, alice.id]
+        );
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn restarts_are_undone_by_the_dm_once() {
+        use viewer::state;
+
+        let mut state = state::State::new()
+            .with_checkpoint(CustomMessage::starts_game)
+            .with_guard(CustomMessage::allowed_in);
+        let (dm, alice) = (user("1"), user("2"));
+        let room_id = room_with(&mut state, &[&dm, &alice]);
+        let undos = |state: &state::State<CustomMessage>| {
+            replayed(state, room_id, |msg| match msg {
+                CustomMessage::UndoRestart => Some(()),
+                _ => None,
+            })
+            .len()
+        };
+
+        // nothing to undo before a game has started
+        send(&mut state, room_id, &dm, CustomMessage::UndoRestart);
+        assert_eq!(undos(&state), 0);
+
+        send(&mut state, room_id, &dm, CustomMessage::StartGame(0));
+        send(&mut state, room_id, &alice, CustomMessage::UndoRestart);
+        assert_eq!(undos(&state), 0);
+        for _ in 0..2 {
+            send(&mut state, room_id, &dm, CustomMessage::UndoRestart);
+        }
+        assert_eq!(undos(&state), 1);
+
+        let room = &state.rooms[&room_id].state;
+        let log = [CustomMessage::StartGame(0)];
+        let undo = |since_checkpoint| {
+            CustomMessage::UndoRestart.allowed_in(room, &dm.id, &log, since_checkpoint)
+        };
+        assert!(undo(UNDO_RESTART_WINDOW));
+        assert!(!undo(UNDO_RESTART_WINDOW * 2));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn collapses_are_reported_by_the_dm_once_per_game() {
+        use viewer::state;
+
+        let mut state = state::State::new()
+            .with_checkpoint(CustomMessage::starts_game)
+            .with_guard(CustomMessage::allowed_in);
+        let (dm, alice, bob) = (user("1"), user("2"), user("3"));
+        let room_id = room_with(&mut state, &[&dm, &alice, &bob]);
+        let collapses = |state: &state::State<CustomMessage>| {
+            replayed(state, room_id, |msg| match msg {
+                CustomMessage::Collapsed(player_id) => Some(player_id),
+                _ => None,
+            })
+        };
+
+        send(&mut state, room_id, &dm, CustomMessage::Collapsed(alice.id));
+        assert!(collapses(&state).is_empty());
+
+        send(&mut state, room_id, &dm, CustomMessage::StartGame(0));
+        send(
+            &mut state,
+            room_id,
+            &alice,
+            CustomMessage::Collapsed(bob.id),
+        );
+        send(&mut state, room_id, &dm, CustomMessage::Collapsed(dm.id));
+        assert!(collapses(&state).is_empty());
+        send(&mut state, room_id, &dm, CustomMessage::Collapsed(alice.id));
+        send(&mut state, room_id, &dm, CustomMessage::Collapsed(bob.id));
+        assert_eq!(collapses(&state), vec![alice.id]);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn only_the_dm_sets_the_time_scale() {
+        let mut state = viewer::state::State::new().with_guard(CustomMessage::allowed_in);
+        let (dm, alice) = (user("1"), user("2"));
+        let room_id = room_with(&mut state, &[&dm, &alice]);
+
+        let sent = [
+            (&alice, 0.),
+            (&dm, -1.),
+            (&dm, f32::NAN),
+            (&dm, f32::INFINITY),
+            (&dm, 1e30),
+            (&dm, 2.),
+        ];
+        for (from, scale) in sent.iter() {
+            send(
+                &mut state,
+                room_id,
+                from,
+                CustomMessage::SetTimeScale(*scale),
+            );
+        }
+        let scales = replayed(&state, room_id, |msg| match msg {
+            CustomMessage::SetTimeScale(scale) => Some(scale),
+            _ => None,
+        });
+        assert_eq!(scales, vec![2.]);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn only_the_dm_resyncs() {
+        let mut state = viewer::state::State::new().with_guard(CustomMessage::allowed_in);
+        let (dm, alice) = (user("1"), user("2"));
+        let room_id = room_with(&mut state, &[&dm, &alice]);
+
+        let snapshot = layout::Snapshot {
+            bodies: vec![],
+            platform: layout::Platform {
+                width: 1.,
+                thickness: 0.1,
+                y: -0.5,
+            },
+        };
+        for (from, tick) in [(&alice, 1), (&dm, 2)].iter() {
+            let msg = CustomMessage::Resync(*tick, snapshot.clone());
+            send(&mut state, room_id, from, msg);
+        }
+        let ticks = replayed(&state, room_id, |msg| match msg {
+            CustomMessage::Resync(tick, _) => Some(tick),
+            _ => None,
+        });
+        assert_eq!(ticks, vec![2]);
     }
 }
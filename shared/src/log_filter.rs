@@ -0,0 +1,134 @@
+//! `RUST_LOG`-style filters like `info,client::states::main=warn`: a default level and any number
+//! of `target=level` overrides, separated by commas.
+
+use log::LevelFilter;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    pub default: LevelFilter,
+    /// Matched against a record's target by prefix, the longest match winning, the same as
+    /// `simple_logger`'s module levels. Kept longest first.
+    pub targets: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self::new(super::DEFAULT_LOG_LEVEL)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid log directive {0:?}")]
+pub struct ParseLogFilterError(String);
+
+impl LogFilter {
+    pub const fn new(default: LevelFilter) -> Self {
+        Self {
+            default,
+            targets: Vec::new(),
+        }
+    }
+
+    /// The default filter when missing.
+    pub fn parse_env(value: Option<&str>) -> Result<Self, ParseLogFilterError> {
+        value.map_or(Ok(Self::default()), str::parse)
+    }
+
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _level)| target.starts_with(prefix.as_str()))
+            .map_or(self.default, |(_prefix, level)| *level)
+    }
+
+    pub fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    /// The most verbose level anything gets through at, for `log::set_max_level`.
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_target, level)| *level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+/// A bare level sets the default and a bare target turns everything on for it. Levels are `off`
+/// or `error` through `trace`, in any case.
+impl std::str::FromStr for LogFilter {
+    type Err = ParseLogFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let directives = s
+            .split(',')
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+            .collect::<Vec<_>>();
+        if directives.is_empty() {
+            return Err(ParseLogFilterError(s.to_owned()));
+        }
+
+        let mut filter = Self::default();
+        for directive in directives {
+            let invalid = || ParseLogFilterError(directive.to_owned());
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let level = level.trim().parse().map_err(|_err| invalid())?;
+                    filter.targets.push((target.trim().to_owned(), level));
+                }
+                None => match directive.parse() {
+                    Ok(level) => filter.default = level,
+                    Err(_err) if directive.contains(char::is_whitespace) => return Err(invalid()),
+                    Err(_err) => filter
+                        .targets
+                        .push((directive.to_owned(), LevelFilter::Trace)),
+                },
+            }
+        }
+        filter
+            .targets
+            .sort_by_key(|(target, _level)| std::cmp::Reverse(target.len()));
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn enabled(filter: &LogFilter, target: &str, level: Level) -> bool {
+        filter.enabled(&log::Metadata::builder().target(target).level(level).build())
+    }
+
+    #[test]
+    fn levels_parse() {
+        let default = |s: Option<&str>| LogFilter::parse_env(s).map(|filter| filter.default);
+        assert_eq!(default(None), Ok(super::super::DEFAULT_LOG_LEVEL));
+        assert_eq!(default(Some("warn")), Ok(LevelFilter::Warn));
+        assert_eq!(default(Some(" INFO\n")), Ok(LevelFilter::Info));
+        assert_eq!(default(Some("Trace")), Ok(LevelFilter::Trace));
+        assert_eq!(default(Some("off")), Ok(LevelFilter::Off));
+        assert!(default(Some("very loud")).is_err());
+        assert!(default(Some("client=loud")).is_err());
+        assert!(default(Some("")).is_err());
+    }
+
+    #[test]
+    fn target_filters_suppress_only_their_records() {
+        let filter: LogFilter = "info,client::states=warn,client::states::lobby=debug,websocket"
+            .parse()
+            .unwrap();
+        assert_eq!(filter.default, LevelFilter::Info);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+
+        assert!(!enabled(&filter, "client::states::main", Level::Debug));
+        assert!(!enabled(&filter, "client::states::main", Level::Info));
+        assert!(enabled(&filter, "client::states::main", Level::Warn));
+        assert!(enabled(&filter, "client::states::lobby", Level::Debug));
+        assert!(enabled(&filter, "client::net", Level::Info));
+        assert!(!enabled(&filter, "client::net", Level::Debug));
+        assert!(enabled(&filter, "websocket::native", Level::Trace));
+    }
+}
@@ -1,11 +1,24 @@
 use crate::{PlayerID as UserID, RoomID};
 use serde::{Deserialize, Serialize};
 
+/// Client-chosen tag matching a `Command::Reliable` with its `ChangeType::Ack`.
+pub type Nonce = u64;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ChangeType<T> {
     UserJoin(User),
     UserLeave(UserID),
     Custom(T),
+    /// Sent only to the issuer of a `Command::Reliable` once it has been applied.
+    Ack(Nonce),
+    /// A user's connection dropped or came back. Disconnected users stay in their rooms until
+    /// the server gives up on them and sends `UserLeave`.
+    UserStatus(UserID, bool),
+    /// Something said in the room, relayed from a `Command::Chat`.
+    Chat {
+        from: UserID,
+        text: String,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,23 +31,115 @@ pub struct StateChange<T> {
 pub struct User {
     pub id: UserID,
     pub name: String,
+    #[serde(default = "connected_default")]
+    pub connected: bool,
+}
+
+fn connected_default() -> bool {
+    true
+}
+
+/// Limits on who can be in a room, sent to clients along with it.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RoomSettings {
+    /// The most users, the DM included, the room takes. `None` for no limit.
+    #[serde(default)]
+    pub capacity: Option<u32>,
+    /// Players needed besides the DM before a game can start.
+    #[serde(default)]
+    pub min_players: u32,
+    /// Turns in a row the DM can give one player while anyone else could be having one. `None`
+    /// for no limit.
+    #[serde(default)]
+    pub max_consecutive_turns: Option<u32>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomState {
     pub id: RoomID,
+    #[serde(default)]
+    pub name: Option<String>,
     pub users: Vec<UserID>,
+    #[serde(default)]
+    pub settings: RoomSettings,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InitialRoomState {
     pub id: RoomID,
+    #[serde(default)]
+    pub name: Option<String>,
     pub users: Vec<User>,
+    #[serde(default)]
+    pub settings: RoomSettings,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Command<T> {
     Custom(RoomID, T),
+    /// Like `Custom` but acknowledged. Resending a nonce is acknowledged again but not reapplied.
+    Reliable(RoomID, T, Nonce),
+    /// Gives up the user's seat in the room while keeping their connection for another.
+    Leave(RoomID),
+    /// Said to everyone in the room. Surrounding whitespace is trimmed, and anything blank or
+    /// longer than `MAX_CHAT_LENGTH` characters after that is dropped.
+    Chat(RoomID, String),
+    /// Only from the DM. Whether the user's chat is dropped rather than relayed to the room.
+    Mute(RoomID, UserID, bool),
+}
+
+pub const MAX_CHAT_LENGTH: usize = 200;
+
+/// Bumped whenever `Command`, `StateChange` or anything they carry changes shape, so that a
+/// client and server built apart notice instead of misreading each other.
+pub const PROTOCOL_VERSION: u32 = 9;
+
+/// How every `Command` and `StateChange` goes over the websocket. Doesn't deserialize at all
+/// from any other version.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    #[serde(deserialize_with = "current_version")]
+    pub version: u32,
+    pub payload: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(payload: T) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            payload,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, thiserror::Error)]
+#[error("got protocol version {0} but expected version {}", PROTOCOL_VERSION)]
+pub struct VersionMismatch(pub u32);
+
+impl VersionMismatch {
+    /// Whether a JSON message that didn't open as an `Envelope` came from another version
+    /// rather than being malformed. Objects without a version are from before there were any,
+    /// which counts as version 0.
+    pub fn in_json(json: &[u8]) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct Version {
+            #[serde(default)]
+            version: u32,
+        }
+        serde_json::from_slice::<Version>(json)
+            .ok()
+            .filter(|v| v.version != PROTOCOL_VERSION)
+            .map(|v| Self(v.version))
+    }
+}
+
+fn current_version<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version == PROTOCOL_VERSION {
+        Ok(version)
+    } else {
+        Err(serde::de::Error::custom(VersionMismatch(version)))
+    }
 }
 
 #[cfg(feature = "server")]
@@ -46,12 +151,184 @@ pub mod state {
     pub struct Room<T> {
         pub state: RoomState,
         pub channel: channel::Sender<StateChange<T>>,
+        recent_nonces: std::collections::VecDeque<(UserID, Nonce)>,
+        log: std::collections::VecDeque<T>,
+        /// Whether anything has been dropped from `log` since the last checkpoint.
+        log_overflowed: bool,
+        /// When the last checkpoint was published, or the room created before there was one.
+        checkpoint_at: std::time::Instant,
+        password_hash: Option<String>,
+        muted: std::collections::HashSet<UserID>,
+    }
+
+    impl<T: std::fmt::Debug + Clone> Room<T> {
+        const RECENT_NONCES: usize = 64;
+        const LOG_CAPACITY: usize = 256;
+
+        /// Broadcasts a custom event and, unless it's transient, records it for anyone
+        /// subscribing later. A full log drops its oldest events but keeps the checkpoint they
+        /// came after.
+        fn publish(&mut self, payload: T, checkpoint: &Checkpoint<T>, transient: &Transient<T>) {
+            if (transient.0)(&payload) {
+                self.send(ChangeType::Custom(payload));
+                return;
+            }
+            if (checkpoint.0)(&payload) {
+                self.log.clear();
+                self.log_overflowed = false;
+                self.checkpoint_at = std::time::Instant::now();
+            } else if self.log.len() >= Self::LOG_CAPACITY {
+                if !self.log_overflowed {
+                    log::warn!(
+                        "Event log for {} is full. Late joiners may desync.",
+                        self.state.id
+                    );
+                    self.log_overflowed = true;
+                }
+                let pinned = self.log.front().is_some_and(|first| (checkpoint.0)(first));
+                self.log.remove(pinned as usize);
+            }
+            self.log.push_back(payload.clone());
+            self.send(ChangeType::Custom(payload));
+        }
+
+        fn send(&self, ty: ChangeType<T>) {
+            let result = self.channel.send(StateChange {
+                target: self.state.id,
+                ty,
+            });
+            if let Err(err) = result {
+                log::error!("{:?}", err);
+            }
+        }
+
+        /// Returns false if this nonce was already seen from this user.
+        fn record_nonce(&mut self, user_id: UserID, nonce: Nonce) -> bool {
+            if self.recent_nonces.contains(&(user_id, nonce)) {
+                false
+            } else {
+                if self.recent_nonces.len() >= Self::RECENT_NONCES {
+                    self.recent_nonces.pop_front();
+                }
+                self.recent_nonces.push_back((user_id, nonce));
+                true
+            }
+        }
+
+        /// A user starting over in the room starts their nonces over too, so whatever they
+        /// sent in an earlier session mustn't make their new commands look like resends.
+        fn forget_nonces(&mut self, user_id: UserID) {
+            self.recent_nonces
+                .retain(|(other, _nonce)| *other != user_id);
+        }
+    }
+
+    /// New room codes drawn before giving up on finding one that isn't taken. Only likely to run
+    /// out once most codes are in use.
+    pub const MAX_ROOM_ID_ATTEMPTS: usize = 64;
+
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, thiserror::Error)]
+    #[error("no unused room codes could be found")]
+    pub struct RoomsFull;
+
+    /// The room as it stands, its changes from here on and the backlog to catch up on first.
+    pub type Reconnected<T> = (
+        InitialRoomState,
+        channel::Receiver<StateChange<T>>,
+        Vec<StateChange<T>>,
+    );
+
+    /// Words masked out of chat, matched whole and ignoring case. Empty by default, which
+    /// leaves chat as it was said.
+    #[derive(Debug, Clone, Default, Eq, PartialEq)]
+    pub struct ChatFilter {
+        words: Vec<String>,
+    }
+
+    impl ChatFilter {
+        pub fn new<I: IntoIterator<Item = S>, S: AsRef<str>>(words: I) -> Self {
+            Self {
+                words: words
+                    .into_iter()
+                    .map(|word| word.as_ref().trim().to_lowercase())
+                    .filter(|word| !word.is_empty())
+                    .collect(),
+            }
+        }
+
+        /// Each filtered word becomes as many asterisks as it had characters.
+        pub fn mask(&self, text: &str) -> String {
+            if self.words.is_empty() {
+                return text.to_owned();
+            }
+            let mut masked = String::with_capacity(text.len());
+            let mut rest = text;
+            while let Some(start) = rest.find(char::is_alphanumeric) {
+                masked.push_str(&rest[..start]);
+                rest = &rest[start..];
+                let end = rest
+                    .find(|c: char| !c.is_alphanumeric())
+                    .unwrap_or(rest.len());
+                let word = &rest[..end];
+                if self.words.contains(&word.to_lowercase()) {
+                    masked.extend(word.chars().map(|_| '*'));
+                } else {
+                    masked.push_str(word);
+                }
+                rest = &rest[end..];
+            }
+            masked.push_str(rest);
+            masked
+        }
+    }
+
+    /// Marks events that make everything before them irrelevant to a late joiner, such as the
+    /// start of a new game.
+    pub struct Checkpoint<T>(fn(&T) -> bool);
+
+    impl<T> std::fmt::Debug for Checkpoint<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_tuple("Checkpoint").finish()
+        }
+    }
+
+    /// Marks events that are only of interest as they happen, such as something being dragged
+    /// around, and so are sent on without being kept for late joiners.
+    pub struct Transient<T>(fn(&T) -> bool);
+
+    impl<T> std::fmt::Debug for Transient<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_tuple("Transient").finish()
+        }
+    }
+
+    /// Decides whether a custom event from a user may happen in a room as it stands, like a game
+    /// only starting once enough players are there. It's also given the events since the last
+    /// checkpoint and how long ago that was. Refused events are dropped, although reliable ones
+    /// are still acknowledged so that they aren't resent.
+    pub struct Guard<T>(fn(&T, &RoomState, &UserID, &[T], std::time::Duration) -> bool);
+
+    impl<T> std::fmt::Debug for Guard<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_tuple("Guard").finish()
+        }
     }
 
     #[derive(Debug)]
     pub struct State<T> {
         pub users: std::collections::HashMap<UserID, User>,
         pub rooms: std::collections::HashMap<RoomID, Room<T>>,
+        checkpoint: Checkpoint<T>,
+        transient: Transient<T>,
+        guard: Guard<T>,
+        chat_filter: ChatFilter,
+        disconnected_since: std::collections::HashMap<UserID, std::time::Instant>,
+    }
+
+    impl<T: std::fmt::Debug + Clone> Default for State<T> {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     /// When joining a room, is it better to join then sub or sub then join?
@@ -60,6 +337,42 @@ pub mod state {
             Self {
                 users: Default::default(),
                 rooms: Default::default(),
+                checkpoint: Checkpoint(|_| false),
+                transient: Transient(|_| false),
+                guard: Guard(|_, _, _, _, _| true),
+                chat_filter: Default::default(),
+                disconnected_since: Default::default(),
+            }
+        }
+
+        pub fn with_checkpoint(self, checkpoint: fn(&T) -> bool) -> Self {
+            Self {
+                checkpoint: Checkpoint(checkpoint),
+                ..self
+            }
+        }
+
+        pub fn with_transient(self, transient: fn(&T) -> bool) -> Self {
+            Self {
+                transient: Transient(transient),
+                ..self
+            }
+        }
+
+        pub fn with_guard(
+            self,
+            guard: fn(&T, &RoomState, &UserID, &[T], std::time::Duration) -> bool,
+        ) -> Self {
+            Self {
+                guard: Guard(guard),
+                ..self
+            }
+        }
+
+        pub fn with_chat_filter(self, chat_filter: ChatFilter) -> Self {
+            Self {
+                chat_filter,
+                ..self
             }
         }
 
@@ -78,32 +391,121 @@ pub mod state {
             self.rooms.get(&room_id).map(|room| {
                 let initial_state = InitialRoomState {
                     id: room.state.id,
+                    name: room.state.name.clone(),
                     users: room
                         .state
                         .users
                         .iter()
                         .filter_map(|user_id| self.users.get(user_id).cloned())
                         .collect(),
+                    settings: room.state.settings,
                 };
                 (initial_state, room.channel.subscribe())
             })
         }
 
-        pub fn create_room(&mut self) -> RoomID {
-            let mut rng = rand::thread_rng();
-            let room_id = crate::RoomID::new(&mut rng);
+        /// Picks a seat back up after the user's connection dropped, as `subscribe` and `replay`
+        /// would but without joining again, so nobody is told about a `UserJoin`. `None` once the
+        /// user has given up the seat or been reaped from it. The new connection's nonces aren't
+        /// mistaken for the old one's.
+        pub fn reconnect(&mut self, room_id: RoomID, user_id: UserID) -> Option<Reconnected<T>> {
+            let room = self.rooms.get_mut(&room_id)?;
+            if !room.state.users.contains(&user_id) || !self.users.contains_key(&user_id) {
+                return None;
+            }
+            room.forget_nonces(user_id);
+            let (initial_state, channel) = self.subscribe(room_id)?;
+            Some((initial_state, channel, self.replay(room_id)))
+        }
+
+        /// Custom events since the last checkpoint. Sending these ahead of the channel from
+        /// `subscribe` brings a late subscriber up to date.
+        pub fn replay(&self, room_id: RoomID) -> Vec<StateChange<T>> {
+            self.rooms
+                .get(&room_id)
+                .map(|room| {
+                    room.log
+                        .iter()
+                        .cloned()
+                        .map(|payload| StateChange {
+                            target: room_id,
+                            ty: ChangeType::Custom(payload),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
+
+        pub fn create_room(&mut self) -> Result<RoomID, RoomsFull> {
+            self.create_named_room(None)
+        }
+
+        /// `name` is expected to have been checked with `validate_room_name`.
+        pub fn create_named_room(&mut self, name: Option<String>) -> Result<RoomID, RoomsFull> {
+            self.create_room_with(name, &mut rand::thread_rng())
+        }
+
+        /// Codes are drawn from `rng` until one isn't in use, giving up after
+        /// `MAX_ROOM_ID_ATTEMPTS`.
+        pub fn create_room_with<R: rand::Rng>(
+            &mut self,
+            name: Option<String>,
+            rng: &mut R,
+        ) -> Result<RoomID, RoomsFull> {
+            let room_id = std::iter::repeat_with(|| crate::RoomID::new(rng))
+                .take(MAX_ROOM_ID_ATTEMPTS)
+                .find(|room_id| !self.rooms.contains_key(room_id))
+                .ok_or(RoomsFull)?;
             let (channel, _) = channel::channel(32);
             self.rooms.insert(
                 room_id,
                 Room {
                     state: RoomState {
                         id: room_id,
+                        name,
                         users: vec![],
+                        settings: Default::default(),
                     },
                     channel,
+                    recent_nonces: Default::default(),
+                    log: Default::default(),
+                    log_overflowed: false,
+                    checkpoint_at: std::time::Instant::now(),
+                    password_hash: None,
+                    muted: Default::default(),
                 },
             );
-            room_id
+            Ok(room_id)
+        }
+
+        /// Hashing is up to the caller. This only keeps the result away from `RoomState`, which
+        /// is sent to clients.
+        pub fn set_room_password_hash(&mut self, room_id: RoomID, hash: Option<String>) {
+            if let Some(room) = self.rooms.get_mut(&room_id) {
+                room.password_hash = hash;
+            }
+        }
+
+        pub fn set_room_settings(&mut self, room_id: RoomID, settings: RoomSettings) {
+            if let Some(room) = self.rooms.get_mut(&room_id) {
+                room.state.settings = settings;
+            }
+        }
+
+        /// Whether the room has space for one more user. `None` if there's no such room.
+        pub fn has_space(&self, room_id: RoomID) -> Option<bool> {
+            self.rooms.get(&room_id).map(|room| {
+                room.state
+                    .settings
+                    .capacity
+                    .is_none_or(|capacity| room.state.users.len() < capacity as usize)
+            })
+        }
+
+        pub fn room_password_hash(&self, room_id: RoomID) -> Option<&str> {
+            self.rooms
+                .get(&room_id)
+                .and_then(|room| room.password_hash.as_deref())
         }
 
         pub fn join(
@@ -114,6 +516,7 @@ pub mod state {
             let room = self.rooms.get_mut(&room_id);
             let user = self.users.get(&user_id);
             room.zip(user).map(|(room, user)| {
+                room.forget_nonces(user.id);
                 room.state.users.push(user.id);
                 room.channel.send(StateChange {
                     target: room_id,
@@ -122,22 +525,67 @@ pub mod state {
             })
         }
 
+        /// Removes the room once the last user has left it, the same as `unregister_user`.
         pub fn leave(
             &mut self,
             room_id: RoomID,
             user_id: UserID,
         ) -> Option<Result<usize, channel::error::SendError<StateChange<T>>>> {
-            self.rooms.get_mut(&room_id).map(|room| {
-                room.state.users.retain(|user| user != &user_id);
-                room.channel.send(StateChange {
-                    target: room_id,
-                    ty: ChangeType::UserLeave(user_id),
-                })
-            })
+            let room = self.rooms.get_mut(&room_id)?;
+            room.state.users.retain(|user| user != &user_id);
+            let result = room.channel.send(StateChange {
+                target: room_id,
+                ty: ChangeType::UserLeave(user_id),
+            });
+            if room.state.users.is_empty() {
+                self.rooms.remove(&room_id);
+            }
+            Some(result)
+        }
+
+        /// Updates the user's status and tells their rooms if it changed.
+        pub fn set_connected(&mut self, user_id: UserID, connected: bool, now: std::time::Instant) {
+            let user = match self.users.get_mut(&user_id) {
+                Some(user) => user,
+                None => return,
+            };
+            if connected {
+                self.disconnected_since.remove(&user_id);
+            } else {
+                self.disconnected_since.entry(user_id).or_insert(now);
+            }
+            if user.connected == connected {
+                return;
+            }
+            user.connected = connected;
+            for room in self.rooms.values() {
+                if room.state.users.contains(&user_id) {
+                    room.send(ChangeType::UserStatus(user_id, connected));
+                }
+            }
+        }
+
+        /// Unregisters users that have been disconnected for longer than `grace`, returning them.
+        pub fn reap_disconnected(
+            &mut self,
+            grace: std::time::Duration,
+            now: std::time::Instant,
+        ) -> Vec<UserID> {
+            let expired = self
+                .disconnected_since
+                .iter()
+                .filter(|(_user_id, since)| now.saturating_duration_since(**since) >= grace)
+                .map(|(user_id, _since)| *user_id)
+                .collect::<Vec<_>>();
+            for user_id in expired.iter() {
+                self.unregister_user(*user_id);
+            }
+            expired
         }
 
         pub fn unregister_user(&mut self, user_id: UserID) {
             self.users.remove(&user_id);
+            self.disconnected_since.remove(&user_id);
             let to_remove = self
                 .rooms
                 .iter_mut()
@@ -165,20 +613,79 @@ pub mod state {
             }
         }
 
-        pub fn handle_command(&mut self, cmd: Command<T>, from: &UserID) {
+        /// Returns a change to be delivered only to `from`, such as an acknowledgement.
+        pub fn handle_command(&mut self, cmd: Command<T>, from: &UserID) -> Option<StateChange<T>> {
             match cmd {
                 Command::Custom(room_id, payload) => {
-                    if let Some(room) = self.rooms.get(&room_id) {
-                        if room.state.users.contains(&from) {
-                            let result = room.channel.send(StateChange {
-                                target: room.state.id,
-                                ty: ChangeType::Custom(payload),
-                            });
-                            if let Err(err) = result {
-                                log::error!("{:?}", err);
-                            }
+                    if let Some(room) = self.rooms.get_mut(&room_id) {
+                        if room.state.users.contains(from)
+                            && (self.guard.0)(
+                                &payload,
+                                &room.state,
+                                from,
+                                room.log.make_contiguous(),
+                                room.checkpoint_at.elapsed(),
+                            )
+                        {
+                            room.publish(payload, &self.checkpoint, &self.transient);
                         }
                     }
+                    None
+                }
+                Command::Reliable(room_id, payload, nonce) => {
+                    let room = self.rooms.get_mut(&room_id)?;
+                    if !room.state.users.contains(from) {
+                        return None;
+                    }
+                    let since_checkpoint = room.checkpoint_at.elapsed();
+                    let log = room.log.make_contiguous();
+                    if (self.guard.0)(&payload, &room.state, from, log, since_checkpoint)
+                        && room.record_nonce(*from, nonce)
+                    {
+                        room.publish(payload, &self.checkpoint, &self.transient);
+                    }
+                    Some(StateChange {
+                        target: room_id,
+                        ty: ChangeType::Ack(nonce),
+                    })
+                }
+                Command::Chat(room_id, text) => {
+                    let room = self.rooms.get(&room_id)?;
+                    let text = text.trim();
+                    if room.state.users.contains(from)
+                        && !room.muted.contains(from)
+                        && !text.is_empty()
+                        && text.chars().count() <= MAX_CHAT_LENGTH
+                    {
+                        room.send(ChangeType::Chat {
+                            from: *from,
+                            text: self.chat_filter.mask(text),
+                        });
+                    }
+                    None
+                }
+                Command::Mute(room_id, user_id, muted) => {
+                    let room = self.rooms.get_mut(&room_id)?;
+                    if room.state.users.first() == Some(from) {
+                        if muted {
+                            room.muted.insert(user_id);
+                        } else {
+                            room.muted.remove(&user_id);
+                        }
+                    }
+                    None
+                }
+                Command::Leave(room_id) => {
+                    let seated = self
+                        .rooms
+                        .get(&room_id)
+                        .is_some_and(|room| room.state.users.contains(from));
+                    if seated {
+                        if let Some(Err(err)) = self.leave(room_id, *from) {
+                            log::error!("{:?}", err);
+                        }
+                    }
+                    None
                 }
             }
         }
@@ -233,6 +740,7 @@ mod tests {
                 user: User {
                     id: UserID(USER_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst)),
                     name,
+                    connected: true,
                 },
                 view: view::View {
                     state: view::State {
@@ -264,6 +772,9 @@ mod tests {
                             room.state.users.retain(|user| user != &user_id);
                         }
                         ChangeType::Custom(_) => {}
+                        ChangeType::Ack(_) => {}
+                        ChangeType::UserStatus(_, _) => {}
+                        ChangeType::Chat { .. } => {}
                     }
                 }
             }
@@ -294,7 +805,7 @@ mod tests {
             let user1_id = user1.user.id;
             let user2_id = user2.user.id;
             async move {
-                let streams = std::array::IntoIter::new([
+                let streams = IntoIterator::into_iter([
                     (from_user1, user1_id),
                     (from_user2, user2_id),
                 ])
@@ -318,7 +829,9 @@ mod tests {
             user1.view.state.rooms.push(view::Room {
                 state: RoomState {
                     id: room_id,
+                    name: initial_state.name.clone(),
                     users: initial_state.users.iter().map(|user| user.id).collect(),
+                    settings: initial_state.settings,
                 },
             });
             room_id
@@ -329,7 +842,9 @@ mod tests {
             user2.view.state.rooms.push(view::Room {
                 state: RoomState {
                     id: initial_state.id,
+                    name: initial_state.name.clone(),
                     users: initial_state.users.iter().map(|user| user.id).collect(),
+                    settings: initial_state.settings,
                 },
             });
         }
@@ -360,7 +875,7 @@ mod tests {
     where
         T: std::fmt::Debug + Clone + Send + 'static,
     {
-        let room_id = state.create_room();
+        let room_id = state.create_room().unwrap();
         state.join(room_id, user.user.id);
         let (state, channel) = state.subscribe(room_id).unwrap();
         let sx = user.sx.clone();
@@ -411,4 +926,412 @@ mod tests {
         let rhs = rhs.iter().map(|room| &room.state).collect::<Vec<_>>();
         assert_eq!(lhs, rhs);
     }
+
+    #[test]
+    fn envelopes_from_other_versions_are_refused() {
+        let room_id: RoomID = "ABCD".parse().unwrap();
+        let current = serde_json::to_string(&Envelope::new(Command::Custom(room_id, 1))).unwrap();
+        let parsed = serde_json::from_str::<Envelope<Command<u32>>>(&current).unwrap();
+        assert_eq!(parsed.payload, Command::Custom(room_id, 1));
+        assert_eq!(VersionMismatch::in_json(current.as_bytes()), None);
+
+        let old = current.replace(
+            &format!(r#""version":{}"#, PROTOCOL_VERSION),
+            r#""version":0"#,
+        );
+        assert_ne!(old, current);
+        assert!(serde_json::from_str::<Envelope<Command<u32>>>(&old).is_err());
+        assert_eq!(
+            VersionMismatch::in_json(old.as_bytes()),
+            Some(VersionMismatch(0))
+        );
+
+        // unversioned messages from before envelopes existed
+        let bare = serde_json::to_string(&Command::Custom(room_id, 1)).unwrap();
+        assert!(serde_json::from_str::<Envelope<Command<u32>>>(&bare).is_err());
+        assert_eq!(
+            VersionMismatch::in_json(bare.as_bytes()),
+            Some(VersionMismatch(0))
+        );
+        assert_eq!(VersionMismatch::in_json(b"not json"), None);
+    }
+
+    #[test]
+    fn reliable_command_is_acked_once_applied() {
+        let mut state = state::State::<u32>::new();
+        let user = User {
+            id: UserID(u64::MAX),
+            name: "Alice".to_string(),
+            connected: true,
+        };
+        let stranger = UserID(u64::MAX - 1);
+        state.register_user(user.clone());
+        let room_id = state.create_room().unwrap();
+        state.join(room_id, user.id);
+        let (_initial_state, mut channel) = state.subscribe(room_id).unwrap();
+
+        let ack = state.handle_command(Command::Reliable(room_id, 1, 7), &user.id);
+        assert!(matches!(
+            ack.map(|change| change.ty),
+            Some(ChangeType::Ack(7))
+        ));
+        assert!(matches!(
+            channel.try_recv().map(|change| change.ty),
+            Ok(ChangeType::Custom(1))
+        ));
+
+        // a replay is acknowledged again without being reapplied
+        let ack = state.handle_command(Command::Reliable(room_id, 1, 7), &user.id);
+        assert!(matches!(
+            ack.map(|change| change.ty),
+            Some(ChangeType::Ack(7))
+        ));
+        assert!(channel.try_recv().is_err());
+
+        let ack = state.handle_command(Command::Reliable(room_id, 2, 8), &stranger);
+        assert!(ack.is_none());
+        assert!(channel.try_recv().is_err());
+
+        let ack = state.handle_command(Command::Custom(room_id, 3), &user.id);
+        assert!(ack.is_none());
+    }
+
+    #[test]
+    fn leaving_the_last_seat_removes_the_room() {
+        let mut state = state::State::<u32>::new();
+        let users = [UserID(u64::MAX), UserID(u64::MAX - 1)];
+        for (id, name) in users.iter().zip(["Alice", "Bob"].iter()) {
+            state.register_user(User {
+                id: *id,
+                name: name.to_string(),
+                connected: true,
+            });
+        }
+        let room_id = state.create_room().unwrap();
+        state.join(room_id, users[0]);
+        let (_initial_state, mut channel) = state.subscribe(room_id).unwrap();
+
+        // only those seated can leave
+        assert!(state
+            .handle_command(Command::Leave(room_id), &users[1])
+            .is_none());
+        assert!(channel.try_recv().is_err());
+
+        state.join(room_id, users[1]);
+        state.handle_command(Command::Leave(room_id), &users[0]);
+        assert!(matches!(
+            channel.try_recv().map(|change| change.ty),
+            Ok(ChangeType::UserJoin(_))
+        ));
+        assert!(matches!(
+            channel.try_recv().map(|change| change.ty),
+            Ok(ChangeType::UserLeave(id)) if id == users[0]
+        ));
+        assert_eq!(state.rooms[&room_id].state.users, vec![users[1]]);
+        // still registered and free to join another room
+        assert!(state.users.contains_key(&users[0]));
+
+        state.handle_command(Command::Leave(room_id), &users[1]);
+        assert!(!state.rooms.contains_key(&room_id));
+    }
+
+    #[test]
+    fn chat_reaches_the_room_within_limits() {
+        let mut state = state::State::<u32>::new();
+        let alice = UserID(u64::MAX);
+        let stranger = UserID(u64::MAX - 1);
+        for id in [alice, stranger].iter() {
+            state.register_user(User {
+                id: *id,
+                name: id.to_string(),
+                connected: true,
+            });
+        }
+        let room_id = state.create_room().unwrap();
+        state.join(room_id, alice);
+        let (_initial_state, mut channel) = state.subscribe(room_id).unwrap();
+
+        let chat = |text: &str| Command::Chat(room_id, text.to_owned());
+        assert!(state.handle_command(chat("  hi all "), &alice).is_none());
+        assert!(matches!(
+            channel.try_recv().map(|change| change.ty),
+            Ok(ChangeType::Chat { from, text }) if from == alice && text == "hi all"
+        ));
+
+        state.handle_command(chat("hello?"), &stranger);
+        state.handle_command(chat(" \n "), &alice);
+        state.handle_command(chat(&"é".repeat(MAX_CHAT_LENGTH + 1)), &alice);
+        assert!(channel.try_recv().is_err());
+
+        state.handle_command(chat(&"é".repeat(MAX_CHAT_LENGTH)), &alice);
+        assert!(channel.try_recv().is_ok());
+        // not something late joiners are caught up on
+        assert!(state.replay(room_id).is_empty());
+    }
+
+    #[test]
+    fn chat_is_moderated() {
+        let mut state = state::State::<u32>::new()
+            .with_chat_filter(state::ChatFilter::new(vec!["heck", " Darn "]));
+        let (dm, alice) = (UserID(u64::MAX - 7), UserID(u64::MAX - 8));
+        for id in [dm, alice].iter() {
+            state.register_user(User {
+                id: *id,
+                name: id.to_string(),
+                connected: true,
+            });
+        }
+        let room_id = state.create_room().unwrap();
+        state.join(room_id, dm);
+        state.join(room_id, alice);
+        let (_initial_state, mut channel) = state.subscribe(room_id).unwrap();
+        let mut said = || match channel.try_recv().map(|change| change.ty) {
+            Ok(ChangeType::Chat { text, .. }) => Some(text),
+            _ => None,
+        };
+
+        let chat = |text: &str| Command::Chat(room_id, text.to_owned());
+        state.handle_command(chat("HECK, darned darn-it"), &alice);
+        assert_eq!(said().as_deref(), Some("****, darned ****-it"));
+
+        // only the DM mutes
+        state.handle_command(Command::Mute(room_id, dm, true), &alice);
+        state.handle_command(chat("still here"), &dm);
+        assert_eq!(said().as_deref(), Some("still here"));
+
+        state.handle_command(Command::Mute(room_id, alice, true), &dm);
+        state.handle_command(chat("hello?"), &alice);
+        assert_eq!(said(), None);
+
+        state.handle_command(Command::Mute(room_id, alice, false), &dm);
+        state.handle_command(chat("hello!"), &alice);
+        assert_eq!(said().as_deref(), Some("hello!"));
+    }
+
+    #[test]
+    fn late_subscriber_catches_up() {
+        fn apply(total: &mut i32, change: StateChange<i32>) {
+            match change.ty {
+                ChangeType::Custom(0) => *total = 0,
+                ChangeType::Custom(n) => *total += n,
+                _ => {}
+            }
+        }
+
+        let mut state = state::State::<i32>::new().with_checkpoint(|n| *n == 0);
+        let user = User {
+            id: UserID(u64::MAX - 2),
+            name: "Alice".to_string(),
+            connected: true,
+        };
+        state.register_user(user.clone());
+        let room_id = state.create_room().unwrap();
+        state.join(room_id, user.id);
+
+        let (_initial_state, mut early) = state.subscribe(room_id).unwrap();
+        let mut early_total = 0;
+        for n in [5, 0, 1, 2, 3].iter() {
+            state.handle_command(Command::Custom(room_id, *n), &user.id);
+        }
+
+        let (_initial_state, mut late) = state.subscribe(room_id).unwrap();
+        let backlog = state.replay(room_id);
+        assert_eq!(backlog.len(), 4);
+        let mut late_total = 0;
+        for change in backlog {
+            apply(&mut late_total, change);
+        }
+
+        state.handle_command(Command::Custom(room_id, 4), &user.id);
+        while let Ok(change) = early.try_recv() {
+            apply(&mut early_total, change);
+        }
+        while let Ok(change) = late.try_recv() {
+            apply(&mut late_total, change);
+        }
+        assert_eq!(early_total, 10);
+        assert_eq!(late_total, early_total);
+    }
+
+    #[test]
+    fn transient_events_stay_out_of_the_backlog() {
+        let mut state = state::State::<i32>::new()
+            .with_checkpoint(|n| *n == 0)
+            .with_transient(|n| *n < 0);
+        let user = User {
+            id: UserID(u64::MAX - 2),
+            name: "Alice".to_string(),
+            connected: true,
+        };
+        state.register_user(user.clone());
+        let room_id = state.create_room().unwrap();
+        state.join(room_id, user.id);
+
+        state.handle_command(Command::Custom(room_id, 0), &user.id);
+        for n in 1..1000 {
+            state.handle_command(Command::Custom(room_id, n), &user.id);
+            state.handle_command(Command::Custom(room_id, -n), &user.id);
+        }
+
+        let backlog = state
+            .replay(room_id)
+            .into_iter()
+            .filter_map(|change| match change.ty {
+                ChangeType::Custom(n) => Some(n),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // a full log still starts from the checkpoint, but without anything transient
+        assert_eq!(backlog.first(), Some(&0));
+        assert!(backlog.iter().all(|n| *n >= 0));
+        assert!(backlog.len() < 1000);
+        assert_eq!(backlog.last(), Some(&999));
+    }
+
+    #[test]
+    fn disconnected_user_is_kept_through_grace_period() {
+        let mut state = state::State::<()>::new();
+        let user = User {
+            id: UserID(u64::MAX - 3),
+            name: "Alice".to_string(),
+            connected: true,
+        };
+        state.register_user(user.clone());
+        let room_id = state.create_room().unwrap();
+        state.join(room_id, user.id);
+        let (_initial_state, mut channel) = state.subscribe(room_id).unwrap();
+
+        let grace = std::time::Duration::from_secs(30);
+        let start = std::time::Instant::now();
+        state.set_connected(user.id, false, start);
+        assert!(matches!(
+            channel.try_recv().map(|change| change.ty),
+            Ok(ChangeType::UserStatus(id, false)) if id == user.id
+        ));
+        assert!(!state.users[&user.id].connected);
+
+        let reaped = state.reap_disconnected(grace, start + grace / 2);
+        assert!(reaped.is_empty());
+        assert!(state.rooms[&room_id].state.users.contains(&user.id));
+        assert!(channel.try_recv().is_err());
+
+        // coming back cancels the pending removal
+        state.set_connected(user.id, true, start + grace / 2);
+        assert!(matches!(
+            channel.try_recv().map(|change| change.ty),
+            Ok(ChangeType::UserStatus(id, true)) if id == user.id
+        ));
+        assert!(state.reap_disconnected(grace, start + grace * 2).is_empty());
+
+        let later = start + grace;
+        state.set_connected(user.id, false, later);
+        assert_eq!(state.reap_disconnected(grace, later + grace), vec![user.id]);
+        assert!(!state.users.contains_key(&user.id));
+    }
+
+    #[test]
+    fn room_codes_in_use_are_not_reused() {
+        use rand::SeedableRng;
+        let mut state = state::State::<()>::new();
+        let rng = rand::rngs::StdRng::seed_from_u64(7);
+        let first = state
+            .create_room_with(Some("First".to_owned()), &mut rng.clone())
+            .unwrap();
+        assert_eq!(first, RoomID::new(&mut rng.clone()));
+
+        // the same draws again, starting with the code that's now taken
+        let second = state.create_room_with(None, &mut rng.clone()).unwrap();
+        assert_ne!(second, first);
+        assert_eq!(state.rooms[&first].state.name.as_deref(), Some("First"));
+
+        // every draw the same code, which is soon taken
+        let mut stuck = rand::rngs::mock::StepRng::new(0, 0);
+        assert!(state.create_room_with(None, &mut stuck).is_ok());
+        assert_eq!(
+            state.create_room_with(None, &mut stuck),
+            Err(state::RoomsFull)
+        );
+        assert_eq!(state.rooms.len(), 3);
+        assert_eq!(state.rooms[&first].state.name.as_deref(), Some("First"));
+    }
+
+    #[test]
+    fn reconnecting_keeps_the_seat_quietly() {
+        let mut state = state::State::<u32>::new();
+        let users = [u64::MAX - 5, u64::MAX - 6]
+            .iter()
+            .map(|id| User {
+                id: UserID(*id),
+                name: id.to_string(),
+                connected: true,
+            })
+            .collect::<Vec<_>>();
+        for user in users.iter() {
+            state.register_user(user.clone());
+        }
+        let room_id = state.create_room().unwrap();
+        state.join(room_id, users[0].id);
+        state.join(room_id, users[1].id);
+        let (_initial_state, mut dm) = state.subscribe(room_id).unwrap();
+
+        state.set_connected(users[1].id, false, std::time::Instant::now());
+        assert!(dm.try_recv().is_ok());
+        let (initial_state, _channel, backlog) = state.reconnect(room_id, users[1].id).unwrap();
+        let seated = initial_state
+            .users
+            .iter()
+            .map(|user| user.id)
+            .collect::<Vec<_>>();
+        assert_eq!(seated, vec![users[0].id, users[1].id]);
+        assert!(backlog.is_empty());
+        assert!(dm.try_recv().is_err());
+
+        state.leave(room_id, users[1].id);
+        assert!(state.reconnect(room_id, users[1].id).is_none());
+    }
+
+    #[test]
+    fn rejoining_starts_nonces_over() {
+        let mut state = state::State::<u32>::new();
+        let users = [u64::MAX - 7, u64::MAX - 8]
+            .iter()
+            .map(|id| User {
+                id: UserID(*id),
+                name: id.to_string(),
+                connected: true,
+            })
+            .collect::<Vec<_>>();
+        for user in users.iter() {
+            state.register_user(user.clone());
+        }
+        let room_id = state.create_room().unwrap();
+        state.join(room_id, users[0].id);
+        state.join(room_id, users[1].id);
+        let backlog = |state: &state::State<u32>| {
+            state
+                .replay(room_id)
+                .into_iter()
+                .filter_map(|change| match change.ty {
+                    ChangeType::Custom(n) => Some(n),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let player = users[1].id;
+        state.handle_command(Command::Reliable(room_id, 1, 0), &player);
+        // a resend from the same session is acknowledged but not reapplied
+        state.handle_command(Command::Reliable(room_id, 1, 0), &player);
+        assert_eq!(backlog(&state), vec![1]);
+
+        // a refreshed page picking the seat back up counts from 0 again
+        state.reconnect(room_id, player).unwrap();
+        state.handle_command(Command::Reliable(room_id, 2, 0), &player);
+        assert_eq!(backlog(&state), vec![1, 2]);
+
+        state.leave(room_id, player);
+        state.join(room_id, player);
+        state.handle_command(Command::Reliable(room_id, 3, 0), &player);
+        assert_eq!(backlog(&state), vec![1, 2, 3]);
+    }
 }
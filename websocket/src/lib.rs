@@ -8,6 +8,9 @@ pub use native::*;
 #[cfg(target_arch = "wasm32")]
 pub use websys::*;
 
+/// Extra handshake headers as name and value pairs.
+pub type Headers = Vec<(String, Vec<u8>)>;
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Error(std::borrow::Cow<'static, str>);
 
@@ -17,6 +20,10 @@ pub enum Message {
     Text(String),
     /// A binary WebSocket message
     Binary(Vec<u8>),
+    /// A ping control frame. The other end answers with a `Pong` carrying the same payload
+    Ping(Vec<u8>),
+    /// A pong control frame, usually in answer to a `Ping`
+    Pong(Vec<u8>),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -87,12 +94,65 @@ pub enum CloseCode {
     Other(u16),
 }
 
+impl CloseCode {
+    /// Whether the connection was lost in a way that redialing may fix, rather than closed on
+    /// purpose or over something the other end will only object to again.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            CloseCode::Away | CloseCode::Abnormal | CloseCode::Restart | CloseCode::Again
+        )
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum WebSocketEvent {
     Open,
     Message(Message),
     Error(WebSocketError),
-    Close(CloseCode),
+    /// `reason` is whatever the other end gave for closing, like "room full", and empty if it
+    /// didn't say or the connection was lost without a closing handshake.
+    Close {
+        code: CloseCode,
+        reason: String,
+    },
+    /// A socket from `WebSocket::connect_with_retry` dropped and has been redialed. The other
+    /// end sees a brand new connection so anything it only knew about the old one, like what
+    /// it was subscribed to, needs setting up again.
+    Reopened,
+}
+
+/// How `WebSocket::connect_with_retry` redials a connection that was lost.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Before the first attempt. Each one after that waits twice as long as the last.
+    pub base_delay: std::time::Duration,
+    pub max_delay: std::time::Duration,
+    /// Failed attempts in a row before giving up and passing the close on.
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            base_delay: std::time::Duration::from_secs(1),
+            max_delay: std::time::Duration::from_secs(30),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Before attempt number `attempt`, counting from zero. `jitter`, between zero and one,
+    /// takes up to half of it off so that everyone dropped at once doesn't redial at once too.
+    pub fn delay(&self, attempt: u32, jitter: f32) -> std::time::Duration {
+        let backoff = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        backoff.mul_f32(1. - jitter.clamp(0., 1.) / 2.)
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, thiserror::Error)]
@@ -103,26 +163,84 @@ pub enum WebSocketError {
     SendError,
     #[error("could not receive message")]
     ReceiveError,
+    #[error("no message waiting")]
+    Empty,
+    #[error("websocket closed ({0:?})")]
+    Closed(CloseCode),
+}
+
+/// Messages sent while the socket isn't open yet, like while it's still connecting or being
+/// redialed. They're passed on in order once it is.
+#[derive(Debug)]
+struct SendBuffer {
+    queue: std::collections::VecDeque<Message>,
+    capacity: usize,
+}
+
+impl Default for SendBuffer {
+    fn default() -> Self {
+        Self::with_capacity(Self::CAPACITY)
+    }
+}
+
+impl SendBuffer {
+    const CAPACITY: usize = 64;
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            queue: Default::default(),
+            capacity,
+        }
+    }
+
+    /// `WebSocketError::SendError` if it's full.
+    fn push(&mut self, msg: Message) -> Result<(), WebSocketError> {
+        if self.queue.len() >= self.capacity {
+            Err(WebSocketError::SendError)
+        } else {
+            self.queue.push_back(msg);
+            Ok(())
+        }
+    }
+
+    /// Stops at the first failure, keeping that message and everything after it for next time.
+    fn flush<F>(&mut self, mut send: F) -> Result<(), WebSocketError>
+    where
+        F: FnMut(Message) -> Result<(), WebSocketError>,
+    {
+        while let Some(msg) = self.queue.pop_front() {
+            if let Err(err) = send(msg.clone()) {
+                self.queue.push_front(msg);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl futures::stream::Stream for WebSocket {
     type Item = WebSocketEvent;
 
     fn poll_next(
-        mut self: std::pin::Pin<&mut Self>,
-        _cx: &mut std::task::Context,
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context,
     ) -> std::task::Poll<Option<Self::Item>> {
         use std::task::Poll;
-        match self.as_mut().poll() {
-            None => Poll::Pending,
-            Some(WebSocketEvent::Close(_code)) => Poll::Ready(None),
-            Some(event) => Poll::Ready(Some(event)),
+        match self.poll_event(cx) {
+            Poll::Pending => Poll::Pending,
+            // the reason can still be had from `close_reason`
+            Poll::Ready(Some(WebSocketEvent::Close { .. })) | Poll::Ready(None) => {
+                Poll::Ready(None)
+            }
+            Poll::Ready(Some(event)) => Poll::Ready(Some(event)),
         }
     }
 }
 
+/// Clones share the same socket.
+#[derive(Clone)]
 pub struct WsSend {
-    socket: std::sync::Arc<WebSocket>,
+    socket: std::rc::Rc<WebSocket>,
 }
 
 impl WsSend {
@@ -131,26 +249,74 @@ impl WsSend {
     }
 }
 
+/// Clones share the same socket, so each message only goes to one of them.
+#[derive(Clone)]
 pub struct WsRecv {
-    socket: std::sync::Arc<WebSocket>,
+    socket: std::rc::Rc<WebSocket>,
 }
 
 impl WsRecv {
+    /// `WebSocketError::Empty` when there's nothing waiting and `Closed` from the close event
+    /// on. Opening events are skipped over.
     pub fn try_recv(&self) -> Result<Message, WebSocketError> {
-        if let Some(event) = self.socket.poll() {
-            match event {
-                WebSocketEvent::Message(msg) => Ok(msg),
-                _ => Err(WebSocketError::ReceiveError),
+        loop {
+            if let WebSocketEvent::Message(msg) = self.try_recv_event()? {
+                return Ok(msg);
             }
-        } else {
-            Err(WebSocketError::ReceiveError)
         }
     }
+
+    /// Like `try_recv` but `WebSocketEvent::Reopened` is passed on too, for anything that has
+    /// to be set up again on a redialed connection.
+    pub fn try_recv_event(&self) -> Result<WebSocketEvent, WebSocketError> {
+        loop {
+            match self.socket.poll() {
+                Some(WebSocketEvent::Error(err)) => return Err(err),
+                Some(WebSocketEvent::Close { code, .. }) => {
+                    return Err(WebSocketError::Closed(code))
+                }
+                Some(WebSocketEvent::Open) => continue,
+                Some(event) => return Ok(event),
+                None => {
+                    return Err(self
+                        .socket
+                        .close_code()
+                        .map_or(WebSocketError::Empty, WebSocketError::Closed))
+                }
+            }
+        }
+    }
+
+    /// What the other end gave for closing, once the close has been received.
+    pub fn close_reason(&self) -> Option<String> {
+        self.socket.close_reason()
+    }
+
+    /// Waits for the next message, skipping over any other events. Once the socket has closed
+    /// this is `WebSocketError::Closed` instead.
+    pub async fn recv(&self) -> Result<Message, WebSocketError> {
+        use std::task::Poll;
+        futures::future::poll_fn(|cx| loop {
+            match self.socket.poll_event(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(WebSocketEvent::Message(msg))) => return Poll::Ready(Ok(msg)),
+                Poll::Ready(Some(WebSocketEvent::Close { code, .. })) => {
+                    return Poll::Ready(Err(WebSocketError::Closed(code)))
+                }
+                // gone without a word, which the backends report as abnormal too
+                Poll::Ready(None) => {
+                    return Poll::Ready(Err(WebSocketError::Closed(CloseCode::Abnormal)))
+                }
+                Poll::Ready(Some(_event)) => continue,
+            }
+        })
+        .await
+    }
 }
 
 impl WebSocket {
     pub fn into_channels(self) -> (WsSend, WsRecv) {
-        let socket = std::sync::Arc::new(self);
+        let socket = std::rc::Rc::new(self);
 
         let send = WsSend {
             socket: socket.clone(),
@@ -177,4 +343,41 @@ mod tests {
         let result = futures::executor::block_on(fut);
         assert_eq!(result.unwrap_err(), WebSocketError::CreationError);
     }
+
+    #[test]
+    fn retry_delays_back_off() {
+        let policy = RetryPolicy {
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_secs(1),
+            max_attempts: 5,
+        };
+        let delays = (0..6)
+            .map(|attempt| policy.delay(attempt, 0.).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.delay(u32::MAX, 0.), policy.max_delay);
+        assert_eq!(policy.delay(1, 1.).as_millis(), 100);
+        assert_eq!(policy.delay(1, 7.).as_millis(), 100);
+    }
+
+    #[test]
+    fn send_buffer_keeps_order_and_bounds() {
+        let text = |s: &str| Message::Text(s.to_owned());
+        let mut buffer = SendBuffer::with_capacity(2);
+        assert_eq!(buffer.push(text("first")), Ok(()));
+        assert_eq!(buffer.push(text("second")), Ok(()));
+        assert_eq!(buffer.push(text("third")), Err(WebSocketError::SendError));
+
+        let result = buffer.flush(|_msg| Err(WebSocketError::SendError));
+        assert_eq!(result, Err(WebSocketError::SendError));
+        assert_eq!(buffer.queue.len(), 2);
+
+        let mut sent = vec![];
+        let result = buffer.flush(|msg| {
+            sent.push(msg);
+            Ok(())
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(sent, vec![text("first"), text("second")]);
+    }
 }
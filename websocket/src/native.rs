@@ -1,10 +1,47 @@
-use std::sync::mpsc;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use ws::{Handler, Handshake};
 
+/// Shared between a `ConnectionFuture` and its event loop thread so that a future dropped before
+/// resolving can stop the thread instead of leaving it to run forever.
+#[derive(Default)]
+struct Cancel {
+    canceled: bool,
+    event_loop: Option<ws::Sender>,
+}
+
+impl Cancel {
+    fn cancel(&mut self) {
+        self.canceled = true;
+        if let Some(event_loop) = self.event_loop.take() {
+            let _result = event_loop.shutdown();
+        }
+    }
+}
+
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct ConnectionFuture {
-    rx: Option<mpsc::Receiver<super::WebSocketEvent>>,
+    rx: Option<UnboundedReceiver<super::WebSocketEvent>>,
     channel: futures::channel::oneshot::Receiver<Result<ws::Sender, ws::Error>>,
+    liveness: Arc<Liveness>,
+    cancel: Arc<Mutex<Cancel>>,
+    /// Handed on to the socket when it's from `connect_with_retry`.
+    redial: Option<Redial>,
+}
+
+impl Drop for ConnectionFuture {
+    fn drop(&mut self) {
+        // `rx` is only taken once the connection has been handed off
+        if self.rx.is_some() {
+            if let Ok(mut cancel) = self.cancel.lock() {
+                cancel.cancel();
+            }
+        }
+    }
 }
 
 impl std::future::Future for ConnectionFuture {
@@ -19,8 +56,16 @@ impl std::future::Future for ConnectionFuture {
             .poll_unpin(cx)
             .map(|result| match result.unwrap() {
                 Ok(sender) => Ok(WebSocket {
-                    rx: self.rx.take().unwrap(),
-                    sender,
+                    connection: RefCell::new(Connection {
+                        rx: self.rx.take().unwrap(),
+                        sender,
+                        liveness: self.liveness.clone(),
+                        keepalive: None,
+                    }),
+                    redial: self.redial.take().map(RefCell::new),
+                    closed: Default::default(),
+                    send_buffer: Default::default(),
+                    keepalive: None,
                 }),
                 Err(_err) => Err(super::WebSocketError::CreationError),
             })
@@ -33,99 +78,892 @@ impl futures::future::FusedFuture for ConnectionFuture {
     }
 }
 
+/// Kept up to date by the event loop.
 #[derive(Debug)]
-pub struct WebSocket {
-    rx: mpsc::Receiver<super::WebSocketEvent>,
+struct Liveness {
+    /// From the handshake completing until the close.
+    open: AtomicBool,
+    /// Or when the connection opened, if there haven't been any pongs since.
+    last_pong: Mutex<Instant>,
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self {
+            open: AtomicBool::new(false),
+            last_pong: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl Liveness {
+    fn heard_from(&self) {
+        if let Ok(mut last_pong) = self.last_pong.lock() {
+            *last_pong = Instant::now();
+        }
+    }
+}
+
+/// Pings the other end every `interval` from a timer thread until dropped.
+#[derive(Debug)]
+struct Keepalive {
+    _stop: mpsc::Sender<()>,
+}
+
+impl Keepalive {
+    fn start(sender: ws::Sender, liveness: Arc<Liveness>, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if !liveness.open.load(Ordering::Acquire) {
+                    continue;
+                }
+                if sender.ping(Vec::new()).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { _stop: stop }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    rx: UnboundedReceiver<super::WebSocketEvent>,
     sender: ws::Sender,
+    liveness: Arc<Liveness>,
+    keepalive: Option<Keepalive>,
+}
+
+impl Connection {
+    fn is_open(&self) -> bool {
+        self.liveness.open.load(Ordering::Acquire)
+    }
+
+    fn keep_alive(&mut self, interval: Duration) {
+        let sender = self.sender.clone();
+        self.keepalive = Some(Keepalive::start(sender, self.liveness.clone(), interval));
+    }
+
+    /// Open but without a pong for three pings' worth of time.
+    fn is_stale(&self, interval: Duration) -> bool {
+        self.is_open()
+            && self
+                .liveness
+                .last_pong
+                .lock()
+                .is_ok_and(|last_pong| last_pong.elapsed() > interval * 3)
+    }
+
+    fn send(&self, msg: super::Message) -> Result<(), super::WebSocketError> {
+        match msg {
+            super::Message::Ping(data) => self.sender.ping(data),
+            super::Message::Pong(data) => self.sender.pong(data),
+            super::Message::Text(text) => self.sender.send(ws::Message::Text(text)),
+            super::Message::Binary(bin) => self.sender.send(ws::Message::Binary(bin)),
+        }
+        .map_err(|_err| super::WebSocketError::SendError)
+    }
+
+    /// `Ready(None)` once the event loop has stopped. Only registers for a wake up when given a
+    /// context.
+    fn next_event(&mut self, cx: Option<&mut Context>) -> Poll<Option<super::WebSocketEvent>> {
+        use futures::StreamExt;
+        match cx {
+            Some(cx) => self.rx.poll_next_unpin(cx),
+            None => match self.rx.try_recv() {
+                Ok(event) => Poll::Ready(Some(event)),
+                Err(futures::channel::mpsc::TryRecvError::Closed) => Poll::Ready(None),
+                Err(futures::channel::mpsc::TryRecvError::Empty) => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WebSocket {
+    /// Replaced whenever a lost connection is redialed.
+    connection: RefCell<Connection>,
+    redial: Option<RefCell<Redial>>,
+    /// Latched from the close event once it's been polled.
+    closed: std::cell::RefCell<Option<(super::CloseCode, String)>>,
+    send_buffer: RefCell<super::SendBuffer>,
+    /// Ping interval for every connection, redialed ones included.
+    keepalive: Option<Duration>,
 }
 
 impl WebSocket {
+    /// Goes through the proxy in `WS_PROXY` or `HTTPS_PROXY` if either is set and the host
+    /// isn't excluded by `NO_PROXY`.
     pub fn connect<S: AsRef<str>>(url: S) -> ConnectionFuture {
-        let (tx, rx) = mpsc::channel();
+        Self::connect_with_headers(url, Vec::new())
+    }
+
+    /// Like `connect` but adds `headers`, such as a `Cookie`, to the opening handshake.
+    pub fn connect_with_headers<S: AsRef<str>>(
+        url: S,
+        headers: super::Headers,
+    ) -> ConnectionFuture {
+        let proxy = proxy::from_env(url.as_ref());
+        Self::spawn_connect(url.as_ref(), proxy.as_deref(), headers, None).0
+    }
+
+    /// Like `connect` but the future only resolves once the handshake has finished, and with
+    /// `WebSocketError::CreationError` if that takes longer than `timeout`.
+    pub fn connect_with_timeout<S: AsRef<str>>(url: S, timeout: Duration) -> ConnectionFuture {
+        let proxy = proxy::from_env(url.as_ref());
+        Self::spawn_connect(url.as_ref(), proxy.as_deref(), Vec::new(), Some(timeout)).0
+    }
+
+    /// Like `connect` but the connection is redialed according to `policy` whenever it's lost
+    /// with a recoverable close code. Redialing happens as the socket is polled and stops as soon
+    /// as it's dropped. `WebSocketEvent::Reopened` is polled once a redial opens.
+    pub fn connect_with_retry<S: AsRef<str>>(
+        url: S,
+        policy: super::RetryPolicy,
+    ) -> ConnectionFuture {
+        Self::connect_with_headers_and_retry(url, Vec::new(), policy)
+    }
+
+    /// Like `connect_with_retry` but every dial, the first and each redial, adds `headers` to
+    /// the opening handshake.
+    pub fn connect_with_headers_and_retry<S: AsRef<str>>(
+        url: S,
+        headers: super::Headers,
+        policy: super::RetryPolicy,
+    ) -> ConnectionFuture {
+        let redial = Redial {
+            url: url.as_ref().to_owned(),
+            proxy: proxy::from_env(url.as_ref()),
+            headers,
+            policy,
+            attempt: 0,
+            state: RedialState::Connected,
+        };
+        let mut future = Self::spawn_connect(
+            &redial.url,
+            redial.proxy.as_deref(),
+            redial.headers.clone(),
+            None,
+        )
+        .0;
+        future.redial = Some(redial);
+        future
+    }
+
+    /// Tunnels the connection through an HTTP proxy with `CONNECT`. `proxy` may omit the
+    /// `http://` scheme.
+    pub fn connect_via_proxy<S: AsRef<str>, P: AsRef<str>>(url: S, proxy: P) -> ConnectionFuture {
+        Self::spawn_connect(url.as_ref(), Some(proxy.as_ref()), Vec::new(), None).0
+    }
+
+    /// Without a `timeout` the future resolves as soon as the connection is under way.
+    fn spawn_connect(
+        url: &str,
+        proxy: Option<&str>,
+        headers: super::Headers,
+        timeout: Option<Duration>,
+    ) -> (ConnectionFuture, std::thread::JoinHandle<()>) {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
         let (sx, trx) = mpsc::sync_channel(1);
-        std::thread::spawn({
+        let liveness = Arc::new(Liveness::default());
+        let cancel = Arc::new(Mutex::new(Cancel::default()));
+        let event_loop = std::thread::spawn({
             let sx = sx.clone();
-            let url = url.as_ref().to_owned();
+            let url = url.to_owned();
+            let proxy = proxy.map(str::to_owned);
+            let liveness = liveness.clone();
+            let cancel = cancel.clone();
             move || {
-                let result = ws::connect(url.as_str(), {
+                let (connect_url, request_url) = match resolve(&url, proxy.as_deref()) {
+                    Ok(urls) => urls,
+                    Err(err) => {
+                        let _result = sx.send(Err(*err));
+                        return;
+                    }
+                };
+
+                let factory = {
                     let sx = sx.clone();
                     move |sender| {
-                        sx.send(Ok(sender))
-                            .expect("could not send connection to client.");
+                        let notify_open = match timeout {
+                            Some(_) => Some((sx.clone(), sender)),
+                            None => {
+                                let _result = sx.send(Ok(sender));
+                                None
+                            }
+                        };
                         MyHandler {
-                            tx: mpsc::Sender::clone(&tx),
+                            tx: tx.clone(),
+                            liveness: liveness.clone(),
+                            request_url: request_url.clone(),
+                            headers: headers.clone(),
+                            notify_open,
                         }
                     }
-                });
-                if let Err(err) = result {
-                    sx.send(Err(err)).expect("could not send error to client.");
+                };
+                let mut socket = match ws::WebSocket::new(factory) {
+                    Ok(socket) => socket,
+                    Err(err) => {
+                        let _result = sx.send(Err(err));
+                        return;
+                    }
+                };
+
+                {
+                    let mut cancel = cancel.lock().expect("cancel lock poisoned");
+                    if cancel.canceled {
+                        return;
+                    }
+                    cancel.event_loop = Some(socket.broadcaster());
+                }
+
+                if let Err(err) = socket.connect(connect_url) {
+                    let _result = sx.send(Err(err));
+                    return;
+                }
+                if let Err(err) = socket.run() {
+                    let _result = sx.send(Err(err));
                 }
             }
         });
 
         let (notice_send, notice_recv) = futures::channel::oneshot::channel();
-        std::thread::spawn(move || match trx.recv() {
-            Ok(result) => notice_send.send(result),
-            Err(err) => notice_send.send(Err(ws::Error::new(
-                ws::ErrorKind::Internal,
-                err.to_string(),
-            ))),
+        std::thread::spawn({
+            let cancel = cancel.clone();
+            move || {
+                let notice = match timeout {
+                    Some(timeout) => trx.recv_timeout(timeout).map_err(|err| {
+                        if let Ok(mut cancel) = cancel.lock() {
+                            cancel.cancel();
+                        }
+                        err.to_string()
+                    }),
+                    None => trx.recv().map_err(|err| err.to_string()),
+                };
+                let notice = match notice {
+                    Ok(notice) => notice,
+                    Err(err) => Err(ws::Error::new(ws::ErrorKind::Internal, err)),
+                };
+                let _result = notice_send.send(notice);
+            }
         });
 
-        ConnectionFuture {
+        let future = ConnectionFuture {
             rx: Some(rx),
             channel: notice_recv,
-        }
+            liveness,
+            cancel,
+            redial: None,
+        };
+        (future, event_loop)
     }
 
     pub fn poll(&self) -> Option<super::WebSocketEvent> {
-        self.rx.try_recv().ok()
+        let poll = self.latch_close(self.next_event(None));
+        self.flush_sends();
+        match poll {
+            Poll::Ready(event) => event,
+            Poll::Pending => None,
+        }
     }
 
+    /// Like `poll` but `cx` is woken once there's an event. `Ready(None)` when there won't be
+    /// any more.
+    pub fn poll_event(&self, cx: &mut Context) -> Poll<Option<super::WebSocketEvent>> {
+        let poll = self.latch_close(self.next_event(Some(cx)));
+        self.flush_sends();
+        poll
+    }
+
+    /// Why the socket closed, once that's been polled. Redialed closes don't count.
+    pub fn close_code(&self) -> Option<super::CloseCode> {
+        self.closed.borrow().as_ref().map(|(code, _reason)| *code)
+    }
+
+    /// What the other end said about closing, alongside `close_code`. Often empty.
+    pub fn close_reason(&self) -> Option<String> {
+        self.closed
+            .borrow()
+            .as_ref()
+            .map(|(_code, reason)| reason.clone())
+    }
+
+    fn latch_close(
+        &self,
+        poll: Poll<Option<super::WebSocketEvent>>,
+    ) -> Poll<Option<super::WebSocketEvent>> {
+        if let Poll::Ready(Some(super::WebSocketEvent::Close { code, reason })) = &poll {
+            *self.closed.borrow_mut() = Some((*code, reason.clone()));
+        }
+        poll
+    }
+
+    /// While redialing, `cx` is woken once the next attempt is due or the dial it's waiting on
+    /// resolves.
+    fn next_event(&self, mut cx: Option<&mut Context>) -> Poll<Option<super::WebSocketEvent>> {
+        let mut redial = match &self.redial {
+            Some(redial) => redial.borrow_mut(),
+            None => return self.connection_event(cx),
+        };
+        if let RedialState::Connected = redial.state {
+            let event = match self.connection_event(cx.as_deref_mut()) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(event)) => event,
+                // the event loop gives up on connections that never opened without closing them
+                Poll::Ready(None) => super::WebSocketEvent::Close {
+                    code: super::CloseCode::Abnormal,
+                    reason: String::new(),
+                },
+            };
+            match event {
+                super::WebSocketEvent::Open if redial.attempt > 0 => {
+                    redial.attempt = 0;
+                    Poll::Ready(Some(super::WebSocketEvent::Reopened))
+                }
+                super::WebSocketEvent::Close { code, .. } if redial.lost(code) => keep_going(cx),
+                event => Poll::Ready(Some(event)),
+            }
+        } else {
+            match redial.poll(cx.as_deref_mut()) {
+                Some(Ok(mut connection)) => {
+                    if let Some(interval) = self.keepalive {
+                        connection.keep_alive(interval);
+                    }
+                    *self.connection.borrow_mut() = connection;
+                    keep_going(cx)
+                }
+                Some(Err(code)) => Poll::Ready(Some(super::WebSocketEvent::Close {
+                    code,
+                    reason: String::new(),
+                })),
+                None if matches!(redial.state, RedialState::Closed) => Poll::Ready(None),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    /// A connection that's stopped answering pings is cut off and reported as closed abnormally.
+    fn connection_event(&self, cx: Option<&mut Context>) -> Poll<Option<super::WebSocketEvent>> {
+        let mut connection = self.connection.borrow_mut();
+        if let Some(interval) = self.keepalive {
+            if connection.is_stale(interval) {
+                log::warn!("no pong in {:?}, dropping the connection", interval * 3);
+                connection.liveness.open.store(false, Ordering::Release);
+                let _result = connection.sender.shutdown();
+                return Poll::Ready(Some(super::WebSocketEvent::Close {
+                    code: super::CloseCode::Abnormal,
+                    reason: String::new(),
+                }));
+            }
+        }
+        connection.next_event(cx)
+    }
+
+    /// Pings the other end every `interval`. A connection that goes three intervals without a
+    /// pong back is closed abnormally, which is redialed like any other lost connection.
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.connection.get_mut().keep_alive(interval);
+        self.keepalive = Some(interval);
+        self
+    }
+
+    /// Whether the connection has stopped answering pings. Always false without a keepalive.
+    pub fn is_stale(&self) -> bool {
+        self.keepalive
+            .is_some_and(|interval| self.connection.borrow().is_stale(interval))
+    }
+
+    /// Until the socket is open, `msg` waits in a buffer with any others sent before it. Fails
+    /// once the buffer is full.
     pub fn send(&self, msg: super::Message) -> Result<(), super::WebSocketError> {
-        self.sender
-            .send(msg)
-            .map_err(|_err| super::WebSocketError::SendError)
+        let connection = self.connection.borrow();
+        let mut send_buffer = self.send_buffer.borrow_mut();
+        if connection.is_open() {
+            send_buffer.flush(|msg| connection.send(msg))?;
+            connection.send(msg)
+        } else if self.closed.borrow().is_some() {
+            Err(super::WebSocketError::SendError)
+        } else {
+            send_buffer.push(msg)
+        }
+    }
+
+    /// How many messages sent before the socket opens are held on to. 64 by default.
+    pub fn with_send_buffer(self, capacity: usize) -> Self {
+        Self {
+            send_buffer: RefCell::new(super::SendBuffer::with_capacity(capacity)),
+            ..self
+        }
+    }
+
+    /// Passes on whatever was sent while the socket wasn't open, now that it might be.
+    fn flush_sends(&self) {
+        let connection = self.connection.borrow();
+        if connection.is_open() {
+            if let Err(err) = self
+                .send_buffer
+                .borrow_mut()
+                .flush(|msg| connection.send(msg))
+            {
+                log::warn!("couldn't send buffered messages: {}", err);
+            }
+        }
+    }
+}
+
+/// Has `cx` polled again straight away, for when there's more to do right now, like reading a
+/// fresh connection's events.
+fn keep_going<T>(cx: Option<&mut Context>) -> Poll<T> {
+    if let Some(cx) = cx {
+        cx.waker().wake_by_ref();
+    }
+    Poll::Pending
+}
+
+/// Everything a socket from `connect_with_retry` needs to dial its connection again.
+struct Redial {
+    url: String,
+    proxy: Option<String>,
+    headers: super::Headers,
+    policy: super::RetryPolicy,
+    /// Made since the connection was last open.
+    attempt: u32,
+    state: RedialState,
+}
+
+enum RedialState {
+    /// Or at least dialed and not known to have failed yet.
+    Connected,
+    Waiting {
+        until: std::time::Instant,
+        code: super::CloseCode,
+        alarm: Alarm,
+    },
+    /// Dropping the future stops the attempt.
+    Dialing {
+        future: Box<ConnectionFuture>,
+        code: super::CloseCode,
+    },
+    /// Out of attempts, or lost for a reason that isn't worth redialing over.
+    Closed,
+}
+
+impl Redial {
+    /// Schedules the next attempt after the connection closed with `code`. Returns whether
+    /// there'll be one.
+    fn lost(&mut self, code: super::CloseCode) -> bool {
+        if code.is_recoverable() && self.attempt < self.policy.max_attempts {
+            let delay = self.policy.delay(self.attempt, jitter());
+            log::debug!("redialing {} in {:?}", self.url, delay);
+            self.attempt += 1;
+            let until = std::time::Instant::now() + delay;
+            self.state = RedialState::Waiting {
+                until,
+                code,
+                alarm: Alarm::at(until),
+            };
+            true
+        } else {
+            self.state = RedialState::Closed;
+            false
+        }
+    }
+
+    /// Moves a redial along, with `cx` woken once there's more to do. Resolves to the new
+    /// connection, or to the close code that started it all once there's no point trying again.
+    fn poll(
+        &mut self,
+        mut cx: Option<&mut Context>,
+    ) -> Option<Result<Connection, super::CloseCode>> {
+        use futures::FutureExt;
+        loop {
+            match &mut self.state {
+                RedialState::Connected | RedialState::Closed => return None,
+                RedialState::Waiting { until, code, alarm } => {
+                    if std::time::Instant::now() < *until {
+                        if let Some(cx) = cx {
+                            alarm.wake(cx);
+                        }
+                        return None;
+                    }
+                    let code = *code;
+                    let (future, _event_loop) = WebSocket::spawn_connect(
+                        &self.url,
+                        self.proxy.as_deref(),
+                        self.headers.clone(),
+                        None,
+                    );
+                    self.state = RedialState::Dialing {
+                        future: Box::new(future),
+                        code,
+                    };
+                }
+                RedialState::Dialing { future, code } => {
+                    let code = *code;
+                    let result = match cx.as_deref_mut() {
+                        Some(cx) => match future.poll_unpin(cx) {
+                            Poll::Ready(result) => result,
+                            Poll::Pending => return None,
+                        },
+                        None => (&mut **future).now_or_never()?,
+                    };
+                    match result {
+                        Ok(socket) => {
+                            self.state = RedialState::Connected;
+                            return Some(Ok(socket.connection.into_inner()));
+                        }
+                        // round again to wait for the next attempt
+                        Err(_err) if self.lost(code) => {}
+                        Err(_err) => return Some(Err(code)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Redial {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Redial")
+            .field("url", &self.url)
+            .field("policy", &self.policy)
+            .field("attempt", &self.attempt)
+            .finish()
+    }
+}
+
+/// Wakes whichever task last waited on it once `until` comes, from a thread of its own, so that
+/// a redial's delay is waited out rather than polled through.
+struct Alarm(Arc<Mutex<AlarmState>>);
+
+#[derive(Default)]
+struct AlarmState {
+    rung: bool,
+    waker: Option<std::task::Waker>,
+}
+
+impl Alarm {
+    fn at(until: Instant) -> Self {
+        let state = Arc::new(Mutex::new(AlarmState::default()));
+        std::thread::spawn({
+            let state = state.clone();
+            move || {
+                std::thread::sleep(until.saturating_duration_since(Instant::now()));
+                let waker = state.lock().ok().and_then(|mut state| {
+                    state.rung = true;
+                    state.waker.take()
+                });
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+        });
+        Self(state)
+    }
+
+    /// `cx` is woken straight away if the alarm has already gone off.
+    fn wake(&self, cx: &mut Context) {
+        match self.0.lock() {
+            Ok(mut state) if !state.rung => state.waker = Some(cx.waker().clone()),
+            _ => cx.waker().wake_by_ref(),
+        }
+    }
+}
+
+/// Somewhere between zero and one. It only has to keep clients that dropped together from
+/// redialing in step.
+fn jitter() -> f32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0., |now| now.subsec_nanos() as f32 / 1e9)
+}
+
+/// Returns the URL to open the socket against and, when tunneling, the URL the handshake is
+/// really for. The error is boxed since `ws::Error` is large.
+fn resolve(url: &str, proxy: Option<&str>) -> Result<(url::Url, Option<url::Url>), Box<ws::Error>> {
+    let parse = |url: &str| {
+        url::Url::parse(url).map_err(|err| {
+            Box::new(ws::Error::new(
+                ws::ErrorKind::Internal,
+                format!("Unable to parse {} as url due to {:?}", url, err),
+            ))
+        })
+    };
+    let target = parse(url)?;
+    match proxy {
+        None => Ok((target, None)),
+        Some(proxy) => {
+            let proxy = if proxy.contains("://") {
+                parse(proxy)?
+            } else {
+                parse(&format!("http://{}", proxy))?
+            };
+            let relay = proxy::tunnel(&proxy, &target).map_err(|err| Box::new(err.into()))?;
+            let mut local = target.clone();
+            local
+                .set_ip_host(relay.ip())
+                .and_then(|()| local.set_port(Some(relay.port())))
+                .map_err(|()| {
+                    Box::new(ws::Error::new(
+                        ws::ErrorKind::Internal,
+                        "Unable to address proxy relay",
+                    ))
+                })?;
+            Ok((local, Some(target)))
+        }
+    }
+}
+
+mod proxy {
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    const ENV_VARS: [&str; 4] = ["WS_PROXY", "ws_proxy", "HTTPS_PROXY", "https_proxy"];
+    const MAX_RESPONSE_HEAD: usize = 8 * 1024;
+    /// How long the relay waits for the websocket to connect to it.
+    const ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn var(key: &str) -> Option<String> {
+        std::env::var(key)
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+    }
+
+    pub fn from_env(url: &str) -> Option<String> {
+        let target = url::Url::parse(url).ok()?;
+        let no_proxy = var("NO_PROXY").or_else(|| var("no_proxy"));
+        if let (Some(host), Some(no_proxy)) = (target.host_str(), no_proxy) {
+            if is_excluded(host, &no_proxy) {
+                return None;
+            }
+        }
+        ENV_VARS.iter().find_map(|key| var(key))
+    }
+
+    pub fn is_excluded(host: &str, no_proxy: &str) -> bool {
+        no_proxy.split(',').map(str::trim).any(|entry| {
+            let entry = entry.trim_start_matches('.');
+            entry == "*"
+                || (!entry.is_empty() && (host == entry || host.ends_with(&format!(".{}", entry))))
+        })
+    }
+
+    /// Opens a `CONNECT` tunnel to `target` and exposes it on a loopback port for the event
+    /// loop to connect to, since `ws` can only dial addresses itself.
+    pub fn tunnel(proxy: &url::Url, target: &url::Url) -> std::io::Result<SocketAddr> {
+        let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+        let proxy_host = proxy
+            .host_str()
+            .ok_or_else(|| invalid("proxy has no host"))?;
+        let proxy_port = proxy.port_or_known_default().unwrap_or(80);
+        let host = target
+            .host_str()
+            .ok_or_else(|| invalid("url has no host"))?;
+        let port = match target.port() {
+            Some(port) => port,
+            None if target.scheme() == "wss" => 443,
+            None => 80,
+        };
+
+        let mut stream = TcpStream::connect((proxy_host, proxy_port))?;
+        write!(
+            stream,
+            "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n\r\n",
+            host = host,
+            port = port
+        )?;
+        let status = read_response_head(&mut stream)?;
+        let accepted = status
+            .split_whitespace()
+            .nth(1)
+            .map(|code| code.starts_with('2'))
+            .unwrap_or(false);
+        if !accepted {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionRefused,
+                format!("proxy refused tunnel: {}", status),
+            ));
+        }
+
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        std::thread::spawn(move || {
+            let deadline = Instant::now() + ACCEPT_TIMEOUT;
+            while Instant::now() < deadline {
+                match listener.accept() {
+                    Ok((local, _addr)) => {
+                        if local.set_nonblocking(false).is_ok() {
+                            pipe(local, stream);
+                        }
+                        return;
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(err) => {
+                        log::error!("proxy relay accept error: {}", err);
+                        return;
+                    }
+                }
+            }
+            log::warn!("proxy relay timed out waiting for a connection");
+        });
+        Ok(addr)
+    }
+
+    /// Reads up to the blank line ending the response head and returns the status line.
+    fn read_response_head(stream: &mut TcpStream) -> std::io::Result<String> {
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_RESPONSE_HEAD {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "proxy response too large",
+                ));
+            }
+            stream.read_exact(&mut byte)?;
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        Ok(head.lines().next().unwrap_or_default().to_owned())
+    }
+
+    pub fn pipe(a: TcpStream, b: TcpStream) {
+        let (mut a_read, mut b_read) = match (a.try_clone(), b.try_clone()) {
+            (Ok(a_read), Ok(b_read)) => (a_read, b_read),
+            _ => return,
+        };
+        let upstream = std::thread::spawn(move || {
+            let mut b = b;
+            let _result = std::io::copy(&mut a_read, &mut b);
+            let _result = b.shutdown(Shutdown::Both);
+        });
+        let mut a = a;
+        let _result = std::io::copy(&mut b_read, &mut a);
+        let _result = a.shutdown(Shutdown::Both);
+        let _result = upstream.join();
     }
 }
 
 struct MyHandler {
-    tx: mpsc::Sender<super::WebSocketEvent>,
+    tx: UnboundedSender<super::WebSocketEvent>,
+    liveness: Arc<Liveness>,
+    /// Set when tunneling so the handshake and TLS name the real host rather than the relay.
+    request_url: Option<url::Url>,
+    headers: super::Headers,
+    /// Set when the connect future is waiting for the handshake to finish, and taken once it has
+    /// or the connection failed first.
+    notify_open: Option<(mpsc::SyncSender<Result<ws::Sender, ws::Error>>, ws::Sender)>,
+}
+
+impl MyHandler {
+    fn notify_failed(&mut self, err: ws::Error) {
+        if let Some((notify, _sender)) = self.notify_open.take() {
+            let _result = notify.send(Err(err));
+        }
+    }
 }
 
 impl Handler for MyHandler {
+    fn build_request(&mut self, url: &url::Url) -> ws::Result<ws::Request> {
+        let mut request = ws::Request::from_url(self.request_url.as_ref().unwrap_or(url))?;
+        request.headers_mut().extend(self.headers.iter().cloned());
+        Ok(request)
+    }
+
+    #[cfg(feature = "ssl")]
+    fn upgrade_ssl_client(
+        &mut self,
+        stream: ws::util::TcpStream,
+        url: &url::Url,
+    ) -> ws::Result<openssl::ssl::SslStream<ws::util::TcpStream>> {
+        let url = self.request_url.as_ref().unwrap_or(url);
+        let domain = url.domain().ok_or_else(|| {
+            ws::Error::new(
+                ws::ErrorKind::Protocol,
+                format!("Unable to parse domain from {}. Needed for SSL.", url),
+            )
+        })?;
+        let connector = openssl::ssl::SslConnector::builder(openssl::ssl::SslMethod::tls())
+            .map_err(|err| {
+                ws::Error::new(
+                    ws::ErrorKind::Internal,
+                    format!("Failed to upgrade client to SSL: {}", err),
+                )
+            })?
+            .build();
+        connector.connect(domain, stream).map_err(ws::Error::from)
+    }
+
     fn on_open(&mut self, _shake: Handshake) -> ws::Result<()> {
+        self.liveness.heard_from();
+        self.liveness.open.store(true, Ordering::Release);
         self.tx
-            .send(super::WebSocketEvent::Open)
-            .map_err(|err| ws::Error::new(ws::ErrorKind::Custom(Box::new(err)), ""))
+            .unbounded_send(super::WebSocketEvent::Open)
+            .map_err(|err| ws::Error::new(ws::ErrorKind::Custom(Box::new(err)), ""))?;
+        // after the open event so that it's there to be polled as soon as the future resolves
+        if let Some((notify, sender)) = self.notify_open.take() {
+            let _result = notify.send(Ok(sender));
+        }
+        Ok(())
     }
 
     fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
         self.tx
-            .send(super::WebSocketEvent::Message(msg.into()))
+            .unbounded_send(super::WebSocketEvent::Message(msg.into()))
             .map_err(|err| ws::Error::new(ws::ErrorKind::Custom(Box::new(err)), ""))
     }
 
-    fn on_close(&mut self, code: ws::CloseCode, _reason: &str) {
-        let _result = self.tx.send(super::WebSocketEvent::Close(code.into()));
+    fn on_frame(&mut self, frame: ws::Frame) -> ws::Result<Option<ws::Frame>> {
+        // same as the default, which only checks for reserved bits
+        if frame.has_rsv1() || frame.has_rsv2() || frame.has_rsv3() {
+            return Err(ws::Error::new(
+                ws::ErrorKind::Protocol,
+                "Encountered frame with reserved bits set.",
+            ));
+        }
+        // passed on as well as handled as usual, which answers pings
+        let control = match frame.opcode() {
+            ws::OpCode::Ping => Some(super::Message::Ping(frame.payload().clone())),
+            ws::OpCode::Pong => {
+                self.liveness.heard_from();
+                Some(super::Message::Pong(frame.payload().clone()))
+            }
+            _ => None,
+        };
+        if let Some(msg) = control {
+            self.tx
+                .unbounded_send(super::WebSocketEvent::Message(msg))
+                .map_err(|err| ws::Error::new(ws::ErrorKind::Custom(Box::new(err)), ""))?;
+        }
+        Ok(Some(frame))
     }
 
-    fn on_error(&mut self, _err: ws::Error) {
-        let _result = self.tx.send(super::WebSocketEvent::Error(
-            super::WebSocketError::ReceiveError,
+    fn on_close(&mut self, code: ws::CloseCode, reason: &str) {
+        self.liveness.open.store(false, Ordering::Release);
+        self.notify_failed(ws::Error::new(
+            ws::ErrorKind::Internal,
+            "closed before the handshake finished",
         ));
+        let _result = self.tx.unbounded_send(super::WebSocketEvent::Close {
+            code: code.into(),
+            reason: reason.to_owned(),
+        });
     }
-}
 
-impl Into<ws::Message> for super::Message {
-    fn into(self) -> ws::Message {
-        match self {
-            super::Message::Text(text) => ws::Message::Text(text),
-            super::Message::Binary(bin) => ws::Message::Binary(bin),
-        }
+    fn on_error(&mut self, err: ws::Error) {
+        self.notify_failed(err);
+        let _result = self.tx.unbounded_send(super::WebSocketEvent::Error(
+            super::WebSocketError::ReceiveError,
+        ));
     }
 }
 
+/// Only data frames come through as a `ws::Message`. Control frames are picked up in `on_frame`.
 impl From<ws::Message> for super::Message {
     fn from(msg: ws::Message) -> Self {
         match msg {
@@ -157,3 +995,578 @@ impl From<ws::CloseCode> for super::CloseCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_connect_stops_event_loop() {
+        // accepts the TCP connection but never completes the websocket handshake
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let (future, event_loop) = WebSocket::spawn_connect(&url, None, Vec::new(), None);
+        drop(future);
+
+        let (sx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _result = event_loop.join();
+            let _result = sx.send(());
+        });
+        assert!(
+            rx.recv_timeout(std::time::Duration::from_secs(5)).is_ok(),
+            "event loop thread is still running"
+        );
+    }
+
+    #[test]
+    fn handshakes_that_never_finish_time_out() {
+        use super::super::{WebSocketError, WebSocketEvent};
+
+        // accepts the TCP connection but never answers the handshake
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let started = std::time::Instant::now();
+        let result = futures::executor::block_on(WebSocket::connect_with_timeout(
+            &url,
+            std::time::Duration::from_millis(100),
+        ));
+        assert_eq!(result.err(), Some(WebSocketError::CreationError));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        struct Silent;
+        impl Handler for Silent {}
+        let server = ws::WebSocket::new(|_sender| Silent)
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let url = format!("ws://{}/socket", server.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+        let ws = futures::executor::block_on(WebSocket::connect_with_timeout(
+            &url,
+            std::time::Duration::from_secs(5),
+        ))
+        .unwrap();
+        assert_eq!(ws.poll(), Some(WebSocketEvent::Open));
+    }
+
+    #[test]
+    fn connect_through_proxy() {
+        use std::io::{BufRead, BufReader, Write};
+
+        struct Echo(ws::Sender);
+        impl Handler for Echo {
+            fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+                self.0.send(msg)
+            }
+        }
+
+        let server = ws::WebSocket::new(Echo)
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let server_addr = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        // a bare-bones CONNECT proxy that remembers what it was asked for
+        let proxy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let (requested_sx, requested_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (client, _addr) = proxy.accept().unwrap();
+            let mut reader = BufReader::new(client.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut line = String::new();
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+            let target = request_line.split_whitespace().nth(1).unwrap().to_owned();
+            requested_sx.send(request_line).unwrap();
+
+            let upstream = std::net::TcpStream::connect(target).unwrap();
+            let mut client = client;
+            client
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .unwrap();
+            proxy::pipe(client, upstream);
+        });
+
+        let url = format!("ws://{}/socket", server_addr);
+        let ws =
+            futures::executor::block_on(WebSocket::connect_via_proxy(&url, proxy_addr.to_string()))
+                .unwrap();
+
+        let request_line = requested_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert!(request_line.starts_with(&format!("CONNECT {} ", server_addr)));
+
+        let next_event = || {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            loop {
+                if let Some(event) = ws.poll() {
+                    return event;
+                }
+                assert!(std::time::Instant::now() < deadline, "timed out");
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+        };
+        assert_eq!(next_event(), super::super::WebSocketEvent::Open);
+        ws.send(super::super::Message::Text("hello".to_owned()))
+            .unwrap();
+        assert_eq!(
+            next_event(),
+            super::super::WebSocketEvent::Message(super::super::Message::Text("hello".to_owned()))
+        );
+    }
+
+    #[test]
+    fn handshake_carries_headers() {
+        struct Recorder(mpsc::Sender<Option<Vec<u8>>>);
+        impl Handler for Recorder {
+            fn on_request(&mut self, req: &ws::Request) -> ws::Result<ws::Response> {
+                let _result = self.0.send(req.header("cookie").cloned());
+                ws::Response::from_request(req)
+            }
+        }
+
+        let (cookie_sx, cookie_rx) = mpsc::channel();
+        let server = ws::WebSocket::new(move |_sender| Recorder(cookie_sx.clone()))
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let server_addr = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        let url = format!("ws://{}/socket", server_addr);
+        let headers = vec![("Cookie".to_owned(), b"game-player-id=1".to_vec())];
+        let _ws =
+            futures::executor::block_on(WebSocket::connect_with_headers(&url, headers)).unwrap();
+
+        let cookie = cookie_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(cookie.as_deref(), Some(&b"game-player-id=1"[..]));
+    }
+
+    /// Forwards the first `limit` connections to `upstream` and refuses the rest. Each
+    /// client-side stream is handed over first so a test can cut it without a closing handshake.
+    fn relay(
+        upstream: std::net::SocketAddr,
+        limit: usize,
+    ) -> (String, mpsc::Receiver<std::net::TcpStream>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/socket", listener.local_addr().unwrap());
+        let (accepted_sx, accepted_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for client in listener.incoming().take(limit) {
+                let client = client.unwrap();
+                if accepted_sx.send(client.try_clone().unwrap()).is_err() {
+                    return;
+                }
+                if let Ok(upstream) = std::net::TcpStream::connect(upstream) {
+                    std::thread::spawn(move || proxy::pipe(client, upstream));
+                }
+            }
+        });
+        (url, accepted_rx)
+    }
+
+    fn next_event(ws: &WebSocket) -> super::super::WebSocketEvent {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            if let Some(event) = ws.poll() {
+                return event;
+            }
+            assert!(std::time::Instant::now() < deadline, "timed out");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    struct Echo(ws::Sender);
+    impl Handler for Echo {
+        fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+            self.0.send(msg)
+        }
+    }
+
+    #[test]
+    fn lost_connections_are_redialed() {
+        use super::super::{Message, RetryPolicy, WebSocketEvent};
+
+        let server = ws::WebSocket::new(Echo)
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let (url, accepted) = relay(server.local_addr().unwrap(), usize::MAX);
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        let policy = RetryPolicy {
+            base_delay: std::time::Duration::from_millis(10),
+            max_delay: std::time::Duration::from_millis(50),
+            max_attempts: 3,
+        };
+        let ws = futures::executor::block_on(WebSocket::connect_with_retry(&url, policy)).unwrap();
+        assert_eq!(next_event(&ws), WebSocketEvent::Open);
+
+        let first = accepted
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        first.shutdown(std::net::Shutdown::Both).unwrap();
+        assert_eq!(next_event(&ws), WebSocketEvent::Reopened);
+
+        ws.send(Message::Text("hello".to_owned())).unwrap();
+        assert_eq!(
+            next_event(&ws),
+            WebSocketEvent::Message(Message::Text("hello".to_owned()))
+        );
+    }
+
+    #[test]
+    fn redials_carry_headers() {
+        use super::super::{RetryPolicy, WebSocketEvent};
+
+        struct Recorder(mpsc::Sender<Option<Vec<u8>>>);
+        impl Handler for Recorder {
+            fn on_request(&mut self, req: &ws::Request) -> ws::Result<ws::Response> {
+                let _result = self.0.send(req.header("cookie").cloned());
+                ws::Response::from_request(req)
+            }
+        }
+
+        let (cookie_sx, cookie_rx) = mpsc::channel();
+        let server = ws::WebSocket::new(move |_sender| Recorder(cookie_sx.clone()))
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let (url, accepted) = relay(server.local_addr().unwrap(), usize::MAX);
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        let policy = RetryPolicy {
+            base_delay: std::time::Duration::from_millis(10),
+            max_delay: std::time::Duration::from_millis(50),
+            max_attempts: 3,
+        };
+        let headers = vec![("Cookie".to_owned(), b"game-player-id=1".to_vec())];
+        let ws = futures::executor::block_on(WebSocket::connect_with_headers_and_retry(
+            &url, headers, policy,
+        ))
+        .unwrap();
+        assert_eq!(next_event(&ws), WebSocketEvent::Open);
+
+        accepted
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap()
+            .shutdown(std::net::Shutdown::Both)
+            .unwrap();
+        assert_eq!(next_event(&ws), WebSocketEvent::Reopened);
+
+        for _ in 0..2 {
+            let cookie = cookie_rx
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .unwrap();
+            assert_eq!(cookie.as_deref(), Some(&b"game-player-id=1"[..]));
+        }
+    }
+
+    #[test]
+    fn waiting_to_redial_does_not_spin() {
+        use super::super::{RetryPolicy, WebSocketEvent};
+
+        let server = ws::WebSocket::new(Echo)
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let (url, accepted) = relay(server.local_addr().unwrap(), usize::MAX);
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        let policy = RetryPolicy {
+            base_delay: std::time::Duration::from_millis(500),
+            max_delay: std::time::Duration::from_millis(500),
+            max_attempts: 3,
+        };
+        let ws = futures::executor::block_on(WebSocket::connect_with_retry(&url, policy)).unwrap();
+        assert_eq!(next_event(&ws), WebSocketEvent::Open);
+
+        accepted
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap()
+            .shutdown(std::net::Shutdown::Both)
+            .unwrap();
+        let mut polls = 0;
+        let event = futures::executor::block_on(futures::future::poll_fn(|cx| {
+            polls += 1;
+            ws.poll_event(cx)
+        }));
+        assert_eq!(event, Some(WebSocketEvent::Reopened));
+        assert!(polls < 20, "polled {} times while waiting", polls);
+    }
+
+    #[test]
+    fn receiving_tells_empty_from_closed() {
+        use super::super::{CloseCode, Message, WebSocketError};
+
+        let server = ws::WebSocket::new(Echo)
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let (url, accepted) = relay(server.local_addr().unwrap(), usize::MAX);
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        let ws = futures::executor::block_on(WebSocket::connect(&url)).unwrap();
+        let (send, recv) = ws.into_channels();
+        assert_eq!(recv.try_recv(), Err(WebSocketError::Empty));
+        send.send(Message::Text("hello".to_owned())).unwrap();
+        assert_eq!(
+            futures::executor::block_on(recv.recv()),
+            Ok(Message::Text("hello".to_owned()))
+        );
+
+        let client = accepted
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        client.shutdown(std::net::Shutdown::Both).unwrap();
+        assert_eq!(
+            futures::executor::block_on(recv.recv()),
+            Err(WebSocketError::Closed(CloseCode::Abnormal))
+        );
+        assert_eq!(
+            recv.try_recv(),
+            Err(WebSocketError::Closed(CloseCode::Abnormal))
+        );
+    }
+
+    #[test]
+    fn sends_wait_for_the_socket_to_open() {
+        use super::super::{Message, WebSocketError};
+
+        // accepts the TCP connection but never completes the websocket handshake
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let ws = futures::executor::block_on(WebSocket::connect(&url))
+            .unwrap()
+            .with_send_buffer(1);
+        assert_eq!(ws.send(Message::Text("first".to_owned())), Ok(()));
+        assert_eq!(
+            ws.send(Message::Text("second".to_owned())),
+            Err(WebSocketError::SendError)
+        );
+    }
+
+    #[test]
+    fn close_reasons_come_through() {
+        use super::super::{CloseCode, WebSocketEvent};
+
+        struct Kick(ws::Sender);
+        impl Handler for Kick {
+            fn on_open(&mut self, _shake: Handshake) -> ws::Result<()> {
+                self.0.close_with_reason(ws::CloseCode::Policy, "room full")
+            }
+        }
+        let server = ws::WebSocket::new(Kick)
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let url = format!("ws://{}/socket", server.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        let ws = futures::executor::block_on(WebSocket::connect(&url)).unwrap();
+        assert_eq!(next_event(&ws), WebSocketEvent::Open);
+        assert_eq!(
+            next_event(&ws),
+            WebSocketEvent::Close {
+                code: CloseCode::Policy,
+                reason: "room full".to_owned()
+            }
+        );
+        assert_eq!(ws.close_code(), Some(CloseCode::Policy));
+        assert_eq!(ws.close_reason().as_deref(), Some("room full"));
+    }
+
+    #[test]
+    fn control_frames_come_through() {
+        use super::super::{Message, WebSocketEvent};
+        use futures::StreamExt;
+
+        struct PingOnOpen(ws::Sender);
+        impl Handler for PingOnOpen {
+            fn on_open(&mut self, _shake: Handshake) -> ws::Result<()> {
+                self.0.ping(b"server".to_vec())
+            }
+        }
+        let server = ws::WebSocket::new(PingOnOpen)
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let url = format!("ws://{}/socket", server.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        let mut ws = futures::executor::block_on(WebSocket::connect(&url)).unwrap();
+        assert_eq!(next_event(&ws), WebSocketEvent::Open);
+        assert_eq!(
+            next_event(&ws),
+            WebSocketEvent::Message(Message::Ping(b"server".to_vec()))
+        );
+
+        // answered by the server on its own, and the stream carries on past it
+        ws.send(Message::Ping(b"client".to_vec())).unwrap();
+        assert_eq!(
+            futures::executor::block_on(ws.next()),
+            Some(WebSocketEvent::Message(Message::Pong(b"client".to_vec())))
+        );
+    }
+
+    #[test]
+    fn unanswered_pings_close_the_connection() {
+        use super::super::{CloseCode, Message, WebSocketEvent};
+
+        // answers pings like any server would
+        struct Echo;
+        impl Handler for Echo {}
+        // swallows pings instead of answering them
+        struct Deaf;
+        impl Handler for Deaf {
+            fn on_frame(&mut self, frame: ws::Frame) -> ws::Result<Option<ws::Frame>> {
+                Ok(Some(frame).filter(|frame| frame.opcode() != ws::OpCode::Ping))
+            }
+        }
+
+        let interval = std::time::Duration::from_millis(20);
+        fn listen<H: Handler + Send + 'static>(
+            factory: fn(ws::Sender) -> H,
+            interval: std::time::Duration,
+        ) -> WebSocket {
+            let server = ws::WebSocket::new(factory)
+                .unwrap()
+                .bind("127.0.0.1:0")
+                .unwrap();
+            let url = format!("ws://{}/socket", server.local_addr().unwrap());
+            std::thread::spawn(move || {
+                let _result = server.run();
+            });
+            futures::executor::block_on(WebSocket::connect(&url))
+                .unwrap()
+                .with_keepalive(interval)
+        }
+
+        let ws = listen(|_sender| Echo, interval);
+        assert_eq!(next_event(&ws), WebSocketEvent::Open);
+        std::thread::sleep(interval * 6);
+        assert!(!ws.is_stale());
+        while let Some(event) = ws.poll() {
+            assert!(matches!(event, WebSocketEvent::Message(Message::Pong(_))));
+        }
+
+        let ws = listen(|_sender| Deaf, interval);
+        assert_eq!(next_event(&ws), WebSocketEvent::Open);
+        assert_eq!(
+            next_event(&ws),
+            WebSocketEvent::Close {
+                code: CloseCode::Abnormal,
+                reason: String::new()
+            }
+        );
+    }
+
+    #[test]
+    fn cloned_senders_keep_order() {
+        use super::super::Message;
+
+        let server = ws::WebSocket::new(Echo)
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let url = format!("ws://{}/socket", server.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        let ws = futures::executor::block_on(WebSocket::connect(&url)).unwrap();
+        let (send, recv) = ws.into_channels();
+        let other = send.clone();
+        let text = |s: &str| Message::Text(s.to_owned());
+        send.send(text("first")).unwrap();
+        other.send(text("second")).unwrap();
+        send.send(text("third")).unwrap();
+
+        let received = (0..3)
+            .map(|_| futures::executor::block_on(recv.recv()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(received, vec![text("first"), text("second"), text("third")]);
+    }
+
+    #[test]
+    fn redialing_gives_up_after_max_attempts() {
+        use super::super::{CloseCode, RetryPolicy, WebSocketEvent};
+
+        let server = ws::WebSocket::new(Echo)
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap();
+        let (url, accepted) = relay(server.local_addr().unwrap(), 1);
+        std::thread::spawn(move || {
+            let _result = server.run();
+        });
+
+        let policy = RetryPolicy {
+            base_delay: std::time::Duration::from_millis(10),
+            max_delay: std::time::Duration::from_millis(20),
+            max_attempts: 2,
+        };
+        let ws = futures::executor::block_on(WebSocket::connect_with_retry(&url, policy)).unwrap();
+        assert_eq!(next_event(&ws), WebSocketEvent::Open);
+
+        let only = accepted
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        only.shutdown(std::net::Shutdown::Both).unwrap();
+        // one for each refused attempt
+        for _ in 0..policy.max_attempts {
+            assert!(matches!(next_event(&ws), WebSocketEvent::Error(_)));
+        }
+        assert_eq!(
+            next_event(&ws),
+            WebSocketEvent::Close {
+                code: CloseCode::Abnormal,
+                reason: String::new()
+            }
+        );
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(ws.poll(), None);
+    }
+
+    #[test]
+    fn no_proxy_matching() {
+        assert!(proxy::is_excluded("localhost", "localhost,example.com"));
+        assert!(proxy::is_excluded(
+            "api.example.com",
+            "localhost, .example.com"
+        ));
+        assert!(proxy::is_excluded("anything", "*"));
+        assert!(!proxy::is_excluded("example.org", "example.com"));
+        assert!(!proxy::is_excluded("notexample.com", "example.com"));
+        assert!(!proxy::is_excluded("example.com", ""));
+    }
+}
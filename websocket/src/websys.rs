@@ -1,11 +1,24 @@
 use futures::{FutureExt, StreamExt};
+use std::cell::RefCell;
+use std::task::{Context, Poll};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{CloseEvent, ErrorEvent, MessageEvent};
 
 pub struct WebSocket {
+    /// Replaced whenever a lost connection is redialed.
+    connection: RefCell<Connection>,
+    redial: Option<RefCell<Redial>>,
+    /// Latched from the close event once it's been polled.
+    closed: std::cell::RefCell<Option<(super::CloseCode, String)>>,
+    send_buffer: RefCell<super::SendBuffer>,
+}
+
+struct Connection {
     inner: web_sys::WebSocket,
-    event_queue: std::sync::mpsc::Receiver<super::WebSocketEvent>,
+    event_queue: futures::channel::mpsc::UnboundedReceiver<super::WebSocketEvent>,
+    /// For the pongs made up in answer to our own pings.
+    event_sender: futures::channel::mpsc::UnboundedSender<super::WebSocketEvent>,
     on_message_callback: Closure<dyn FnMut(MessageEvent)>,
     on_open_callback: Closure<dyn FnMut(JsValue)>,
     on_error_callback: Closure<dyn FnMut(ErrorEvent)>,
@@ -16,27 +29,413 @@ pub struct WebSocket {
 impl WebSocket {
     pub fn connect<S: AsRef<str>>(url: S) -> ConnectionFuture {
         match web_sys::WebSocket::new(url.as_ref()) {
-            Ok(ws) => ConnectionFuture::Connecting(Some(ws.into())),
+            Ok(ws) => ConnectionFuture::Connecting(Some(WebSocket {
+                connection: RefCell::new(ws.into()),
+                redial: None,
+                closed: Default::default(),
+                send_buffer: Default::default(),
+            })),
             Err(_err) => ConnectionFuture::Error(futures::future::ready(
                 super::WebSocketError::CreationError,
             )),
         }
     }
 
+    /// Like `connect` but resolves to `WebSocketError::CreationError` if the handshake takes
+    /// longer than `timeout`. Closing a socket that's still connecting fails it, which is what
+    /// the opening future is already waiting on.
+    pub fn connect_with_timeout<S: AsRef<str>>(
+        url: S,
+        timeout: std::time::Duration,
+    ) -> ConnectionFuture {
+        let future = Self::connect(url);
+        if let ConnectionFuture::Connecting(Some(ws)) = &future {
+            let inner = ws.connection.borrow().inner.clone();
+            let give_up = Closure::once_into_js(move || {
+                if inner.ready_state() == web_sys::WebSocket::CONNECTING {
+                    let _result = inner.close();
+                }
+            });
+            let scheduled = web_sys::window().and_then(|window| {
+                window
+                    .set_timeout_with_callback_and_timeout_and_arguments_0(
+                        give_up.unchecked_ref(),
+                        timeout.as_millis().min(i32::MAX as u128) as i32,
+                    )
+                    .ok()
+            });
+            if scheduled.is_none() {
+                log::warn!("couldn't schedule the connection timeout");
+            }
+        }
+        future
+    }
+
+    /// Like `connect` but the connection is redialed according to `policy` whenever it's lost
+    /// with a recoverable close code. Redialing happens as the socket is polled and stops as soon
+    /// as it's dropped. `WebSocketEvent::Reopened` is polled once a redial opens.
+    pub fn connect_with_retry<S: AsRef<str>>(
+        url: S,
+        policy: super::RetryPolicy,
+    ) -> ConnectionFuture {
+        Self::connect_with_headers_and_retry(url, Vec::new(), policy)
+    }
+
+    /// The browser sends its own cookies with every dial, so as with `connect_with_headers`,
+    /// `headers` is ignored.
+    pub fn connect_with_headers_and_retry<S: AsRef<str>>(
+        url: S,
+        _headers: super::Headers,
+        policy: super::RetryPolicy,
+    ) -> ConnectionFuture {
+        let mut future = Self::connect(url.as_ref());
+        if let ConnectionFuture::Connecting(Some(ws)) = &mut future {
+            ws.redial = Some(RefCell::new(Redial {
+                url: url.as_ref().to_owned(),
+                policy,
+                attempt: 0,
+                state: RedialState::Connected,
+            }));
+        }
+        future
+    }
+
+    /// Browsers send their own cookies with the handshake and don't allow adding other headers,
+    /// so `headers` is ignored.
+    pub fn connect_with_headers<S: AsRef<str>>(
+        url: S,
+        _headers: super::Headers,
+    ) -> ConnectionFuture {
+        Self::connect(url)
+    }
+
+    /// The browser applies its own proxy settings so this is the same as `connect`.
+    pub fn connect_via_proxy<S: AsRef<str>, P: AsRef<str>>(url: S, _proxy: P) -> ConnectionFuture {
+        Self::connect(url)
+    }
+
     pub fn poll(&self) -> Option<super::WebSocketEvent> {
-        self.event_queue.try_recv().ok()
+        let poll = self.latch_close(self.next_event(None));
+        self.flush_sends();
+        match poll {
+            Poll::Ready(event) => event,
+            Poll::Pending => None,
+        }
+    }
+
+    /// Like `poll` but `cx` is woken once there's an event. `Ready(None)` when there won't be
+    /// any more.
+    pub fn poll_event(&self, cx: &mut Context) -> Poll<Option<super::WebSocketEvent>> {
+        let poll = self.latch_close(self.next_event(Some(cx)));
+        self.flush_sends();
+        poll
+    }
+
+    /// Why the socket closed, once that's been polled. Redialed closes don't count.
+    pub fn close_code(&self) -> Option<super::CloseCode> {
+        self.closed.borrow().as_ref().map(|(code, _reason)| *code)
+    }
+
+    /// What the other end said about closing, alongside `close_code`. Often empty.
+    pub fn close_reason(&self) -> Option<String> {
+        self.closed
+            .borrow()
+            .as_ref()
+            .map(|(_code, reason)| reason.clone())
+    }
+
+    fn latch_close(
+        &self,
+        poll: Poll<Option<super::WebSocketEvent>>,
+    ) -> Poll<Option<super::WebSocketEvent>> {
+        if let Poll::Ready(Some(super::WebSocketEvent::Close { code, reason })) = &poll {
+            *self.closed.borrow_mut() = Some((*code, reason.clone()));
+        }
+        poll
     }
 
+    /// While redialing, `cx` is woken once the next attempt is due or the dial it's waiting on
+    /// opens or fails.
+    fn next_event(&self, mut cx: Option<&mut Context>) -> Poll<Option<super::WebSocketEvent>> {
+        let mut redial = match &self.redial {
+            Some(redial) => redial.borrow_mut(),
+            None => return self.connection.borrow_mut().next_event(cx),
+        };
+        if let RedialState::Connected = redial.state {
+            match self.connection.borrow_mut().next_event(cx.as_deref_mut()) {
+                Poll::Ready(Some(super::WebSocketEvent::Close { code, .. }))
+                    if redial.lost(code) =>
+                {
+                    keep_going(cx)
+                }
+                poll => poll,
+            }
+        } else {
+            match redial.poll(cx) {
+                Some(Ok(connection)) => {
+                    *self.connection.borrow_mut() = connection;
+                    Poll::Ready(Some(super::WebSocketEvent::Reopened))
+                }
+                Some(Err(code)) => Poll::Ready(Some(super::WebSocketEvent::Close {
+                    code,
+                    reason: String::new(),
+                })),
+                None if matches!(redial.state, RedialState::Closed) => Poll::Ready(None),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    /// Until the socket is open, `msg` waits in a buffer with any others sent before it rather
+    /// than the browser throwing. Fails once the buffer is full.
     pub fn send(&self, msg: super::Message) -> Result<(), super::WebSocketError> {
+        let connection = self.connection.borrow();
+        let mut send_buffer = self.send_buffer.borrow_mut();
+        if connection.is_open() {
+            send_buffer.flush(|msg| connection.send(msg))?;
+            connection.send(msg)
+        } else if self.closed.borrow().is_some() {
+            Err(super::WebSocketError::SendError)
+        } else {
+            send_buffer.push(msg)
+        }
+    }
+
+    /// How many messages sent before the socket opens are held on to. 64 by default.
+    pub fn with_send_buffer(self, capacity: usize) -> Self {
+        Self {
+            send_buffer: RefCell::new(super::SendBuffer::with_capacity(capacity)),
+            ..self
+        }
+    }
+
+    /// Does nothing: browsers answer pings themselves but can't send any.
+    pub fn with_keepalive(self, _interval: std::time::Duration) -> Self {
+        self
+    }
+
+    /// Always false since there's no keepalive to notice with.
+    pub fn is_stale(&self) -> bool {
+        false
+    }
+
+    /// Passes on whatever was sent while the socket wasn't open, now that it might be.
+    fn flush_sends(&self) {
+        let connection = self.connection.borrow();
+        if connection.is_open() {
+            if let Err(err) = self
+                .send_buffer
+                .borrow_mut()
+                .flush(|msg| connection.send(msg))
+            {
+                log::warn!("couldn't send buffered messages: {}", err);
+            }
+        }
+    }
+}
+
+/// Has `cx` polled again straight away, for when there's more to do right now, like dialing again.
+fn keep_going<T>(cx: Option<&mut Context>) -> Poll<T> {
+    if let Some(cx) = cx {
+        cx.waker().wake_by_ref();
+    }
+    Poll::Pending
+}
+
+impl Connection {
+    fn is_open(&self) -> bool {
+        self.inner.ready_state() == web_sys::WebSocket::OPEN
+    }
+
+    /// Browsers can't send control frames. They answer pings from the other end themselves, so
+    /// a `Pong` has nothing left to do, and a `Ping` is answered straight away since the browser
+    /// is already keeping the connection alive.
+    fn send(&self, msg: super::Message) -> Result<(), super::WebSocketError> {
         match msg {
             super::Message::Text(text) => self.inner.send_with_str(text.as_str()),
             super::Message::Binary(mut bin) => self.inner.send_with_u8_array(bin.as_mut_slice()),
+            super::Message::Ping(data) => {
+                let pong = super::WebSocketEvent::Message(super::Message::Pong(data));
+                return self
+                    .event_sender
+                    .unbounded_send(pong)
+                    .map_err(|_err| super::WebSocketError::SendError);
+            }
+            super::Message::Pong(_data) => return Ok(()),
         }
         .map_err(|_err| super::WebSocketError::SendError)
     }
+
+    /// Only registers for a wake up when given a context.
+    fn next_event(&mut self, cx: Option<&mut Context>) -> Poll<Option<super::WebSocketEvent>> {
+        match cx {
+            Some(cx) => self.event_queue.poll_next_unpin(cx),
+            None => match self.event_queue.try_recv() {
+                Ok(event) => Poll::Ready(Some(event)),
+                Err(futures::channel::mpsc::TryRecvError::Closed) => Poll::Ready(None),
+                Err(futures::channel::mpsc::TryRecvError::Empty) => Poll::Pending,
+            },
+        }
+    }
+}
+
+/// Everything a socket from `connect_with_retry` needs to dial its connection again.
+struct Redial {
+    url: String,
+    policy: super::RetryPolicy,
+    /// Made since the connection was last open.
+    attempt: u32,
+    state: RedialState,
+}
+
+enum RedialState {
+    Connected,
+    Waiting {
+        /// In milliseconds since the epoch, like `Date.now()`.
+        until: f64,
+        code: super::CloseCode,
+        alarm: Alarm,
+    },
+    /// Dropping the connection stops the attempt.
+    Dialing {
+        connection: Connection,
+        code: super::CloseCode,
+    },
+    /// Out of attempts, or lost for a reason that isn't worth redialing over.
+    Closed,
+}
+
+impl Redial {
+    /// Schedules the next attempt after the connection closed with `code`. Returns whether
+    /// there'll be one.
+    fn lost(&mut self, code: super::CloseCode) -> bool {
+        if code.is_recoverable() && self.attempt < self.policy.max_attempts {
+            let delay = self
+                .policy
+                .delay(self.attempt, js_sys::Math::random() as f32);
+            log::debug!("redialing {} in {:?}", self.url, delay);
+            self.attempt += 1;
+            self.state = RedialState::Waiting {
+                until: js_sys::Date::now() + delay.as_secs_f64() * 1000.,
+                code,
+                alarm: Alarm::after(delay),
+            };
+            true
+        } else {
+            self.state = RedialState::Closed;
+            false
+        }
+    }
+
+    /// Moves a redial along, with `cx` woken once there's more to do. Resolves to the new
+    /// connection once it's open, or to the close code that started it all once there's no point
+    /// trying again.
+    fn poll(
+        &mut self,
+        mut cx: Option<&mut Context>,
+    ) -> Option<Result<Connection, super::CloseCode>> {
+        loop {
+            match &mut self.state {
+                RedialState::Connected | RedialState::Closed => return None,
+                RedialState::Waiting { until, code, alarm } => {
+                    if js_sys::Date::now() < *until {
+                        if let Some(cx) = cx {
+                            alarm.wake(cx);
+                        }
+                        return None;
+                    }
+                    let code = *code;
+                    match web_sys::WebSocket::new(&self.url) {
+                        Ok(ws) => {
+                            self.state = RedialState::Dialing {
+                                connection: ws.into(),
+                                code,
+                            };
+                        }
+                        // round again to wait for the next attempt
+                        Err(_err) if self.lost(code) => {}
+                        Err(_err) => return Some(Err(code)),
+                    }
+                }
+                RedialState::Dialing { connection, code } => {
+                    let code = *code;
+                    let opened = match cx.as_deref_mut() {
+                        Some(cx) => match connection.on_open_notification.poll_next_unpin(cx) {
+                            Poll::Pending => return None,
+                            Poll::Ready(result) => matches!(result, Some(Ok(()))),
+                        },
+                        None => match connection.on_open_notification.try_recv() {
+                            Err(futures::channel::mpsc::TryRecvError::Empty) => return None,
+                            result => matches!(result, Ok(Ok(()))),
+                        },
+                    };
+                    match std::mem::replace(&mut self.state, RedialState::Connected) {
+                        RedialState::Dialing { connection, .. } if opened => {
+                            self.attempt = 0;
+                            return Some(Ok(connection));
+                        }
+                        _ if self.lost(code) => {}
+                        _ => return Some(Err(code)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Wakes whichever task last waited on it once its timeout fires, so that a redial's delay is
+/// waited out rather than polled through.
+struct Alarm(std::rc::Rc<RefCell<AlarmState>>);
+
+#[derive(Default)]
+struct AlarmState {
+    rung: bool,
+    waker: Option<std::task::Waker>,
+}
+
+impl Alarm {
+    fn after(delay: std::time::Duration) -> Self {
+        let state = std::rc::Rc::new(RefCell::new(AlarmState::default()));
+        let ring = Closure::once_into_js({
+            let state = state.clone();
+            move || {
+                let waker = {
+                    let mut state = state.borrow_mut();
+                    state.rung = true;
+                    state.waker.take()
+                };
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+        });
+        let scheduled = web_sys::window().and_then(|window| {
+            window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(
+                    ring.unchecked_ref(),
+                    delay.as_millis().min(i32::MAX as u128) as i32,
+                )
+                .ok()
+        });
+        // with nowhere to schedule it, waiting falls back to being polled through
+        if scheduled.is_none() {
+            state.borrow_mut().rung = true;
+        }
+        Self(state)
+    }
+
+    /// `cx` is woken straight away if the alarm has already gone off.
+    fn wake(&self, cx: &mut Context) {
+        let mut state = self.0.borrow_mut();
+        if state.rung {
+            cx.waker().wake_by_ref();
+        } else {
+            state.waker = Some(cx.waker().clone());
+        }
+    }
 }
 
-impl Drop for WebSocket {
+impl Drop for Connection {
     fn drop(&mut self) {
         self.inner
             .remove_event_listener_with_callback(
@@ -65,22 +464,28 @@ impl Drop for WebSocket {
     }
 }
 
-impl From<web_sys::WebSocket> for WebSocket {
+/// Binary frames only ever come through as `ArrayBuffer`s since that's what every `Connection`
+/// asks for, rather than the default of `Blob`s which can only be read asynchronously.
+fn message_event(data: JsValue) -> super::WebSocketEvent {
+    if let Some(text) = data.as_string() {
+        super::WebSocketEvent::Message(super::Message::Text(text))
+    } else if let Some(buffer) = data.dyn_ref::<js_sys::ArrayBuffer>() {
+        let bytes = js_sys::Uint8Array::new(buffer).to_vec();
+        super::WebSocketEvent::Message(super::Message::Binary(bytes))
+    } else {
+        super::WebSocketEvent::Error(super::WebSocketError::ReceiveError)
+    }
+}
+
+impl From<web_sys::WebSocket> for Connection {
     fn from(inner: web_sys::WebSocket) -> Self {
-        let (sx, rx) = std::sync::mpsc::channel();
+        inner.set_binary_type(web_sys::BinaryType::Arraybuffer);
+        let (sx, rx) = futures::channel::mpsc::unbounded();
         let (mut on_open_sender, on_open_recver) = futures::channel::mpsc::channel(1);
         let on_message_callback = {
             let queue = sx.clone();
             Closure::wrap(Box::new(move |e: MessageEvent| {
-                let result = match e.data().as_string() {
-                    Some(response) => queue.send(super::WebSocketEvent::Message(
-                        super::Message::Text(response),
-                    )),
-                    None => queue.send(super::WebSocketEvent::Error(
-                        super::WebSocketError::ReceiveError,
-                    )),
-                };
-                if let Err(err) = result {
+                if let Err(err) = queue.unbounded_send(message_event(e.data())) {
                     log::error!("{}", err);
                 }
             }) as Box<dyn FnMut(MessageEvent)>)
@@ -119,8 +524,11 @@ impl From<web_sys::WebSocket> for WebSocket {
         let on_close_callback = {
             let queue = sx.clone();
             Closure::wrap(Box::new(move |close_event: CloseEvent| {
-                if let Err(e) = queue.send(super::WebSocketEvent::Close(close_event.code().into()))
-                {
+                let event = super::WebSocketEvent::Close {
+                    code: close_event.code().into(),
+                    reason: close_event.reason(),
+                };
+                if let Err(e) = queue.unbounded_send(event) {
                     log::error!("{}", e)
                 }
             }) as Box<dyn FnMut(CloseEvent)>)
@@ -129,9 +537,10 @@ impl From<web_sys::WebSocket> for WebSocket {
             .add_event_listener_with_callback("close", on_close_callback.as_ref().unchecked_ref())
             .unwrap();
 
-        WebSocket {
+        Connection {
             inner,
             event_queue: rx,
+            event_sender: sx,
             on_message_callback,
             on_open_callback,
             on_error_callback,
@@ -158,9 +567,13 @@ impl futures::future::Future for ConnectionFuture {
             ConnectionFuture::Error(err) => err.poll_unpin(cx).map(|err| Err(err)),
             ConnectionFuture::Connecting(maybe_ws) => {
                 if let Some(ws) = maybe_ws {
-                    if let std::task::Poll::Ready(result) =
-                        ws.on_open_notification.next().poll_unpin(cx)
-                    {
+                    let notification = ws
+                        .connection
+                        .get_mut()
+                        .on_open_notification
+                        .next()
+                        .poll_unpin(cx);
+                    if let std::task::Poll::Ready(result) = notification {
                         match result {
                             Some(Ok(_)) => std::task::Poll::Ready(Ok(maybe_ws.take().unwrap())),
                             _ => std::task::Poll::Ready(Err(super::WebSocketError::CreationError)),
@@ -207,3 +620,26 @@ impl From<u16> for super::CloseCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Message, WebSocketEvent};
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn binary_frames_round_trip() {
+        let bytes = vec![0, 1, 2, 254, 255];
+        let frame = js_sys::Uint8Array::from(bytes.as_slice()).buffer();
+        assert_eq!(
+            message_event(frame.into()),
+            WebSocketEvent::Message(Message::Binary(bytes))
+        );
+        assert_eq!(
+            message_event(JsValue::from_str("text")),
+            WebSocketEvent::Message(Message::Text("text".to_owned()))
+        );
+    }
+}